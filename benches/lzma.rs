@@ -13,6 +13,12 @@ fn compress_bench(x: &[u8], b: &mut Bencher) {
     });
 }
 
+/// Same as [`compress_bench()`], with the output pre-allocated from the
+/// estimated compressed size.
+fn compress_to_vec_bench(x: &[u8], b: &mut Bencher) {
+    b.iter(|| lzma_rs::lzma_compress_to_vec(x).unwrap());
+}
+
 fn decompress_after_compress_bench(x: &[u8], b: &mut Bencher) {
     let mut compressed: Vec<u8> = Vec::new();
     lzma_rs::lzma_compress(&mut std::io::BufReader::new(x), &mut compressed).unwrap();
@@ -101,6 +107,71 @@ fn decompress_after_compress_65536(b: &mut Bencher) {
     decompress_after_compress_bench(&[0; 0x10000], b);
 }
 
+#[bench]
+fn compress_big_file(b: &mut Bencher) {
+    #[cfg(feature = "enable_logging")]
+    let _ = env_logger::try_init();
+    let mut f = std::fs::File::open("tests/files/foo.txt").unwrap();
+    let mut x = Vec::new();
+    f.read_to_end(&mut x).unwrap();
+    compress_bench(&x, b);
+}
+
+#[bench]
+fn compress_to_vec_big_file(b: &mut Bencher) {
+    #[cfg(feature = "enable_logging")]
+    let _ = env_logger::try_init();
+    let mut f = std::fs::File::open("tests/files/foo.txt").unwrap();
+    let mut x = Vec::new();
+    f.read_to_end(&mut x).unwrap();
+    compress_to_vec_bench(&x, b);
+}
+
+/// A thousand messages of a few dozen bytes.
+fn tiny_messages() -> Vec<Vec<u8>> {
+    let mut f = std::fs::File::open("tests/files/foo.txt").unwrap();
+    let mut x = Vec::new();
+    f.read_to_end(&mut x).unwrap();
+    (0..1000)
+        .map(|i| {
+            let start = i * 97 % (x.len() - 100);
+            x[start..start + 8 + i * 31 % 64].to_vec()
+        })
+        .collect()
+}
+
+#[bench]
+fn compress_tiny_messages(b: &mut Bencher) {
+    #[cfg(feature = "enable_logging")]
+    let _ = env_logger::try_init();
+    let messages = tiny_messages();
+    b.iter(|| {
+        messages
+            .iter()
+            .map(|message| lzma_rs::lzma_compress_to_vec(message).unwrap().len())
+            .sum::<usize>()
+    });
+}
+
+/// Same as [`compress_tiny_messages()`], with the size of the messages left
+/// unknown, which requires the full encoder setup.
+#[bench]
+fn compress_tiny_messages_full_setup(b: &mut Bencher) {
+    #[cfg(feature = "enable_logging")]
+    let _ = env_logger::try_init();
+    let messages = tiny_messages();
+    b.iter(|| {
+        messages
+            .iter()
+            .map(|message| {
+                let mut compressed: Vec<u8> = Vec::new();
+                lzma_rs::lzma_compress(&mut message.as_slice(), &mut compressed).unwrap();
+                compressed.len()
+            })
+            .sum::<usize>()
+    });
+}
+
 #[bench]
 fn decompress_big_file(b: &mut Bencher) {
     #[cfg(feature = "enable_logging")]
@@ -108,6 +179,50 @@ fn decompress_big_file(b: &mut Bencher) {
     decompress_bench_file("tests/files/foo.txt.lzma", b);
 }
 
+#[bench]
+fn decompress_runs(b: &mut Bencher) {
+    #[cfg(feature = "enable_logging")]
+    let _ = env_logger::try_init();
+    decompress_bench_file("tests/files/runs.lzma", b);
+}
+
+#[bench]
+fn decompress_lzma2_large_stream(b: &mut Bencher) {
+    #[cfg(feature = "enable_logging")]
+    let _ = env_logger::try_init();
+    // About 4 MiB of shuffled lines of text, compressed by liblzma so that
+    // the decoder goes through all kinds of matches and reps, not only
+    // literals. The LZMA2 data of its single block follows the 12-byte
+    // stream header and the block header.
+    let mut f = std::fs::File::open("tests/files/foo.txt").unwrap();
+    let mut text = Vec::new();
+    f.read_to_end(&mut text).unwrap();
+    let mut lines: Vec<&[u8]> = text.split(|&c| c == b'\n').collect();
+    let mut state = 0x2545_f491u32;
+    let mut x = Vec::new();
+    for _ in 0..24 {
+        for i in (1..lines.len()).rev() {
+            state ^= state << 13;
+            state ^= state >> 17;
+            state ^= state << 5;
+            lines.swap(i, state as usize % (i + 1));
+        }
+        for line in &lines {
+            x.extend_from_slice(line);
+            x.push(b'\n');
+        }
+    }
+    let xz = lzma::compress(&x, 6).unwrap();
+    let compressed = &xz[12 + (xz[12] as usize + 1) * 4..];
+
+    b.iter(|| {
+        let mut decomp: Vec<u8> = Vec::with_capacity(x.len());
+        lzma_rs::lzma2_decompress(&mut &compressed[..], &mut decomp).unwrap();
+        assert_eq!(decomp.len(), x.len());
+        decomp
+    });
+}
+
 #[cfg(feature = "stream")]
 #[bench]
 fn decompress_stream_big_file(b: &mut Bencher) {
@@ -125,5 +240,284 @@ fn decompress_huge_dict(b: &mut Bencher) {
                               \x49\x98\x6f\x10\x19\xc6\xd7\x31\
                               \xeb\x36\x50\xb2\x98\x48\xff\xfe\
                               \xa5\xb0\x00";
-    decompress_bench(&compressed, b);
+    decompress_bench(compressed, b);
+}
+
+#[cfg(feature = "xz")]
+#[bench]
+fn decompress_xz_crc64_big_file(b: &mut Bencher) {
+    #[cfg(feature = "enable_logging")]
+    let _ = env_logger::try_init();
+    let mut f = std::fs::File::open("tests/files/foo.txt").unwrap();
+    let mut x = Vec::new();
+    f.read_to_end(&mut x).unwrap();
+    let options = lzma_rs::compress::Options {
+        check: lzma_rs::compress::CheckMethod::Crc64,
+        ..Default::default()
+    };
+    let mut compressed: Vec<u8> = Vec::new();
+    lzma_rs::xz_compress_with_options(&mut x.as_slice(), &mut compressed, &options).unwrap();
+
+    b.iter(|| {
+        let mut decomp: Vec<u8> = Vec::new();
+        lzma_rs::xz_decompress(&mut compressed.as_slice(), &mut decomp).unwrap();
+        decomp
+    });
+}
+
+#[cfg(feature = "xz")]
+/// Compress a file into an XZ stream of several blocks.
+fn compress_xz_multi_block_file(filename: &str) -> Vec<u8> {
+    let mut f = std::fs::File::open(filename).unwrap();
+    let mut x = Vec::new();
+    f.read_to_end(&mut x).unwrap();
+    let options = lzma_rs::compress::Options {
+        block_size: Some(0x4000),
+        ..Default::default()
+    };
+    let mut compressed: Vec<u8> = Vec::new();
+    lzma_rs::xz_compress_with_options(&mut x.as_slice(), &mut compressed, &options).unwrap();
+    compressed
+}
+
+#[cfg(feature = "xz")]
+#[bench]
+fn decompress_xz_multi_block_big_file(b: &mut Bencher) {
+    #[cfg(feature = "enable_logging")]
+    let _ = env_logger::try_init();
+    let compressed = compress_xz_multi_block_file("tests/files/foo.txt");
+
+    b.iter(|| {
+        let mut decomp: Vec<u8> = Vec::new();
+        lzma_rs::xz_decompress(&mut compressed.as_slice(), &mut decomp).unwrap();
+        decomp
+    });
+}
+
+#[cfg(feature = "xz")]
+#[bench]
+fn compress_xz_threads_big_file(b: &mut Bencher) {
+    #[cfg(feature = "enable_logging")]
+    let _ = env_logger::try_init();
+    let mut f = std::fs::File::open("tests/files/foo.txt").unwrap();
+    let mut x = Vec::new();
+    f.read_to_end(&mut x).unwrap();
+    let options = lzma_rs::compress::Options {
+        block_size: Some(0x4000),
+        threads: 4,
+        ..Default::default()
+    };
+
+    b.iter(|| {
+        let mut compressed: Vec<u8> = Vec::new();
+        lzma_rs::xz_compress_with_options(&mut x.as_slice(), &mut compressed, &options).unwrap();
+        compressed
+    });
+}
+
+#[cfg(feature = "xz")]
+#[bench]
+fn decompress_xz_seek_reader_read_to_end_big_file(b: &mut Bencher) {
+    #[cfg(feature = "enable_logging")]
+    let _ = env_logger::try_init();
+    let compressed = compress_xz_multi_block_file("tests/files/foo.txt");
+
+    b.iter(|| {
+        let mut reader =
+            lzma_rs::decompress::XzSeekReader::new(std::io::Cursor::new(compressed.as_slice()))
+                .unwrap();
+        let mut decomp: Vec<u8> = Vec::new();
+        reader.read_to_end(&mut decomp).unwrap();
+        decomp
+    });
+}
+
+/// Binary data: little-endian counters, as in tables of integers.
+fn binary_data() -> Vec<u8> {
+    (0..0x10000u32)
+        .flat_map(|i| (i * 7).to_le_bytes())
+        .collect()
+}
+
+/// Incompressible data, from a xorshift generator.
+fn incompressible_data() -> Vec<u8> {
+    let mut state = 0x2545_f491u32;
+    (0..0x40000)
+        .map(|_| {
+            state ^= state << 13;
+            state ^= state >> 17;
+            state ^= state << 5;
+            state as u8
+        })
+        .collect()
+}
+
+#[bench]
+fn compress_binary(b: &mut Bencher) {
+    #[cfg(feature = "enable_logging")]
+    let _ = env_logger::try_init();
+    compress_bench(&binary_data(), b);
+}
+
+#[bench]
+fn decompress_after_compress_binary(b: &mut Bencher) {
+    #[cfg(feature = "enable_logging")]
+    let _ = env_logger::try_init();
+    decompress_after_compress_bench(&binary_data(), b);
+}
+
+#[bench]
+fn compress_incompressible(b: &mut Bencher) {
+    #[cfg(feature = "enable_logging")]
+    let _ = env_logger::try_init();
+    compress_bench(&incompressible_data(), b);
+}
+
+#[bench]
+fn compress_to_vec_incompressible(b: &mut Bencher) {
+    #[cfg(feature = "enable_logging")]
+    let _ = env_logger::try_init();
+    compress_to_vec_bench(&incompressible_data(), b);
+}
+
+#[cfg(feature = "xz")]
+fn compress_xz_auto_store_bench(x: &[u8], auto_store: bool, b: &mut Bencher) {
+    let options = lzma_rs::compress::Options {
+        auto_store,
+        ..Default::default()
+    };
+    b.iter(|| {
+        let mut compressed: Vec<u8> = Vec::new();
+        lzma_rs::xz_compress_with_options(&mut &x[..], &mut compressed, &options).unwrap();
+        compressed
+    });
+}
+
+#[cfg(feature = "xz")]
+#[bench]
+fn compress_xz_incompressible(b: &mut Bencher) {
+    #[cfg(feature = "enable_logging")]
+    let _ = env_logger::try_init();
+    compress_xz_auto_store_bench(&incompressible_data(), false, b);
+}
+
+#[cfg(feature = "xz")]
+#[bench]
+fn compress_xz_auto_store_incompressible(b: &mut Bencher) {
+    #[cfg(feature = "enable_logging")]
+    let _ = env_logger::try_init();
+    compress_xz_auto_store_bench(&incompressible_data(), true, b);
+}
+
+#[cfg(feature = "xz")]
+#[bench]
+fn compress_xz_auto_store_big_file(b: &mut Bencher) {
+    #[cfg(feature = "enable_logging")]
+    let _ = env_logger::try_init();
+    let mut f = std::fs::File::open("tests/files/foo.txt").unwrap();
+    let mut x = Vec::new();
+    f.read_to_end(&mut x).unwrap();
+    compress_xz_auto_store_bench(&x, true, b);
+}
+
+#[bench]
+fn decompress_after_compress_incompressible(b: &mut Bencher) {
+    #[cfg(feature = "enable_logging")]
+    let _ = env_logger::try_init();
+    decompress_after_compress_bench(&incompressible_data(), b);
+}
+
+/// Split the text file into messages of 100 bytes, each compressed on its
+/// own with `compress`.
+fn compress_small_streams<F>(compress: F) -> Vec<Vec<u8>>
+where
+    F: Fn(&mut &[u8], &mut Vec<u8>) -> std::io::Result<lzma_rs::compress::CompressStats>,
+{
+    let mut f = std::fs::File::open("tests/files/foo.txt").unwrap();
+    let mut x = Vec::new();
+    f.read_to_end(&mut x).unwrap();
+    x.chunks(100)
+        .map(|mut message| {
+            let mut compressed: Vec<u8> = Vec::new();
+            compress(&mut message, &mut compressed).unwrap();
+            compressed
+        })
+        .collect()
+}
+
+#[bench]
+fn decompress_many_small_streams(b: &mut Bencher) {
+    #[cfg(feature = "enable_logging")]
+    let _ = env_logger::try_init();
+    let streams = compress_small_streams(|input, output| lzma_rs::lzma_compress(input, output));
+
+    b.iter(|| {
+        let mut decomp: Vec<u8> = Vec::new();
+        for compressed in &streams {
+            lzma_rs::lzma_decompress(&mut compressed.as_slice(), &mut decomp).unwrap();
+        }
+        decomp
+    });
+}
+
+#[cfg(feature = "xz")]
+#[bench]
+fn decompress_xz_many_small_streams(b: &mut Bencher) {
+    #[cfg(feature = "enable_logging")]
+    let _ = env_logger::try_init();
+    let streams = compress_small_streams(|input, output| lzma_rs::xz_compress(input, output));
+
+    b.iter(|| {
+        let mut decomp: Vec<u8> = Vec::new();
+        for compressed in &streams {
+            lzma_rs::xz_decompress(&mut compressed.as_slice(), &mut decomp).unwrap();
+        }
+        decomp
+    });
+}
+
+#[cfg(feature = "xz")]
+#[bench]
+fn compress_xz_big_file(b: &mut Bencher) {
+    #[cfg(feature = "enable_logging")]
+    let _ = env_logger::try_init();
+    let mut f = std::fs::File::open("tests/files/foo.txt").unwrap();
+    let mut x = Vec::new();
+    f.read_to_end(&mut x).unwrap();
+
+    b.iter(|| {
+        let mut compressed: Vec<u8> = Vec::new();
+        lzma_rs::xz_compress(&mut x.as_slice(), &mut compressed).unwrap();
+        compressed
+    });
+}
+
+#[cfg(feature = "xz")]
+#[bench]
+fn decompress_xz_big_file(b: &mut Bencher) {
+    #[cfg(feature = "enable_logging")]
+    let _ = env_logger::try_init();
+    let mut f = std::fs::File::open("tests/files/foo.txt.xz").unwrap();
+    let mut compressed = Vec::new();
+    f.read_to_end(&mut compressed).unwrap();
+
+    b.iter(|| {
+        let mut decomp: Vec<u8> = Vec::new();
+        lzma_rs::xz_decompress(&mut compressed.as_slice(), &mut decomp).unwrap();
+        decomp
+    });
+}
+
+#[bench]
+fn decompress_lzma2_after_compress_binary(b: &mut Bencher) {
+    #[cfg(feature = "enable_logging")]
+    let _ = env_logger::try_init();
+    let mut compressed: Vec<u8> = Vec::new();
+    lzma_rs::lzma2_compress(&mut binary_data().as_slice(), &mut compressed).unwrap();
+
+    b.iter(|| {
+        let mut decomp: Vec<u8> = Vec::new();
+        lzma_rs::lzma2_decompress(&mut compressed.as_slice(), &mut decomp).unwrap();
+        decomp
+    });
 }
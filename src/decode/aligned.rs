@@ -0,0 +1,148 @@
+use std::{fmt, io};
+
+/// An [`io::Write`] passing the bytes written to the underlying writer in
+/// multiples of a given alignment, e.g. for files opened with `O_DIRECT` or
+/// sinks that perform best with block-sized writes.
+///
+/// Bytes are coalesced in a buffer of `alignment` bytes until it's full,
+/// while the aligned part of large writes goes straight to the underlying
+/// writer. Flushing only flushes the aligned data: the last unaligned bytes
+/// are written by [`AlignedWriter::finish()`].
+pub struct AlignedWriter<W>
+where
+    W: io::Write,
+{
+    /// Underlying writer
+    inner: W,
+    /// Partial block
+    buf: Vec<u8>,
+    /// Size of the blocks
+    alignment: usize,
+}
+
+impl<W> AlignedWriter<W>
+where
+    W: io::Write,
+{
+    /// Wraps `inner`, writing to it in multiples of `alignment` bytes.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `alignment` is zero.
+    pub fn new(inner: W, alignment: usize) -> Self {
+        assert!(alignment > 0, "alignment must be non-zero");
+        Self {
+            inner,
+            buf: Vec::with_capacity(alignment),
+            alignment,
+        }
+    }
+
+    /// Gets a reference to the underlying writer.
+    pub fn get_ref(&self) -> &W {
+        &self.inner
+    }
+
+    /// Writes the last unaligned bytes, if any, and flushes the underlying
+    /// writer, which is returned.
+    pub fn finish(mut self) -> io::Result<W> {
+        if !self.buf.is_empty() {
+            self.inner.write_all(&self.buf)?;
+            self.buf.clear();
+        }
+        self.inner.flush()?;
+        Ok(self.inner)
+    }
+}
+
+impl<W> io::Write for AlignedWriter<W>
+where
+    W: io::Write,
+{
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if self.buf.is_empty() && buf.len() >= self.alignment {
+            let len = buf.len() - buf.len() % self.alignment;
+            self.inner.write_all(&buf[..len])?;
+            return Ok(len);
+        }
+
+        let len = std::cmp::min(buf.len(), self.alignment - self.buf.len());
+        self.buf.extend_from_slice(&buf[..len]);
+        if self.buf.len() == self.alignment {
+            self.inner.write_all(&self.buf)?;
+            self.buf.clear();
+        }
+        Ok(len)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+impl<W> fmt::Debug for AlignedWriter<W>
+where
+    W: io::Write + fmt::Debug,
+{
+    fn fmt(&self, fmt: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt.debug_struct("AlignedWriter")
+            .field("inner", &self.inner)
+            .field("alignment", &self.alignment)
+            .field("buffered", &self.buf.len())
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::io::Write;
+
+    /// Writer recording the size of each write
+    #[derive(Debug, Default)]
+    struct RecordingWriter {
+        data: Vec<u8>,
+        writes: Vec<usize>,
+    }
+
+    impl io::Write for RecordingWriter {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.data.extend_from_slice(buf);
+            self.writes.push(buf.len());
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_aligned_writer() {
+        let data: Vec<u8> = (0..100).collect();
+        let mut writer = AlignedWriter::new(RecordingWriter::default(), 8);
+        writer.write_all(&data[..3]).unwrap();
+        writer.flush().unwrap();
+        writer.write_all(&data[3..30]).unwrap();
+        writer.write_all(&data[30..31]).unwrap();
+        writer.write_all(&data[31..100]).unwrap();
+        assert_eq!(writer.get_ref().data, &data[..96]);
+        let inner = writer.finish().unwrap();
+        assert_eq!(inner.data, data);
+        // Large writes go through in one aligned piece.
+        assert_eq!(inner.writes, [8, 16, 8, 64, 4]);
+    }
+
+    #[test]
+    fn test_aligned_decoding() {
+        let x = b"Hello world!\n".repeat(1000);
+        let compressed = crate::lzma_compress_to_vec(&x).unwrap();
+        let mut writer = AlignedWriter::new(RecordingWriter::default(), 512);
+        crate::lzma_decompress(&mut compressed.as_slice(), &mut writer).unwrap();
+        let inner = writer.finish().unwrap();
+        assert_eq!(inner.data, x);
+        let (last, writes) = inner.writes.split_last().unwrap();
+        assert!(writes.iter().all(|len| len % 512 == 0), "{:?}", writes);
+        assert_eq!(*last, x.len() % 512);
+    }
+}
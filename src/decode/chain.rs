@@ -0,0 +1,79 @@
+use std::io;
+
+/// An [`io::BufRead`] over a chain of slices, e.g. scatter-gather buffers
+/// or network frames, read in turn without copying them into a contiguous
+/// buffer.
+///
+/// The decoders read fields through the [`io::Read`] interface, so a field
+/// spanning two slices is reassembled like with any other reader. Empty
+/// slices are skipped.
+#[derive(Clone, Debug)]
+pub struct ChainedReader<'a> {
+    /// Slices that remain to be read, starting with the current one
+    slices: &'a [&'a [u8]],
+    /// Position in the current slice
+    pos: usize,
+}
+
+impl<'a> ChainedReader<'a> {
+    /// Creates a reader over the concatenation of `slices`.
+    pub fn new(slices: &'a [&'a [u8]]) -> Self {
+        Self { slices, pos: 0 }
+    }
+
+    /// Number of bytes left to read.
+    pub fn remaining(&self) -> usize {
+        self.slices.iter().map(|slice| slice.len()).sum::<usize>() - self.pos
+    }
+}
+
+impl<'a> io::Read for ChainedReader<'a> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let available = io::BufRead::fill_buf(self)?;
+        let len = std::cmp::min(buf.len(), available.len());
+        buf[..len].copy_from_slice(&available[..len]);
+        io::BufRead::consume(self, len);
+        Ok(len)
+    }
+}
+
+impl<'a> io::BufRead for ChainedReader<'a> {
+    fn fill_buf(&mut self) -> io::Result<&[u8]> {
+        while let Some((first, rest)) = self.slices.split_first() {
+            if self.pos < first.len() {
+                return Ok(&first[self.pos..]);
+            }
+            self.slices = rest;
+            self.pos = 0;
+        }
+        Ok(&[])
+    }
+
+    fn consume(&mut self, amt: usize) {
+        self.pos += amt;
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::io::{BufRead, Read};
+
+    #[test]
+    fn test_chained_reader() {
+        let slices: [&[u8]; 5] = [b"ab", b"", b"c", b"defg", b""];
+        let mut reader = ChainedReader::new(&slices);
+        assert_eq!(reader.remaining(), 7);
+        let mut buf = [0; 3];
+        reader.read_exact(&mut buf).unwrap();
+        assert_eq!(&buf, b"abc");
+        assert_eq!(reader.remaining(), 4);
+        assert_eq!(reader.fill_buf().unwrap(), b"defg");
+        reader.consume(1);
+        let mut rest = Vec::new();
+        reader.read_to_end(&mut rest).unwrap();
+        assert_eq!(rest, b"efg");
+        assert_eq!(reader.remaining(), 0);
+        assert!(reader.fill_buf().unwrap().is_empty());
+    }
+}
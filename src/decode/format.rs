@@ -0,0 +1,76 @@
+use crate::error;
+use crate::xz::XZ_MAGIC;
+use std::io;
+
+/// Length of the header of `.lzma` data.
+const LZMA_HEADER_LEN: usize = 13;
+
+/// Largest unpacked size that `.lzma` headers plausibly declare, 256 GiB,
+/// as liblzma assumes when detecting the format.
+const MAX_LZMA_UNPACKED_SIZE: u64 = 1 << 38;
+
+/// Formats of compressed data, as detected by
+/// [`sniff_format()`](crate::sniff_format).
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum Format {
+    /// `.xz` data, starting with the XZ magic bytes.
+    Xz,
+    /// `.lzma` data, also known as LZMA-alone, whose header looks valid.
+    LzmaAlone,
+    /// Anything else, e.g. raw LZMA2 data, which has no header.
+    Unknown,
+}
+
+/// Detect the format of `input` from its leading bytes, without consuming
+/// them.
+pub(crate) fn sniff<R: io::BufRead>(input: &mut R) -> error::Result<Format> {
+    let buf = input.fill_buf()?;
+    if buf.starts_with(XZ_MAGIC) {
+        return Ok(Format::Xz);
+    }
+    if buf.len() < LZMA_HEADER_LEN {
+        return Ok(Format::Unknown);
+    }
+
+    // The `.lzma` header has no magic bytes, but its properties byte must
+    // be valid, and its unpacked size is either unknown or plausible.
+    let mut unpacked_size = [0; 8];
+    unpacked_size.copy_from_slice(&buf[5..LZMA_HEADER_LEN]);
+    let unpacked_size = u64::from_le_bytes(unpacked_size);
+    if buf[0] < 225 && (unpacked_size == u64::MAX || unpacked_size < MAX_LZMA_UNPACKED_SIZE) {
+        Ok(Format::LzmaAlone)
+    } else {
+        Ok(Format::Unknown)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_sniff() {
+        let sniff = |buf: &[u8]| sniff(&mut &buf[..]).unwrap();
+        assert_eq!(sniff(b"\xfd7zXZ\x00\x00\x04"), Format::Xz);
+        assert_eq!(
+            sniff(b"\x5d\x00\x00\x80\x00\xff\xff\xff\xff\xff\xff\xff\xff"),
+            Format::LzmaAlone
+        );
+        assert_eq!(
+            sniff(b"\x5d\x00\x00\x80\x00\x0c\x00\x00\x00\x00\x00\x00\x00\x00"),
+            Format::LzmaAlone
+        );
+        // Invalid properties, implausible sizes and short inputs.
+        assert_eq!(
+            sniff(b"\xe1\x00\x00\x80\x00\xff\xff\xff\xff\xff\xff\xff\xff"),
+            Format::Unknown
+        );
+        assert_eq!(
+            sniff(b"\x5d\x00\x00\x80\x00\x00\x00\x00\x00\x00\x01\x00\x00"),
+            Format::Unknown
+        );
+        assert_eq!(sniff(b"\x5d\x00\x00\x80\x00"), Format::Unknown);
+        assert_eq!(sniff(b""), Format::Unknown);
+    }
+}
@@ -0,0 +1,105 @@
+use std::{fmt, io};
+
+/// An [`io::Write`] passing the bytes written to a callback, in frames of a
+/// fixed size.
+///
+/// Every frame holds exactly `frame_size` bytes, except for the last one which
+/// is delivered by [`FrameWriter::finish()`]. Flushing doesn't cut frames
+/// short.
+pub struct FrameWriter<F>
+where
+    F: FnMut(&[u8]) -> io::Result<()>,
+{
+    /// Callback receiving each frame
+    callback: F,
+    /// Partial frame
+    frame: Vec<u8>,
+    /// Size of the frames
+    frame_size: usize,
+}
+
+impl<F> FrameWriter<F>
+where
+    F: FnMut(&[u8]) -> io::Result<()>,
+{
+    /// Creates a writer delivering frames of `frame_size` bytes to `callback`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `frame_size` is zero.
+    pub fn new(frame_size: usize, callback: F) -> Self {
+        assert!(frame_size > 0, "frame size must be non-zero");
+        Self {
+            callback,
+            frame: Vec::with_capacity(frame_size),
+            frame_size,
+        }
+    }
+
+    /// Delivers the last partial frame, if any.
+    pub fn finish(mut self) -> io::Result<()> {
+        if !self.frame.is_empty() {
+            (self.callback)(&self.frame)?;
+            self.frame.clear();
+        }
+        Ok(())
+    }
+}
+
+impl<F> io::Write for FrameWriter<F>
+where
+    F: FnMut(&[u8]) -> io::Result<()>,
+{
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let len = std::cmp::min(buf.len(), self.frame_size - self.frame.len());
+        self.frame.extend_from_slice(&buf[..len]);
+        if self.frame.len() == self.frame_size {
+            (self.callback)(&self.frame)?;
+            self.frame.clear();
+        }
+        Ok(len)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl<F> fmt::Debug for FrameWriter<F>
+where
+    F: FnMut(&[u8]) -> io::Result<()>,
+{
+    fn fmt(&self, fmt: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt.debug_struct("FrameWriter")
+            .field("frame_size", &self.frame_size)
+            .field("buffered", &self.frame.len())
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::io::Write;
+
+    #[test]
+    fn test_frame_writer() {
+        let mut frames: Vec<Vec<u8>> = Vec::new();
+        let mut writer = FrameWriter::new(3, |frame| {
+            frames.push(frame.to_vec());
+            Ok(())
+        });
+        writer.write_all(b"ab").unwrap();
+        writer.flush().unwrap();
+        writer.write_all(b"cdefg").unwrap();
+        writer.finish().unwrap();
+        assert_eq!(frames, [&b"abc"[..], b"def", b"g"]);
+    }
+
+    #[test]
+    fn test_frame_writer_error() {
+        let mut writer = FrameWriter::new(2, |_| Err(io::Error::new(io::ErrorKind::Other, "full")));
+        writer.write_all(b"a").unwrap();
+        assert_eq!(writer.write_all(b"b").unwrap_err().to_string(), "full");
+    }
+}
@@ -0,0 +1,350 @@
+use crate::decode::options::{Digest, Options};
+use crate::error;
+use crate::xz::checksum::{Crc32, Crc64};
+use std::{fmt, io};
+
+/// An [`io::Write`] accepting at most a given number of bytes.
+///
+/// Writing past the limit fails with an [`io::Error`] that the decoders
+/// surface as [`Error::OutputTooLarge`](crate::error::Error::OutputTooLarge),
+/// which is handy for sinks of a fixed capacity.
+#[derive(Debug)]
+pub struct LimitedWriter<W> {
+    /// Underlying writer
+    inner: W,
+    /// Maximum number of bytes to write
+    limit: u64,
+    /// Number of bytes written
+    count: u64,
+}
+
+impl<W> LimitedWriter<W> {
+    /// Wraps `inner`, accepting at most `limit` bytes.
+    pub fn new(inner: W, limit: u64) -> Self {
+        Self {
+            inner,
+            limit,
+            count: 0,
+        }
+    }
+
+    /// Number of bytes written so far.
+    pub fn count(&self) -> u64 {
+        self.count
+    }
+
+    /// Gets a reference to the underlying writer.
+    pub fn get_ref(&self) -> &W {
+        &self.inner
+    }
+
+    /// Unwraps this `LimitedWriter`, returning the underlying writer.
+    pub fn into_inner(self) -> W {
+        self.inner
+    }
+}
+
+impl<W> io::Write for LimitedWriter<W>
+where
+    W: io::Write,
+{
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let remaining = self.limit - self.count;
+        if remaining == 0 && !buf.is_empty() {
+            return Err(io::Error::new(
+                io::ErrorKind::Other,
+                LimitExceeded { limit: self.limit },
+            ));
+        }
+
+        let len = std::cmp::min(buf.len() as u64, remaining) as usize;
+        let result = self.inner.write(&buf[..len])?;
+        self.count += result as u64;
+        Ok(result)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+/// An [`io::Write`] keeping only the first bytes written to it, up to an
+/// optional limit, and silently discarding the rest.
+///
+/// This truncates the output of decoders stopping early, which may overshoot
+/// their limit.
+#[derive(Debug)]
+pub(crate) struct TruncatingWriter<W> {
+    /// Underlying writer
+    inner: W,
+    /// Number of bytes still to keep, or [`None`] to keep everything
+    remaining: Option<u64>,
+}
+
+impl<W> TruncatingWriter<W> {
+    pub(crate) fn new(inner: W, limit: Option<u64>) -> Self {
+        Self {
+            inner,
+            remaining: limit,
+        }
+    }
+
+    /// Number of bytes still to keep, or [`None`] if unlimited.
+    pub(crate) fn remaining(&self) -> Option<u64> {
+        self.remaining
+    }
+}
+
+impl<W> io::Write for TruncatingWriter<W>
+where
+    W: io::Write,
+{
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self.remaining {
+            None => self.inner.write(buf),
+            Some(0) => Ok(buf.len()),
+            Some(remaining) => {
+                let len = std::cmp::min(buf.len() as u64, remaining) as usize;
+                let result = self.inner.write(&buf[..len])?;
+                self.remaining = Some(remaining - result as u64);
+                Ok(result)
+            }
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+/// An [`io::Write`] checking that the output has an expected size and
+/// digest, see [`Options::expected_size`] and [`Options::expected_digest`].
+///
+/// Writing past the expected size fails with an [`io::Error`] that the
+/// decoders surface as
+/// [`Error::SizeMismatch`](crate::error::Error::SizeMismatch).
+#[derive(Debug)]
+pub(crate) struct CheckedWriter<W> {
+    /// Underlying writer
+    inner: W,
+    /// Expected number of bytes, or [`None`] to check nothing
+    expected: Option<u64>,
+    /// Number of bytes written
+    count: u64,
+    /// Expected digest and digest of the bytes written, or [`None`] to
+    /// check nothing
+    digest: Option<(Digest, RunningDigest)>,
+    /// Whether writing to or flushing the underlying writer failed
+    inner_failed: bool,
+}
+
+impl<W> CheckedWriter<W> {
+    pub(crate) fn new(inner: W, options: &Options) -> Self {
+        Self {
+            inner,
+            expected: options.expected_size,
+            count: 0,
+            digest: options
+                .expected_digest
+                .map(|expected| (expected, RunningDigest::new(expected))),
+            inner_failed: false,
+        }
+    }
+
+    #[cfg(feature = "xz")]
+    /// Number of bytes written so far.
+    pub(crate) fn count(&self) -> u64 {
+        self.count
+    }
+
+    #[cfg(feature = "xz")]
+    /// Whether the underlying writer failed, so that an error comes from the
+    /// output rather than the input.
+    pub(crate) fn inner_failed(&self) -> bool {
+        self.inner_failed
+    }
+
+    /// Check that exactly the expected number of bytes were written, and
+    /// that they have the expected digest.
+    pub(crate) fn finish(&self) -> error::Result<()> {
+        match self.expected {
+            Some(expected) if expected != self.count => {
+                return Err(error::Error::SizeMismatch {
+                    expected,
+                    actual: self.count,
+                })
+            }
+            _ => {}
+        }
+        if let Some((expected, running)) = self.digest {
+            let actual = running.finalize();
+            if actual != expected {
+                return Err(error::Error::ChecksumMismatch { expected, actual });
+            }
+        }
+        Ok(())
+    }
+}
+
+impl<W> io::Write for CheckedWriter<W>
+where
+    W: io::Write,
+{
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if let Some(expected) = self.expected {
+            let actual = self.count + buf.len() as u64;
+            if actual > expected {
+                return Err(io::Error::new(
+                    io::ErrorKind::Other,
+                    SizeExceeded { expected, actual },
+                ));
+            }
+        }
+
+        let result = self.inner.write(buf).map_err(|e| {
+            self.inner_failed = true;
+            e
+        })?;
+        self.count += result as u64;
+        if let Some((_, running)) = &mut self.digest {
+            running.update(&buf[..result]);
+        }
+        Ok(result)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush().map_err(|e| {
+            self.inner_failed = true;
+            e
+        })
+    }
+}
+
+/// Incremental computation of a [`Digest`].
+#[derive(Clone, Copy, Debug)]
+enum RunningDigest {
+    Crc32(Crc32),
+    Crc64(Crc64),
+}
+
+impl RunningDigest {
+    /// Start computing a digest of the same kind as `digest`.
+    fn new(digest: Digest) -> Self {
+        match digest {
+            Digest::Crc32(_) => RunningDigest::Crc32(Crc32::new()),
+            Digest::Crc64(_) => RunningDigest::Crc64(Crc64::new()),
+        }
+    }
+
+    fn update(&mut self, buf: &[u8]) {
+        match self {
+            RunningDigest::Crc32(crc) => crc.update(buf),
+            RunningDigest::Crc64(crc) => crc.update(buf),
+        }
+    }
+
+    fn finalize(self) -> Digest {
+        match self {
+            RunningDigest::Crc32(crc) => Digest::Crc32(crc.finalize()),
+            RunningDigest::Crc64(crc) => Digest::Crc64(crc.finalize()),
+        }
+    }
+}
+
+/// Payload of the [`io::Error`] returned by [`CheckedWriter`].
+#[derive(Debug)]
+pub(crate) struct SizeExceeded {
+    pub(crate) expected: u64,
+    pub(crate) actual: u64,
+}
+
+impl fmt::Display for SizeExceeded {
+    fn fmt(&self, fmt: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            fmt,
+            "output exceeds the expected size of {} bytes",
+            self.expected
+        )
+    }
+}
+
+impl std::error::Error for SizeExceeded {}
+
+/// Payload of the [`io::Error`] returned by [`LimitedWriter`].
+#[derive(Debug)]
+pub(crate) struct LimitExceeded {
+    pub(crate) limit: u64,
+}
+
+impl fmt::Display for LimitExceeded {
+    fn fmt(&self, fmt: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(fmt, "output exceeds the limit of {} bytes", self.limit)
+    }
+}
+
+impl std::error::Error for LimitExceeded {}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::io::Write;
+
+    #[test]
+    fn test_limited_writer() {
+        let mut writer = LimitedWriter::new(Vec::new(), 5);
+        writer.write_all(b"abc").unwrap();
+        assert_eq!(writer.write(b"defg").unwrap(), 2);
+        assert_eq!(writer.write(b"").unwrap(), 0);
+        let err = writer.write(b"g").unwrap_err();
+        assert!(err.get_ref().unwrap().is::<LimitExceeded>());
+        assert_eq!(writer.count(), 5);
+        assert_eq!(writer.into_inner(), b"abcde");
+    }
+
+    #[test]
+    fn test_truncating_writer() {
+        let mut output = Vec::new();
+        let mut writer = TruncatingWriter::new(&mut output, Some(5));
+        writer.write_all(b"abc").unwrap();
+        writer.write_all(b"defg").unwrap();
+        writer.write_all(b"h").unwrap();
+        assert_eq!(writer.remaining(), Some(0));
+        assert_eq!(output, b"abcde");
+
+        let mut output = Vec::new();
+        let mut writer = TruncatingWriter::new(&mut output, None);
+        writer.write_all(b"abcdefgh").unwrap();
+        assert_eq!(writer.remaining(), None);
+        assert_eq!(output, b"abcdefgh");
+    }
+
+    #[test]
+    fn test_checked_writer() {
+        let options = Options {
+            expected_size: Some(9),
+            expected_digest: Some(Digest::Crc32(0xCBF4_3926)),
+            ..Default::default()
+        };
+        let mut writer = CheckedWriter::new(Vec::new(), &options);
+        writer.write_all(b"1234").unwrap();
+        writer.write_all(b"56789").unwrap();
+        writer.finish().unwrap();
+        let err = writer.write(b"0").unwrap_err();
+        assert!(err.get_ref().unwrap().is::<SizeExceeded>());
+
+        let options = Options {
+            expected_digest: Some(Digest::Crc64(0)),
+            ..Default::default()
+        };
+        let mut writer = CheckedWriter::new(Vec::new(), &options);
+        writer.finish().unwrap();
+        writer.write_all(b"a").unwrap();
+        assert!(matches!(
+            writer.finish(),
+            Err(error::Error::ChecksumMismatch {
+                expected: Digest::Crc64(0),
+                actual: Digest::Crc64(_),
+            })
+        ));
+    }
+}
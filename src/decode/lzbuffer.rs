@@ -1,38 +1,55 @@
 use crate::error;
+use std::fmt::Debug;
 use std::io;
 
+/// Dictionary buffer through which the LZMA decoder produces its output.
+///
+/// The decoder appends each decoded literal or LZ sequence to the buffer,
+/// which keeps the recent bytes that LZ sequences can reference, i.e. the
+/// dictionary window, and writes the bytes to an output sink `W`, possibly
+/// later. Besides [`LzAccumBuffer`] and [`LzCircularBuffer`], implementations
+/// may e.g. keep the window in a memory-mapped file.
+///
+/// The decoder checks LZ sequences against [`LzBuffer::window_len()`] before
+/// appending them, so [`LzBuffer::last_n()`] and [`LzBuffer::append_lz()`]
+/// are only called with distances from 1 to the window length. Their errors
+/// and those of [`LzBuffer::append_literal()`] abort decoding.
+// The length is a stream position rather than a collection size.
+#[allow(clippy::len_without_is_empty)]
 pub trait LzBuffer<W>
 where
     W: io::Write,
 {
+    /// Total number of bytes appended so far, which is the position in the
+    /// stream of the next byte. This selects the probabilities of the
+    /// decoder, and is compared to the unpacked size of the stream.
     fn len(&self) -> usize;
 
-    /// Retrieve the last byte or return a default.
+    /// Number of bytes that LZ sequences can currently reference, at most
+    /// [`LzBuffer::len()`] and the dictionary size.
+    fn window_len(&self) -> usize;
+
+    /// Retrieve the last byte, or `lit` if the window is empty.
     fn last_or(&self, lit: u8) -> u8;
 
-    /// Retrieve the n-th last byte.
+    /// Retrieve the `dist`-th last byte, 1 being the last one.
     fn last_n(&self, dist: usize) -> error::Result<u8>;
 
     /// Append a literal.
     fn append_literal(&mut self, lit: u8) -> error::Result<()>;
 
-    /// Fetch an LZ sequence (length, distance) from inside the buffer.
+    /// Append `len` bytes copied from `dist` bytes back, as if one at a
+    /// time, so that the copy may overlap the bytes it appends when
+    /// `len > dist`.
     fn append_lz(&mut self, len: usize, dist: usize) -> error::Result<()>;
 
-    /// Get a reference to the output sink.
-    fn get_output(&self) -> &W;
-
-    /// Get a mutable reference to the output sink.
-    fn get_output_mut(&mut self) -> &mut W;
-
-    /// Consumes this buffer and flushes any data.
+    /// Consumes this buffer, writing the remaining data to the output sink
+    /// and flushing it, and returns the sink.
     fn finish(self) -> io::Result<W>;
-
-    /// Consumes this buffer without flushing any data.
-    fn into_output(self) -> W;
 }
 
-/// An accumulating buffer for LZ sequences.
+/// An accumulating buffer for LZ sequences, keeping all the data in memory
+/// and referencing all of it, which suits LZMA2 and XZ blocks.
 pub struct LzAccumBuffer<W>
 where
     W: io::Write,
@@ -41,6 +58,13 @@ where
     stream: W,
     /// Buffer
     buf: Vec<u8>,
+    /// Start of the dictionary in the buffer
+    start: usize,
+    /// Whether the buffer is also the output, see [`LzAccumBuffer::from_vec()`]
+    in_place: bool,
+    #[cfg(any(feature = "raw_decoder", feature = "xz"))]
+    /// End of the output already passed to [`LzAccumBuffer::inspect_new()`]
+    inspected: usize,
     /// Buffer memory limit
     memlimit: usize,
     /// Total number of bytes sent through the buffer
@@ -51,28 +75,127 @@ impl<W> LzAccumBuffer<W>
 where
     W: io::Write,
 {
+    /// Create a buffer keeping all the data, as the dictionary, until it's
+    /// finished and the data is written to `stream`. At most `memlimit`
+    /// bytes are accepted.
     pub fn from_stream(stream: W, memlimit: usize) -> Self {
         Self {
             stream,
             buf: Vec::new(),
+            start: 0,
+            in_place: false,
+            #[cfg(any(feature = "raw_decoder", feature = "xz"))]
+            inspected: 0,
             memlimit,
             len: 0,
         }
     }
 
     /// Append bytes.
-    pub fn append_bytes(&mut self, buf: &[u8]) {
+    pub(crate) fn append_bytes(&mut self, buf: &[u8]) -> error::Result<()> {
+        self.check_memlimit(buf.len())?;
+        self.buf.try_reserve(buf.len())?;
         self.buf.extend_from_slice(buf);
         self.len += buf.len();
+        Ok(())
+    }
+
+    /// Reserve room for `additional` more bytes, up to the memory limit, so
+    /// that a failed allocation is reported rather than aborting.
+    pub(crate) fn reserve(&mut self, additional: usize) -> error::Result<()> {
+        let additional = std::cmp::min(additional, self.memlimit.saturating_sub(self.buf.len()));
+        self.buf.try_reserve(additional)?;
+        Ok(())
     }
 
     /// Reset the internal dictionary.
-    pub fn reset(&mut self) -> io::Result<()> {
-        self.stream.write_all(self.buf.as_slice())?;
-        self.buf.clear();
+    pub(crate) fn reset(&mut self) -> io::Result<()> {
+        if self.in_place {
+            // The output stays in the buffer, out of reach of LZ sequences.
+            self.start = self.buf.len();
+        } else {
+            self.stream.write_all(self.buf.as_slice())?;
+            self.buf.clear();
+        }
         self.len = 0;
         Ok(())
     }
+
+    /// Check that `additional` more bytes fit in the memory limit, which
+    /// applies to the whole buffer, including the output kept in place.
+    fn check_memlimit(&self, additional: usize) -> error::Result<()> {
+        if self.buf.len().saturating_add(additional) > self.memlimit {
+            Err(error::Error::LzmaError(format!(
+                "exceeded memory limit of {}",
+                self.memlimit
+            )))
+        } else {
+            Ok(())
+        }
+    }
+
+    /// The current dictionary.
+    fn dict(&self) -> &[u8] {
+        &self.buf[self.start..]
+    }
+
+    /// Copy the bytes that LZ sequences can currently reference, from the
+    /// oldest to the most recent.
+    #[cfg(test)]
+    fn dictionary_window(&self) -> Vec<u8> {
+        self.dict().to_vec()
+    }
+}
+
+impl LzAccumBuffer<io::Sink> {
+    /// Create a buffer writing directly into `buf`, which then serves as both
+    /// the dictionary and the output, without copying data to a separate
+    /// sink. The existing contents of `buf` are kept but can't be referenced
+    /// by LZ sequences.
+    pub(crate) fn from_vec(buf: Vec<u8>, memlimit: usize) -> Self {
+        Self {
+            stream: io::sink(),
+            start: buf.len(),
+            #[cfg(any(feature = "raw_decoder", feature = "xz"))]
+            inspected: buf.len(),
+            buf,
+            in_place: true,
+            memlimit,
+            len: 0,
+        }
+    }
+
+    #[cfg(any(feature = "raw_decoder", feature = "xz"))]
+    /// Passes the output written since the previous call to `inspect`.
+    pub(crate) fn inspect_new<F>(&mut self, mut inspect: F)
+    where
+        F: FnMut(&[u8]),
+    {
+        inspect(&self.buf[self.inspected..]);
+        self.inspected = self.buf.len();
+    }
+
+    #[cfg(any(feature = "raw_decoder", feature = "xz"))]
+    /// Length of the output written in place so far.
+    pub(crate) fn output_len(&self) -> usize {
+        self.buf.len()
+    }
+
+    #[cfg(all(feature = "stream", feature = "xz"))]
+    /// Drop the output written in place that was already passed to
+    /// [`LzAccumBuffer::inspect_new()`], except for its last `keep` bytes,
+    /// so that LZ sequences can only reference these bytes.
+    pub(crate) fn discard_output(&mut self, keep: usize) {
+        let end = std::cmp::min(self.inspected, self.buf.len().saturating_sub(keep));
+        self.buf.drain(..end);
+        self.start = self.start.saturating_sub(end);
+        self.inspected -= end;
+    }
+
+    /// Consumes this buffer and returns the output written in place.
+    pub(crate) fn into_vec(self) -> Vec<u8> {
+        self.buf
+    }
 }
 
 impl<W> LzBuffer<W> for LzAccumBuffer<W>
@@ -83,82 +206,99 @@ where
         self.len
     }
 
+    fn window_len(&self) -> usize {
+        self.buf.len() - self.start
+    }
+
     fn last_or(&self, lit: u8) -> u8 {
-        let buf_len = self.buf.len();
-        if buf_len == 0 {
+        let dict = self.dict();
+        let dict_len = dict.len();
+        if dict_len == 0 {
             lit
         } else {
-            self.buf[buf_len - 1]
+            dict[dict_len - 1]
         }
     }
 
     fn last_n(&self, dist: usize) -> error::Result<u8> {
-        let buf_len = self.buf.len();
-        if dist > buf_len {
+        let dict = self.dict();
+        let dict_len = dict.len();
+        if dist > dict_len {
             return Err(error::Error::LzmaError(format!(
                 "Match distance {} is beyond output size {}",
-                dist, buf_len
+                dist, dict_len
             )));
         }
 
-        Ok(self.buf[buf_len - dist])
+        Ok(dict[dict_len - dist])
     }
 
     fn append_literal(&mut self, lit: u8) -> error::Result<()> {
-        let new_len = self.len + 1;
-
-        if new_len > self.memlimit {
-            Err(error::Error::LzmaError(format!(
-                "exceeded memory limit of {}",
-                self.memlimit
-            )))
-        } else {
-            self.buf.push(lit);
-            self.len = new_len;
-            Ok(())
-        }
+        self.check_memlimit(1)?;
+        self.buf.try_reserve(1)?;
+        self.buf.push(lit);
+        self.len += 1;
+        Ok(())
     }
 
     fn append_lz(&mut self, len: usize, dist: usize) -> error::Result<()> {
         lzma_debug!("LZ {{ len: {}, dist: {} }}", len, dist);
         let buf_len = self.buf.len();
-        if dist > buf_len {
+        let dict_len = buf_len - self.start;
+        if dist > dict_len {
             return Err(error::Error::LzmaError(format!(
                 "LZ distance {} is beyond output size {}",
-                dist, buf_len
+                dist, dict_len
             )));
         }
 
-        let mut offset = buf_len - dist;
-        for _ in 0..len {
+        self.check_memlimit(len)?;
+        self.buf.try_reserve(len)?;
+        let offset = buf_len - dist;
+        if dist == 1 {
+            // Run of a single byte
             let x = self.buf[offset];
-            self.buf.push(x);
-            offset += 1;
+            self.buf.resize(buf_len + len, x);
+        } else {
+            // The copied bytes repeat with a period of `dist`, so when the
+            // copy overlaps the bytes it appends, they can be copied from
+            // `offset` in chunks doubling in size.
+            while self.buf.len() < buf_len + len {
+                let n = std::cmp::min(buf_len + len - self.buf.len(), self.buf.len() - offset);
+                self.buf.extend_from_within(offset..offset + n);
+            }
         }
         self.len += len;
         Ok(())
     }
 
-    fn get_output(&self) -> &W {
-        &self.stream
-    }
-
-    fn get_output_mut(&mut self) -> &mut W {
-        &mut self.stream
-    }
-
     fn finish(mut self) -> io::Result<W> {
-        self.stream.write_all(self.buf.as_slice())?;
+        if !self.in_place {
+            self.stream.write_all(self.buf.as_slice())?;
+        }
         self.stream.flush()?;
         Ok(self.stream)
     }
+}
 
-    fn into_output(self) -> W {
-        self.stream
+impl<W> Debug for LzAccumBuffer<W>
+where
+    W: io::Write + Debug,
+{
+    fn fmt(&self, fmt: &mut std::fmt::Formatter) -> std::fmt::Result {
+        fmt.debug_struct("LzAccumBuffer")
+            .field("stream", &self.stream)
+            .field("buf", &self.buf.len())
+            .field("start", &self.start)
+            .field("in_place", &self.in_place)
+            .field("memlimit", &self.memlimit)
+            .field("len", &self.len)
+            .finish()
     }
 }
 
-/// A circular buffer for LZ sequences
+/// A circular buffer for LZ sequences, keeping only the last `dict_size`
+/// bytes in memory and writing the older ones to the output sink.
 pub struct LzCircularBuffer<W>
 where
     W: io::Write,
@@ -173,6 +313,8 @@ where
     memlimit: usize,
     /// Current position
     cursor: usize,
+    /// Start of the bytes of the buffer not yet written to the output sink
+    flushed: usize,
     /// Total number of bytes sent through the buffer
     len: usize,
 }
@@ -181,27 +323,125 @@ impl<W> LzCircularBuffer<W>
 where
     W: io::Write,
 {
-    pub fn from_stream(stream: W, dict_size: usize, memlimit: usize) -> Self {
+    /// Create a buffer reusing the allocation of `memory`, typically obtained
+    /// from [`Self::finish_with_memory()`] on a previous buffer. The contents
+    /// of `memory` are discarded.
+    pub fn from_stream_with_memory(
+        stream: W,
+        mut memory: Vec<u8>,
+        dict_size: usize,
+        memlimit: usize,
+    ) -> Self {
         lzma_info!("Dict size in LZ buffer: {}", dict_size);
+        memory.clear();
         Self {
             stream,
-            buf: Vec::new(),
+            buf: memory,
             dict_size,
             memlimit,
             cursor: 0,
+            flushed: 0,
             len: 0,
         }
     }
 
+    /// Fill the window with a preset dictionary, which LZ sequences can
+    /// reference but which isn't written to the output sink. Only the last
+    /// `dict_size` bytes of `dict` fit in the window.
+    ///
+    /// This must be called before any data is sent through the buffer.
+    pub(crate) fn preset_dict(&mut self, dict: &[u8]) -> error::Result<()> {
+        debug_assert_eq!(self.len, 0);
+        let dict = &dict[dict.len().saturating_sub(self.dict_size)..];
+        if dict.len() > self.memlimit {
+            return Err(error::Error::LzmaError(format!(
+                "exceeded memory limit of {}",
+                self.memlimit
+            )));
+        }
+        self.buf.try_reserve_exact(dict.len())?;
+        self.buf.extend_from_slice(dict);
+        self.cursor = dict.len() % self.dict_size;
+        self.flushed = self.cursor;
+        self.len = dict.len();
+        Ok(())
+    }
+
+    /// Consumes this buffer and flushes any data. Returns the output sink and
+    /// the dictionary memory, so that the latter can be reused.
+    pub fn finish_with_memory(mut self) -> io::Result<(W, Vec<u8>)> {
+        if self.cursor > self.flushed {
+            self.stream
+                .write_all(&self.buf[self.flushed..self.cursor])?;
+        }
+        self.stream.flush()?;
+        Ok((self.stream, self.buf))
+    }
+
+    /// Write the data not yet sent to the output sink, while keeping it in
+    /// the window, and return the sink.
+    #[cfg(any(feature = "raw_decoder", feature = "stream"))]
+    pub(crate) fn flush_pending(&mut self) -> io::Result<&mut W> {
+        if self.cursor > self.flushed {
+            self.stream
+                .write_all(&self.buf[self.flushed..self.cursor])?;
+            self.flushed = self.cursor;
+        }
+        Ok(&mut self.stream)
+    }
+
+    /// Get a reference to the output sink.
+    #[cfg(feature = "stream")]
+    pub(crate) fn get_output(&self) -> &W {
+        &self.stream
+    }
+
+    /// Get a mutable reference to the output sink.
+    #[cfg(feature = "stream")]
+    pub(crate) fn get_output_mut(&mut self) -> &mut W {
+        &mut self.stream
+    }
+
+    /// Consumes this buffer without flushing any data.
+    #[cfg(feature = "stream")]
+    pub(crate) fn into_output(self) -> W {
+        self.stream
+    }
+
+    /// Copy the bytes that LZ sequences can currently reference, from the
+    /// oldest to the most recent.
+    #[cfg(test)]
+    fn dictionary_window(&self) -> Vec<u8> {
+        if self.len > self.cursor {
+            // The buffer wrapped around, the oldest bytes follow the cursor.
+            [&self.buf[self.cursor..], &self.buf[..self.cursor]].concat()
+        } else {
+            self.buf[..self.cursor].to_vec()
+        }
+    }
+
     fn get(&self, index: usize) -> u8 {
         *self.buf.get(index).unwrap_or(&0)
     }
 
     fn set(&mut self, index: usize, value: u8) -> error::Result<()> {
-        let new_len = index + 1;
+        self.grow(index + 1)?;
+        self.buf[index] = value;
+        Ok(())
+    }
 
+    /// Make room in the buffer for the first `new_len` bytes of the window.
+    fn grow(&mut self, new_len: usize) -> error::Result<()> {
         if self.buf.len() < new_len {
             if new_len <= self.memlimit {
+                if self.buf.capacity() < new_len {
+                    // Grow geometrically, but never beyond the window, so
+                    // that at most `dict_size` bytes are held in memory.
+                    let capacity = std::cmp::max(new_len, 2 * self.buf.capacity());
+                    let capacity =
+                        std::cmp::min(capacity, std::cmp::min(self.dict_size, self.memlimit));
+                    self.buf.try_reserve_exact(capacity - self.buf.len())?;
+                }
                 self.buf.resize(new_len, 0);
             } else {
                 return Err(error::Error::LzmaError(format!(
@@ -210,7 +450,6 @@ where
                 )));
             }
         }
-        self.buf[index] = value;
         Ok(())
     }
 }
@@ -223,6 +462,10 @@ where
         self.len
     }
 
+    fn window_len(&self) -> usize {
+        std::cmp::min(self.len, self.dict_size)
+    }
+
     fn last_or(&self, lit: u8) -> u8 {
         if self.len == 0 {
             lit
@@ -256,8 +499,9 @@ where
 
         // Flush the circular buffer to the output
         if self.cursor == self.dict_size {
-            self.stream.write_all(self.buf.as_slice())?;
+            self.stream.write_all(&self.buf[self.flushed..])?;
             self.cursor = 0;
+            self.flushed = 0;
         }
 
         Ok(())
@@ -279,35 +523,66 @@ where
         }
 
         let mut offset = (self.dict_size + self.cursor - dist) % self.dict_size;
-        for _ in 0..len {
-            let x = self.get(offset);
-            self.append_literal(x)?;
-            offset += 1;
-            if offset == self.dict_size {
-                offset = 0
+        let mut remaining = len;
+        while remaining > 0 {
+            // Neither the source nor the destination wrap around.
+            let mut n = std::cmp::min(
+                remaining,
+                std::cmp::min(self.dict_size - self.cursor, self.dict_size - offset),
+            );
+            // The copied bytes repeat with a period of `dist`, so when the
+            // copy overlaps the bytes it appends, they can be copied from
+            // `offset` in chunks doubling in size: the distance only needs
+            // to stay a multiple of `dist`.
+            if offset < self.cursor {
+                n = std::cmp::min(n, self.cursor - offset);
+            }
+            let double = offset < self.cursor && n == self.cursor - offset;
+            self.grow(self.cursor + n)?;
+            if dist == 1 {
+                // Run of a single byte
+                let x = self.buf[offset];
+                self.buf[self.cursor..self.cursor + n].fill(x);
+            } else {
+                self.buf.copy_within(offset..offset + n, self.cursor);
+            }
+            if !double {
+                offset = (offset + n) % self.dict_size;
+            }
+            self.cursor += n;
+            self.len += n;
+            remaining -= n;
+
+            // Flush the circular buffer to the output
+            if self.cursor == self.dict_size {
+                self.stream.write_all(&self.buf[self.flushed..])?;
+                self.cursor = 0;
+                self.flushed = 0;
             }
         }
         Ok(())
     }
 
-    fn get_output(&self) -> &W {
-        &self.stream
-    }
-
-    fn get_output_mut(&mut self) -> &mut W {
-        &mut self.stream
-    }
-
-    fn finish(mut self) -> io::Result<W> {
-        if self.cursor > 0 {
-            self.stream.write_all(&self.buf[0..self.cursor])?;
-        }
-        self.stream.flush()?;
-        Ok(self.stream)
+    fn finish(self) -> io::Result<W> {
+        let (stream, _) = self.finish_with_memory()?;
+        Ok(stream)
     }
+}
 
-    fn into_output(self) -> W {
-        self.stream
+impl<W> Debug for LzCircularBuffer<W>
+where
+    W: io::Write + Debug,
+{
+    fn fmt(&self, fmt: &mut std::fmt::Formatter) -> std::fmt::Result {
+        fmt.debug_struct("LzCircularBuffer")
+            .field("stream", &self.stream)
+            .field("buf", &self.buf.len())
+            .field("dict_size", &self.dict_size)
+            .field("memlimit", &self.memlimit)
+            .field("cursor", &self.cursor)
+            .field("flushed", &self.flushed)
+            .field("len", &self.len)
+            .finish()
     }
 }
 
@@ -334,16 +609,273 @@ mod test {
         }
     }
 
+    /// Accepts `capacity` bytes, then reports that it can't take any more.
+    #[derive(Debug)]
+    struct BoundedWriter {
+        data: Vec<u8>,
+        capacity: usize,
+    }
+
+    impl io::Write for BoundedWriter {
+        fn write(&mut self, buf: &[u8]) -> Result<usize, io::Error> {
+            let len = std::cmp::min(buf.len(), self.capacity - self.data.len());
+            self.data.extend_from_slice(&buf[..len]);
+            Ok(len)
+        }
+
+        fn flush(&mut self) -> Result<(), io::Error> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn full_output_is_an_error() {
+        let bounded = || BoundedWriter {
+            data: Vec::new(),
+            capacity: 10,
+        };
+        let is_write_zero = |err: &error::Error| matches!(err, error::Error::IoError(e) if e.kind() == io::ErrorKind::WriteZero);
+
+        // The circular buffer writes the data as it leaves the window, and the
+        // rest when finished.
+        let mut b = LzCircularBuffer::from_stream_with_memory(bounded(), Vec::new(), 4, 4);
+        b.append_literal(b'a').unwrap();
+        let err = b.append_lz(20, 1).unwrap_err();
+        assert!(is_write_zero(&err), "{:?}", err);
+        let mut b = LzCircularBuffer::from_stream_with_memory(bounded(), Vec::new(), 16, 16);
+        b.append_literal(b'a').unwrap();
+        b.append_lz(11, 1).unwrap();
+        let err = b.finish().unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::WriteZero);
+
+        // The accumulating buffer writes everything when reset or finished.
+        let mut b = LzAccumBuffer::from_stream(bounded(), usize::MAX);
+        b.append_bytes(&[0; 11]).unwrap();
+        assert_eq!(b.reset().unwrap_err().kind(), io::ErrorKind::WriteZero);
+        let mut b = LzAccumBuffer::from_stream(bounded(), usize::MAX);
+        b.append_bytes(&[0; 10]).unwrap();
+        assert_eq!(b.finish().unwrap().data.len(), 10);
+    }
+
     #[test]
     fn finish_flushes_everything() {
         const MEM_LIMIT: usize = 8;
         const DICT_SIZE: usize = MEM_LIMIT;
         let stream = ManuallyFlushedWriter::default();
-        let mut b = LzCircularBuffer::from_stream(stream, DICT_SIZE, MEM_LIMIT);
+        let mut b =
+            LzCircularBuffer::from_stream_with_memory(stream, Vec::new(), DICT_SIZE, MEM_LIMIT);
         for _ in 0..(DICT_SIZE * 4) {
             b.append_literal(5).unwrap();
         }
         let stream = b.finish().unwrap();
         assert!(stream.unflushed.is_empty());
     }
+
+    #[test]
+    fn circular_memory_bounded() {
+        // Bytes leave the buffer as soon as they age out of the window, and
+        // the buffer never holds more than the window.
+        const DICT_SIZE: usize = 1000;
+        let mut b = LzCircularBuffer::from_stream_with_memory(
+            Vec::new(),
+            Vec::new(),
+            DICT_SIZE,
+            usize::MAX,
+        );
+        let mut i = 0u8;
+        while b.len() < DICT_SIZE * 100 {
+            b.append_literal(i).unwrap();
+            b.append_lz(usize::from(i) % 273 + 2, 1 + usize::from(i) * 3 % b.len())
+                .unwrap();
+            i = i.wrapping_add(1);
+            assert!(b.buf.capacity() <= DICT_SIZE, "{}", b.buf.capacity());
+            assert!(b.len() - b.stream.len() <= DICT_SIZE);
+        }
+        let len = b.len();
+        assert_eq!(b.finish().unwrap().len(), len);
+    }
+
+    #[test]
+    fn lz_copies_overlap() {
+        // Runs of a single byte and short periods replicate the source as
+        // it's appended.
+        let mut b = LzAccumBuffer::from_stream(Vec::new(), usize::MAX);
+        b.append_bytes(b"xab").unwrap();
+        b.append_lz(5, 1).unwrap();
+        b.append_lz(7, 3).unwrap();
+        assert_eq!(b.finish().unwrap(), b"xabbbbbbbbbbbbb");
+
+        // Copies of any length and distance match a copy byte by byte, also
+        // when the window wraps around.
+        let mut state = 0x2545_f491u32;
+        let mut rand = |n: usize| {
+            state ^= state << 13;
+            state ^= state >> 17;
+            state ^= state << 5;
+            state as usize % n
+        };
+        for &dict_size in &[1, 2, 7, 64, 1000] {
+            let mut expected = vec![rand(256) as u8];
+            let mut accum = LzAccumBuffer::from_stream(Vec::new(), usize::MAX);
+            let mut circular = LzCircularBuffer::from_stream_with_memory(
+                Vec::new(),
+                Vec::new(),
+                dict_size,
+                usize::MAX,
+            );
+            accum.append_literal(expected[0]).unwrap();
+            circular.append_literal(expected[0]).unwrap();
+            for _ in 0..2000 {
+                if rand(4) == 0 {
+                    let lit = rand(256) as u8;
+                    expected.push(lit);
+                    accum.append_literal(lit).unwrap();
+                    circular.append_literal(lit).unwrap();
+                } else {
+                    let len = 2 + rand(272);
+                    let dist = 1 + rand(std::cmp::min(expected.len(), dict_size));
+                    for _ in 0..len {
+                        expected.push(expected[expected.len() - dist]);
+                    }
+                    accum.append_lz(len, dist).unwrap();
+                    circular.append_lz(len, dist).unwrap();
+                }
+            }
+            assert_eq!(accum.finish().unwrap(), expected);
+            assert_eq!(circular.finish().unwrap(), expected, "{}", dict_size);
+        }
+    }
+
+    #[test]
+    #[cfg(all(feature = "stream", feature = "xz"))]
+    fn accum_discard_output() {
+        let mut b = LzAccumBuffer::from_vec(Vec::new(), 8);
+        for &lit in b"abcdef" {
+            b.append_literal(lit).unwrap();
+        }
+        // Only the inspected output is dropped.
+        b.inspect_new(|buf| assert_eq!(buf, b"abcdef"));
+        b.append_literal(b'g').unwrap();
+        b.discard_output(3);
+        assert_eq!(b.dictionary_window(), b"efg");
+        assert_eq!(b.len(), 7);
+        assert!(b.append_lz(1, 4).is_err());
+        b.append_lz(3, 3).unwrap();
+        b.inspect_new(|buf| assert_eq!(buf, b"gefg"));
+        assert_eq!(b.into_vec(), b"efgefg");
+    }
+
+    #[test]
+    fn circular_dictionary_window() {
+        let mut b = LzCircularBuffer::from_stream_with_memory(Vec::new(), Vec::new(), 4, 4);
+        assert!(b.dictionary_window().is_empty());
+        b.append_literal(b'a').unwrap();
+        b.append_literal(b'b').unwrap();
+        assert_eq!(b.dictionary_window(), b"ab");
+        b.append_lz(3, 2).unwrap();
+        assert_eq!(b.dictionary_window(), b"baba");
+        b.append_literal(b'c').unwrap();
+        assert_eq!(b.dictionary_window(), b"abac");
+    }
+
+    #[test]
+    fn circular_preset_dict() {
+        // The preset dictionary is referenced but not written to the output.
+        let mut b = LzCircularBuffer::from_stream_with_memory(Vec::new(), Vec::new(), 4, 4);
+        b.preset_dict(b"ab").unwrap();
+        assert_eq!(b.len(), 2);
+        assert_eq!(b.dictionary_window(), b"ab");
+        b.append_lz(2, 2).unwrap();
+        b.append_literal(b'c').unwrap();
+        assert_eq!(b.dictionary_window(), b"babc");
+        assert_eq!(b.finish().unwrap(), b"abc");
+
+        // Only the end of a larger preset dictionary is kept.
+        let mut b = LzCircularBuffer::from_stream_with_memory(Vec::new(), Vec::new(), 4, 4);
+        b.preset_dict(b"vwxyz").unwrap();
+        assert_eq!(b.dictionary_window(), b"wxyz");
+        b.append_lz(3, 4).unwrap();
+        b.append_literal(b'a').unwrap();
+        b.append_literal(b'b').unwrap();
+        assert_eq!(b.finish().unwrap(), b"wxyab");
+
+        let mut b = LzCircularBuffer::from_stream_with_memory(Vec::new(), Vec::new(), 4, 2);
+        assert!(b.preset_dict(b"abc").is_err());
+    }
+
+    #[test]
+    fn dictionary_window_after_partial_decode() {
+        use crate::decode::lzma::{DecoderState, LzmaProperties};
+        use crate::decode::rangecoder::RangeDecoder;
+
+        let data = b"0123456789abcdef";
+        let mut compressed = Vec::new();
+        crate::lzma_compress(&mut &data[..], &mut compressed).unwrap();
+
+        // Decode the first 10 bytes through a 4-byte dictionary.
+        let mut state: DecoderState = DecoderState::new(
+            LzmaProperties {
+                lc: 3,
+                lp: 0,
+                pb: 2,
+            },
+            Some(10),
+        );
+        let mut input = &compressed[13..];
+        let mut rangecoder = RangeDecoder::new(&mut input).unwrap();
+        let mut b = LzCircularBuffer::from_stream_with_memory(Vec::new(), Vec::new(), 4, 4);
+        state.process(&mut b, &mut rangecoder).unwrap();
+        assert_eq!(b.dictionary_window(), b"6789");
+        assert_eq!(b.finish().unwrap(), b"0123456789");
+    }
+
+    #[test]
+    fn accum_memlimit() {
+        // The limit applies to literals, LZ sequences and stored bytes.
+        let mut b = LzAccumBuffer::from_stream(Vec::new(), 4);
+        b.append_bytes(b"ab").unwrap();
+        assert!(b.append_lz(3, 1).is_err());
+        b.append_lz(1, 1).unwrap();
+        b.append_literal(b'c').unwrap();
+        assert!(b.append_literal(b'd').is_err());
+        assert!(b.append_bytes(b"d").is_err());
+        assert_eq!(b.finish().unwrap(), b"abbc");
+
+        // In place, it also includes the output kept in the buffer.
+        let mut b = LzAccumBuffer::from_vec(b"xy".to_vec(), 4);
+        b.append_literal(b'a').unwrap();
+        b.reset().unwrap();
+        b.append_literal(b'b').unwrap();
+        assert!(b.append_literal(b'c').is_err());
+        assert_eq!(b.into_vec(), b"xyab");
+    }
+
+    #[test]
+    fn accum_in_place() {
+        let mut b = LzAccumBuffer::from_vec(b"xy".to_vec(), usize::MAX);
+        // Existing contents aren't part of the dictionary.
+        assert_eq!(b.last_or(0), 0);
+        assert!(b.append_lz(1, 1).is_err());
+
+        b.append_bytes(b"ab").unwrap();
+        b.append_lz(3, 2).unwrap();
+        assert_eq!(b.last_n(5).unwrap(), b'a');
+        assert!(b.last_n(6).is_err());
+
+        // Resetting the dictionary keeps the output.
+        b.reset().unwrap();
+        assert_eq!(b.len(), 0);
+        assert!(b.last_n(1).is_err());
+        b.append_literal(b'c').unwrap();
+
+        #[cfg(any(feature = "raw_decoder", feature = "xz"))]
+        {
+            let mut inspected = Vec::new();
+            b.inspect_new(|buf| inspected.extend_from_slice(buf));
+            b.inspect_new(|buf| assert!(buf.is_empty()));
+            assert_eq!(inspected, b"ababac");
+        }
+        assert_eq!(b.dictionary_window(), b"c");
+        assert_eq!(b.into_vec(), b"xyababac");
+    }
 }
@@ -1,8 +1,9 @@
+use crate::decode::limit::{CheckedWriter, TruncatingWriter};
 use crate::decode::lzbuffer::{LzBuffer, LzCircularBuffer};
-use crate::decode::rangecoder::{BitTree, LenDecoder, RangeDecoder};
+use crate::decode::rangecoder::{BitTree, LenDecoder, Prob, RangeDecoder};
+use crate::decode::util;
 use crate::decompress::{Options, UnpackedSize};
 use crate::error;
-use crate::util::vec2d::Vec2D;
 use byteorder::{LittleEndian, ReadBytesExt};
 use std::io;
 
@@ -38,6 +39,104 @@ enum ProcessingStatus {
     Finished,
 }
 
+/// Symbol decoded from an LZMA stream, see
+/// [`lzma_decompress_with_symbols`](crate::lzma_decompress_with_symbols).
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum Symbol {
+    /// Literal byte.
+    Literal(u8),
+    /// Match at a new distance.
+    Match {
+        /// Number of bytes copied.
+        len: usize,
+        /// Distance back in the output of the first byte copied.
+        dist: usize,
+    },
+    /// Match at one of the four most recent distances (a single byte at the
+    /// most recent distance is a "short rep").
+    Rep {
+        /// Index of the distance among the recent ones, 0 being the most
+        /// recent.
+        index: usize,
+        /// Number of bytes copied.
+        len: usize,
+        /// Distance back in the output of the first byte copied.
+        dist: usize,
+    },
+}
+
+/// How an LZMA stream signals its end, see
+/// [`LzmaStatus::termination`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum Termination {
+    /// The header leaves the size unknown and the stream ends with an
+    /// end-of-stream marker.
+    EndMarker,
+    /// The header gives the size of the decompressed data, in bytes.
+    KnownSize(u64),
+    /// The header leaves the size unknown and the stream ends with the input,
+    /// without end-of-stream marker.
+    EndOfInput,
+}
+
+/// Outcome of decoding an LZMA stream, see
+/// [`lzma_decompress_with_status`](crate::lzma_decompress_with_status).
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct LzmaStatus {
+    /// How the stream ended, e.g. to re-encode it in the same form. With the
+    /// [`concatenated`](crate::decompress::Options::concatenated) option,
+    /// this is about the last member decoded.
+    ///
+    /// This is [`None`] if decoding stopped at the output
+    /// [`limit`](crate::decompress::Options::limit) before the end of a
+    /// stream of unknown size.
+    pub termination: Option<Termination>,
+}
+
+/// Number of recent symbols reported in decoding errors, see
+/// [`RecentSymbols`].
+#[cfg(feature = "trace")]
+const RECENT_SYMBOLS_LEN: usize = 8;
+
+/// Ring of the last symbols decoded, to give some context about where a
+/// corrupt stream went wrong.
+#[cfg(feature = "trace")]
+#[derive(Clone, Copy, Debug, Default)]
+struct RecentSymbols {
+    symbols: [Option<Symbol>; RECENT_SYMBOLS_LEN],
+    // Index of the slot of the next symbol, i.e. of the oldest one once full
+    next: usize,
+}
+
+#[cfg(feature = "trace")]
+impl RecentSymbols {
+    fn is_empty(&self) -> bool {
+        self.symbols[0].is_none()
+    }
+
+    fn push(&mut self, symbol: Symbol) {
+        self.symbols[self.next] = Some(symbol);
+        self.next = (self.next + 1) % RECENT_SYMBOLS_LEN;
+    }
+}
+
+#[cfg(feature = "trace")]
+impl std::fmt::Display for RecentSymbols {
+    /// List the symbols from the oldest to the most recent.
+    fn fmt(&self, fmt: &mut std::fmt::Formatter) -> std::fmt::Result {
+        let (recent, old) = self.symbols.split_at(self.next);
+        for (i, symbol) in old.iter().chain(recent).flatten().enumerate() {
+            if i > 0 {
+                write!(fmt, ", ")?;
+            }
+            write!(fmt, "{:?}", symbol)?;
+        }
+        Ok(())
+    }
+}
+
 #[derive(Debug, Copy, Clone)]
 /// LZMA "lclppb" decompression properties.
 pub struct LzmaProperties {
@@ -161,37 +260,113 @@ impl LzmaParams {
     }
 }
 
+// Layout of the contiguous probability array of a `DecoderState`. The
+// fixed-size models come first, followed by the `0x300 << (lc + lp)` literal
+// probabilities.
+const IS_MATCH_OFFSET: usize = 0;
+const IS_MATCH_LEN: usize = 192; // 12 states << 4 pos_states
+const IS_REP_OFFSET: usize = IS_MATCH_OFFSET + IS_MATCH_LEN;
+const IS_REP_LEN: usize = 12;
+const IS_REP_G0_OFFSET: usize = IS_REP_OFFSET + IS_REP_LEN;
+const IS_REP_G1_OFFSET: usize = IS_REP_G0_OFFSET + IS_REP_LEN;
+const IS_REP_G2_OFFSET: usize = IS_REP_G1_OFFSET + IS_REP_LEN;
+const IS_REP_0LONG_OFFSET: usize = IS_REP_G2_OFFSET + IS_REP_LEN;
+const POS_DECODERS_OFFSET: usize = IS_REP_0LONG_OFFSET + IS_MATCH_LEN;
+const POS_DECODERS_LEN: usize = 115;
+const LITERAL_OFFSET: usize = POS_DECODERS_OFFSET + POS_DECODERS_LEN;
+const LITERAL_CODER_LEN: usize = 0x300;
+
+/// Total length of the probability array for the given properties.
+fn probs_len(lzma_props: LzmaProperties) -> usize {
+    LITERAL_OFFSET + (LITERAL_CODER_LEN << (lzma_props.lc + lzma_props.lp))
+}
+
+/// Check that an LZ sequence at distance `dist` refers to bytes of the
+/// dictionary window, i.e. already decoded and within the dictionary size.
+///
+/// Match lengths need no such check, as the length decoders can't exceed
+/// the format maximum of 273.
+fn check_distance<W, LZB>(
+    output: &LZB,
+    dist: usize,
+    declared_dict_size: Option<usize>,
+) -> error::Result<()>
+where
+    W: io::Write,
+    LZB: LzBuffer<W>,
+{
+    let window_len = output.window_len();
+    if dist > window_len {
+        // The data is there with the declared dictionary, so the stream is
+        // valid but doesn't fit in the overridden one.
+        if let Some(declared) = declared_dict_size {
+            if dist <= declared && dist <= output.len() {
+                return Err(error::Error::DistanceBeyondOverride {
+                    distance: dist as u64,
+                    dict_size: window_len as u64,
+                });
+            }
+        }
+        return Err(error::Error::LzmaError(format!(
+            "Invalid match distance {} at position {}, beyond the {} bytes of the dictionary window",
+            dist,
+            output.len(),
+            window_len
+        )));
+    }
+    Ok(())
+}
+
+/// State of the LZMA decoder, storing its probabilities as `P`, see [`Prob`].
 #[derive(Debug)]
-pub(crate) struct DecoderState {
+pub(crate) struct DecoderState<P: Prob = u16> {
     // Buffer input data here if we need more for decompression. Up to
     // MAX_REQUIRED_INPUT bytes can be consumed during one iteration.
     partial_input_buf: std::io::Cursor<[u8; MAX_REQUIRED_INPUT]>,
     pub(crate) lzma_props: LzmaProperties,
     unpacked_size: Option<u64>,
-    literal_probs: Vec2D<u16>,
-    pos_slot_decoder: [BitTree<{ 1 << 6 }>; 4],
-    align_decoder: BitTree<{ 1 << 4 }>,
-    pos_decoders: [u16; 115],
-    is_match: [u16; 192], // true = LZ, false = literal
-    is_rep: [u16; 12],
-    is_rep_g0: [u16; 12],
-    is_rep_g1: [u16; 12],
-    is_rep_g2: [u16; 12],
-    is_rep_0long: [u16; 192],
+    // Number of output bytes after which to stop decoding early, possibly
+    // overshooting by the length of one match.
+    output_limit: Option<u64>,
+    // Whether the end-of-stream marker was decoded.
+    end_marker: bool,
+    // Dictionary size declared by the stream, when decoding it with a
+    // smaller one.
+    declared_dict_size: Option<usize>,
+    // Last symbols decoded since the state was reset.
+    #[cfg(feature = "trace")]
+    recent_symbols: RecentSymbols,
+    // All the single-bit probabilities (is_match, is_rep*, pos_decoders and
+    // literals) live in one allocation to improve cache locality in the
+    // decoding loop. See the `*_OFFSET` constants for the layout.
+    probs: Box<[P]>,
+    pos_slot_decoder: [BitTree<{ 1 << 6 }, P>; 4],
+    align_decoder: BitTree<{ 1 << 4 }, P>,
     state: usize,
     rep: [usize; 4],
-    len_decoder: LenDecoder,
-    rep_len_decoder: LenDecoder,
+    len_decoder: LenDecoder<P>,
+    rep_len_decoder: LenDecoder<P>,
 }
 
-impl DecoderState {
+impl<P: Prob> DecoderState<P> {
+    /// Memory taken by a decoder state for the properties `lzma_props`,
+    /// including its probabilities.
+    pub(crate) fn memory_usage(lzma_props: LzmaProperties) -> usize {
+        std::mem::size_of::<Self>() + probs_len(lzma_props) * std::mem::size_of::<P>()
+    }
+
     pub fn new(lzma_props: LzmaProperties, unpacked_size: Option<u64>) -> Self {
         lzma_props.validate();
         DecoderState {
             partial_input_buf: std::io::Cursor::new([0; MAX_REQUIRED_INPUT]),
             lzma_props,
             unpacked_size,
-            literal_probs: Vec2D::init(0x400, (1 << (lzma_props.lc + lzma_props.lp), 0x300)),
+            output_limit: None,
+            end_marker: false,
+            declared_dict_size: None,
+            #[cfg(feature = "trace")]
+            recent_symbols: RecentSymbols::default(),
+            probs: vec![P::HALF; probs_len(lzma_props)].into_boxed_slice(),
             pos_slot_decoder: [
                 BitTree::new(),
                 BitTree::new(),
@@ -199,13 +374,6 @@ impl DecoderState {
                 BitTree::new(),
             ],
             align_decoder: BitTree::new(),
-            pos_decoders: [0x400; 115],
-            is_match: [0x400; 192],
-            is_rep: [0x400; 12],
-            is_rep_g0: [0x400; 12],
-            is_rep_g1: [0x400; 12],
-            is_rep_g2: [0x400; 12],
-            is_rep_0long: [0x400; 192],
             state: 0,
             rep: [0; 4],
             len_decoder: LenDecoder::new(),
@@ -215,12 +383,12 @@ impl DecoderState {
 
     pub fn reset_state(&mut self, new_props: LzmaProperties) {
         new_props.validate();
-        if self.lzma_props.lc + self.lzma_props.lp == new_props.lc + new_props.lp {
+        if self.probs.len() == probs_len(new_props) {
             // We can reset here by filling the existing buffer with 0x400.
-            self.literal_probs.fill(0x400);
+            self.probs.fill(P::HALF);
         } else {
             // We need to reallocate because of the new size of `lc+lp`.
-            self.literal_probs = Vec2D::init(0x400, (1 << (new_props.lc + new_props.lp), 0x300));
+            self.probs = vec![P::HALF; probs_len(new_props)].into_boxed_slice();
         }
 
         self.lzma_props = new_props;
@@ -235,15 +403,13 @@ impl DecoderState {
             BitTree::new(),
         ];
         self.align_decoder = BitTree::new();
-        self.pos_decoders = [0x400; 115];
-        self.is_match = [0x400; 192];
-        self.is_rep = [0x400; 12];
-        self.is_rep_g0 = [0x400; 12];
-        self.is_rep_g1 = [0x400; 12];
-        self.is_rep_g2 = [0x400; 12];
-        self.is_rep_0long = [0x400; 192];
         self.state = 0;
         self.rep = [0; 4];
+        self.end_marker = false;
+        #[cfg(feature = "trace")]
+        {
+            self.recent_symbols = RecentSymbols::default();
+        }
         self.len_decoder = LenDecoder::new();
         self.rep_len_decoder = LenDecoder::new();
     }
@@ -252,47 +418,77 @@ impl DecoderState {
         self.unpacked_size = unpacked_size;
     }
 
+    pub fn set_output_limit(&mut self, output_limit: Option<u64>) {
+        self.output_limit = output_limit;
+    }
+
     pub fn process<W: io::Write, LZB: LzBuffer<W>, R: io::BufRead>(
         &mut self,
         output: &mut LZB,
         rangecoder: &mut RangeDecoder<'_, R>,
     ) -> error::Result<()> {
-        self.process_mode(output, rangecoder, ProcessingMode::Finish)
+        self.process_mode(output, rangecoder, ProcessingMode::Finish, &mut |_| {})
+    }
+
+    /// Same as [`DecoderState::process()`], passing each decoded symbol to
+    /// `on_symbol`.
+    pub fn process_with_symbols<W, LZB, R, S>(
+        &mut self,
+        output: &mut LZB,
+        rangecoder: &mut RangeDecoder<'_, R>,
+        on_symbol: &mut S,
+    ) -> error::Result<()>
+    where
+        W: io::Write,
+        LZB: LzBuffer<W>,
+        R: io::BufRead,
+        S: FnMut(Symbol),
+    {
+        self.process_mode(output, rangecoder, ProcessingMode::Finish, on_symbol)
     }
 
-    #[cfg(feature = "stream")]
+    #[cfg(any(feature = "stream", feature = "raw_decoder"))]
     pub fn process_stream<W: io::Write, LZB: LzBuffer<W>, R: io::BufRead>(
         &mut self,
         output: &mut LZB,
         rangecoder: &mut RangeDecoder<'_, R>,
     ) -> error::Result<()> {
-        self.process_mode(output, rangecoder, ProcessingMode::Partial)
+        self.process_mode(output, rangecoder, ProcessingMode::Partial, &mut |_| {})
     }
 
     /// Process the next iteration of the loop.
     ///
-    /// If the update flag is true, the decoder's state will be updated.
+    /// If the update flag is true, the decoder's state will be updated and
+    /// the decoded symbol passed to `on_symbol`.
     ///
     /// Returns [`ProcessingStatus`] to determine whether one should continue
     /// processing the loop.
-    fn process_next_inner<W: io::Write, LZB: LzBuffer<W>, R: io::BufRead>(
+    fn process_next_inner<W, LZB, R, S>(
         &mut self,
         output: &mut LZB,
         rangecoder: &mut RangeDecoder<'_, R>,
         update: bool,
-    ) -> error::Result<ProcessingStatus> {
+        on_symbol: &mut S,
+    ) -> error::Result<ProcessingStatus>
+    where
+        W: io::Write,
+        LZB: LzBuffer<W>,
+        R: io::BufRead,
+        S: FnMut(Symbol),
+    {
         let pos_state = output.len() & ((1 << self.lzma_props.pb) - 1);
 
         // Literal
         if !rangecoder.decode_bit(
-            // TODO: assumes pb = 2 ??
-            &mut self.is_match[(self.state << 4) + pos_state],
+            // There are `1 << 4` slots per state, enough for the maximum pb.
+            &mut self.probs[IS_MATCH_OFFSET + (self.state << 4) + pos_state],
             update,
         )? {
             let byte: u8 = self.decode_literal(output, rangecoder, update)?;
 
             if update {
                 lzma_debug!("Literal: {}", byte);
+                on_symbol(Symbol::Literal(byte));
                 output.append_literal(byte)?;
 
                 self.state = if self.state < 4 {
@@ -308,33 +504,45 @@ impl DecoderState {
 
         // LZ
         let mut len: usize;
+        // Index of the recent distance, for rep matches
+        let mut rep_index = None;
         // Distance is repeated from LRU
-        if rangecoder.decode_bit(&mut self.is_rep[self.state], update)? {
+        if rangecoder.decode_bit(&mut self.probs[IS_REP_OFFSET + self.state], update)? {
             // dist = rep[0]
-            if !rangecoder.decode_bit(&mut self.is_rep_g0[self.state], update)? {
+            if !rangecoder.decode_bit(&mut self.probs[IS_REP_G0_OFFSET + self.state], update)? {
                 // len = 1
                 if !rangecoder.decode_bit(
-                    &mut self.is_rep_0long[(self.state << 4) + pos_state],
+                    &mut self.probs[IS_REP_0LONG_OFFSET + (self.state << 4) + pos_state],
                     update,
                 )? {
                     // update state (short rep)
                     if update {
                         self.state = if self.state < 7 { 9 } else { 11 };
                         let dist = self.rep[0] + 1;
+                        check_distance(output, dist, self.declared_dict_size)?;
+                        on_symbol(Symbol::Rep {
+                            index: 0,
+                            len: 1,
+                            dist,
+                        });
                         output.append_lz(1, dist)?;
                     }
                     return Ok(ProcessingStatus::Continue);
                 }
+                rep_index = Some(0);
             // dist = rep[i]
             } else {
                 let idx: usize;
-                if !rangecoder.decode_bit(&mut self.is_rep_g1[self.state], update)? {
+                if !rangecoder.decode_bit(&mut self.probs[IS_REP_G1_OFFSET + self.state], update)? {
                     idx = 1;
-                } else if !rangecoder.decode_bit(&mut self.is_rep_g2[self.state], update)? {
+                } else if !rangecoder
+                    .decode_bit(&mut self.probs[IS_REP_G2_OFFSET + self.state], update)?
+                {
                     idx = 2;
                 } else {
                     idx = 3;
                 }
+                rep_index = Some(idx);
                 if update {
                     // Update LRU
                     let dist = self.rep[idx];
@@ -373,6 +581,7 @@ impl DecoderState {
                 self.rep[0] = rep_0;
                 if self.rep[0] == 0xFFFF_FFFF {
                     if rangecoder.is_finished_ok()? {
+                        self.end_marker = true;
                         return Ok(ProcessingStatus::Finished);
                     }
                     return Err(error::Error::LzmaError(String::from(
@@ -386,18 +595,61 @@ impl DecoderState {
             len += 2;
 
             let dist = self.rep[0] + 1;
+            check_distance(output, dist, self.declared_dict_size)?;
+            // The declared size is a hard bound, which a match must not
+            // cross. Literals and short reps can't, as decoding stops at
+            // this size.
+            if let Some(unpacked_size) = self.unpacked_size {
+                let end = output.len() as u64 + len as u64;
+                if end > unpacked_size {
+                    return Err(error::Error::SizeMismatch {
+                        expected: unpacked_size,
+                        actual: end,
+                    });
+                }
+            }
+            on_symbol(match rep_index {
+                Some(index) => Symbol::Rep { index, len, dist },
+                None => Symbol::Match { len, dist },
+            });
             output.append_lz(len, dist)?;
         }
 
         Ok(ProcessingStatus::Continue)
     }
 
-    fn process_next<W: io::Write, LZB: LzBuffer<W>, R: io::BufRead>(
+    fn process_next<W, LZB, R, S>(
         &mut self,
         output: &mut LZB,
         rangecoder: &mut RangeDecoder<'_, R>,
-    ) -> error::Result<ProcessingStatus> {
-        self.process_next_inner(output, rangecoder, true)
+        on_symbol: &mut S,
+    ) -> error::Result<ProcessingStatus>
+    where
+        W: io::Write,
+        LZB: LzBuffer<W>,
+        R: io::BufRead,
+        S: FnMut(Symbol),
+    {
+        #[cfg(feature = "trace")]
+        {
+            let mut recent_symbols = self.recent_symbols;
+            let result = self.process_next_inner(output, rangecoder, true, &mut |symbol| {
+                recent_symbols.push(symbol);
+                on_symbol(symbol)
+            });
+            self.recent_symbols = recent_symbols;
+            result.map_err(|e| match e {
+                error::Error::LzmaError(e) if !self.recent_symbols.is_empty() => {
+                    error::Error::LzmaError(format!(
+                        "{} (recent symbols: {})",
+                        e, self.recent_symbols
+                    ))
+                }
+                e => e,
+            })
+        }
+        #[cfg(not(feature = "trace"))]
+        self.process_next_inner(output, rangecoder, true, on_symbol)
     }
 
     /// Try to process the next iteration of the loop.
@@ -414,7 +666,7 @@ impl DecoderState {
     ) -> error::Result<()> {
         let mut temp = std::io::Cursor::new(buf);
         let mut rangecoder = RangeDecoder::from_parts(&mut temp, range, code);
-        let _ = self.process_next_inner(output, &mut rangecoder, false)?;
+        let _ = self.process_next_inner(output, &mut rangecoder, false, &mut |_| {})?;
         Ok(())
     }
 
@@ -432,13 +684,27 @@ impl DecoderState {
         Ok(())
     }
 
-    fn process_mode<W: io::Write, LZB: LzBuffer<W>, R: io::BufRead>(
+    fn process_mode<W, LZB, R, S>(
         &mut self,
         output: &mut LZB,
         rangecoder: &mut RangeDecoder<'_, R>,
         mode: ProcessingMode,
-    ) -> error::Result<()> {
+        on_symbol: &mut S,
+    ) -> error::Result<()>
+    where
+        W: io::Write,
+        LZB: LzBuffer<W>,
+        R: io::BufRead,
+        S: FnMut(Symbol),
+    {
         loop {
+            if let Some(output_limit) = self.output_limit {
+                if output.len() as u64 >= output_limit {
+                    // The rest of the stream is deliberately left undecoded.
+                    return Ok(());
+                }
+            }
+
             if let Some(unpacked_size) = self.unpacked_size {
                 if output.len() as u64 >= unpacked_size {
                     break;
@@ -478,7 +744,7 @@ impl DecoderState {
                     io::Cursor::new(&tmp[..self.partial_input_buf.position() as usize]);
                 let mut tmp_rangecoder =
                     RangeDecoder::from_parts(&mut tmp_reader, rangecoder.range, rangecoder.code);
-                let res = self.process_next(output, &mut tmp_rangecoder)?;
+                let res = self.process_next(output, &mut tmp_rangecoder, on_symbol)?;
 
                 // Update the actual rangecoder
                 rangecoder.set(tmp_rangecoder.range, tmp_rangecoder.code);
@@ -504,19 +770,19 @@ impl DecoderState {
                     return self.read_partial_input_buf(rangecoder);
                 }
 
-                if self.process_next(output, rangecoder)? == ProcessingStatus::Finished {
+                if self.process_next(output, rangecoder, on_symbol)? == ProcessingStatus::Finished {
                     break;
                 };
             }
         }
 
         if let Some(len) = self.unpacked_size {
+            // The end-of-stream marker came before the declared size.
             if mode == ProcessingMode::Finish && len != output.len() as u64 {
-                return Err(error::Error::LzmaError(format!(
-                    "Expected unpacked size of {} but decompressed to {}",
-                    len,
-                    output.len()
-                )));
+                return Err(error::Error::SizeMismatch {
+                    expected: len,
+                    actual: output.len() as u64,
+                });
             }
         }
 
@@ -535,7 +801,8 @@ impl DecoderState {
         let mut result: usize = 1;
         let lit_state = ((output.len() & ((1 << self.lzma_props.lp) - 1)) << self.lzma_props.lc)
             + (prev_byte >> (8 - self.lzma_props.lc));
-        let probs = &mut self.literal_probs[lit_state];
+        let offset = LITERAL_OFFSET + lit_state * LITERAL_CODER_LEN;
+        let probs = &mut self.probs[offset..offset + LITERAL_CODER_LEN];
 
         if self.state >= 7 {
             let mut match_byte = output.last_n(self.rep[0] + 1)? as usize;
@@ -579,7 +846,7 @@ impl DecoderState {
         if pos_slot < 14 {
             result += rangecoder.parse_reverse_bit_tree(
                 num_direct_bits,
-                &mut self.pos_decoders,
+                &mut self.probs[POS_DECODERS_OFFSET..POS_DECODERS_OFFSET + POS_DECODERS_LEN],
                 result - pos_slot,
                 update,
             )? as usize;
@@ -598,6 +865,9 @@ pub struct LzmaDecoder {
     params: LzmaParams,
     memlimit: usize,
     state: DecoderState,
+    // Dictionary memory, kept across calls to `decompress` to avoid
+    // reallocating it for each stream.
+    dict_buf: Vec<u8>,
 }
 
 impl LzmaDecoder {
@@ -609,6 +879,7 @@ impl LzmaDecoder {
             params,
             memlimit: memlimit.unwrap_or(usize::MAX),
             state: DecoderState::new(params.properties, params.unpacked_size),
+            dict_buf: Vec::new(),
         })
     }
 
@@ -620,7 +891,9 @@ impl LzmaDecoder {
     /// the decoder was initialized with remains unchanged.
     ///
     /// This function may not allocate memory and will attempt to reuse any
-    /// previously allocated resources.
+    /// previously allocated resources, including the dictionary buffer. Call
+    /// this between independent streams decoded with the same decoder, so that
+    /// no state carries over from one stream to the next.
     #[cfg(feature = "raw_decoder")]
     pub fn reset(&mut self, unpacked_size: Option<Option<u64>>) {
         self.state.reset_state(self.params.properties);
@@ -630,20 +903,304 @@ impl LzmaDecoder {
         }
     }
 
+    /// Decode with a dictionary of `dict_size` bytes if it's smaller than the
+    /// declared one, see
+    /// [`Options::override_dict_size`](crate::decompress::Options::override_dict_size).
+    pub(crate) fn override_dict_size(&mut self, dict_size: u64) {
+        let declared = self.params.dict_size;
+        let dict_size = dict_size.max(0x1000);
+        if dict_size < u64::from(declared) {
+            lzma_info!("Dict size overridden: {}", dict_size);
+            self.params.dict_size = dict_size as u32;
+            self.state.declared_dict_size = Some(declared as usize);
+        }
+    }
+
+    /// Stops decoding once `output_limit` bytes were produced, possibly
+    /// overshooting by the length of one match.
+    pub(crate) fn set_output_limit(&mut self, output_limit: Option<u64>) {
+        self.state.set_output_limit(output_limit);
+    }
+
+    /// How the last decoded stream ended, given whether it was decoded to its
+    /// end rather than stopped at the output limit.
+    pub(crate) fn termination(&self, finished: bool) -> Option<Termination> {
+        match self.state.unpacked_size {
+            Some(size) => Some(Termination::KnownSize(size)),
+            None if self.state.end_marker => Some(Termination::EndMarker),
+            None if finished => Some(Termination::EndOfInput),
+            None => None,
+        }
+    }
+
     /// Decompresses the input data into the output, consuming only as much
     /// input as needed and writing as much output as possible.
+    #[cfg(feature = "raw_decoder")]
     pub fn decompress<W: io::Write, R: io::BufRead>(
         &mut self,
         input: &mut R,
         output: &mut W,
     ) -> error::Result<()> {
-        let mut output =
-            LzCircularBuffer::from_stream(output, self.params.dict_size as usize, self.memlimit);
+        self.decompress_with_symbols(input, output, &mut |_| {})
+    }
+
+    /// Same as [`LzmaDecoder::decompress()`], producing the output through
+    /// the caller-supplied dictionary `buffer` instead of an internal
+    /// [`LzCircularBuffer`].
+    ///
+    /// The buffer must be able to reference as many bytes as the dictionary
+    /// size of the [`LzmaParams`] to decode any stream, and isn't finished:
+    /// call [`LzBuffer::finish()`] to flush it once decoding succeeded.
+    #[cfg(feature = "raw_decoder")]
+    pub fn decompress_with_buffer<W, LZB, R>(
+        &mut self,
+        input: &mut R,
+        buffer: &mut LZB,
+    ) -> error::Result<()>
+    where
+        W: io::Write,
+        LZB: LzBuffer<W>,
+        R: io::BufRead,
+    {
+        let mut rangecoder = RangeDecoder::new(input).map_err(|e| match e {
+            error::Error::IoError(e) => {
+                error::Error::LzmaError(format!("LZMA stream too short: {}", e))
+            }
+            e => e,
+        })?;
+        self.state
+            .process(buffer, &mut rangecoder)
+            .map_err(|e| check_truncated(e, &mut rangecoder, buffer.len()))
+    }
 
-        let mut rangecoder = RangeDecoder::new(input)
-            .map_err(|e| error::Error::LzmaError(format!("LZMA stream too short: {}", e)))?;
-        self.state.process(&mut output, &mut rangecoder)?;
-        output.finish()?;
+    /// Same as `LzmaDecoder::decompress()`, passing each decoded symbol to
+    /// `on_symbol`.
+    pub(crate) fn decompress_with_symbols<W, R, S>(
+        &mut self,
+        input: &mut R,
+        output: &mut W,
+        on_symbol: &mut S,
+    ) -> error::Result<()>
+    where
+        W: io::Write,
+        R: io::BufRead,
+        S: FnMut(Symbol),
+    {
+        let mut output = LzCircularBuffer::from_stream_with_memory(
+            output,
+            std::mem::take(&mut self.dict_buf),
+            self.params.dict_size as usize,
+            self.memlimit,
+        );
+
+        let mut rangecoder = RangeDecoder::new(input).map_err(|e| match e {
+            error::Error::IoError(e) => {
+                error::Error::LzmaError(format!("LZMA stream too short: {}", e))
+            }
+            e => e,
+        })?;
+        self.state
+            .process_with_symbols(&mut output, &mut rangecoder, on_symbol)
+            .map_err(|e| check_truncated(e, &mut rangecoder, output.len()))?;
+        let (_, dict_buf) = output.finish_with_memory()?;
+        self.dict_buf = dict_buf;
         Ok(())
     }
 }
+
+/// Report the error `e` as [`Error::Truncated`](error::Error::Truncated) if
+/// it comes from the input of `rangecoder` ending in the middle of a symbol,
+/// after `pos` bytes of output. Unlike a range coder finalized at the end of
+/// the input, this can't be the end of a stream of unknown size.
+pub(crate) fn check_truncated<R>(
+    e: error::Error,
+    rangecoder: &mut RangeDecoder<'_, R>,
+    pos: usize,
+) -> error::Error
+where
+    R: io::BufRead,
+{
+    match e {
+        error::Error::IoError(ref io_err)
+            if io_err.kind() == io::ErrorKind::UnexpectedEof
+                && rangecoder.is_eof().unwrap_or(false) =>
+        {
+            error::Error::Truncated(format!(
+                "LZMA stream ended in the middle of a symbol, after {} bytes of output",
+                pos
+            ))
+        }
+        e => e,
+    }
+}
+
+/// Decode the LZMA stream of `input`, and the concatenated members following
+/// it if the `concatenated` option is set, passing each decoded symbol to
+/// `on_symbol`.
+pub(crate) fn decode_stream<R, W, F>(
+    input: &mut R,
+    output: &mut W,
+    options: &Options,
+    mut on_symbol: F,
+) -> error::Result<LzmaStatus>
+where
+    R: io::BufRead,
+    W: io::Write,
+    F: FnMut(Symbol),
+{
+    let mut checked = CheckedWriter::new(output, options);
+    let mut output = TruncatingWriter::new(&mut checked, options.limit);
+    loop {
+        let params = LzmaParams::read_header(input, options)?;
+        let known_size = params.unpacked_size.is_some();
+        let mut decoder = LzmaDecoder::new(params, options.memlimit)?;
+        if let Some(dict_size) = options.override_dict_size {
+            decoder.override_dict_size(dict_size);
+        }
+        decoder.set_output_limit(output.remaining());
+        decoder.decompress_with_symbols(input, &mut output, &mut on_symbol)?;
+        let limit_reached = output.remaining() == Some(0);
+        if limit_reached || !options.concatenated || !known_size || util::is_eof(input)? {
+            // Decoding stopped at the limit doesn't reach the expected size.
+            if !limit_reached {
+                checked.finish()?;
+            }
+            return Ok(LzmaStatus {
+                termination: decoder.termination(!limit_reached),
+            });
+        }
+        lzma_info!("LZMA decoding concatenated member");
+    }
+}
+
+/// Number of bytes initializing the range coder.
+#[cfg(feature = "raw_decoder")]
+const RANGE_CODER_INIT_LEN: usize = 5;
+
+/// Raw decoder for a single LZMA stream received in several chunks, e.g.
+/// split across network messages.
+///
+/// Unlike [`LzmaDecoder`], the range coder and the dictionary are carried
+/// over from one call to [`RawLzmaDecoder::feed()`] to the next, so that the
+/// stream can be split at any byte.
+#[cfg(feature = "raw_decoder")]
+pub struct RawLzmaDecoder {
+    state: DecoderState,
+    output: LzCircularBuffer<Vec<u8>>,
+    // First bytes of the stream, until the range coder is initialized.
+    init: Vec<u8>,
+    // Range and code of the range coder, once initialized.
+    coder: Option<(u32, u32)>,
+}
+
+#[cfg(feature = "raw_decoder")]
+impl RawLzmaDecoder {
+    /// Creates a decoder for a stream with the given parameters, and memory
+    /// limit for the dictionary.
+    pub fn new(params: LzmaParams, memlimit: Option<usize>) -> Self {
+        Self {
+            state: DecoderState::new(params.properties, params.unpacked_size),
+            output: LzCircularBuffer::from_stream_with_memory(
+                Vec::new(),
+                Vec::new(),
+                params.dict_size as usize,
+                memlimit.unwrap_or(usize::MAX),
+            ),
+            init: Vec::with_capacity(RANGE_CODER_INIT_LEN),
+            coder: None,
+        }
+    }
+
+    /// Decompresses the next chunk of the stream, returning as much of the
+    /// output as can be decoded so far.
+    ///
+    /// A few bytes of `input` may be kept internally until the next chunk
+    /// allows decoding them. The decoder must not be used after an error.
+    pub fn feed(&mut self, mut input: &[u8]) -> error::Result<Vec<u8>> {
+        let (range, code) = match self.coder {
+            Some(coder) => coder,
+            None => {
+                let missing = RANGE_CODER_INIT_LEN - self.init.len();
+                let (head, rest) = input.split_at(std::cmp::min(missing, input.len()));
+                self.init.extend_from_slice(head);
+                input = rest;
+                if self.init.len() < RANGE_CODER_INIT_LEN {
+                    return Ok(Vec::new());
+                }
+                let mut init = self.init.as_slice();
+                let rangecoder = RangeDecoder::new(&mut init)?;
+                (rangecoder.range, rangecoder.code)
+            }
+        };
+
+        let mut rangecoder = RangeDecoder::from_parts(&mut input, range, code);
+        self.state
+            .process_stream(&mut self.output, &mut rangecoder)?;
+        self.coder = Some((rangecoder.range, rangecoder.code));
+        Ok(std::mem::take(self.output.flush_pending()?))
+    }
+
+    /// Decompresses the bytes kept from the last chunk, and checks that the
+    /// stream ends there. Returns the rest of the output.
+    pub fn finish(mut self) -> error::Result<Vec<u8>> {
+        let (range, code) = self.coder.ok_or_else(|| {
+            error::Error::LzmaError(format!("LZMA stream too short: {} bytes", self.init.len()))
+        })?;
+        let mut input: &[u8] = &[];
+        let mut rangecoder = RangeDecoder::from_parts(&mut input, range, code);
+        self.state.process(&mut self.output, &mut rangecoder)?;
+        Ok(self.output.finish()?)
+    }
+}
+
+#[cfg(feature = "raw_decoder")]
+impl std::fmt::Debug for RawLzmaDecoder {
+    fn fmt(&self, fmt: &mut std::fmt::Formatter) -> std::fmt::Result {
+        fmt.debug_struct("RawLzmaDecoder")
+            .field("state", &self.state)
+            .field("coder", &self.coder)
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::decode::lzbuffer::LzAccumBuffer;
+
+    fn decode_with<P: Prob>(compressed: &[u8], unpacked_size: u64) -> Vec<u8> {
+        let props = u32::from(compressed[0]);
+        let mut state: DecoderState<P> = DecoderState::new(
+            LzmaProperties {
+                lc: props % 9,
+                lp: (props / 9) % 5,
+                pb: props / 45,
+            },
+            Some(unpacked_size),
+        );
+        let mut input = &compressed[13..];
+        let mut rangecoder = RangeDecoder::new(&mut input).unwrap();
+        let mut output = LzAccumBuffer::from_stream(Vec::new(), usize::MAX);
+        state.process(&mut output, &mut rangecoder).unwrap();
+        output.finish().unwrap()
+    }
+
+    #[test]
+    fn prob_widths_decode_identically() {
+        for (data, compressed) in [
+            (
+                &include_bytes!("../../tests/files/foo.txt")[..],
+                &include_bytes!("../../tests/files/foo.txt.lzma")[..],
+            ),
+            (
+                &include_bytes!("../../tests/files/records-pb4-lp2")[..],
+                &include_bytes!("../../tests/files/records-pb4-lp2.lzma")[..],
+            ),
+        ] {
+            let narrow = decode_with::<u16>(compressed, data.len() as u64);
+            let wide = decode_with::<u32>(compressed, data.len() as u64);
+            assert_eq!(narrow, data);
+            assert_eq!(wide, narrow);
+        }
+    }
+}
@@ -3,13 +3,168 @@ use crate::decode::lzma::{DecoderState, LzmaProperties};
 use crate::decode::{lzbuffer, rangecoder};
 use crate::error;
 use byteorder::{BigEndian, ReadBytesExt};
+use std::cell::Cell;
 use std::io;
 use std::io::Read;
+use std::time::Instant;
+
+/// Number of compressed bytes decoded to check a candidate resume point, see
+/// [`Lzma2Decoder::decompress_resync()`].
+const RESYNC_TRIAL_BYTES: usize = 4096;
+
+/// Outcome of decoding a raw LZMA2 stream, see
+/// [`lzma2_decompress_with_status`](crate::lzma2_decompress_with_status).
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Lzma2Status {
+    /// Chunks of the stream in order, if the
+    /// [`record_chunk_map`](crate::decompress::Options::record_chunk_map)
+    /// option is set. Otherwise, this is empty.
+    pub chunks: Vec<ChunkEntry>,
+    /// Damaged parts of the stream that were skipped, in order, if the
+    /// [`resync`](crate::decompress::Options::resync) option is set.
+    /// Otherwise, this is empty.
+    pub gaps: Vec<Lzma2Gap>,
+}
+
+/// Damaged part of an LZMA2 stream, skipped up to the next chunk that
+/// resets the dictionary, see [`Lzma2Status::gaps`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Lzma2Gap {
+    /// Offset of the chunk that failed to decode, from the start of the
+    /// stream.
+    pub compressed_offset: u64,
+    /// Offset in the decompressed stream at which the data of this chunk and
+    /// of the skipped ones is missing.
+    pub uncompressed_offset: u64,
+    /// Number of bytes of the stream skipped, up to the chunk where decoding
+    /// resumed or to the end of the input.
+    pub skipped: u64,
+}
+
+/// Position and kind of an LZMA2 chunk, see [`Lzma2Status::chunks`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ChunkEntry {
+    /// Offset of the chunk, i.e. of its control byte, from the start of the
+    /// stream.
+    pub compressed_offset: u64,
+    /// Offset of the data of the chunk in the decompressed stream.
+    pub uncompressed_offset: u64,
+    /// Whether the data is stored uncompressed.
+    pub uncompressed: bool,
+    /// Whether the chunk resets the dictionary, so that decoding can start
+    /// at it.
+    pub dict_reset: bool,
+    /// Whether the chunk resets the LZMA state.
+    pub state_reset: bool,
+    /// Whether the chunk sets new LZMA properties.
+    pub props_reset: bool,
+}
+
+impl ChunkEntry {
+    /// Entry of a chunk starting with the control byte `status`.
+    pub(crate) fn new(status: u8, compressed_offset: u64, uncompressed_offset: u64) -> Self {
+        ChunkEntry {
+            compressed_offset,
+            uncompressed_offset,
+            uncompressed: status < 0x80,
+            dict_reset: status == 1 || status >= 0xE0,
+            state_reset: status >= 0xA0,
+            props_reset: status >= 0xC0,
+        }
+    }
+}
+
+/// Reset of the dictionary or of the LZMA state at the start of an LZMA2
+/// chunk, see
+/// [`lzma2_decompress_with_resets`](crate::lzma2_decompress_with_resets).
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ResetEvent {
+    /// Offset of the chunk, i.e. of its control byte, from the start of the
+    /// stream.
+    pub compressed_offset: u64,
+    /// Offset of the data of the chunk in the decompressed stream.
+    pub uncompressed_offset: u64,
+    /// Whether the dictionary is reset, so that decoding can start at the
+    /// chunk. Otherwise, only the LZMA state is reset.
+    pub dict_reset: bool,
+}
+
+/// Reconstruct the size of a chunk from its stored bits, which hold the size
+/// minus one: at most 5 high bits from the control byte (in the case of the
+/// unpacked size of an LZMA chunk), followed by a 16-bit field.
+fn chunk_size(high: u8, low: u16) -> error::Result<u64> {
+    let size_overflow = || error::Error::LzmaError(String::from("size overflow"));
+    if high > 0x1F {
+        return Err(size_overflow());
+    }
+    (high as u64)
+        .checked_shl(16)
+        .map(|size| size | low as u64)
+        .and_then(|size| size.checked_add(1))
+        .ok_or_else(size_overflow)
+}
+
+/// Report the error `e` reading `what` in the chunk number `chunk`, as
+/// [`Error::Truncated`](error::Error::Truncated) if the input ended there.
+fn chunk_read_error(e: io::Error, chunk: usize, what: &str) -> error::Error {
+    if e.kind() == io::ErrorKind::UnexpectedEof {
+        error::Error::Truncated(format!("LZMA2 chunk {} expected {}", chunk, what))
+    } else {
+        error::Error::LzmaError(format!("LZMA2 expected {}: {}", what, e))
+    }
+}
+
+/// Check the LZMA properties of an LZMA2 chunk.
+pub(crate) fn check_props(lc: u32, lp: u32, pb: u32) -> error::Result<LzmaProperties> {
+    if lc + lp > 4 {
+        return Err(error::Error::LzmaError(format!(
+            "LZMA2 invalid properties: lc + lp ({} + {}) must be <= 4",
+            lc, lp
+        )));
+    }
+    if pb > 4 {
+        return Err(error::Error::LzmaError(format!(
+            "LZMA2 invalid properties: pb ({}) must be <= 4",
+            pb
+        )));
+    }
+    Ok(LzmaProperties { lc, lp, pb })
+}
+
+/// Decode the dictionary size from the properties byte of the LZMA2 filter,
+/// see sect. 5.3.1 of the XZ spec.
+pub fn dict_size_from_props(props: u8) -> error::Result<u32> {
+    match props {
+        0..=39 => Ok((2 | (props as u32 & 1)) << (props / 2 + 11)),
+        40 => Ok(0xFFFF_FFFF),
+        _ => Err(error::Error::LzmaError(format!(
+            "LZMA2 invalid dictionary size property {}, must be <= 40",
+            props
+        ))),
+    }
+}
 
 #[derive(Debug)]
 /// Raw decoder for LZMA2.
 pub struct Lzma2Decoder {
     lzma_state: DecoderState,
+    // Whether a chunk has set the LZMA properties since the last dictionary
+    // reset. Until then, chunks that re-use the previous properties are
+    // invalid.
+    has_props: bool,
+    // Properties used instead of those of the chunks, for diagnostics.
+    force_props: Option<LzmaProperties>,
+    // Whether the next chunk must reset the dictionary, as the first chunk
+    // of an XZ block does.
+    need_dict_reset: bool,
+    // Maximum number of bytes held in the buffer of the decoded data.
+    memlimit: usize,
+    // Point in time after which decoding fails.
+    deadline: Option<Instant>,
+    // Maximum number of chunks to decode.
+    max_chunks: u64,
+    // Number of chunks decoded so far.
+    chunks: u64,
 }
 
 impl Default for Lzma2Decoder {
@@ -30,9 +185,45 @@ impl Lzma2Decoder {
                 },
                 None,
             ),
+            has_props: false,
+            force_props: None,
+            need_dict_reset: false,
+            memlimit: usize::MAX,
+            deadline: None,
+            max_chunks: u64::MAX,
+            chunks: 0,
         }
     }
 
+    /// Use the given properties on every state reset instead of those set by
+    /// the chunks, see
+    /// [`Options::force_props`](crate::decompress::Options::force_props).
+    pub(crate) fn force_props(&mut self, props: Option<(u32, u32, u32)>) -> error::Result<()> {
+        self.force_props = match props {
+            Some((lc, lp, pb)) => Some(check_props(lc, lp, pb)?),
+            None => None,
+        };
+        Ok(())
+    }
+
+    /// Limit the buffer of the decoded data to `memlimit` bytes, see
+    /// [`Options::memlimit`](crate::decompress::Options::memlimit).
+    pub(crate) fn memlimit(&mut self, memlimit: Option<usize>) {
+        self.memlimit = memlimit.unwrap_or(usize::MAX);
+    }
+
+    /// Fail once `deadline` has passed, checked before each chunk, see
+    /// [`Options::deadline`](crate::decompress::Options::deadline).
+    pub(crate) fn deadline(&mut self, deadline: Option<Instant>) {
+        self.deadline = deadline;
+    }
+
+    /// Fail beyond `max_chunks` chunks decoded by this decoder, see
+    /// [`Options::max_chunks`](crate::decompress::Options::max_chunks).
+    pub(crate) fn max_chunks(&mut self, max_chunks: Option<u64>) {
+        self.max_chunks = max_chunks.unwrap_or(u64::MAX);
+    }
+
     /// Performs the equivalent of replacing this decompression state with a
     /// freshly allocated copy.
     ///
@@ -45,6 +236,19 @@ impl Lzma2Decoder {
             lp: 0,
             pb: 0,
         });
+        self.has_props = false;
+        self.chunks = 0;
+    }
+
+    /// Prepares the decoder for a new sequence of chunks, which must start by
+    /// resetting the dictionary and setting the properties, so that it
+    /// doesn't depend on previous data.
+    ///
+    /// Unlike `Lzma2Decoder::reset()`, this keeps the probability tables
+    /// sized for the previous properties, which are most likely the next ones.
+    pub(crate) fn restart(&mut self) {
+        self.has_props = false;
+        self.need_dict_reset = true;
     }
 
     /// Decompresses the input data into the output, consuming only as much
@@ -54,11 +258,290 @@ impl Lzma2Decoder {
         input: &mut R,
         output: &mut W,
     ) -> error::Result<()> {
-        let mut accum = lzbuffer::LzAccumBuffer::from_stream(output, usize::MAX);
+        let mut accum = lzbuffer::LzAccumBuffer::from_stream(output, self.memlimit);
+        self.decompress_chunks(&mut accum, input, |_| {}, |_| false)?;
+        accum.finish()?;
+        Ok(())
+    }
+
+    /// Same as [`Lzma2Decoder::decompress()`], additionally passing each
+    /// chunk to `on_entry` before decoding it, with offsets relative to the
+    /// start of `input`.
+    pub(crate) fn decompress_with_chunk_entries<W, R, E>(
+        &mut self,
+        input: &mut R,
+        output: &mut W,
+        on_entry: E,
+    ) -> error::Result<()>
+    where
+        W: io::Write,
+        R: io::BufRead,
+        E: FnMut(&ChunkEntry),
+    {
+        let mut accum = lzbuffer::LzAccumBuffer::from_stream(output, self.memlimit);
+        self.decompress_chunks(&mut accum, input, on_entry, |_| false)?;
+        accum.finish()?;
+        Ok(())
+    }
+
+    /// Decompresses the input data, appending it to `output`.
+    ///
+    /// Unlike [`Lzma2Decoder::decompress()`], LZ sequences are copied from the
+    /// output itself, without keeping a separate dictionary.
+    #[cfg(feature = "raw_decoder")]
+    pub fn decompress_to_vec<R: io::BufRead>(
+        &mut self,
+        input: &mut R,
+        output: &mut Vec<u8>,
+    ) -> error::Result<()> {
+        self.decompress_to_vec_inspect(input, output, None, |_| {})
+    }
 
+    #[cfg(any(feature = "raw_decoder", feature = "xz"))]
+    /// Same as `Lzma2Decoder::decompress_to_vec()`, additionally passing the
+    /// output of each chunk to `inspect` while it's still fresh in memory.
+    ///
+    /// If a `limit` is given, decoding stops early after the chunk reaching
+    /// this number of appended bytes, without reading the rest of the input.
+    pub(crate) fn decompress_to_vec_inspect<R, F>(
+        &mut self,
+        input: &mut R,
+        output: &mut Vec<u8>,
+        limit: Option<usize>,
+        mut inspect: F,
+    ) -> error::Result<()>
+    where
+        R: io::BufRead,
+        F: FnMut(&[u8]),
+    {
+        let start = output.len();
+        self.decompress_chunks_to_vec(
+            input,
+            output,
+            |_| {},
+            |accum| {
+                accum.inspect_new(&mut inspect);
+                limit.map_or(false, |limit| accum.output_len() - start >= limit)
+            },
+        )
+    }
+
+    #[cfg(all(feature = "stream", feature = "xz"))]
+    /// Create a buffer for [`Lzma2Decoder::decompress_chunk()`], writing in
+    /// place into `buf`, which is cleared, within the memory limit of this
+    /// decoder.
+    pub(crate) fn chunk_buffer(&self, mut buf: Vec<u8>) -> lzbuffer::LzAccumBuffer<io::Sink> {
+        buf.clear();
+        lzbuffer::LzAccumBuffer::from_vec(buf, self.memlimit)
+    }
+
+    #[cfg(all(feature = "stream", feature = "xz"))]
+    /// Decodes the chunk at the start of `input`, appending its output to
+    /// `accum`, which holds the dictionary from one call to the next. Returns
+    /// whether this was the end-of-stream marker rather than a chunk.
+    pub(crate) fn decompress_chunk<R>(
+        &mut self,
+        accum: &mut lzbuffer::LzAccumBuffer<io::Sink>,
+        input: &mut R,
+    ) -> error::Result<bool>
+    where
+        R: io::BufRead,
+    {
+        let mut end = true;
+        self.decompress_chunks(
+            accum,
+            input,
+            |_| {},
+            |_| {
+                end = false;
+                true
+            },
+        )?;
+        Ok(end)
+    }
+
+    /// Same as [`Lzma2Decoder::decompress_with_chunk_entries()`] over the
+    /// whole stream in `data`, but a chunk that fails to decode is skipped,
+    /// along with the following ones, up to the next chunk resetting the
+    /// dictionary that decodes correctly. Each skipped part is appended to
+    /// `gaps`.
+    ///
+    /// This is best-effort recovery: the output of each part is buffered, so
+    /// that the partial output of a failed chunk is dropped. Finding the next
+    /// resume point trial-decodes the candidates, i.e. the bytes that could
+    /// start a chunk resetting the dictionary, up to [`RESYNC_TRIAL_BYTES`] of
+    /// LZMA data each. Skipping a gap of `n` bytes thus decodes at most about
+    /// `n * RESYNC_TRIAL_BYTES` bytes, though most candidates fail within a
+    /// few bytes.
+    pub(crate) fn decompress_resync<W, E>(
+        &mut self,
+        data: &[u8],
+        output: &mut W,
+        gaps: &mut Vec<Lzma2Gap>,
+        mut on_entry: E,
+    ) -> error::Result<()>
+    where
+        W: io::Write,
+        E: FnMut(&ChunkEntry),
+    {
+        let mut compressed_offset = 0;
+        let mut uncompressed_offset = 0;
+        let mut buf = Vec::new();
+        let mut scratch = Vec::new();
+        loop {
+            let mut input = &data[compressed_offset..];
+            // Chunk being decoded, if any
+            let current_chunk = Cell::new(None);
+            buf.clear();
+            let result = self.decompress_chunks_to_vec(
+                &mut input,
+                &mut buf,
+                |entry| {
+                    current_chunk.set(Some((entry.compressed_offset, entry.uncompressed_offset)));
+                    on_entry(&ChunkEntry {
+                        compressed_offset: (compressed_offset as u64) + entry.compressed_offset,
+                        uncompressed_offset: uncompressed_offset + entry.uncompressed_offset,
+                        ..*entry
+                    });
+                },
+                |_| {
+                    current_chunk.set(None);
+                    false
+                },
+            );
+            if result.is_ok() {
+                output.write_all(&buf)?;
+                return Ok(());
+            }
+
+            // Keep the chunks before the failed one. Failing between chunks
+            // means that the input ended before the end-of-stream marker, as
+            // every resume point resets the dictionary.
+            let (failed_compressed, failed_uncompressed) = current_chunk.get().unwrap_or((
+                (data.len() - compressed_offset - input.len()) as u64,
+                buf.len() as u64,
+            ));
+            output.write_all(&buf[..failed_uncompressed as usize])?;
+            let failed_offset = compressed_offset + failed_compressed as usize;
+            uncompressed_offset += failed_uncompressed;
+            let resume_offset = (failed_offset + 1..data.len())
+                .find(|&offset| self.can_resume_at(&data[offset..], &mut scratch))
+                .unwrap_or(data.len());
+            lzma_info!(
+                "LZMA2 skipping {} byte(s) after chunk at offset {}: {:?}",
+                resume_offset - failed_offset,
+                failed_offset,
+                result
+            );
+            gaps.push(Lzma2Gap {
+                compressed_offset: failed_offset as u64,
+                uncompressed_offset,
+                skipped: (resume_offset - failed_offset) as u64,
+            });
+            if resume_offset == data.len() {
+                return Ok(());
+            }
+            self.restart();
+            compressed_offset = resume_offset;
+        }
+    }
+
+    /// Whether `data` plausibly starts with a chunk resetting the dictionary,
+    /// i.e. whether this chunk decodes correctly along with the next one, if
+    /// any. Only the first [`RESYNC_TRIAL_BYTES`] of LZMA data are decoded,
+    /// after the data of an uncompressed first chunk, which is only copied.
+    /// The output goes to `scratch`, reused across candidates.
+    fn can_resume_at(&mut self, data: &[u8], scratch: &mut Vec<u8>) -> bool {
+        let status = data[0];
+        if status != 1 && status < 0xE0 {
+            return false;
+        }
+        let copied = match (status, data.get(1..3)) {
+            (1, Some(&[hi, lo])) => 3 + (u16::from_be_bytes([hi, lo]) as usize + 1),
+            _ => 0,
+        };
+        let trial_len = copied.saturating_add(RESYNC_TRIAL_BYTES);
+        self.restart();
+        let mut chunks = 0;
+        // Whether the chunk being decoded, if any, is uncompressed
+        let current_uncompressed = Cell::new(None);
+        let mut input = &data[..std::cmp::min(data.len(), trial_len)];
+        scratch.clear();
+        let result = self.decompress_chunks_to_vec(
+            &mut input,
+            scratch,
+            |entry| current_uncompressed.set(Some(entry.uncompressed)),
+            |_| {
+                current_uncompressed.set(None);
+                chunks += 1;
+                chunks == 2
+            },
+        );
+        // Running out of the trial input, rather than of `data`, means that
+        // everything decoded so far is correct, unless it ran out in the
+        // middle of uncompressed data, which isn't checked.
+        result.is_ok()
+            || (input.is_empty()
+                && data.len() > trial_len
+                && current_uncompressed.get() != Some(true))
+    }
+
+    /// Decodes chunks like [`Lzma2Decoder::decompress_chunks()`], appending
+    /// them to `output`.
+    fn decompress_chunks_to_vec<R, E, F>(
+        &mut self,
+        input: &mut R,
+        output: &mut Vec<u8>,
+        on_entry: E,
+        on_chunk: F,
+    ) -> error::Result<()>
+    where
+        R: io::BufRead,
+        E: FnMut(&ChunkEntry),
+        F: FnMut(&mut lzbuffer::LzAccumBuffer<io::Sink>) -> bool,
+    {
+        let mut accum = lzbuffer::LzAccumBuffer::from_vec(std::mem::take(output), self.memlimit);
+        let result = self.decompress_chunks(&mut accum, input, on_entry, on_chunk);
+        *output = accum.into_vec();
+        result
+    }
+
+    /// Decodes chunks up to the end-of-stream marker, or until `on_chunk`
+    /// returns true after a chunk. Each chunk is passed to `on_entry` before
+    /// being decoded.
+    fn decompress_chunks<W, R, E, F>(
+        &mut self,
+        accum: &mut lzbuffer::LzAccumBuffer<W>,
+        input: &mut R,
+        mut on_entry: E,
+        mut on_chunk: F,
+    ) -> error::Result<()>
+    where
+        W: io::Write,
+        R: io::BufRead,
+        E: FnMut(&ChunkEntry),
+        F: FnMut(&mut lzbuffer::LzAccumBuffer<W>) -> bool,
+    {
+        let mut chunk = 0usize;
+        let mut compressed_offset = 0u64;
+        let mut uncompressed_offset = 0u64;
         loop {
+            if self
+                .deadline
+                .map_or(false, |deadline| Instant::now() >= deadline)
+            {
+                return Err(error::Error::TimedOut);
+            }
             let status = input.read_u8().map_err(|e| {
-                error::Error::LzmaError(format!("LZMA2 expected new status: {}", e))
+                // Running out of input between two chunks means the stream
+                // was cut short rather than corrupted.
+                if e.kind() == io::ErrorKind::UnexpectedEof {
+                    error::Error::Truncated(String::from(
+                        "LZMA2 stream ended before the end-of-stream marker",
+                    ))
+                } else {
+                    error::Error::LzmaError(format!("LZMA2 expected new status: {}", e))
+                }
             })?;
 
             lzma_info!("LZMA2 status: {}", status);
@@ -66,18 +549,41 @@ impl Lzma2Decoder {
             if status == 0 {
                 lzma_info!("LZMA2 end of input");
                 break;
-            } else if status == 1 {
+            }
+            if self.need_dict_reset && (status == 2 || (0x80..0xE0).contains(&status)) {
+                return Err(error::Error::LzmaError(format!(
+                    "LZMA2 first chunk with status {} doesn't reset the dictionary",
+                    status
+                )));
+            }
+            self.need_dict_reset = false;
+            self.chunks += 1;
+            if self.chunks > self.max_chunks {
+                return Err(error::Error::TooManyChunks(self.chunks));
+            }
+            on_entry(&ChunkEntry::new(
+                status,
+                compressed_offset,
+                uncompressed_offset,
+            ));
+            let (packed_size, unpacked_size) = if status == 1 {
                 // uncompressed reset dict
-                Self::parse_uncompressed(&mut accum, input, true)?;
+                self.parse_uncompressed(accum, input, true, chunk)?
             } else if status == 2 {
                 // uncompressed no reset
-                Self::parse_uncompressed(&mut accum, input, false)?;
+                self.parse_uncompressed(accum, input, false, chunk)?
             } else {
-                self.parse_lzma(&mut accum, input, status)?;
+                self.parse_lzma(accum, input, status, chunk)?
+            };
+            compressed_offset += packed_size;
+            uncompressed_offset += unpacked_size;
+            chunk += 1;
+            if on_chunk(accum) {
+                lzma_info!("LZMA2 stopping early");
+                break;
             }
         }
 
-        accum.finish()?;
         Ok(())
     }
 
@@ -86,7 +592,8 @@ impl Lzma2Decoder {
         accum: &mut lzbuffer::LzAccumBuffer<W>,
         input: &mut R,
         status: u8,
-    ) -> error::Result<()>
+        chunk: usize,
+    ) -> error::Result<(u64, u64)>
     where
         R: io::BufRead,
         W: io::Write,
@@ -127,13 +634,13 @@ impl Lzma2Decoder {
 
         let unpacked_size = input
             .read_u16::<BigEndian>()
-            .map_err(|e| error::Error::LzmaError(format!("LZMA2 expected unpacked size: {}", e)))?;
-        let unpacked_size = ((((status & 0x1F) as u64) << 16) | (unpacked_size as u64)) + 1;
+            .map_err(|e| chunk_read_error(e, chunk, "unpacked size"))?;
+        let unpacked_size = chunk_size(status & 0x1F, unpacked_size)?;
 
         let packed_size = input
             .read_u16::<BigEndian>()
-            .map_err(|e| error::Error::LzmaError(format!("LZMA2 expected packed size: {}", e)))?;
-        let packed_size = (packed_size as u64) + 1;
+            .map_err(|e| chunk_read_error(e, chunk, "packed size"))?;
+        let packed_size = chunk_size(0, packed_size)?;
 
         lzma_info!(
             "LZMA2 compressed block {{ unpacked_size: {}, packed_size: {}, reset_dict: {}, reset_state: {}, reset_props: {} }}",
@@ -144,15 +651,22 @@ impl Lzma2Decoder {
             reset_props
         );
 
+        if !reset_props && !self.has_props && self.force_props.is_none() {
+            return Err(error::Error::LzmaError(String::from(
+                "LZMA2 missing initial properties",
+            )));
+        }
+
         if reset_dict {
             accum.reset()?;
         }
+        accum.reserve(unpacked_size as usize)?;
 
         if reset_state {
             let new_props = if reset_props {
-                let props = input.read_u8().map_err(|e| {
-                    error::Error::LzmaError(format!("LZMA2 expected new properties: {}", e))
-                })?;
+                let props = input
+                    .read_u8()
+                    .map_err(|e| chunk_read_error(e, chunk, "new properties"))?;
 
                 let mut pb = props as u32;
                 if pb >= 225 {
@@ -167,44 +681,97 @@ impl Lzma2Decoder {
                 let lp = pb % 5;
                 pb /= 5;
 
-                if lc + lp > 4 {
-                    return Err(error::Error::LzmaError(format!(
-                        "LZMA2 invalid properties: lc + lp ({} + {}) must be <= 4",
-                        lc, lp
-                    )));
-                }
-
+                let props = check_props(lc, lp, pb)?;
                 lzma_info!("Properties {{ lc: {}, lp: {}, pb: {} }}", lc, lp, pb);
-                LzmaProperties { lc, lp, pb }
+                self.has_props = true;
+                props
             } else {
                 self.lzma_state.lzma_props
             };
+            let new_props = match self.force_props {
+                Some(props) => {
+                    lzma_info!("Forcing properties {:?}", props);
+                    props
+                }
+                None => new_props,
+            };
 
             self.lzma_state.reset_state(new_props);
         }
 
-        self.lzma_state
-            .set_unpacked_size(Some(unpacked_size + accum.len() as u64));
+        let total_unpacked_size = unpacked_size
+            .checked_add(accum.len() as u64)
+            .ok_or_else(|| error::Error::LzmaError(String::from("size overflow")))?;
+        self.lzma_state.set_unpacked_size(Some(total_unpacked_size));
 
+        // Running out of input before the packed size means the stream was
+        // cut short rather than corrupted.
+        let truncated = || {
+            error::Error::Truncated(format!(
+                "LZMA2 chunk {} expected {} packed bytes",
+                chunk, packed_size
+            ))
+        };
         let mut taken = input.take(packed_size);
-        let mut rangecoder = rangecoder::RangeDecoder::new(&mut taken)
-            .map_err(|e| error::Error::LzmaError(format!("LZMA input too short: {}", e)))?;
-        self.lzma_state.process(accum, &mut rangecoder)
+        let mut rangecoder = rangecoder::RangeDecoder::new(&mut taken).map_err(|e| match e {
+            // The range coder starts with five bytes.
+            error::Error::IoError(e)
+                if e.kind() == io::ErrorKind::UnexpectedEof && packed_size >= 5 =>
+            {
+                truncated()
+            }
+            error::Error::IoError(e) => {
+                error::Error::LzmaError(format!("LZMA input too short: {}", e))
+            }
+            e => e,
+        })?;
+        let result = self.lzma_state.process(accum, &mut rangecoder);
+
+        // The chunk must consume exactly its packed size, neither leaving
+        // bytes behind nor running out of them.
+        match result {
+            Err(error::Error::IoError(e))
+                if e.kind() == io::ErrorKind::UnexpectedEof && taken.limit() != 0 =>
+            {
+                Err(truncated())
+            }
+            Err(error::Error::IoError(e))
+                if e.kind() == io::ErrorKind::UnexpectedEof && taken.limit() == 0 =>
+            {
+                Err(error::Error::LzmaError(String::from(
+                    "LZMA2 packed size mismatch",
+                )))
+            }
+            Ok(()) if taken.limit() != 0 => Err(error::Error::LzmaError(String::from(
+                "LZMA2 packed size mismatch",
+            ))),
+            // Report the sizes of the chunk rather than of the whole output.
+            Err(error::Error::SizeMismatch { actual, .. }) => Err(error::Error::SizeMismatch {
+                expected: unpacked_size,
+                actual: actual - (total_unpacked_size - unpacked_size),
+            }),
+            // The control byte, both sizes and the properties, if any,
+            // precede the packed data.
+            Ok(()) => Ok((5 + reset_props as u64 + packed_size, unpacked_size)),
+            Err(e) => Err(e),
+        }
     }
 
     fn parse_uncompressed<R, W>(
+        &mut self,
         accum: &mut lzbuffer::LzAccumBuffer<W>,
         input: &mut R,
         reset_dict: bool,
-    ) -> error::Result<()>
+        chunk: usize,
+    ) -> error::Result<(u64, u64)>
     where
         R: io::BufRead,
         W: io::Write,
     {
         let unpacked_size = input
             .read_u16::<BigEndian>()
-            .map_err(|e| error::Error::LzmaError(format!("LZMA2 expected unpacked size: {}", e)))?;
-        let unpacked_size = (unpacked_size as usize) + 1;
+            .map_err(|e| chunk_read_error(e, chunk, "unpacked size"))?;
+        let unpacked_size = chunk_size(0, unpacked_size)? as usize;
 
         lzma_info!(
             "LZMA2 uncompressed block {{ unpacked_size: {}, reset_dict: {} }}",
@@ -214,17 +781,60 @@ impl Lzma2Decoder {
 
         if reset_dict {
             accum.reset()?;
+            // The next LZMA chunk must set the properties and thus reset the
+            // state, so that no distance from before the reset is reused.
+            self.has_props = false;
         }
+        accum.reserve(unpacked_size)?;
 
-        let mut buf = vec![0; unpacked_size];
-        input.read_exact(buf.as_mut_slice()).map_err(|e| {
-            error::Error::LzmaError(format!(
-                "LZMA2 expected {} uncompressed bytes: {}",
-                unpacked_size, e
-            ))
-        })?;
-        accum.append_bytes(buf.as_slice());
+        // Copy straight from the input buffer, without an intermediate one.
+        let mut remaining = unpacked_size;
+        let what = || format!("{} uncompressed bytes", unpacked_size);
+        while remaining > 0 {
+            let buf = input
+                .fill_buf()
+                .map_err(|e| chunk_read_error(e, chunk, &what()))?;
+            if buf.is_empty() {
+                return Err(chunk_read_error(
+                    io::ErrorKind::UnexpectedEof.into(),
+                    chunk,
+                    &what(),
+                ));
+            }
+            let len = std::cmp::min(buf.len(), remaining);
+            accum.append_bytes(&buf[..len])?;
+            input.consume(len);
+            remaining -= len;
+        }
 
-        Ok(())
+        // The control byte and the size precede the data.
+        Ok((3 + unpacked_size as u64, unpacked_size as u64))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_chunk_size() {
+        assert_eq!(chunk_size(0, 0).unwrap(), 1);
+        assert_eq!(chunk_size(0, 0xFFFF).unwrap(), 0x1_0000);
+        assert_eq!(chunk_size(1, 0).unwrap(), 0x1_0001);
+        assert_eq!(chunk_size(0x1F, 0xFFFF).unwrap(), 0x20_0000);
+        // A control byte only holds 5 bits of the unpacked size.
+        assert!(chunk_size(0x20, 0).is_err());
+        assert!(chunk_size(0xFF, 0xFFFF).is_err());
+    }
+
+    #[test]
+    fn test_dict_size_from_props() {
+        assert_eq!(dict_size_from_props(0).unwrap(), 4096);
+        assert_eq!(dict_size_from_props(1).unwrap(), 6144);
+        assert_eq!(dict_size_from_props(22).unwrap(), 0x0080_0000);
+        assert_eq!(dict_size_from_props(39).unwrap(), 0xC000_0000);
+        assert_eq!(dict_size_from_props(40).unwrap(), 0xFFFF_FFFF);
+        assert!(dict_size_from_props(41).is_err());
+        assert!(dict_size_from_props(0xFF).is_err());
     }
 }
@@ -0,0 +1,202 @@
+//! Decoding `.lzma` data through the [`Read`] interface.
+
+use crate::decode::lzbuffer::{LzBuffer, LzCircularBuffer};
+use crate::decode::lzma::{self, DecoderState, LzmaParams};
+use crate::decode::options::Options;
+use crate::decode::rangecoder::RangeDecoder;
+use crate::decode::util;
+use crate::error::{self, Error};
+use std::fmt::Debug;
+use std::io::{self, BufRead, Read};
+
+/// LZMA decompressor providing the decompressed data of an `.lzma` stream
+/// through the [`Read`] interface.
+///
+/// The stream may have been compressed with a preset dictionary, see
+/// [`LzmaReader::with_dict()`]. It is meant for small messages: the whole
+/// stream is decoded upon the first read, and the following reads return the
+/// buffered data.
+#[cfg_attr(
+    feature = "raw_decoder",
+    doc = "",
+    doc = "Raw LZMA data without a header, whose parameters are known out of band,",
+    doc = "can be read as well with [`LzmaReader::new_raw()`]."
+)]
+pub struct LzmaReader<R>
+where
+    R: BufRead,
+{
+    /// Underlying reader
+    inner: R,
+    /// Preset dictionary, until the stream is decoded
+    dict: Vec<u8>,
+    /// Options given when the reader is created
+    options: Options,
+    /// Parameters of a raw stream, or `None` to read them from the header
+    params: Option<LzmaParams>,
+    /// Decompressed data, once the stream is decoded
+    data: Option<Vec<u8>>,
+    /// Position of the next byte to read in `data`
+    pos: usize,
+    /// Number of bytes of the stream consumed from `inner`
+    consumed: u64,
+}
+
+impl<R> LzmaReader<R>
+where
+    R: BufRead,
+{
+    /// Decode the stream of `inner`.
+    pub fn new(inner: R) -> Self {
+        Self::with_dict(inner, Vec::new())
+    }
+
+    /// Decode the stream of `inner`, which was compressed with the preset
+    /// dictionary `dict`.
+    ///
+    /// The dictionary serves as history for the LZ sequences of the stream
+    /// but isn't part of the decompressed data. Only its last `dict_size`
+    /// bytes, as given in the header, are used.
+    pub fn with_dict(inner: R, dict: Vec<u8>) -> Self {
+        Self::with_options(inner, dict, &Options::default())
+    }
+
+    /// Decode the stream of `inner`, which was compressed with the preset
+    /// dictionary `dict`, with the provided options. Only
+    /// [`unpacked_size`](Options::unpacked_size) and
+    /// [`memlimit`](Options::memlimit) apply.
+    pub fn with_options(inner: R, dict: Vec<u8>, options: &Options) -> Self {
+        Self {
+            inner,
+            dict,
+            options: *options,
+            params: None,
+            data: None,
+            pos: 0,
+            consumed: 0,
+        }
+    }
+
+    /// Decode the raw LZMA stream of `inner`, which has no header, with the
+    /// parameters `params` provided by the caller, e.g. from the framing of
+    /// a protocol.
+    ///
+    /// The stream ends with an end-of-payload marker unless the parameters
+    /// give its unpacked size.
+    #[cfg(feature = "raw_decoder")]
+    #[cfg_attr(docsrs, doc(cfg(raw_decoder)))]
+    pub fn new_raw(inner: R, params: LzmaParams) -> Self {
+        let mut reader = Self::new(inner);
+        reader.params = Some(params);
+        reader
+    }
+
+    /// Number of compressed bytes consumed from the underlying reader so far,
+    /// e.g. to resume transferring the input after the stream.
+    ///
+    /// This is zero until the first read, which decodes the whole stream.
+    /// Only the bytes of the stream are counted, not those the underlying
+    /// reader may have buffered past its end.
+    pub fn compressed_bytes_consumed(&self) -> u64 {
+        self.consumed
+    }
+
+    /// Unwraps this `LzmaReader`, returning the underlying reader.
+    pub fn into_inner(self) -> R {
+        self.inner
+    }
+
+    /// Decode the whole stream.
+    fn decode(&mut self) -> error::Result<Vec<u8>> {
+        let mut input = util::CountBufRead::new(&mut self.inner);
+        let result = decode(&mut input, &self.dict, self.params, &self.options);
+        self.consumed = input.count() as u64;
+        result
+    }
+}
+
+/// Decode the whole stream of `input`, compressed with the preset dictionary
+/// `dict`, with the parameters `params` or those of its header.
+fn decode<R>(
+    input: &mut R,
+    dict: &[u8],
+    params: Option<LzmaParams>,
+    options: &Options,
+) -> error::Result<Vec<u8>>
+where
+    R: BufRead,
+{
+    let params = match params {
+        Some(params) => params,
+        None => LzmaParams::read_header(input, options)?,
+    };
+    let mut output = LzCircularBuffer::from_stream_with_memory(
+        Vec::new(),
+        Vec::new(),
+        params.dict_size as usize,
+        options.memlimit.unwrap_or(usize::MAX),
+    );
+    output.preset_dict(dict)?;
+    // The positions in the stream count the preset dictionary.
+    let preset_len = output.len() as u64;
+    let mut state: DecoderState = DecoderState::new(
+        params.properties,
+        params
+            .unpacked_size
+            .map(|size| size.saturating_add(preset_len)),
+    );
+
+    let mut rangecoder = RangeDecoder::new(input).map_err(|e| match e {
+        Error::IoError(e) => Error::LzmaError(format!("LZMA stream too short: {}", e)),
+        e => e,
+    })?;
+    state
+        .process(&mut output, &mut rangecoder)
+        .map_err(|e| lzma::check_truncated(e, &mut rangecoder, output.len() - preset_len as usize))
+        .map_err(|e| match e {
+            Error::SizeMismatch { expected, actual } => Error::SizeMismatch {
+                expected: expected - preset_len,
+                actual: actual - preset_len,
+            },
+            e => e,
+        })?;
+    Ok(output.finish()?)
+}
+
+impl<R> Read for LzmaReader<R>
+where
+    R: BufRead,
+{
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let data = match self.data {
+            Some(ref data) => data,
+            None => {
+                let data = self.decode().map_err(util::into_io_error)?;
+                self.dict = Vec::new();
+                self.data.insert(data)
+            }
+        };
+
+        let len = std::cmp::min(buf.len(), data.len() - self.pos);
+        buf[..len].copy_from_slice(&data[self.pos..self.pos + len]);
+        self.pos += len;
+        Ok(len)
+    }
+}
+
+impl<R> Debug for LzmaReader<R>
+where
+    R: BufRead + Debug,
+{
+    fn fmt(&self, fmt: &mut std::fmt::Formatter) -> std::fmt::Result {
+        fmt.debug_struct("LzmaReader")
+            .field("inner", &self.inner)
+            .field("dict", &self.dict.len())
+            .field("options", &self.options)
+            .field("params", &self.params)
+            .field("decoded", &self.data.as_ref().map(Vec::len))
+            .field("pos", &self.pos)
+            .field("consumed", &self.consumed)
+            .finish()
+    }
+}
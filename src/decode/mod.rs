@@ -1,12 +1,26 @@
 //! Decoding logic.
 
+pub mod aligned;
+pub mod chain;
+pub mod format;
+pub mod frame;
+pub mod limit;
 pub mod lzbuffer;
 pub mod lzma;
 pub mod lzma2;
+pub mod lzma_reader;
 pub mod options;
+pub mod progress;
 pub mod rangecoder;
 pub mod util;
+#[cfg(feature = "xz")]
 pub mod xz;
+#[cfg(feature = "xz")]
+pub mod xz_list;
+#[cfg(feature = "xz")]
+pub mod xz_seek;
 
 #[cfg(feature = "stream")]
 pub mod stream;
+#[cfg(all(feature = "stream", feature = "xz"))]
+pub mod xz_stream;
@@ -1,3 +1,8 @@
+use crate::decode::lzma2;
+use crate::error;
+use std::fmt;
+use std::time::Instant;
+
 /// Options to tweak decompression behavior.
 #[derive(Clone, Copy, Debug, PartialEq, Eq, Default)]
 pub struct Options {
@@ -9,6 +14,10 @@ pub struct Options {
     /// Defines whether the dictionary's dynamic size should be limited during
     /// decompression.
     ///
+    /// For raw LZMA2 and XZ, this limits the buffer of the data decoded since
+    /// the last dictionary reset, i.e. of a whole XZ block, as the decoder
+    /// keeps all of it.
+    ///
     /// The default is unlimited.
     pub memlimit: Option<usize>,
     /// Determines whether to bypass end of stream validation.
@@ -17,6 +26,342 @@ pub struct Options {
     ///
     /// The default is false (always do completion check).
     pub allow_incomplete: bool,
+    /// Determines whether to recover the complete blocks of a truncated or
+    /// damaged stream, instead of failing.
+    ///
+    /// This option only applies to the `xz_decompress_with_options` function,
+    /// which then reports the truncation in its
+    /// [`XzStatus`](crate::decompress::XzStatus). Each recovered block is
+    /// still validated against its check. Only errors reading or parsing the
+    /// input are salvaged: failures of the output and the limits set by other
+    /// options are still returned.
+    ///
+    /// The default is false.
+    pub salvage: bool,
+    /// Determines whether to decode further `.lzma` members concatenated
+    /// after the first one, with the same options.
+    ///
+    /// This option only applies to the `lzma_decompress_with_options`
+    /// function. As the `.lzma` format has no end magic, decoding only
+    /// continues after a member of known unpacked size, if more input
+    /// remains. A member ending with an end-of-payload marker must be the
+    /// last one.
+    ///
+    /// The default is false.
+    pub concatenated: bool,
+    /// Number of decompressed bytes after which to stop decoding, e.g. to
+    /// preview the start of a large stream.
+    ///
+    /// This option only applies to the `lzma_decompress_with_options` and
+    /// `xz_decompress_with_options` functions. Once the limit is reached,
+    /// decoding stops successfully with exactly this number of bytes
+    /// written, and the rest of the input is neither decoded nor validated.
+    /// Unlike with a [`LimitedWriter`](crate::decompress::LimitedWriter),
+    /// reaching the limit isn't an error.
+    ///
+    /// The default is unlimited.
+    pub limit: Option<u64>,
+    /// Determines whether to tolerate deviations from the spec that don't
+    /// prevent decoding, such as padding that isn't made of null bytes or
+    /// reserved bits that are set, instead of failing.
+    ///
+    /// This option only applies to the `xz_decompress_with_options` function,
+    /// which then reports each deviation as a
+    /// [`Warning`](crate::decompress::Warning) in its
+    /// [`XzStatus`](crate::decompress::XzStatus). Checks and CRCs are still
+    /// validated, except for block checks of an unsupported type, e.g.
+    /// SHA-256, which are skipped.
+    ///
+    /// The default is false (strict).
+    pub lenient: bool,
+    /// Maximum number of records, i.e. of blocks, in an XZ index, to reject
+    /// early the streams declaring an implausible number of them.
+    ///
+    /// This option applies to the `xz_decompress_with_options` function and
+    /// to [`XzSeekReader::with_options`](crate::decompress::XzSeekReader::with_options),
+    /// which fail with
+    /// [`Error::IndexTooLarge`](crate::error::Error::IndexTooLarge)
+    /// beyond this number. The seek reader also never accepts more records
+    /// than the index could hold given its size.
+    ///
+    /// The default is unlimited.
+    pub max_index_records: Option<u64>,
+    /// LZMA properties `(lc, lp, pb)` to use on every state reset of LZMA2
+    /// chunks, instead of those found in the stream.
+    ///
+    /// **This is a diagnostic aid** to examine how a damaged stream decodes
+    /// under a given interpretation. It's not meant for normal use, as it
+    /// makes valid streams decode to garbage or fail. The properties must
+    /// satisfy `lc + lp <= 4` and `pb <= 4`.
+    ///
+    /// This option only applies to the `lzma2_decompress_with_options` and
+    /// `xz_decompress_with_options` functions.
+    ///
+    /// The default is [`None`], using the properties of the stream.
+    pub force_props: Option<(u32, u32, u32)>,
+    /// Exact size of the decompressed data, known out of band, e.g. from a
+    /// manifest. This is checked in addition to any size found in the
+    /// stream, including for `.lzma` streams of unknown size.
+    ///
+    /// This option applies to the `lzma_decompress_with_options`,
+    /// `lzma2_decompress_with_options` and `xz_decompress_with_options`
+    /// functions, which fail with
+    /// [`Error::SizeMismatch`](crate::error::Error::SizeMismatch) as soon as
+    /// the output exceeds this size, or if it's shorter at the end. Only the
+    /// excess is detected when decoding stops at the [`limit`](Options::limit).
+    ///
+    /// The default is [`None`], which checks nothing.
+    pub expected_size: Option<u64>,
+    /// Maximum size of the decompressed data of any single XZ block, e.g. to
+    /// keep the memory used per block bounded with untrusted multi-block
+    /// files. This is checked independently of the total output.
+    ///
+    /// This option applies to the `xz_decompress_with_options` function and
+    /// to [`XzSeekReader::with_options`](crate::decompress::XzSeekReader::with_options),
+    /// which fail on a block larger than this. Blocks declaring a larger size
+    /// in their header are rejected before being decoded, and the others stop
+    /// decoding within one LZMA2 chunk past the limit.
+    ///
+    /// The default is unlimited.
+    pub max_block_output: Option<u64>,
+    /// Whether to record the map of the chunks of a raw LZMA2 stream, e.g.
+    /// to build a seek index: decoding can later start at any chunk that
+    /// resets the dictionary.
+    ///
+    /// This option only applies to the `lzma2_decompress_with_status`
+    /// function, which then lists each chunk in the
+    /// [`chunks`](crate::decompress::Lzma2Status::chunks) of its status.
+    ///
+    /// The default is false.
+    pub record_chunk_map: bool,
+    /// Digest of the decompressed data, known out of band, e.g. from a
+    /// manifest or a sidecar file. This is checked in addition to, and
+    /// independently of, any check found in the stream.
+    ///
+    /// This option applies to the `lzma_decompress_with_options`,
+    /// `lzma2_decompress_with_options` and `xz_decompress_with_options`
+    /// functions, which fail with
+    /// [`Error::ChecksumMismatch`](crate::error::Error::ChecksumMismatch) at
+    /// the end if the digest of the whole output differs. Nothing is checked
+    /// when decoding stops at the [`limit`](Options::limit).
+    ///
+    /// The default is [`None`], which checks nothing.
+    pub expected_digest: Option<Digest>,
+    /// Determines whether to skip the damaged parts of a raw LZMA2 stream,
+    /// instead of failing, for best-effort recovery. Raw LZMA2 has no checks,
+    /// so damage is only detected when a chunk fails to decode.
+    ///
+    /// Decoding then resumes at the next chunk that resets the dictionary and
+    /// that decodes correctly along with the chunk after it, the only ones
+    /// trusted as resume points. Only the first 4 KiB of LZMA data of each
+    /// candidate are checked, so skipping a damaged part costs up to 4 KiB
+    /// of decoding per byte. The rest of the input is read into memory
+    /// first, including what follows the stream, and the output of the
+    /// failed chunk is dropped.
+    ///
+    /// This option only applies to the `lzma2_decompress_with_options`,
+    /// `lzma2_decompress_with_status` and `lzma2_decompress_with_resets`
+    /// functions, which report each skipped part in the
+    /// [`gaps`](crate::decompress::Lzma2Status::gaps) of their status.
+    ///
+    /// The default is false.
+    pub resync: bool,
+    /// Number of decompressed bytes after which the streaming decoder sends
+    /// its pending output to the sink and flushes it, to trade throughput for
+    /// latency, e.g. for network sinks. Output is otherwise only written when
+    /// the dictionary window wraps around, and flushed at the end.
+    ///
+    /// This option only applies to the `stream` API, whose `Stream::finish`
+    /// always flushes the sink. The sink is flushed at most once per call to
+    /// `write`.
+    ///
+    /// The default is [`None`], which only flushes at the end.
+    pub flush_interval_bytes: Option<u64>,
+    /// Point in time after which decoding fails with
+    /// [`Error::TimedOut`](crate::error::Error::TimedOut), e.g. to bound the
+    /// time spent on a request without a separate thread to cancel it.
+    ///
+    /// The deadline is checked cheaply, before each LZMA2 chunk, so decoding
+    /// may overrun it by the time of one chunk, of at most 2 MiB of output.
+    /// This option applies to the `lzma2_decompress_with_options`,
+    /// `lzma2_decompress_with_status`, `lzma2_decompress_with_resets` and
+    /// `xz_decompress_with_options` functions. `.lzma` data isn't split in
+    /// chunks, so the deadline doesn't apply to it.
+    ///
+    /// The default is [`None`], which never times out.
+    pub deadline: Option<Instant>,
+    /// Maximum number of LZMA2 chunks to decode, to reject early the streams
+    /// made of an implausible number of tiny chunks, each of which costs a
+    /// header and possibly a state reset.
+    ///
+    /// The chunks of all the blocks of an XZ stream are counted together.
+    /// This option applies to the same functions as
+    /// [`deadline`](Options::deadline), which fail with
+    /// [`Error::TooManyChunks`](crate::error::Error::TooManyChunks) beyond
+    /// this number.
+    ///
+    /// The default is unlimited.
+    pub max_chunks: Option<u64>,
+    /// Dictionary size to decode `.lzma` streams with, when it's smaller
+    /// than the one declared by their header, e.g. to decode within less
+    /// memory the streams of encoders that declare a larger dictionary than
+    /// they use. Like the declared size, it's rounded up to 4 KiB.
+    ///
+    /// This option only applies to the `lzma_decompress_with_options`
+    /// function. A stream referring to data further back than this window,
+    /// while still within the declared one, fails with
+    /// [`Error::DistanceBeyondOverride`](crate::error::Error::DistanceBeyondOverride)
+    /// rather than decoding garbage. A larger size than the declared one is
+    /// ignored.
+    ///
+    /// The default is to use the declared size.
+    pub override_dict_size: Option<u64>,
+}
+
+impl Options {
+    /// Start building options, with the defaults for the settings that
+    /// aren't set.
+    pub fn builder() -> OptionsBuilder {
+        OptionsBuilder::default()
+    }
+}
+
+/// Builder for [`Options`], see [`Options::builder`].
+#[derive(Clone, Copy, Debug, Default)]
+#[must_use]
+pub struct OptionsBuilder {
+    options: Options,
+}
+
+impl OptionsBuilder {
+    /// Set [`Options::unpacked_size`].
+    pub fn unpacked_size(mut self, unpacked_size: UnpackedSize) -> Self {
+        self.options.unpacked_size = unpacked_size;
+        self
+    }
+
+    /// Set [`Options::memlimit`].
+    pub fn memlimit(mut self, memlimit: usize) -> Self {
+        self.options.memlimit = Some(memlimit);
+        self
+    }
+
+    /// Set [`Options::allow_incomplete`].
+    pub fn allow_incomplete(mut self, allow_incomplete: bool) -> Self {
+        self.options.allow_incomplete = allow_incomplete;
+        self
+    }
+
+    /// Set [`Options::salvage`].
+    pub fn salvage(mut self, salvage: bool) -> Self {
+        self.options.salvage = salvage;
+        self
+    }
+
+    /// Set [`Options::concatenated`].
+    pub fn concatenated(mut self, concatenated: bool) -> Self {
+        self.options.concatenated = concatenated;
+        self
+    }
+
+    /// Set [`Options::limit`].
+    pub fn limit(mut self, limit: u64) -> Self {
+        self.options.limit = Some(limit);
+        self
+    }
+
+    /// Set [`Options::lenient`].
+    pub fn lenient(mut self, lenient: bool) -> Self {
+        self.options.lenient = lenient;
+        self
+    }
+
+    /// Set [`Options::max_index_records`].
+    pub fn max_index_records(mut self, max_index_records: u64) -> Self {
+        self.options.max_index_records = Some(max_index_records);
+        self
+    }
+
+    /// Set [`Options::force_props`], a diagnostic aid not meant for normal
+    /// use.
+    pub fn force_props(mut self, lc: u32, lp: u32, pb: u32) -> Self {
+        self.options.force_props = Some((lc, lp, pb));
+        self
+    }
+
+    /// Set [`Options::expected_size`].
+    pub fn expected_size(mut self, expected_size: u64) -> Self {
+        self.options.expected_size = Some(expected_size);
+        self
+    }
+
+    /// Set [`Options::max_block_output`].
+    pub fn max_block_output(mut self, max_block_output: u64) -> Self {
+        self.options.max_block_output = Some(max_block_output);
+        self
+    }
+
+    /// Set [`Options::record_chunk_map`].
+    pub fn record_chunk_map(mut self, record_chunk_map: bool) -> Self {
+        self.options.record_chunk_map = record_chunk_map;
+        self
+    }
+
+    /// Set [`Options::expected_digest`].
+    pub fn expected_digest(mut self, expected_digest: Digest) -> Self {
+        self.options.expected_digest = Some(expected_digest);
+        self
+    }
+
+    /// Set [`Options::resync`].
+    pub fn resync(mut self, resync: bool) -> Self {
+        self.options.resync = resync;
+        self
+    }
+
+    /// Set [`Options::flush_interval_bytes`].
+    pub fn flush_interval_bytes(mut self, flush_interval_bytes: u64) -> Self {
+        self.options.flush_interval_bytes = Some(flush_interval_bytes);
+        self
+    }
+
+    /// Set [`Options::deadline`].
+    pub fn deadline(mut self, deadline: Instant) -> Self {
+        self.options.deadline = Some(deadline);
+        self
+    }
+
+    /// Set [`Options::max_chunks`].
+    pub fn max_chunks(mut self, max_chunks: u64) -> Self {
+        self.options.max_chunks = Some(max_chunks);
+        self
+    }
+
+    /// Set [`Options::override_dict_size`].
+    pub fn override_dict_size(mut self, override_dict_size: u64) -> Self {
+        self.options.override_dict_size = Some(override_dict_size);
+        self
+    }
+
+    /// Check the settings and build the options.
+    ///
+    /// Fails if [`concatenated`](Options::concatenated) members don't read
+    /// their unpacked size from their header, as a provided size can't apply
+    /// to all of them, or if the [`forced properties`](Options::force_props)
+    /// are invalid.
+    pub fn build(self) -> error::Result<Options> {
+        let options = self.options;
+        if let Some((lc, lp, pb)) = options.force_props {
+            lzma2::check_props(lc, lp, pb)?;
+        }
+        if options.concatenated && options.unpacked_size != UnpackedSize::ReadFromHeader {
+            return Err(error::Error::LzmaError(format!(
+                "Concatenated members must read their unpacked size from the header, not {:?}",
+                options.unpacked_size
+            )));
+        }
+        Ok(options)
+    }
 }
 
 /// Alternatives for defining the unpacked size of the decoded data.
@@ -42,6 +387,24 @@ pub enum UnpackedSize {
     UseProvided(Option<u64>),
 }
 
+/// Digest of some data, see [`Options::expected_digest`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Digest {
+    /// CRC32 (IEEE 802.3), as in the `.xz` and gzip formats.
+    Crc32(u32),
+    /// CRC64 (ECMA-182), as in the `.xz` format.
+    Crc64(u64),
+}
+
+impl fmt::Display for Digest {
+    fn fmt(&self, fmt: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Digest::Crc32(crc) => write!(fmt, "CRC32 {:#010x}", crc),
+            Digest::Crc64(crc) => write!(fmt, "CRC64 {:#018x}", crc),
+        }
+    }
+}
+
 #[cfg(test)]
 mod test {
     use super::*;
@@ -53,8 +416,60 @@ mod test {
                 unpacked_size: UnpackedSize::ReadFromHeader,
                 memlimit: None,
                 allow_incomplete: false,
+                salvage: false,
+                concatenated: false,
+                limit: None,
+                lenient: false,
+                max_index_records: None,
+                force_props: None,
+                expected_size: None,
+                max_block_output: None,
+                record_chunk_map: false,
+                expected_digest: None,
+                resync: false,
+                flush_interval_bytes: None,
+                deadline: None,
+                max_chunks: None,
+                override_dict_size: None,
             },
             Options::default()
         );
     }
+
+    #[test]
+    fn test_builder() {
+        assert_eq!(Options::builder().build().unwrap(), Options::default());
+
+        let options = Options::builder()
+            .memlimit(0x10000)
+            .lenient(true)
+            .limit(42)
+            .build()
+            .unwrap();
+        assert_eq!(
+            options,
+            Options {
+                memlimit: Some(0x10000),
+                lenient: true,
+                limit: Some(42),
+                ..Default::default()
+            }
+        );
+        // Settings left unset keep their defaults.
+        assert_eq!(options.unpacked_size, UnpackedSize::ReadFromHeader);
+        assert!(!options.salvage);
+        assert!(!options.concatenated);
+
+        assert!(Options::builder()
+            .concatenated(true)
+            .unpacked_size(UnpackedSize::UseProvided(Some(42)))
+            .build()
+            .is_err());
+        assert!(Options::builder()
+            .concatenated(true)
+            .unpacked_size(UnpackedSize::ReadFromHeader)
+            .build()
+            .is_ok());
+        assert!(Options::builder().force_props(4, 1, 0).build().is_err());
+    }
 }
@@ -0,0 +1,192 @@
+use std::{fmt, io};
+
+/// Progress of reading compressed input, see [`ProgressReader`].
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Progress {
+    /// Number of compressed bytes consumed so far.
+    pub consumed: u64,
+    /// Fraction of the input consumed so far, between 0 and 1, if the length
+    /// of the input is known.
+    pub fraction: Option<f64>,
+}
+
+/// An [`io::BufRead`] reporting how much of the compressed input the
+/// decoders consumed, e.g. to show progress for streams whose decompressed
+/// size is unknown.
+///
+/// The callback is called each time another `interval` bytes are consumed,
+/// 64 KiB by default, and once more when the input ends or when `input_len`
+/// bytes are consumed, so that the last fraction reported for a complete
+/// input is 1.
+pub struct ProgressReader<R, F>
+where
+    F: FnMut(Progress),
+{
+    /// Underlying reader
+    inner: R,
+    /// Callback receiving the progress
+    on_progress: F,
+    /// Length of the input, if known
+    input_len: Option<u64>,
+    /// Number of bytes consumed
+    consumed: u64,
+    /// Number of bytes between two reports
+    interval: u64,
+    /// Number of consumed bytes at which to report next
+    next_report: u64,
+    /// Whether the end of the input was reported
+    done: bool,
+}
+
+impl<R, F> ProgressReader<R, F>
+where
+    F: FnMut(Progress),
+{
+    /// Default number of bytes between two reports.
+    const DEFAULT_INTERVAL: u64 = 0x10000;
+
+    /// Wraps `inner`, whose length is `input_len` if known, e.g. the size of
+    /// a file, passing the progress to `on_progress`.
+    pub fn new(inner: R, input_len: Option<u64>, on_progress: F) -> Self {
+        Self {
+            inner,
+            on_progress,
+            input_len,
+            consumed: 0,
+            interval: Self::DEFAULT_INTERVAL,
+            next_report: Self::DEFAULT_INTERVAL,
+            done: false,
+        }
+    }
+
+    /// Report the progress every `interval` bytes instead of every 64 KiB.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `interval` is zero.
+    pub fn with_interval(mut self, interval: u64) -> Self {
+        assert!(interval > 0, "progress interval must be non-zero");
+        self.interval = interval;
+        self.next_report = self.consumed + interval;
+        self
+    }
+
+    /// Number of bytes consumed so far.
+    pub fn consumed(&self) -> u64 {
+        self.consumed
+    }
+
+    /// Unwraps this `ProgressReader`, returning the underlying reader.
+    pub fn into_inner(self) -> R {
+        self.inner
+    }
+
+    fn advance(&mut self, amt: usize) {
+        self.consumed += amt as u64;
+        let complete = self.input_len.map_or(false, |len| self.consumed >= len);
+        if complete {
+            self.report_end();
+        } else if self.consumed >= self.next_report {
+            self.report();
+            self.next_report = self.consumed + self.interval;
+        }
+    }
+
+    fn report_end(&mut self) {
+        if !self.done {
+            self.done = true;
+            self.report();
+        }
+    }
+
+    fn report(&mut self) {
+        let consumed = self.consumed;
+        let fraction = self.input_len.map(|len| match len {
+            0 => 1.0,
+            len => f64::min(consumed as f64 / len as f64, 1.0),
+        });
+        (self.on_progress)(Progress { consumed, fraction });
+    }
+}
+
+impl<R, F> io::Read for ProgressReader<R, F>
+where
+    R: io::BufRead,
+    F: FnMut(Progress),
+{
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let result = self.inner.read(buf)?;
+        if result == 0 && !buf.is_empty() {
+            self.report_end();
+        }
+        self.advance(result);
+        Ok(result)
+    }
+}
+
+impl<R, F> io::BufRead for ProgressReader<R, F>
+where
+    R: io::BufRead,
+    F: FnMut(Progress),
+{
+    fn fill_buf(&mut self) -> io::Result<&[u8]> {
+        if self.inner.fill_buf()?.is_empty() {
+            self.report_end();
+        }
+        self.inner.fill_buf()
+    }
+
+    fn consume(&mut self, amt: usize) {
+        self.inner.consume(amt);
+        self.advance(amt);
+    }
+}
+
+impl<R, F> fmt::Debug for ProgressReader<R, F>
+where
+    F: FnMut(Progress),
+{
+    fn fmt(&self, fmt: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt.debug_struct("ProgressReader")
+            .field("input_len", &self.input_len)
+            .field("consumed", &self.consumed)
+            .field("interval", &self.interval)
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::io::{BufRead, Read};
+
+    #[test]
+    fn test_progress_reader() {
+        let data = [0; 10];
+        let mut reports = Vec::new();
+        let mut reader =
+            ProgressReader::new(&data[..], Some(10), |p| reports.push(p.consumed)).with_interval(4);
+        let mut buf = [0; 3];
+        reader.read_exact(&mut buf).unwrap();
+        reader.read_exact(&mut buf).unwrap();
+        reader.consume(3);
+        assert_eq!(reader.consumed(), 9);
+        assert_eq!(reader.fill_buf().unwrap().len(), 1);
+        reader.consume(1);
+        // The end is only reported once.
+        assert!(reader.fill_buf().unwrap().is_empty());
+        assert_eq!(reports, [6, 10]);
+
+        // Without a length, the end is reported when the input ends.
+        let mut reports = Vec::new();
+        let mut reader = ProgressReader::new(&data[..], None, |p| reports.push(p));
+        reader.read_to_end(&mut Vec::new()).unwrap();
+        assert_eq!(
+            reports,
+            [Progress {
+                consumed: 10,
+                fraction: None
+            }]
+        );
+    }
+}
@@ -2,7 +2,47 @@ use crate::decode::util;
 use crate::error;
 use crate::util::const_assert;
 use byteorder::{BigEndian, ReadBytesExt};
-use std::io;
+use std::{fmt, io};
+
+/// Storage of the 11-bit probabilities of the range decoder, [`u16`] by
+/// default. Wider types may suit alignment or vectorization better, and
+/// decode identically.
+pub(crate) trait Prob: Copy + fmt::Debug {
+    /// Initial probability, of one half.
+    const HALF: Self;
+
+    fn get(self) -> u32;
+
+    fn set(&mut self, prob: u32);
+}
+
+impl Prob for u16 {
+    const HALF: Self = 0x400;
+
+    #[inline]
+    fn get(self) -> u32 {
+        self as u32
+    }
+
+    #[inline]
+    fn set(&mut self, prob: u32) {
+        *self = prob as u16;
+    }
+}
+
+impl Prob for u32 {
+    const HALF: Self = 0x400;
+
+    #[inline]
+    fn get(self) -> u32 {
+        self
+    }
+
+    #[inline]
+    fn set(&mut self, prob: u32) {
+        *self = prob;
+    }
+}
 
 pub struct RangeDecoder<'a, R>
 where
@@ -17,13 +57,22 @@ impl<'a, R> RangeDecoder<'a, R>
 where
     R: io::BufRead,
 {
-    pub fn new(stream: &'a mut R) -> io::Result<Self> {
+    /// Initialize the range coder from the first 5 bytes of `stream`.
+    ///
+    /// The stream being too short is reported as [`error::Error::IoError`],
+    /// so that callers can tell it apart from an invalid start.
+    pub fn new(stream: &'a mut R) -> error::Result<Self> {
         let mut dec = Self {
             stream,
             range: 0xFFFF_FFFF,
             code: 0,
         };
-        let _ = dec.stream.read_u8()?;
+        // The encoder always emits a null byte first.
+        if dec.stream.read_u8()? != 0 {
+            return Err(error::Error::LzmaError(
+                "invalid range coder start byte".to_string(),
+            ));
+        }
         dec.code = dec.stream.read_u32::<BigEndian>()?;
         lzma_debug!("0 {{ range: {:08x}, code: {:08x} }}", dec.range, dec.code);
         Ok(dec)
@@ -90,18 +139,19 @@ where
     }
 
     #[inline]
-    pub fn decode_bit(&mut self, prob: &mut u16, update: bool) -> io::Result<bool> {
-        let bound: u32 = (self.range >> 11) * (*prob as u32);
+    pub fn decode_bit<P: Prob>(&mut self, prob: &mut P, update: bool) -> io::Result<bool> {
+        let p = prob.get();
+        let bound: u32 = (self.range >> 11) * p;
 
         lzma_trace!(
             " bound: {:08x}, prob: {:04x}, bit: {}",
             bound,
-            prob,
+            p,
             (self.code > bound) as u8
         );
         if self.code < bound {
             if update {
-                *prob += (0x800_u16 - *prob) >> 5;
+                prob.set(p + ((0x800 - p) >> 5));
             }
             self.range = bound;
 
@@ -109,7 +159,7 @@ where
             Ok(false)
         } else {
             if update {
-                *prob -= *prob >> 5;
+                prob.set(p - (p >> 5));
             }
             self.code -= bound;
             self.range -= bound;
@@ -119,10 +169,10 @@ where
         }
     }
 
-    fn parse_bit_tree(
+    fn parse_bit_tree<P: Prob>(
         &mut self,
         num_bits: usize,
-        probs: &mut [u16],
+        probs: &mut [P],
         update: bool,
     ) -> io::Result<u32> {
         let mut tmp: u32 = 1;
@@ -133,10 +183,10 @@ where
         Ok(tmp - (1 << num_bits))
     }
 
-    pub fn parse_reverse_bit_tree(
+    pub fn parse_reverse_bit_tree<P: Prob>(
         &mut self,
         num_bits: usize,
-        probs: &mut [u16],
+        probs: &mut [P],
         offset: usize,
         update: bool,
     ) -> io::Result<u32> {
@@ -152,11 +202,11 @@ where
 }
 
 #[derive(Debug, Clone)]
-pub struct BitTree<const PROBS_ARRAY_LEN: usize> {
-    probs: [u16; PROBS_ARRAY_LEN],
+pub struct BitTree<const PROBS_ARRAY_LEN: usize, P: Prob = u16> {
+    probs: [P; PROBS_ARRAY_LEN],
 }
 
-impl<const PROBS_ARRAY_LEN: usize> BitTree<PROBS_ARRAY_LEN> {
+impl<const PROBS_ARRAY_LEN: usize, P: Prob> BitTree<PROBS_ARRAY_LEN, P> {
     pub fn new() -> Self {
         // The validity of PROBS_ARRAY_LEN is checked at compile-time with a macro
         // that confirms that the argument P passed is indeed 1 << N for
@@ -173,7 +223,7 @@ impl<const PROBS_ARRAY_LEN: usize> BitTree<PROBS_ARRAY_LEN> {
         const_assert!("BitTree's PROBS_ARRAY_LEN parameter must be a power of 2",
             PROBS_ARRAY_LEN: usize => (1 << (PROBS_ARRAY_LEN.trailing_zeros() as usize)) == PROBS_ARRAY_LEN);
         BitTree {
-            probs: [0x400; PROBS_ARRAY_LEN],
+            probs: [P::HALF; PROBS_ARRAY_LEN],
         }
     }
 
@@ -200,19 +250,19 @@ impl<const PROBS_ARRAY_LEN: usize> BitTree<PROBS_ARRAY_LEN> {
 }
 
 #[derive(Debug)]
-pub struct LenDecoder {
-    choice: u16,
-    choice2: u16,
-    low_coder: [BitTree<{ 1 << 3 }>; 16],
-    mid_coder: [BitTree<{ 1 << 3 }>; 16],
-    high_coder: BitTree<{ 1 << 8 }>,
+pub struct LenDecoder<P: Prob = u16> {
+    choice: P,
+    choice2: P,
+    low_coder: [BitTree<{ 1 << 3 }, P>; 16],
+    mid_coder: [BitTree<{ 1 << 3 }, P>; 16],
+    high_coder: BitTree<{ 1 << 8 }, P>,
 }
 
-impl LenDecoder {
+impl<P: Prob> LenDecoder<P> {
     pub fn new() -> Self {
         LenDecoder {
-            choice: 0x400,
-            choice2: 0x400,
+            choice: P::HALF,
+            choice2: P::HALF,
             low_coder: [
                 BitTree::new(),
                 BitTree::new(),
@@ -268,3 +318,27 @@ impl LenDecoder {
         }
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn invalid_start_byte() {
+        let mut input: &[u8] = &[1, 0, 0, 0, 0];
+        match RangeDecoder::new(&mut input) {
+            Err(error::Error::LzmaError(e)) => assert_eq!(e, "invalid range coder start byte"),
+            _ => panic!("expected an invalid start byte"),
+        }
+
+        let mut input: &[u8] = &[0, 0, 0];
+        assert!(matches!(
+            RangeDecoder::new(&mut input),
+            Err(error::Error::IoError(_))
+        ));
+
+        let mut input: &[u8] = &[0, 0x12, 0x34, 0x56, 0x78];
+        let decoder = RangeDecoder::new(&mut input).unwrap();
+        assert_eq!(decoder.code, 0x1234_5678);
+    }
+}
@@ -1,5 +1,5 @@
 use crate::decode::lzbuffer::{LzBuffer, LzCircularBuffer};
-use crate::decode::lzma::{DecoderState, LzmaParams};
+use crate::decode::lzma::{self, DecoderState, LzmaParams};
 use crate::decode::rangecoder::RangeDecoder;
 use crate::decompress::Options;
 use crate::error::Error;
@@ -46,6 +46,8 @@ where
     range: u32,
     code: u32,
     output: LzCircularBuffer<W>,
+    /// Number of decompressed bytes when the sink was last flushed
+    flushed_len: usize,
 }
 
 impl<W> Debug for RunState<W>
@@ -56,6 +58,7 @@ where
         fmt.debug_struct("RunState")
             .field("range", &self.range)
             .field("code", &self.code)
+            .field("flushed_len", &self.flushed_len)
             .finish()
     }
 }
@@ -136,7 +139,10 @@ where
                             RangeDecoder::from_parts(&mut stream, state.range, state.code);
                         state
                             .decoder
-                            .process(&mut state.output, &mut range_decoder)?;
+                            .process(&mut state.output, &mut range_decoder)
+                            .map_err(|e| {
+                                lzma::check_truncated(e, &mut range_decoder, state.output.len())
+                            })?;
                     }
                     let output = state.output.finish()?;
                     Ok(output)
@@ -162,24 +168,27 @@ where
         match LzmaParams::read_header(&mut input, options) {
             Ok(params) => {
                 let decoder = DecoderState::new(params.properties, params.unpacked_size);
-                let output = LzCircularBuffer::from_stream(
+                let output = LzCircularBuffer::from_stream_with_memory(
                     output,
+                    Vec::new(),
                     params.dict_size as usize,
                     options.memlimit.unwrap_or(usize::MAX),
                 );
                 // The RangeDecoder is only kept temporarily as we are processing
                 // chunks of data.
-                if let Ok(rangecoder) = RangeDecoder::new(&mut input) {
-                    Ok(State::Data(Box::new(RunState {
+                match RangeDecoder::new(&mut input) {
+                    Ok(rangecoder) => Ok(State::Data(Box::new(RunState {
                         decoder,
                         output,
                         range: rangecoder.range,
                         code: rangecoder.code,
-                    })))
-                } else {
+                        flushed_len: 0,
+                    }))),
                     // Failed to create a RangeDecoder because we need more data,
                     // try again later.
-                    Ok(State::Header(output.into_output()))
+                    Err(Error::IoError(_)) => Ok(State::Header(output.into_output())),
+                    // Fatal error. Don't retry.
+                    Err(e) => Err(e),
                 }
             }
             // Failed to read_header() because we need more data, try again later.
@@ -205,6 +214,42 @@ where
         state.code = rangecoder.code;
         Ok(())
     }
+
+    /// Send the pending output to the sink and flush it, if at least
+    /// `flush_interval_bytes` were decompressed since the last flush.
+    fn flush_at_interval(state: &mut RunState<W>, options: &Options) -> io::Result<()> {
+        if let Some(interval) = options.flush_interval_bytes {
+            let len = state.output.len();
+            if (len - state.flushed_len) as u64 >= interval {
+                state.output.flush_pending()?.flush()?;
+                state.flushed_len = len;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Stream<Vec<u8>> {
+    /// Process the compressed `input` and return the decompressed data it
+    /// produced, in the manner of a hasher's `update`.
+    ///
+    /// The input may end at any point of the stream: bytes that don't
+    /// complete a symbol yet are kept until the next call. The returned data
+    /// may therefore lag behind the input, see [`Self::finalize()`] for the
+    /// rest of it.
+    pub fn update(&mut self, input: &[u8]) -> crate::error::Result<Vec<u8>> {
+        self.write_all(input)?;
+        match self.state {
+            Some(State::Data(ref mut state)) => Ok(std::mem::take(state.output.flush_pending()?)),
+            _ => Ok(Vec::new()),
+        }
+    }
+
+    /// Consume the stream, making sure its end was properly reached, and
+    /// return the decompressed data not yet returned by [`Self::update()`].
+    pub fn finalize(self) -> crate::error::Result<Vec<u8>> {
+        self.finish()
+    }
 }
 
 impl<W> Debug for Stream<W>
@@ -236,7 +281,7 @@ where
                         let position = self.tmp.position();
                         let bytes_read =
                             input.read(&mut self.tmp.get_mut()[position as usize..])?;
-                        let bytes_read = if bytes_read < std::u64::MAX as usize {
+                        let bytes_read = if bytes_read < u64::MAX as usize {
                             bytes_read as u64
                         } else {
                             return Err(io::Error::new(
@@ -277,7 +322,7 @@ where
                                 // reset the cursor because we may have partial reads
                                 input.set_position(0);
                                 let bytes_read = input.read(&mut self.tmp.get_mut()[..])?;
-                                let bytes_read = if bytes_read < std::u64::MAX as usize {
+                                let bytes_read = if bytes_read < u64::MAX as usize {
                                     bytes_read as u64
                                 } else {
                                     return Err(io::Error::new(
@@ -302,6 +347,31 @@ where
                                 Error::LzmaError(e) | Error::XzError(e) => {
                                     io::Error::new(io::ErrorKind::Other, e)
                                 }
+                                Error::Truncated(e) => {
+                                    io::Error::new(io::ErrorKind::UnexpectedEof, e)
+                                }
+                                Error::AllocationFailed(e) => {
+                                    io::Error::new(io::ErrorKind::OutOfMemory, e)
+                                }
+                                Error::TimedOut => {
+                                    io::Error::new(io::ErrorKind::TimedOut, e.to_string())
+                                }
+                                e @ (Error::OutputTooLarge(_)
+                                | Error::SizeMismatch { .. }
+                                | Error::ChecksumMismatch { .. }
+                                | Error::IndexTooLarge(_)
+                                | Error::TooManyChunks(_)
+                                | Error::NonCanonicalVli
+                                | Error::StreamFlagsMismatch { .. }
+                                | Error::StreamHeaderCorrupt { .. }
+                                | Error::Incompressible { .. }
+                                | Error::RoundtripFailed
+                                | Error::UnsupportedFilter(_)
+                                | Error::InvalidFilterChain(_)
+                                | Error::DistanceBeyondOverride { .. }
+                                | Error::Unsupported { .. }) => {
+                                    io::Error::new(io::ErrorKind::Other, e.to_string())
+                                }
                             });
                         }
                     }
@@ -316,6 +386,7 @@ where
                         self.tmp.set_position(0);
                     };
                     Stream::read_data(&mut state, &mut input)?;
+                    Stream::flush_at_interval(&mut state, &self.options)?;
                     State::Data(state)
                 }
             };
@@ -425,7 +496,7 @@ mod test {
             }
 
             let err = stream.finish().unwrap_err();
-            assert!(err.to_string().contains("failed to fill whole buffer"));
+            assert!(matches!(err, Error::Truncated(_)), "error was: {}", err);
 
             end += 1;
         }
@@ -464,7 +535,7 @@ mod test {
         let err = stream
             .write_all(b"corrupted bytes here corrupted bytes here")
             .unwrap_err();
-        assert!(err.to_string().contains("beyond output size"));
+        assert!(err.to_string().contains("invalid range coder start byte"));
         let err = stream.finish().unwrap_err();
         assert!(err
             .to_string()
@@ -497,4 +568,66 @@ mod test {
         let output = stream.finish().unwrap();
         assert_eq!(output, &input[..26]);
     }
+
+    /// A sink counting how often it's flushed
+    #[derive(Default)]
+    struct FlushCounter {
+        data: Vec<u8>,
+        flushes: usize,
+        flushed_len: usize,
+    }
+
+    impl Write for FlushCounter {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.data.extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            self.flushes += 1;
+            self.flushed_len = self.data.len();
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_flush_interval() {
+        let input = include_bytes!("../../tests/files/foo.txt");
+        let mut reader = io::Cursor::new(&input[..]);
+        let mut compressed = Vec::new();
+        crate::lzma_compress(&mut reader, &mut compressed).unwrap();
+
+        let decompress = |options: &Options| {
+            let mut stream = Stream::new_with_options(options, FlushCounter::default());
+            let mut max_unflushed = 0;
+            for chunk in compressed.chunks(1000) {
+                stream.write_all(chunk).unwrap();
+                if let Some(sink) = stream.get_output() {
+                    max_unflushed =
+                        std::cmp::max(max_unflushed, sink.data.len() - sink.flushed_len);
+                }
+            }
+            let sink = stream.finish().unwrap();
+            assert_eq!(sink.data, &input[..]);
+            assert_eq!(sink.flushed_len, input.len());
+            (sink.flushes, max_unflushed)
+        };
+
+        // Without an interval, the sink is only flushed at the end.
+        let (flushes, _) = decompress(&Options::default());
+        assert_eq!(flushes, 1);
+
+        // The output left unflushed after each write stays under the
+        // interval, and only the last of it waits for the end.
+        for interval in [1000, 50_000] {
+            let options = Options {
+                flush_interval_bytes: Some(interval as u64),
+                ..Default::default()
+            };
+            let (flushes, max_unflushed) = decompress(&options);
+            assert!(max_unflushed < interval, "{}", interval);
+            assert!(flushes > input.len() / (interval + 2000), "{}", interval);
+            assert!(flushes <= input.len() / interval + 1, "{}", interval);
+        }
+    }
 }
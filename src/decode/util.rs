@@ -1,5 +1,9 @@
+use crate::error::Error;
+#[cfg(feature = "xz")]
+use crate::xz::checksum::Crc32;
 use std::io;
 
+#[cfg(feature = "xz")]
 pub fn read_tag<R: io::BufRead>(input: &mut R, tag: &[u8]) -> io::Result<bool> {
     let mut buf = vec![0; tag.len()];
     input.read_exact(buf.as_mut_slice())?;
@@ -11,51 +15,39 @@ pub fn is_eof<R: io::BufRead>(input: &mut R) -> io::Result<bool> {
     Ok(buf.is_empty())
 }
 
-pub fn flush_zero_padding<R: io::BufRead>(input: &mut R) -> io::Result<bool> {
-    loop {
-        let len = {
-            let buf = input.fill_buf()?;
-            let len = buf.len();
-
-            if len == 0 {
-                return Ok(true);
-            }
-
-            for x in buf {
-                if *x != 0u8 {
-                    return Ok(false);
-                }
-            }
-            len
-        };
-
-        input.consume(len);
+/// Convert a decoding error for the [`io::Read`] interface, keeping I/O errors
+/// of the underlying reader as is.
+pub fn into_io_error(e: Error) -> io::Error {
+    match e {
+        Error::IoError(e) => e,
+        e => io::Error::new(io::ErrorKind::Other, e),
     }
 }
 
+#[cfg(feature = "xz")]
 /// An [`io::Read`] computing a digest on the bytes read.
-pub struct CrcDigestRead<'a, 'b, R, S>
+pub struct CrcDigestRead<'a, R>
 where
     R: 'a + io::Read,
-    S: crc::Width,
 {
     /// Underlying reader
     read: &'a mut R,
     /// Hasher
-    digest: &'a mut crc::Digest<'b, S>,
+    digest: &'a mut Crc32,
 }
 
-impl<'a, 'b, R, S> CrcDigestRead<'a, 'b, R, S>
+#[cfg(feature = "xz")]
+impl<'a, R> CrcDigestRead<'a, R>
 where
     R: io::Read,
-    S: crc::Width,
 {
-    pub fn new(read: &'a mut R, digest: &'a mut crc::Digest<'b, S>) -> Self {
+    pub fn new(read: &'a mut R, digest: &'a mut Crc32) -> Self {
         Self { read, digest }
     }
 }
 
-impl<'a, 'b, R> io::Read for CrcDigestRead<'a, 'b, R, u32>
+#[cfg(feature = "xz")]
+impl<'a, R> io::Read for CrcDigestRead<'a, R>
 where
     R: io::Read,
 {
@@ -1,21 +1,137 @@
 //! Decoder for the `.xz` file format.
 
-use crate::decode::lzma2::Lzma2Decoder;
+use crate::decode::limit::CheckedWriter;
+use crate::decode::lzma2::{self, Lzma2Decoder};
+use crate::decode::options::Options;
 use crate::decode::util;
 use crate::error;
-use crate::xz::crc::{CRC32, CRC64};
-use crate::xz::{footer, header, CheckMethod, StreamFlags};
+use crate::xz::checksum::{Crc32, Crc64};
+use crate::xz::{bcj, footer, header, vli, CheckMethod, StreamFlags};
 use byteorder::{BigEndian, LittleEndian, ReadBytesExt};
 use std::io;
 use std::io::Read;
 
-#[derive(Debug)]
-struct Record {
-    unpadded_size: u64,
-    unpacked_size: u64,
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) struct Record {
+    pub(crate) unpadded_size: u64,
+    pub(crate) unpacked_size: u64,
+}
+
+/// Outcome of decoding an XZ stream.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct XzStatus {
+    /// Whether the stream was cut short or damaged, in which case only its
+    /// complete blocks were decoded. This is only set in
+    /// [`salvage`](crate::decompress::Options::salvage) mode.
+    pub truncated: bool,
+    /// Number of blocks decoded.
+    pub blocks: u64,
+    /// Deviations from the spec tolerated in
+    /// [`lenient`](crate::decompress::Options::lenient) mode, in the order
+    /// they were encountered.
+    pub warnings: Vec<Warning>,
+}
+
+/// Outcome of verifying an XZ stream, see
+/// [`xz_verify()`](crate::xz_verify).
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct VerifyReport {
+    /// Size of the decompressed data.
+    pub uncompressed_size: u64,
+    /// Number of blocks decoded.
+    pub blocks: u64,
+    /// Number of block checks verified, which is zero if the stream has no
+    /// integrity check.
+    pub checks_verified: u64,
+}
+
+/// Deviation from the spec that doesn't prevent decoding, tolerated in
+/// [`lenient`](crate::decompress::Options::lenient) mode.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Warning {
+    /// What the deviation is.
+    pub kind: WarningKind,
+    /// Offset in the stream of the first byte deviating from the spec.
+    pub offset: u64,
+}
+
+/// Kinds of [`Warning`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum WarningKind {
+    /// Reserved bits of the block flags are set.
+    ReservedBlockFlags,
+    /// The padding of a block header isn't made of null bytes.
+    BlockHeaderPadding,
+    /// The padding following the compressed data of a block isn't made of
+    /// null bytes.
+    BlockPadding,
+    /// The padding of the index isn't made of null bytes.
+    IndexPadding,
+    /// A variable-length integer of a block header or of the index isn't
+    /// encoded with the minimal number of bytes.
+    NonCanonicalVli,
+    /// The check of a block isn't supported, e.g. SHA-256 or a check ID
+    /// without a defined check yet, so it's skipped without being verified.
+    UnsupportedCheck,
+}
+
+/// Handling of the deviations from the spec that don't prevent decoding.
+pub(crate) struct Leniency {
+    /// Deviations tolerated so far, or [`None`] in strict mode
+    warnings: Option<Vec<Warning>>,
+}
+
+impl Leniency {
+    pub(crate) fn new(lenient: bool) -> Self {
+        Self {
+            warnings: if lenient { Some(Vec::new()) } else { None },
+        }
+    }
+
+    /// Record a deviation in lenient mode, or return the `error` in strict
+    /// mode.
+    fn tolerate<F>(&mut self, kind: WarningKind, offset: u64, error: F) -> error::Result<()>
+    where
+        F: FnOnce() -> error::Error,
+    {
+        match &mut self.warnings {
+            Some(warnings) => {
+                lzma_info!("XZ tolerating {:?} at offset {}", kind, offset);
+                warnings.push(Warning { kind, offset });
+                Ok(())
+            }
+            None => Err(error()),
+        }
+    }
+
+    /// Read a variable-length integer at `*offset` in the stream, advancing
+    /// the offset past it. Encodings that aren't minimal are rejected in
+    /// strict mode.
+    fn read_vli<R>(&mut self, input: &mut R, offset: &mut u64) -> error::Result<u64>
+    where
+        R: io::Read,
+    {
+        let (value, len) = vli::read_vli(input)?;
+        if len > vli::encoded_len(value) {
+            self.tolerate(WarningKind::NonCanonicalVli, *offset, || {
+                error::Error::NonCanonicalVli
+            })?;
+        }
+        *offset += len as u64;
+        Ok(value)
+    }
+
+    fn into_warnings(self) -> Vec<Warning> {
+        self.warnings.unwrap_or_default()
+    }
 }
 
-pub fn decode_stream<R, W>(input: &mut R, output: &mut W) -> error::Result<()>
+pub fn decode_stream<R, W>(
+    input: &mut R,
+    output: &mut W,
+    options: &Options,
+) -> error::Result<XzStatus>
 where
     R: io::BufRead,
     W: io::Write,
@@ -23,29 +139,206 @@ where
     let header = header::StreamHeader::parse(input)?;
 
     let mut records: Vec<Record> = vec![];
+    let mut decoder = BlockDecoder::new();
+    decoder.leniency = Leniency::new(options.lenient);
+    decoder.max_output = options.max_block_output;
+    decoder.lzma2.force_props(options.force_props)?;
+    decoder.lzma2.memlimit(options.memlimit);
+    decoder.lzma2.deadline(options.deadline);
+    decoder.lzma2.max_chunks(options.max_chunks);
+    let mut checked = CheckedWriter::new(output, options);
+    let result = decode_blocks(
+        input,
+        &mut checked,
+        &header,
+        &mut records,
+        &mut decoder,
+        options.limit,
+        options.max_index_records.unwrap_or(u64::MAX),
+    );
+    let blocks = records.len() as u64;
+    let warnings = decoder.leniency.into_warnings();
+    match result {
+        Ok(()) => {
+            // Decoding stopped at the limit doesn't reach the expected size.
+            if options.limit.map_or(true, |limit| checked.count() < limit) {
+                checked.finish()?;
+            }
+            Ok(XzStatus {
+                truncated: false,
+                blocks,
+                warnings,
+            })
+        }
+        // Only damage to the input is salvaged, not failures of the output or
+        // limits set by the caller.
+        Err(e) if options.salvage && !checked.inner_failed() && is_salvageable(&e) => {
+            lzma_info!("XZ salvaged {} block(s) before error: {}", blocks, e);
+            Ok(XzStatus {
+                truncated: true,
+                blocks,
+                warnings,
+            })
+        }
+        Err(e) => Err(e),
+    }
+}
+
+/// Whether `e` comes from reading or parsing damaged input, so that the
+/// blocks decoded before it can be salvaged.
+fn is_salvageable(e: &error::Error) -> bool {
+    match e {
+        error::Error::Truncated(_) | error::Error::XzError(_) | error::Error::LzmaError(_) => true,
+        error::Error::IoError(e) => e.kind() == io::ErrorKind::UnexpectedEof,
+        _ => false,
+    }
+}
+
+/// Decode the stream in strict mode, discarding the decompressed data.
+pub(crate) fn verify<R>(input: &mut R) -> error::Result<VerifyReport>
+where
+    R: io::BufRead,
+{
+    let header = header::StreamHeader::parse(input)?;
+
+    let mut records: Vec<Record> = vec![];
+    let mut decoder = BlockDecoder::new();
+    decode_blocks(
+        input,
+        &mut io::sink(),
+        &header,
+        &mut records,
+        &mut decoder,
+        None,
+        u64::MAX,
+    )?;
+    let blocks = records.len() as u64;
+    // Unsupported checks fail in strict mode.
+    let checks_verified = match header.stream_flags.check_method() {
+        Some(CheckMethod::Crc32) | Some(CheckMethod::Crc64) => blocks,
+        _ => 0,
+    };
+    Ok(VerifyReport {
+        uncompressed_size: records.iter().map(|record| record.unpacked_size).sum(),
+        blocks,
+        checks_verified,
+    })
+}
+
+/// Decode the blocks, index and footer following the stream header. Only
+/// complete and valid blocks are written to the output, unless decoding stops
+/// early at the given output `limit`.
+fn decode_blocks<R, W>(
+    input: &mut R,
+    output: &mut W,
+    header: &header::StreamHeader,
+    records: &mut Vec<Record>,
+    decoder: &mut BlockDecoder,
+    mut limit: Option<u64>,
+    max_records: u64,
+) -> error::Result<()>
+where
+    R: io::BufRead,
+    W: io::Write,
+{
+    // The stream header is 12 bytes long, see sect. 2.1.1.
+    decoder.offset = 12;
     let index_size = loop {
+        if limit == Some(0) {
+            lzma_info!("XZ output limit reached after {} block(s)", records.len());
+            return Ok(());
+        }
+
         let mut count_input = util::CountBufRead::new(input);
         let header_size = count_input.read_u8()?;
         lzma_info!("XZ block header_size byte: 0x{:02x}", header_size);
 
         if header_size == 0 {
             lzma_info!("XZ records: {:?}", records);
-            check_index(&mut count_input, &records)?;
+            check_index(
+                &mut count_input,
+                records,
+                &mut decoder.leniency,
+                decoder.offset,
+                max_records,
+            )?;
             let index_size = count_input.count();
             break index_size;
         }
 
-        read_block(
+        if records.len() as u64 >= max_records {
+            return Err(error::Error::IndexTooLarge(records.len() as u64 + 1));
+        }
+
+        let stopped = read_block(
             &mut count_input,
             output,
-            header.stream_flags.check_method,
-            &mut records,
+            decoder,
+            header.stream_flags,
+            records,
             header_size,
+            limit,
         )?;
+        if stopped {
+            return Ok(());
+        }
+        decoder.offset += count_input.count() as u64;
+        if let (Some(remaining), Some(record)) = (limit.as_mut(), records.last()) {
+            *remaining -= record.unpacked_size;
+        }
     };
 
+    check_footer(input, header, index_size)?;
+    check_stream_padding(input)
+}
+
+/// Check that the stream is only followed by stream padding, i.e. null bytes
+/// in multiples of four, see sect. 2.2.
+fn check_stream_padding<R>(input: &mut R) -> error::Result<()>
+where
+    R: io::BufRead,
+{
+    let mut padding_size = 0u64;
+    loop {
+        let buf = input.fill_buf()?;
+        if buf.is_empty() {
+            break;
+        }
+        if buf.iter().any(|&byte| byte != 0) {
+            return Err(error::Error::XzError(
+                "Unexpected data after last XZ block".to_string(),
+            ));
+        }
+        let len = buf.len();
+        padding_size += len as u64;
+        input.consume(len);
+    }
+    check_stream_padding_size(padding_size)
+}
+
+/// Check the size of the stream padding, which must be a multiple of four.
+pub(crate) fn check_stream_padding_size(padding_size: u64) -> error::Result<()> {
+    if padding_size % 4 != 0 {
+        return Err(error::Error::XzError(format!(
+            "Invalid stream padding of {} bytes, must be a multiple of 4",
+            padding_size
+        )));
+    }
+    Ok(())
+}
+
+/// Check the stream footer against the stream header and the size of the
+/// index, see sect. 2.1.2.
+pub(crate) fn check_footer<R>(
+    input: &mut R,
+    header: &header::StreamHeader,
+    index_size: usize,
+) -> error::Result<()>
+where
+    R: io::BufRead,
+{
     let crc32 = input.read_u32::<LittleEndian>()?;
-    let mut digest = CRC32.digest();
+    let mut digest = Crc32::new();
     {
         let mut digested = util::CrcDigestRead::new(input, &mut digest);
         let backward_size = digested.read_u32::<LittleEndian>()?;
@@ -63,10 +356,21 @@ where
         };
 
         if header.stream_flags != stream_flags {
-            return Err(error::Error::XzError(format!(
-                "Flags in header ({:?}) does not match footer ({:?})",
-                header.stream_flags, stream_flags
-            )));
+            return Err(
+                match (
+                    header.stream_flags.check_method(),
+                    stream_flags.check_method(),
+                ) {
+                    (Some(header), Some(footer)) => {
+                        error::Error::StreamFlagsMismatch { header, footer }
+                    }
+                    _ => error::Error::XzError(format!(
+                        "Check ID in header ({:#x}) does not match footer ({:#x})",
+                        header.stream_flags.check_id(),
+                        stream_flags.check_id()
+                    )),
+                },
+            );
         }
     }
 
@@ -84,29 +388,32 @@ where
             footer::XZ_MAGIC_FOOTER
         )));
     }
-
-    if !util::is_eof(input)? {
-        return Err(error::Error::XzError(
-            "Unexpected data after last XZ block".to_string(),
-        ));
-    }
     Ok(())
 }
 
-fn check_index<R>(
+/// Check the index against the records of the decoded blocks. The leading
+/// null byte, at `offset` in the stream, must have been consumed already.
+pub(crate) fn check_index<R>(
     count_input: &mut util::CountBufRead<'_, R>,
     records: &[Record],
+    leniency: &mut Leniency,
+    offset: u64,
+    max_records: u64,
 ) -> error::Result<()>
 where
     R: io::BufRead,
 {
-    let mut digest = CRC32.digest();
+    let mut digest = Crc32::new();
     let index_tag = 0u8;
     digest.update(&[index_tag]);
     {
         let mut digested = util::CrcDigestRead::new(count_input, &mut digest);
+        let mut vli_offset = offset + 1;
 
-        let num_records = get_multibyte(&mut digested)?;
+        let num_records = leniency.read_vli(&mut digested, &mut vli_offset)?;
+        if num_records > max_records {
+            return Err(error::Error::IndexTooLarge(num_records));
+        }
         if num_records != records.len() as u64 {
             return Err(error::Error::XzError(format!(
                 "Expected {} records but got {} records",
@@ -118,7 +425,7 @@ where
         for (i, record) in records.iter().enumerate() {
             lzma_info!("XZ index checking record {}: {:?}", i, record);
 
-            let unpadded_size = get_multibyte(&mut digested)?;
+            let unpadded_size = leniency.read_vli(&mut digested, &mut vli_offset)?;
             if unpadded_size != record.unpadded_size {
                 return Err(error::Error::XzError(format!(
                     "Invalid index for record {}: unpadded size ({}) does not match index ({})",
@@ -126,7 +433,7 @@ where
                 )));
             }
 
-            let unpacked_size = get_multibyte(&mut digested)?;
+            let unpacked_size = leniency.read_vli(&mut digested, &mut vli_offset)?;
             if unpacked_size != record.unpacked_size {
                 return Err(error::Error::XzError(format!(
                     "Invalid index for record {}: unpacked size ({}) does not match index ({})",
@@ -135,7 +442,71 @@ where
             }
         }
     };
-    // TODO: create padding parser function
+    check_index_end(count_input, digest, leniency, offset)
+}
+
+/// Parse the records of the index, see sect. 4. The leading null byte, at
+/// `offset` in the stream, must have been consumed already.
+///
+/// At most `max_records` are accepted, and the blocks they describe must fit
+/// between the stream header and the index.
+pub(crate) fn read_index<R>(
+    count_input: &mut util::CountBufRead<'_, R>,
+    leniency: &mut Leniency,
+    offset: u64,
+    max_records: u64,
+) -> error::Result<Vec<Record>>
+where
+    R: io::BufRead,
+{
+    let mut digest = Crc32::new();
+    let index_tag = 0u8;
+    digest.update(&[index_tag]);
+    let mut records = Vec::new();
+    {
+        let mut digested = util::CrcDigestRead::new(count_input, &mut digest);
+        let mut vli_offset = offset + 1;
+
+        let num_records = leniency.read_vli(&mut digested, &mut vli_offset)?;
+        if num_records > max_records {
+            return Err(error::Error::IndexTooLarge(num_records));
+        }
+        // The stream header is 12 bytes long, see sect. 2.1.1.
+        let mut remaining = offset.saturating_sub(12);
+        for i in 0..num_records {
+            let unpadded_size = leniency.read_vli(&mut digested, &mut vli_offset)?;
+            let unpacked_size = leniency.read_vli(&mut digested, &mut vli_offset)?;
+            // Blocks are padded to a multiple of four bytes.
+            remaining = unpadded_size
+                .checked_add(3)
+                .and_then(|size| remaining.checked_sub(size & !3))
+                .ok_or_else(|| {
+                    error::Error::XzError(format!(
+                        "Invalid index for record {}: unpadded size ({}) exceeds the stream",
+                        i, unpadded_size
+                    ))
+                })?;
+            records.push(Record {
+                unpadded_size,
+                unpacked_size,
+            });
+        }
+    };
+
+    check_index_end(count_input, digest, leniency, offset)?;
+    Ok(records)
+}
+
+/// Check the padding and the CRC32 ending the index.
+fn check_index_end<R>(
+    count_input: &mut util::CountBufRead<'_, R>,
+    mut digest: Crc32,
+    leniency: &mut Leniency,
+    offset: u64,
+) -> error::Result<()>
+where
+    R: io::BufRead,
+{
     let count = count_input.count();
     let padding_size = ((count ^ 0x03) + 1) & 0x03;
     lzma_info!(
@@ -146,12 +517,18 @@ where
 
     {
         let mut digested = util::CrcDigestRead::new(count_input, &mut digest);
-        for _ in 0..padding_size {
+        for i in 0..padding_size {
             let byte = digested.read_u8()?;
             if byte != 0 {
-                return Err(error::Error::XzError(
-                    "Invalid index padding, must be null bytes".to_string(),
-                ));
+                leniency.tolerate(
+                    WarningKind::IndexPadding,
+                    offset + (count + i) as u64,
+                    || {
+                        error::Error::XzError(
+                            "Invalid index padding, must be null bytes".to_string(),
+                        )
+                    },
+                )?;
             }
         }
     };
@@ -170,65 +547,490 @@ where
     Ok(())
 }
 
-#[derive(Debug)]
+/// Filters defined by the XZ format, see spec section 5.3. Only LZMA2 and
+/// x86 are implemented, the others are recognized to report them as
+/// unsupported.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
 enum FilterId {
+    Delta,
+    X86,
+    PowerPc,
+    Ia64,
+    Arm,
+    ArmThumb,
+    Sparc,
+    Arm64,
+    RiscV,
     Lzma2,
 }
 
-fn get_filter_id(id: u64) -> error::Result<FilterId> {
-    match id {
-        0x21 => Ok(FilterId::Lzma2),
-        _ => Err(error::Error::XzError(format!("Unknown filter id {}", id))),
+impl FilterId {
+    fn from_id(id: u64) -> error::Result<Self> {
+        match id {
+            0x03 => Ok(FilterId::Delta),
+            0x04 => Ok(FilterId::X86),
+            0x05 => Ok(FilterId::PowerPc),
+            0x06 => Ok(FilterId::Ia64),
+            0x07 => Ok(FilterId::Arm),
+            0x08 => Ok(FilterId::ArmThumb),
+            0x09 => Ok(FilterId::Sparc),
+            0x0A => Ok(FilterId::Arm64),
+            0x0B => Ok(FilterId::RiscV),
+            0x21 => Ok(FilterId::Lzma2),
+            // The rest of the range of the filters defined by the format,
+            // e.g. for future BCJ variants. As every filter transforms the
+            // data, a block using one can't be decoded without it.
+            0x00..=0x3F => Err(error::Error::Unsupported {
+                what: format!("reserved filter (ID {:#x})", id),
+            }),
+            // IDs for the internal use of implementations, see spec section
+            // 5.2, must never be stored.
+            0x4000_0000_0000_0000.. => Err(error::Error::XzError(format!(
+                "Invalid filter ID {:#x}, reserved for internal use",
+                id
+            ))),
+            _ => Err(error::Error::UnsupportedFilter(id)),
+        }
+    }
+
+    fn id(self) -> u64 {
+        match self {
+            FilterId::Delta => 0x03,
+            FilterId::X86 => 0x04,
+            FilterId::PowerPc => 0x05,
+            FilterId::Ia64 => 0x06,
+            FilterId::Arm => 0x07,
+            FilterId::ArmThumb => 0x08,
+            FilterId::Sparc => 0x09,
+            FilterId::Arm64 => 0x0A,
+            FilterId::RiscV => 0x0B,
+            FilterId::Lzma2 => 0x21,
+        }
+    }
+
+    /// Whether `len` is a valid size for the properties of this filter.
+    fn valid_props_len(self, len: u64) -> bool {
+        match self {
+            FilterId::Delta | FilterId::Lzma2 => len == 1,
+            // Branch/call/jump filters take an optional start offset.
+            _ => len == 0 || len == 4,
+        }
     }
 }
 
-struct Filter {
+/// Filter flags of a block header, see spec section 3.1.5.
+#[derive(Debug, PartialEq, Eq)]
+struct FilterFlags {
     filter_id: FilterId,
     props: Vec<u8>,
 }
 
+#[derive(Debug)]
 struct BlockHeader {
-    filters: Vec<Filter>,
+    /// Filters in the order of the header, i.e. of encoding, so that LZMA2
+    /// is the last one
+    filters: Vec<FilterFlags>,
     packed_size: Option<u64>,
     unpacked_size: Option<u64>,
 }
 
-fn read_block<R, W>(
+/// Decoding state and buffers reused from one block to the next, so that
+/// streams made of many small blocks (e.g. with `xz --flush-timeout`) don't
+/// allocate for each of them.
+pub(crate) struct BlockDecoder {
+    /// Offset in the stream of the current block
+    pub(crate) offset: u64,
+    /// Handling of the deviations from the spec
+    pub(crate) leniency: Leniency,
+    /// Maximum size of the decompressed data of a block, see
+    /// [`Options::max_block_output`]
+    pub(crate) max_output: Option<u64>,
+    /// Header of the current block
+    header: BlockHeader,
+    /// LZMA2 decoder, whose probability tables are kept between blocks
+    lzma2: Lzma2Decoder,
+    /// Decompressed data of the current block
+    buf: Vec<u8>,
+    /// Output of the filters that aren't the first one
+    filtered: Vec<u8>,
+}
+
+impl BlockDecoder {
+    pub(crate) fn new() -> Self {
+        Self {
+            offset: 0,
+            leniency: Leniency::new(false),
+            max_output: None,
+            header: BlockHeader {
+                filters: Vec::new(),
+                packed_size: None,
+                unpacked_size: None,
+            },
+            lzma2: Lzma2Decoder::new(),
+            buf: Vec::new(),
+            filtered: Vec::new(),
+        }
+    }
+
+    /// Parse the header of a block, following its first byte `header_size`,
+    /// and check its CRC32, as well as its unpacked size against the maximum
+    /// output.
+    pub(crate) fn read_header<R>(&mut self, input: &mut R, header_size: u8) -> error::Result<()>
+    where
+        R: io::BufRead,
+    {
+        let mut digest = Crc32::new();
+        digest.update(&[header_size]);
+        let header_size = ((header_size as u64) << 2) - 1;
+
+        // The block header is at most 1024 bytes, see sect. 3.1.1.
+        let mut header_buf = [0; 1024];
+        let header_buf = &mut header_buf[..header_size as usize];
+        input.read_exact(header_buf)?;
+        digest.update(header_buf);
+        let mut header_input = &header_buf[..];
+        read_block_header(
+            &mut header_input,
+            header_size,
+            &mut self.header,
+            &mut self.leniency,
+            self.offset + 1,
+        )?;
+        if let Some(i) = header_input.iter().position(|&byte| byte != 0) {
+            let padding_offset = 1 + header_buf.len() - header_input.len() + i;
+            self.leniency.tolerate(
+                WarningKind::BlockHeaderPadding,
+                self.offset + padding_offset as u64,
+                || {
+                    error::Error::XzError(
+                        "Invalid block header padding, must be null bytes".to_string(),
+                    )
+                },
+            )?;
+        }
+
+        let crc32 = input.read_u32::<LittleEndian>()?;
+        let digest_crc32 = digest.finalize();
+        if crc32 != digest_crc32 {
+            return Err(error::Error::XzError(format!(
+                "Invalid header CRC32: expected 0x{:08x} but got 0x{:08x}",
+                crc32, digest_crc32
+            )));
+        }
+
+        if let (Some(max_output), Some(size)) = (self.max_output, self.header.unpacked_size) {
+            if size > max_output {
+                return Err(block_too_large(self.offset, self.max_output));
+            }
+        }
+        Ok(())
+    }
+
+    #[cfg(feature = "stream")]
+    /// Start decoding the data of the block whose header was just read, of
+    /// `header_len` bytes, one LZMA2 chunk at a time with
+    /// [`BlockDecoder::decode_chunk()`].
+    pub(crate) fn start_chunks(
+        &mut self,
+        stream_flags: StreamFlags,
+        header_len: usize,
+    ) -> error::Result<BlockChunks> {
+        let filters = &self.header.filters;
+        // The filter chain is validated to end with LZMA2.
+        let dict_size = lzma2::dict_size_from_props(filters[filters.len() - 1].props[0])?;
+        lzma_info!("XZ LZMA2 filter dict size: {}", dict_size);
+        self.lzma2.restart();
+        Ok(BlockChunks {
+            stream_flags,
+            digest: BlockDigest::new(stream_flags),
+            accum: self.lzma2.chunk_buffer(std::mem::take(&mut self.buf)),
+            dict_size: if filters.len() == 1 {
+                Some(dict_size as usize)
+            } else {
+                None
+            },
+            header_len,
+            packed_size: 0,
+            unpacked_size: 0,
+        })
+    }
+
+    #[cfg(feature = "stream")]
+    /// Decode the LZMA2 chunk in `chunk`, writing its output unless other
+    /// filters need the whole block. Returns whether this was the
+    /// end-of-stream marker, after which [`BlockDecoder::finish_chunks()`]
+    /// completes the block.
+    pub(crate) fn decode_chunk<W>(
+        &mut self,
+        chunks: &mut BlockChunks,
+        chunk: &[u8],
+        output: &mut W,
+    ) -> error::Result<bool>
+    where
+        W: io::Write,
+    {
+        let BlockChunks {
+            digest,
+            accum,
+            dict_size,
+            packed_size,
+            unpacked_size,
+            ..
+        } = chunks;
+        let mut input = chunk;
+        let end = self.lzma2.decompress_chunk(accum, &mut input)?;
+        *packed_size += (chunk.len() - input.len()) as u64;
+
+        let expected_unpacked_size = self.header.unpacked_size;
+        let (block_offset, max_output) = (self.offset, self.max_output);
+        let mut result = Ok(());
+        accum.inspect_new(|buf| {
+            *unpacked_size += buf.len() as u64;
+            result = if let Some(expected) =
+                expected_unpacked_size.filter(|&expected| *unpacked_size > expected)
+            {
+                Err(error::Error::SizeMismatch {
+                    expected,
+                    actual: *unpacked_size,
+                })
+            } else if max_output.map_or(false, |max_output| *unpacked_size > max_output) {
+                Err(block_too_large(block_offset, max_output))
+            } else if dict_size.is_some() {
+                digest.update(buf);
+                output.write_all(buf).map_err(error::Error::from)
+            } else {
+                Ok(())
+            };
+        });
+        result?;
+
+        // Only the dictionary is kept once written, trimmed when it reaches
+        // twice its size so that each byte is moved at most once.
+        if let Some(dict_size) = *dict_size {
+            if accum.output_len() / 2 >= dict_size {
+                accum.discard_output(dict_size);
+            }
+        }
+        Ok(end)
+    }
+
+    #[cfg(feature = "stream")]
+    /// Complete the block after its end-of-stream marker, given its padding
+    /// and check in `trailer`, of [`BlockChunks::trailer_len()`] bytes.
+    /// Blocks with other filters than LZMA2 are filtered and written once
+    /// validated.
+    pub(crate) fn finish_chunks<W>(
+        &mut self,
+        chunks: BlockChunks,
+        mut trailer: &[u8],
+        output: &mut W,
+        records: &mut Vec<Record>,
+    ) -> error::Result<()>
+    where
+        W: io::Write,
+    {
+        let padding_size = chunks.padding_size();
+        let BlockChunks {
+            stream_flags,
+            mut digest,
+            accum,
+            dict_size,
+            header_len,
+            packed_size,
+            unpacked_size,
+        } = chunks;
+        let BlockDecoder {
+            offset: block_offset,
+            leniency,
+            header: block_header,
+            lzma2,
+            buf,
+            filtered,
+            ..
+        } = self;
+        *buf = accum.into_vec();
+
+        if let Some(expected_packed_size) = block_header.packed_size {
+            if packed_size != expected_packed_size {
+                return Err(error::Error::XzError(format!(
+                    "Invalid compressed size: expected {} but got {}",
+                    expected_packed_size, packed_size
+                )));
+            }
+        }
+        if let Some(expected_unpacked_size) = block_header.unpacked_size {
+            if unpacked_size != expected_unpacked_size {
+                return Err(error::Error::SizeMismatch {
+                    expected: expected_unpacked_size,
+                    actual: unpacked_size,
+                });
+            }
+        }
+        lzma_info!("XZ block decompressed to {} byte(s)", unpacked_size);
+
+        if dict_size.is_none() {
+            let filters = &block_header.filters;
+            let last = filters.len() - 1;
+            for (i, filter) in filters.iter().rev().enumerate().skip(1) {
+                filtered.clear();
+                decode_filter(&mut buf.as_slice(), filtered, lzma2, filter, None, |buf| {
+                    if i == last {
+                        digest.update(buf)
+                    }
+                })?;
+                std::mem::swap(buf, filtered);
+            }
+        }
+
+        let count = header_len + packed_size as usize;
+        for (i, &byte) in trailer[..padding_size].iter().enumerate() {
+            if byte != 0 {
+                leniency.tolerate(
+                    WarningKind::BlockPadding,
+                    *block_offset + (count + i) as u64,
+                    || {
+                        error::Error::XzError(
+                            "Invalid block padding, must be null bytes".to_string(),
+                        )
+                    },
+                )?;
+            }
+        }
+        trailer = &trailer[padding_size..];
+        let check_offset = *block_offset + (count + padding_size) as u64;
+        validate_block_check(&mut trailer, digest, leniency, check_offset)?;
+
+        if dict_size.is_none() {
+            output.write_all(buf.as_slice())?;
+        }
+        records.push(Record {
+            unpadded_size: (count + stream_flags.check_size()) as u64,
+            unpacked_size,
+        });
+        Ok(())
+    }
+}
+
+#[cfg(feature = "stream")]
+/// Data of a block being decoded one LZMA2 chunk at a time, see
+/// [`BlockDecoder::start_chunks()`].
+pub(crate) struct BlockChunks {
+    stream_flags: StreamFlags,
+    /// Check computed over the decompressed data written so far
+    digest: BlockDigest,
+    /// Output of LZMA2, holding its dictionary
+    accum: crate::decode::lzbuffer::LzAccumBuffer<io::Sink>,
+    /// Size of the dictionary kept once the output is written, or [`None`]
+    /// to keep the whole output for the other filters
+    dict_size: Option<usize>,
+    /// Size of the block header
+    header_len: usize,
+    /// Size of the compressed data so far
+    packed_size: u64,
+    /// Size of the decompressed data so far
+    unpacked_size: u64,
+}
+
+#[cfg(feature = "stream")]
+impl BlockChunks {
+    /// Size of the block padding, once all the chunks are decoded.
+    fn padding_size(&self) -> usize {
+        let count = self.header_len + self.packed_size as usize;
+        ((count ^ 0x03) + 1) & 0x03
+    }
+
+    /// Size of the block padding and check, once all the chunks are decoded.
+    pub(crate) fn trailer_len(&self) -> usize {
+        self.padding_size() + self.stream_flags.check_size()
+    }
+}
+
+#[cfg(feature = "stream")]
+impl std::fmt::Debug for BlockChunks {
+    fn fmt(&self, fmt: &mut std::fmt::Formatter) -> std::fmt::Result {
+        fmt.debug_struct("BlockChunks")
+            .field("packed_size", &self.packed_size)
+            .field("unpacked_size", &self.unpacked_size)
+            .finish()
+    }
+}
+
+/// Error for a block at `block_offset` producing more than `max_output`
+/// bytes, see [`Options::max_block_output`].
+fn block_too_large(block_offset: u64, max_output: Option<u64>) -> error::Error {
+    error::Error::XzError(format!(
+        "Block at offset {} exceeds the maximum output of {} bytes",
+        block_offset,
+        max_output.unwrap_or_default()
+    ))
+}
+
+/// Decode a block and write its data to the output, once validated.
+///
+/// If a `limit` is given, decoding may stop early once this number of bytes
+/// is produced, in which case only these bytes are written, without
+/// validating the rest of the block. Returns whether this happened.
+pub(crate) fn read_block<R, W>(
     count_input: &mut util::CountBufRead<'_, R>,
     output: &mut W,
-    check_method: CheckMethod,
+    decoder: &mut BlockDecoder,
+    stream_flags: StreamFlags,
     records: &mut Vec<Record>,
     header_size: u8,
+    limit: Option<u64>,
 ) -> error::Result<bool>
 where
     R: io::BufRead,
     W: io::Write,
 {
-    let mut digest = CRC32.digest();
-    digest.update(&[header_size]);
-    let header_size = ((header_size as u64) << 2) - 1;
-
-    let block_header = {
-        let mut taken = count_input.take(header_size);
-        let mut digested = io::BufReader::new(util::CrcDigestRead::new(&mut taken, &mut digest));
-        read_block_header(&mut digested, header_size)?
-    };
+    decoder.read_header(count_input, header_size)?;
+    let BlockDecoder {
+        offset: block_offset,
+        leniency,
+        max_output,
+        header: block_header,
+        lzma2,
+        buf: tmpbuf,
+        filtered,
+    } = decoder;
+    let max_output = *max_output;
+    let too_large = || block_too_large(*block_offset, max_output);
 
-    let crc32 = count_input.read_u32::<LittleEndian>()?;
-    let digest_crc32 = digest.finalize();
-    if crc32 != digest_crc32 {
-        return Err(error::Error::XzError(format!(
-            "Invalid header CRC32: expected 0x{:08x} but got 0x{:08x}",
-            crc32, digest_crc32
-        )));
-    }
-
-    let mut tmpbuf: Vec<u8> = Vec::new();
-    let filters = block_header.filters;
-    for (i, filter) in filters.iter().enumerate() {
+    // The check is computed while the last filter produces the decompressed
+    // data, rather than in a second pass over it.
+    let mut digest = BlockDigest::new(stream_flags);
+    tmpbuf.clear();
+    let limit = limit.map(|limit| std::cmp::min(limit, usize::MAX as u64) as usize);
+    // Decoding past the declared size or the maximum output is pointless, so
+    // the last filter stops as soon as one of them is exceeded.
+    let exceeded = |size: u64| std::cmp::min(size, usize::MAX as u64 - 1) as usize + 1;
+    let last_limit = [
+        limit,
+        block_header.unpacked_size.map(exceeded),
+        max_output.map(exceeded),
+    ]
+    .iter()
+    .flatten()
+    .copied()
+    .min();
+    // Decoding applies the filters in reverse, starting with LZMA2 over the
+    // input.
+    let filters = &block_header.filters;
+    let last = filters.len() - 1;
+    for (i, filter) in filters.iter().rev().enumerate() {
+        let inspect = |buf: &[u8]| {
+            if i == last {
+                digest.update(buf)
+            }
+        };
+        let filter_limit = if i == last { last_limit } else { None };
         if i == 0 {
             // TODO: use SubBufRead on input if packed_size is known?
-            let packed_size = decode_filter(count_input, &mut tmpbuf, filter)?;
+            let packed_size =
+                decode_filter(count_input, tmpbuf, lzma2, filter, filter_limit, inspect)?;
+            if filter_limit.map_or(false, |limit| tmpbuf.len() >= limit) {
+                break;
+            }
             if let Some(expected_packed_size) = block_header.packed_size {
                 if (packed_size as u64) != expected_packed_size {
                     return Err(error::Error::XzError(format!(
@@ -238,14 +1040,37 @@ where
                 }
             }
         } else {
-            let mut newbuf: Vec<u8> = Vec::new();
+            filtered.clear();
             decode_filter(
-                &mut io::BufReader::new(tmpbuf.as_slice()),
-                &mut newbuf,
+                &mut tmpbuf.as_slice(),
+                filtered,
+                lzma2,
                 filter,
+                filter_limit,
+                inspect,
             )?;
-            // TODO: does this move or copy?
-            tmpbuf = newbuf;
+            std::mem::swap(tmpbuf, filtered);
+        }
+    }
+
+    let size_mismatch = |expected| error::Error::SizeMismatch {
+        expected,
+        actual: tmpbuf.len() as u64,
+    };
+    if let Some(expected_unpacked_size) = block_header.unpacked_size {
+        if tmpbuf.len() as u64 > expected_unpacked_size {
+            return Err(size_mismatch(expected_unpacked_size));
+        }
+    }
+    if max_output.map_or(false, |max_output| tmpbuf.len() as u64 > max_output) {
+        return Err(too_large());
+    }
+
+    if let Some(limit) = limit {
+        if tmpbuf.len() >= limit {
+            lzma_info!("XZ block stopped at the output limit of {} byte(s)", limit);
+            output.write_all(&tmpbuf[..limit])?;
+            return Ok(true);
         }
     }
 
@@ -254,30 +1079,30 @@ where
 
     if let Some(expected_unpacked_size) = block_header.unpacked_size {
         if (unpacked_size as u64) != expected_unpacked_size {
-            return Err(error::Error::XzError(format!(
-                "Invalid decompressed size: expected {} but got {}",
-                expected_unpacked_size, unpacked_size
-            )));
+            return Err(size_mismatch(expected_unpacked_size));
         }
     }
 
     let count = count_input.count();
     let padding_size = ((count ^ 0x03) + 1) & 0x03;
     lzma_info!(
-        "XZ block: {} byte(s) read, {} byte(s) of padding, check method {:?}",
+        "XZ block: {} byte(s) read, {} byte(s) of padding, check ID {}",
         count,
         padding_size,
-        check_method
+        stream_flags.check_id()
     );
-    for _ in 0..padding_size {
+    for i in 0..padding_size {
         let byte = count_input.read_u8()?;
         if byte != 0 {
-            return Err(error::Error::XzError(
-                "Invalid block padding, must be null bytes".to_string(),
-            ));
+            leniency.tolerate(
+                WarningKind::BlockPadding,
+                *block_offset + (count + i) as u64,
+                || error::Error::XzError("Invalid block padding, must be null bytes".to_string()),
+            )?;
         }
     }
-    validate_block_check(count_input, tmpbuf.as_slice(), check_method)?;
+    let check_offset = *block_offset + (count + padding_size) as u64;
+    validate_block_check(count_input, digest, leniency, check_offset)?;
 
     output.write_all(tmpbuf.as_slice())?;
     records.push(Record {
@@ -285,26 +1110,57 @@ where
         unpacked_size: unpacked_size as u64,
     });
 
-    let finished = false;
-    Ok(finished)
+    Ok(false)
+}
+
+/// Running computation of the "Block Check" field over the decompressed
+/// data of a block.
+enum BlockDigest {
+    None,
+    Crc32(Crc32),
+    Crc64(Crc64),
+    /// Check that isn't supported, only skipped
+    Unsupported(StreamFlags),
+}
+
+impl BlockDigest {
+    fn new(stream_flags: StreamFlags) -> Self {
+        match stream_flags.check_method() {
+            Some(CheckMethod::None) => BlockDigest::None,
+            Some(CheckMethod::Crc32) => BlockDigest::Crc32(Crc32::new()),
+            Some(CheckMethod::Crc64) => BlockDigest::Crc64(Crc64::new()),
+            Some(CheckMethod::Sha256) | None => BlockDigest::Unsupported(stream_flags),
+        }
+    }
+
+    fn update(&mut self, buf: &[u8]) {
+        match self {
+            BlockDigest::None | BlockDigest::Unsupported(_) => (),
+            BlockDigest::Crc32(digest) => digest.update(buf),
+            BlockDigest::Crc64(digest) => digest.update(buf),
+        }
+    }
 }
 
-/// Verify block checksum against the "Block Check" field.
+/// Verify block checksum against the "Block Check" field, at `offset` in the
+/// stream. Checks that aren't supported are skipped in lenient mode, using
+/// the size given by the check ID.
 ///
 /// See spec section 3.4 for details.
 fn validate_block_check<R>(
     input: &mut R,
-    buf: &[u8],
-    check_method: CheckMethod,
+    digest: BlockDigest,
+    leniency: &mut Leniency,
+    offset: u64,
 ) -> error::Result<()>
 where
     R: io::BufRead,
 {
-    match check_method {
-        CheckMethod::None => (),
-        CheckMethod::Crc32 => {
+    match digest {
+        BlockDigest::None => (),
+        BlockDigest::Crc32(digest) => {
             let crc32 = input.read_u32::<LittleEndian>()?;
-            let digest_crc32 = CRC32.checksum(buf);
+            let digest_crc32 = digest.finalize();
             if crc32 != digest_crc32 {
                 return Err(error::Error::XzError(format!(
                     "Invalid block CRC32, expected 0x{:08x} but got 0x{:08x}",
@@ -312,9 +1168,9 @@ where
                 )));
             }
         }
-        CheckMethod::Crc64 => {
+        BlockDigest::Crc64(digest) => {
             let crc64 = input.read_u64::<LittleEndian>()?;
-            let digest_crc64 = CRC64.checksum(buf);
+            let digest_crc64 = digest.finalize();
             if crc64 != digest_crc64 {
                 return Err(error::Error::XzError(format!(
                     "Invalid block CRC64, expected 0x{:016x} but got 0x{:016x}",
@@ -322,38 +1178,107 @@ where
                 )));
             }
         }
-        // TODO
-        CheckMethod::Sha256 => {
-            return Err(error::Error::XzError(
-                "Unsupported SHA-256 checksum (not yet implemented)".to_string(),
-            ));
+        // TODO: SHA-256
+        BlockDigest::Unsupported(stream_flags) => {
+            leniency.tolerate(WarningKind::UnsupportedCheck, offset, || {
+                error::Error::Unsupported {
+                    what: match stream_flags.check_method() {
+                        Some(_) => "SHA-256 check".to_string(),
+                        None => format!("check ID {:#x}", stream_flags.check_id()),
+                    },
+                }
+            })?;
+            let mut check = [0; 64];
+            input.read_exact(&mut check[..stream_flags.check_size()])?;
         }
     }
     Ok(())
 }
 
-fn decode_filter<R, W>(input: &mut R, output: &mut W, filter: &Filter) -> error::Result<usize>
+/// Decode the input through `filter`, passing the output to `inspect` as it
+/// is produced.
+fn decode_filter<R, F>(
+    input: &mut R,
+    output: &mut Vec<u8>,
+    lzma2: &mut Lzma2Decoder,
+    filter: &FilterFlags,
+    limit: Option<usize>,
+    mut inspect: F,
+) -> error::Result<usize>
 where
     R: io::BufRead,
-    W: io::Write,
+    F: FnMut(&[u8]),
 {
     let mut count_input = util::CountBufRead::new(input);
     match filter.filter_id {
         FilterId::Lzma2 => {
-            if filter.props.len() != 1 {
-                return Err(error::Error::XzError(format!(
-                    "Invalid properties for filter {:?}",
-                    filter.filter_id
-                )));
-            }
-            // TODO: properties??
-            Lzma2Decoder::new().decompress(&mut count_input, output)?;
+            let _dict_size = lzma2::dict_size_from_props(filter.props[0])?;
+            lzma_info!("XZ LZMA2 filter dict size: {}", _dict_size);
+            lzma2.restart();
+            lzma2.decompress_to_vec_inspect(&mut count_input, output, limit, inspect)?;
+            Ok(count_input.count())
+        }
+        FilterId::X86 => {
+            // The properties hold an optional start offset.
+            let mut start_offset = [0; 4];
+            start_offset[..filter.props.len()].copy_from_slice(&filter.props);
+            let start_offset = u32::from_le_bytes(start_offset);
+            lzma_info!("XZ x86 filter start offset: {}", start_offset);
+            count_input.read_to_end(output)?;
+            bcj::x86_code(output, start_offset, false);
+            inspect(output);
             Ok(count_input.count())
         }
+        filter_id => Err(error::Error::Unsupported {
+            what: format!("{:?} filter (ID {:#x})", filter_id, filter_id.id()),
+        }),
+    }
+}
+
+/// Maximum number of filters in a chain, see spec section 3.1.2.
+const MAX_FILTERS: usize = 4;
+
+/// Check that a filter chain has at most [`MAX_FILTERS`] filters, ends with
+/// LZMA2 and doesn't repeat any filter, so that untrusted headers can't
+/// chain pointless passes over the data.
+fn validate_filter_chain(filters: &[FilterFlags]) -> error::Result<()> {
+    let invalid = |reason: &str| {
+        let filter_ids: Vec<FilterId> = filters.iter().map(|filter| filter.filter_id).collect();
+        Err(error::Error::InvalidFilterChain(format!(
+            "{:?}, {}",
+            filter_ids, reason
+        )))
+    };
+    if filters.is_empty() || filters.len() > MAX_FILTERS {
+        return invalid("there must be between 1 and 4 filters");
+    }
+    if filters[filters.len() - 1].filter_id != FilterId::Lzma2 {
+        return invalid("LZMA2 must be the last filter");
+    }
+    for (i, filter) in filters.iter().enumerate() {
+        if filters[..i]
+            .iter()
+            .any(|previous| previous.filter_id == filter.filter_id)
+        {
+            return invalid("filters must not be repeated");
+        }
     }
+    Ok(())
 }
 
-fn read_block_header<R>(input: &mut R, header_size: u64) -> error::Result<BlockHeader>
+/// Parse a block header into `block_header`, reusing the allocations of the
+/// previous one. The block flags are at `flags_offset` in the stream, and the
+/// padding is left in the input.
+///
+/// The filter chain is validated: each filter must be known, with properties
+/// of the expected size, see also [`validate_filter_chain()`].
+fn read_block_header<R>(
+    input: &mut R,
+    header_size: u64,
+    block_header: &mut BlockHeader,
+    leniency: &mut Leniency,
+    flags_offset: u64,
+) -> error::Result<()>
 where
     R: io::BufRead,
 {
@@ -373,20 +1298,23 @@ where
     );
 
     if reserved != 0 {
-        return Err(error::Error::XzError(format!(
-            "Invalid block flags {}, reserved bits (mask 0x3C) must be zero",
-            flags
-        )));
+        leniency.tolerate(WarningKind::ReservedBlockFlags, flags_offset, || {
+            error::Error::XzError(format!(
+                "Invalid block flags {}, reserved bits (mask 0x3C) must be zero",
+                flags
+            ))
+        })?;
     }
 
+    let mut vli_offset = flags_offset + 1;
     let packed_size = if has_packed_size {
-        Some(get_multibyte(input)?)
+        Some(leniency.read_vli(input, &mut vli_offset)?)
     } else {
         None
     };
 
     let unpacked_size = if has_unpacked_size {
-        Some(get_multibyte(input)?)
+        Some(leniency.read_vli(input, &mut vli_offset)?)
     } else {
         None
     };
@@ -397,10 +1325,11 @@ where
         unpacked_size
     );
 
-    let mut filters: Vec<Filter> = vec![];
-    for _ in 0..num_filters {
-        let filter_id = get_filter_id(get_multibyte(input)?)?;
-        let size_of_properties = get_multibyte(input)?;
+    let filters = &mut block_header.filters;
+    filters.truncate(num_filters as usize);
+    for i in 0..num_filters as usize {
+        let filter_id = FilterId::from_id(leniency.read_vli(input, &mut vli_offset)?)?;
+        let size_of_properties = leniency.read_vli(input, &mut vli_offset)?;
 
         lzma_info!(
             "XZ filter: {{ filter_id: {:?}, size_of_properties: {} }}",
@@ -415,50 +1344,140 @@ where
                 size_of_properties, header_size
             )));
         }
+        if !filter_id.valid_props_len(size_of_properties) {
+            return Err(error::Error::XzError(format!(
+                "Invalid size of properties {} for filter {:?}",
+                size_of_properties, filter_id
+            )));
+        }
 
-        let mut buf = vec![0; size_of_properties as usize];
-        input.read_exact(buf.as_mut_slice()).map_err(|e| {
+        if i == filters.len() {
+            filters.push(FilterFlags {
+                filter_id,
+                props: Vec::new(),
+            });
+        }
+        let filter = &mut filters[i];
+        filter.filter_id = filter_id;
+        filter.props.clear();
+        filter.props.resize(size_of_properties as usize, 0);
+        input.read_exact(filter.props.as_mut_slice()).map_err(|e| {
             error::Error::XzError(format!(
                 "Could not read filter properties of size {}: {}",
                 size_of_properties, e
             ))
         })?;
+        vli_offset += size_of_properties;
 
-        lzma_info!("XZ filter properties: {:?}", buf);
-
-        filters.push(Filter {
-            filter_id,
-            props: buf,
-        })
+        lzma_info!("XZ filter properties: {:?}", filter.props);
     }
 
-    if !util::flush_zero_padding(input)? {
-        return Err(error::Error::XzError(
-            "Invalid block header padding, must be null bytes".to_string(),
-        ));
-    }
+    validate_filter_chain(filters)?;
 
-    Ok(BlockHeader {
-        filters,
-        packed_size,
-        unpacked_size,
-    })
+    block_header.packed_size = packed_size;
+    block_header.unpacked_size = unpacked_size;
+    Ok(())
 }
 
-pub fn get_multibyte<R>(input: &mut R) -> error::Result<u64>
-where
-    R: io::Read,
-{
-    let mut result = 0;
-    for i in 0..9 {
-        let byte = input.read_u8()?;
-        result ^= ((byte & 0x7F) as u64) << (i * 7);
-        if (byte & 0x80) == 0 {
-            return Ok(result);
-        }
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn parse_block_header(bytes: &[u8]) -> error::Result<BlockHeader> {
+        let mut block_header = BlockHeader {
+            filters: Vec::new(),
+            packed_size: None,
+            unpacked_size: None,
+        };
+        read_block_header(
+            &mut &bytes[..],
+            bytes.len() as u64,
+            &mut block_header,
+            &mut Leniency::new(false),
+            1,
+        )?;
+        Ok(block_header)
     }
 
-    Err(error::Error::XzError(
-        "Invalid multi-byte encoding".to_string(),
-    ))
+    #[test]
+    fn test_filter_chain() {
+        // Delta with a distance of 1, then LZMA2 with an 8 MiB dictionary.
+        let block_header = parse_block_header(&[0x01, 0x03, 0x01, 0x00, 0x21, 0x01, 22]).unwrap();
+        assert_eq!(
+            block_header.filters,
+            vec![
+                FilterFlags {
+                    filter_id: FilterId::Delta,
+                    props: vec![0x00],
+                },
+                FilterFlags {
+                    filter_id: FilterId::Lzma2,
+                    props: vec![22],
+                },
+            ]
+        );
+
+        // LZMA2 must be the last filter, and no filter may be repeated.
+        let err = parse_block_header(&[0x01, 0x21, 0x01, 22, 0x03, 0x01, 0x00]).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "invalid filter chain: [Lzma2, Delta], LZMA2 must be the last filter"
+        );
+        let err = parse_block_header(&[0x01, 0x21, 0x01, 22, 0x21, 0x01, 22]).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "invalid filter chain: [Lzma2, Lzma2], filters must not be repeated"
+        );
+        let err = parse_block_header(&[0x03, 0x04, 0x00, 0x05, 0x00, 0x04, 0x00, 0x21, 0x01, 22])
+            .unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "invalid filter chain: [X86, PowerPc, X86, Lzma2], filters must not be repeated"
+        );
+        // The block flags can't declare more than 4 filters.
+        let filters: Vec<FilterFlags> = [
+            FilterId::X86,
+            FilterId::Arm,
+            FilterId::Sparc,
+            FilterId::Delta,
+            FilterId::Lzma2,
+        ]
+        .iter()
+        .map(|&filter_id| FilterFlags {
+            filter_id,
+            props: Vec::new(),
+        })
+        .collect();
+        let err = validate_filter_chain(&filters).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "invalid filter chain: [X86, Arm, Sparc, Delta, Lzma2], there must be between 1 and 4 filters"
+        );
+
+        // The size of the properties depends on the filter.
+        let err = parse_block_header(&[0x01, 0x04, 0x01, 0x00, 0x21, 0x01, 22]).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "xz error: Invalid size of properties 1 for filter X86"
+        );
+        parse_block_header(&[0x01, 0x04, 0x04, 0, 0, 0, 0, 0x21, 0x01, 22]).unwrap();
+
+        let err = parse_block_header(&[0x00, 0x42, 0x00]).unwrap_err();
+        assert!(
+            matches!(err, error::Error::UnsupportedFilter(0x42)),
+            "{}",
+            err
+        );
+        // Reserved IDs of the format and of implementations.
+        let err = parse_block_header(&[0x00, 0x0C, 0x00]).unwrap_err();
+        assert_eq!(err.to_string(), "unsupported: reserved filter (ID 0xc)");
+        let err = parse_block_header(&[
+            0x00, 0x80, 0x80, 0x80, 0x80, 0x80, 0x80, 0x80, 0x80, 0x40, 0x00,
+        ])
+        .unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "xz error: Invalid filter ID 0x4000000000000000, reserved for internal use"
+        );
+    }
 }
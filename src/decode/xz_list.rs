@@ -0,0 +1,287 @@
+//! Listing the structure of `.xz` files, using the index.
+
+use crate::decode::util;
+use crate::decode::xz::{check_footer, read_index, Leniency, Record};
+use crate::error::{self, Error};
+use crate::xz::header::StreamHeader;
+use crate::xz::{CheckMethod, STREAM_HEADER_FOOTER_LEN};
+use byteorder::ReadBytesExt;
+use std::io::{self, Read, Seek, SeekFrom};
+
+/// Location of a block, computed from the index.
+#[derive(Debug)]
+pub(crate) struct BlockLocation {
+    /// Offset of the block in the file
+    pub(crate) compressed_offset: u64,
+    /// Offset of the decompressed data of the block in its stream
+    pub(crate) uncompressed_offset: u64,
+    /// Record of the block in the index
+    pub(crate) record: Record,
+}
+
+/// Location of a stream, computed from its footer and index.
+#[derive(Debug)]
+pub(crate) struct StreamLocation {
+    /// Offset of the stream header in the file
+    pub(crate) offset: u64,
+    /// Check method of the stream
+    pub(crate) check_method: CheckMethod,
+    /// Blocks listed in the index
+    pub(crate) blocks: Vec<BlockLocation>,
+    /// Total size of the decompressed data of the stream
+    pub(crate) uncompressed_size: u64,
+}
+
+/// Parse the stream ending at offset `end` of `inner`, from its footer back
+/// to its header. At most `max_records` are accepted in the index.
+pub(crate) fn read_stream_backward<R>(
+    inner: &mut io::BufReader<R>,
+    end: u64,
+    max_records: u64,
+) -> error::Result<StreamLocation>
+where
+    R: Read + Seek,
+{
+    let footer_offset = end
+        .checked_sub(STREAM_HEADER_FOOTER_LEN as u64)
+        .filter(|&offset| offset >= STREAM_HEADER_FOOTER_LEN as u64)
+        .ok_or_else(|| Error::XzError(format!("Stream ending at offset {} is too short", end)))?;
+    inner.seek(SeekFrom::Start(footer_offset))?;
+    let mut footer = [0; STREAM_HEADER_FOOTER_LEN];
+    inner.read_exact(&mut footer)?;
+    let backward_size = u32::from_le_bytes([footer[4], footer[5], footer[6], footer[7]]);
+    let index_size = (backward_size as u64 + 1) << 2;
+
+    let index_offset = footer_offset
+        .checked_sub(index_size)
+        .filter(|&offset| offset >= STREAM_HEADER_FOOTER_LEN as u64)
+        .ok_or_else(|| {
+            Error::XzError(format!(
+                "Invalid index size {}, larger than the stream",
+                index_size
+            ))
+        })?;
+    inner.seek(SeekFrom::Start(index_offset))?;
+    let records = {
+        let mut count_input = util::CountBufRead::new(inner);
+        if count_input.read_u8()? != 0 {
+            return Err(Error::XzError(
+                "Invalid index indicator, must be a null byte".to_string(),
+            ));
+        }
+        // The index holds the indicator, the number of records, two
+        // bytes per record at least and the CRC32, see sect. 4.
+        let max_records = std::cmp::min(max_records, index_size.saturating_sub(6) / 2);
+        let records = read_index(
+            &mut count_input,
+            &mut Leniency::new(false),
+            index_offset,
+            max_records,
+        )?;
+        if count_input.count() as u64 != index_size {
+            return Err(Error::XzError(format!(
+                "Invalid index size: expected {} but got {}",
+                index_size,
+                count_input.count()
+            )));
+        }
+        records
+    };
+    lzma_info!("XZ records: {:?}", records);
+
+    let size_overflow = || Error::XzError("Invalid index, sizes overflow".to_string());
+    let mut blocks_size = 0u64;
+    for record in &records {
+        // Blocks are padded to a multiple of four bytes.
+        blocks_size = record
+            .unpadded_size
+            .checked_add(3)
+            .and_then(|size| blocks_size.checked_add(size & !3))
+            .ok_or_else(size_overflow)?;
+    }
+    // The records were checked to fit before the index.
+    let offset = index_offset - blocks_size - STREAM_HEADER_FOOTER_LEN as u64;
+
+    inner.seek(SeekFrom::Start(offset))?;
+    let header = StreamHeader::parse(inner)?;
+    check_footer(&mut &footer[..], &header, index_size as usize)?;
+    let check_method = header
+        .stream_flags
+        .check_method()
+        .ok_or_else(|| Error::Unsupported {
+            what: format!(
+                "check ID {:#x} in the stream at offset {}",
+                header.stream_flags.check_id(),
+                offset
+            ),
+        })?;
+
+    let mut compressed_offset = offset + STREAM_HEADER_FOOTER_LEN as u64;
+    let mut uncompressed_offset = 0u64;
+    let mut blocks = Vec::with_capacity(records.len());
+    for record in records {
+        blocks.push(BlockLocation {
+            compressed_offset,
+            uncompressed_offset,
+            record,
+        });
+        compressed_offset += (record.unpadded_size + 3) & !3;
+        uncompressed_offset = uncompressed_offset
+            .checked_add(record.unpacked_size)
+            .ok_or_else(size_overflow)?;
+    }
+
+    Ok(StreamLocation {
+        offset,
+        check_method,
+        blocks,
+        uncompressed_size: uncompressed_offset,
+    })
+}
+
+/// Find the end of the stream followed by the stream padding ending at
+/// offset `end` of `inner`. Streams are followed by null bytes in multiples
+/// of four, see sect. 2.2.
+pub(crate) fn skip_stream_padding<R>(inner: &mut io::BufReader<R>, end: u64) -> error::Result<u64>
+where
+    R: Read + Seek,
+{
+    let mut stream_end = end;
+    let mut word = [0; 4];
+    loop {
+        if stream_end < 4 {
+            return Err(Error::XzError(format!(
+                "Invalid stream padding ending at offset {}",
+                end
+            )));
+        }
+        inner.seek(SeekFrom::Start(stream_end - 4))?;
+        inner.read_exact(&mut word)?;
+        if word != [0; 4] {
+            return Ok(stream_end);
+        }
+        stream_end -= 4;
+    }
+}
+
+/// Structure of an `.xz` file, as returned by [`xz_list()`](crate::xz_list).
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct XzListing {
+    /// Streams of the file, in order.
+    pub streams: Vec<XzStreamInfo>,
+    /// Size of the file.
+    pub file_size: u64,
+}
+
+impl XzListing {
+    /// Number of blocks in all the streams.
+    pub fn block_count(&self) -> usize {
+        self.streams.iter().map(|stream| stream.blocks.len()).sum()
+    }
+
+    /// Total size of the decompressed data.
+    pub fn uncompressed_size(&self) -> u64 {
+        self.streams.iter().fold(0, |size, stream| {
+            size.saturating_add(stream.uncompressed_size())
+        })
+    }
+
+    /// Ratio of the size of the file to the size of the decompressed data,
+    /// as displayed by `xz --list`. Returns [`None`] if there is no
+    /// decompressed data.
+    pub fn ratio(&self) -> Option<f64> {
+        match self.uncompressed_size() {
+            0 => None,
+            size => Some(self.file_size as f64 / size as f64),
+        }
+    }
+}
+
+/// Structure of a stream of an `.xz` file.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct XzStreamInfo {
+    /// Offset of the stream in the file.
+    pub offset: u64,
+    /// Size of the stream, from its header to its footer.
+    pub compressed_size: u64,
+    /// Size of the stream padding following the stream.
+    pub padding: u64,
+    /// Check method of the stream.
+    pub check: CheckMethod,
+    /// Blocks of the stream, in order.
+    pub blocks: Vec<XzBlockInfo>,
+}
+
+impl XzStreamInfo {
+    /// Total size of the decompressed data of the stream.
+    pub fn uncompressed_size(&self) -> u64 {
+        self.blocks
+            .iter()
+            .map(|block| block.uncompressed_size)
+            .sum()
+    }
+}
+
+/// Structure of a block of an `.xz` file, as recorded in the index.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct XzBlockInfo {
+    /// Offset of the block in the file.
+    pub compressed_offset: u64,
+    /// Offset of the decompressed data of the block, across all streams.
+    pub uncompressed_offset: u64,
+    /// Size of the block, from its header to its check and without its
+    /// padding.
+    pub unpadded_size: u64,
+    /// Size of the decompressed data of the block.
+    pub uncompressed_size: u64,
+}
+
+/// Read the structure of the `.xz` file `input`, from the stream headers,
+/// footers and indexes only.
+pub fn list<R>(input: &mut R) -> error::Result<XzListing>
+where
+    R: Read + Seek,
+{
+    let mut inner = io::BufReader::new(input);
+    let file_size = inner.seek(SeekFrom::End(0))?;
+    let mut streams = Vec::new();
+    let mut end = file_size;
+    while end > 0 {
+        let stream_end = skip_stream_padding(&mut inner, end)?;
+        let stream = read_stream_backward(&mut inner, stream_end, u64::MAX)?;
+        let start = stream.offset;
+        streams.push((stream, stream_end, end - stream_end));
+        end = start;
+    }
+    if streams.is_empty() {
+        return Err(Error::XzError("Empty file, expected a stream".to_string()));
+    }
+
+    let mut uncompressed_offset = 0u64;
+    let streams = streams
+        .into_iter()
+        .rev()
+        .map(|(stream, stream_end, padding)| {
+            let blocks = stream
+                .blocks
+                .iter()
+                .map(|block| XzBlockInfo {
+                    compressed_offset: block.compressed_offset,
+                    uncompressed_offset: uncompressed_offset
+                        .saturating_add(block.uncompressed_offset),
+                    unpadded_size: block.record.unpadded_size,
+                    uncompressed_size: block.record.unpacked_size,
+                })
+                .collect();
+            uncompressed_offset = uncompressed_offset.saturating_add(stream.uncompressed_size);
+            XzStreamInfo {
+                offset: stream.offset,
+                compressed_size: stream_end - stream.offset,
+                padding,
+                check: stream.check_method,
+                blocks,
+            }
+        })
+        .collect();
+    Ok(XzListing { streams, file_size })
+}
@@ -0,0 +1,372 @@
+//! Random access into `.xz` files, using the index.
+
+use crate::decode::options::Options;
+use crate::decode::util;
+use crate::decode::xz::{read_block, BlockDecoder, Record};
+use crate::decode::xz_list::{read_stream_backward, skip_stream_padding, BlockLocation, XzListing};
+use crate::error::{self, Error};
+use crate::xz::header::StreamHeader;
+use crate::xz::{CheckMethod, StreamFlags};
+use byteorder::ReadBytesExt;
+use std::convert::TryFrom;
+use std::fmt::Debug;
+use std::io::{self, Read, Seek, SeekFrom};
+
+/// XZ decompressor providing random access to the decompressed data of a
+/// seekable input.
+///
+/// The index at the end of the stream is parsed upfront, so that
+/// [`XzSeekReader::seek_to_uncompressed()`] only decodes the block containing
+/// the requested offset. Random access is therefore only efficient for files
+/// made of multiple blocks, such as those written by `xz --block-size` or
+/// with [`Options::block_size`](crate::compress::Options::block_size).
+///
+/// Only files made of a single stream, possibly followed by stream padding,
+/// are supported.
+pub struct XzSeekReader<R>
+where
+    R: Read + Seek,
+{
+    /// Underlying reader
+    inner: io::BufReader<R>,
+    /// Check method of the stream
+    check_method: CheckMethod,
+    /// Blocks listed in the index
+    blocks: Vec<BlockLocation>,
+    /// Total size of the decompressed data
+    uncompressed_size: u64,
+    /// State and buffers reused across blocks
+    decoder: BlockDecoder,
+    /// Decompressed data of the loaded block
+    data: Vec<u8>,
+    /// Index of the block in `data`, if any
+    loaded: Option<usize>,
+    /// Position of the next byte to read in `data`
+    pos: usize,
+    /// Index of the block to load once `data` is exhausted
+    next_block: usize,
+}
+
+impl<R> XzSeekReader<R>
+where
+    R: Read + Seek,
+{
+    /// Parse the stream header, footer and index of `inner`.
+    pub fn new(inner: R) -> error::Result<Self> {
+        Self::with_options(inner, &Options::default())
+    }
+
+    /// Parse the stream header, footer and index of `inner`, with the
+    /// provided options. Only
+    /// [`max_index_records`](Options::max_index_records) and
+    /// [`max_block_output`](Options::max_block_output) apply.
+    pub fn with_options(inner: R, options: &Options) -> error::Result<Self> {
+        let mut inner = io::BufReader::new(inner);
+        inner.seek(SeekFrom::Start(0))?;
+        StreamHeader::parse(&mut inner)?;
+
+        let file_size = inner.seek(SeekFrom::End(0))?;
+        let end = skip_stream_padding(&mut inner, file_size)?;
+        let stream = read_stream_backward(
+            &mut inner,
+            end,
+            options.max_index_records.unwrap_or(u64::MAX),
+        )?;
+        if stream.offset != 0 {
+            return Err(Error::XzError(format!(
+                "Invalid index, the last stream starts at offset {} instead of 0",
+                stream.offset
+            )));
+        }
+
+        let mut decoder = BlockDecoder::new();
+        decoder.max_output = options.max_block_output;
+        Ok(Self {
+            inner,
+            check_method: stream.check_method,
+            blocks: stream.blocks,
+            uncompressed_size: stream.uncompressed_size,
+            decoder,
+            data: Vec::new(),
+            loaded: None,
+            pos: 0,
+            next_block: 0,
+        })
+    }
+
+    /// Total size of the decompressed data, according to the index.
+    pub fn uncompressed_size(&self) -> u64 {
+        self.uncompressed_size
+    }
+
+    /// Position of the next byte to read in the decompressed data.
+    pub fn position(&self) -> u64 {
+        match self.loaded {
+            Some(index) => self.blocks[index].uncompressed_offset + self.pos as u64,
+            None => self
+                .blocks
+                .get(self.next_block)
+                .map_or(self.uncompressed_size, |block| block.uncompressed_offset),
+        }
+    }
+
+    /// Number of blocks in the stream.
+    pub fn block_count(&self) -> usize {
+        self.blocks.len()
+    }
+
+    /// Position the reader at `offset` in the decompressed data.
+    ///
+    /// The block containing `offset` is decoded from its start, and only the
+    /// following bytes are returned by subsequent reads.
+    pub fn seek_to_uncompressed(&mut self, offset: u64) -> error::Result<()> {
+        if offset > self.uncompressed_size {
+            return Err(Error::XzError(format!(
+                "Offset {} is past the end of the decompressed data ({} bytes)",
+                offset, self.uncompressed_size
+            )));
+        }
+
+        let index = self.blocks.partition_point(|block| {
+            block.uncompressed_offset + block.record.unpacked_size <= offset
+        });
+        if index == self.blocks.len() {
+            self.data.clear();
+            self.loaded = None;
+            self.pos = 0;
+            self.next_block = index;
+            return Ok(());
+        }
+
+        if self.loaded != Some(index) {
+            self.load_block(index)?;
+        }
+        self.pos = (offset - self.blocks[index].uncompressed_offset) as usize;
+        self.next_block = index + 1;
+        Ok(())
+    }
+
+    /// Unwraps this `XzSeekReader`, returning the underlying reader.
+    pub fn into_inner(self) -> R {
+        self.inner.into_inner()
+    }
+
+    /// Decode the block at `index` into `data`.
+    fn load_block(&mut self, index: usize) -> error::Result<()> {
+        self.loaded = None;
+        self.pos = 0;
+        let mut data = std::mem::take(&mut self.data);
+        data.clear();
+        let result = self.decode_block(index, &mut data);
+        self.data = data;
+        result?;
+
+        self.loaded = Some(index);
+        self.next_block = index + 1;
+        Ok(())
+    }
+
+    /// Decode the block at `index` into `output`, and check it against its
+    /// record in the index.
+    fn decode_block<W>(&mut self, index: usize, output: &mut W) -> error::Result<()>
+    where
+        W: io::Write,
+    {
+        let location = &self.blocks[index];
+        lzma_info!("XZ loading block {}: {:?}", index, location);
+        decode_block_at(
+            &mut self.inner,
+            output,
+            &mut self.decoder,
+            StreamFlags::new(self.check_method),
+            index,
+            location.compressed_offset,
+            location.record,
+        )
+    }
+}
+
+/// Decode the block number `index` at `offset` in `inner` into `output`, and
+/// check it against its `record` in the index.
+fn decode_block_at<R, W>(
+    inner: &mut io::BufReader<R>,
+    output: &mut W,
+    decoder: &mut BlockDecoder,
+    stream_flags: StreamFlags,
+    index: usize,
+    offset: u64,
+    record: Record,
+) -> error::Result<()>
+where
+    R: Read + Seek,
+    W: io::Write,
+{
+    inner.seek(SeekFrom::Start(offset))?;
+    let mut count_input = util::CountBufRead::new(inner);
+    let header_size = count_input.read_u8()?;
+    if header_size == 0 {
+        return Err(Error::XzError(format!(
+            "Expected block {} at offset {}, found the index",
+            index, offset
+        )));
+    }
+
+    let mut records = Vec::with_capacity(1);
+    read_block(
+        &mut count_input,
+        output,
+        decoder,
+        stream_flags,
+        &mut records,
+        header_size,
+        None,
+    )?;
+    if records[..] != [record] {
+        return Err(Error::XzError(format!(
+            "Invalid index for record {}: {:?} does not match block {:?}",
+            index, record, records
+        )));
+    }
+    Ok(())
+}
+
+/// Decode the block number `index` of the file `input`, as listed in
+/// `listing`, into `output`.
+pub fn decompress_block<R, W>(
+    input: &mut R,
+    listing: &XzListing,
+    index: usize,
+    output: &mut W,
+) -> error::Result<()>
+where
+    R: Read + Seek,
+    W: io::Write,
+{
+    let (stream, block) = listing
+        .streams
+        .iter()
+        .flat_map(|stream| stream.blocks.iter().map(move |block| (stream, block)))
+        .nth(index)
+        .ok_or_else(|| {
+            Error::XzError(format!(
+                "Block {} is out of range, the file has {} blocks",
+                index,
+                listing.block_count()
+            ))
+        })?;
+    lzma_info!("XZ decoding block {}: {:?}", index, block);
+    let record = Record {
+        unpadded_size: block.unpadded_size,
+        unpacked_size: block.uncompressed_size,
+    };
+    decode_block_at(
+        &mut io::BufReader::new(input),
+        output,
+        &mut BlockDecoder::new(),
+        StreamFlags::new(stream.check),
+        index,
+        block.compressed_offset,
+        record,
+    )
+}
+
+/// Decode all the blocks of the file `input` into `output`, which must hold
+/// the total decompressed size given by the index.
+pub fn decompress_into<R>(input: &mut R, output: &mut [u8]) -> error::Result<usize>
+where
+    R: Read + Seek,
+{
+    let listing = crate::decode::xz_list::list(input)?;
+    let size = listing.uncompressed_size();
+    if size > output.len() as u64 {
+        lzma_info!("XZ output of {} bytes too small for {}", output.len(), size);
+        return Err(Error::OutputTooLarge(output.len() as u64));
+    }
+
+    let mut inner = io::BufReader::new(input);
+    let mut decoder = BlockDecoder::new();
+    let mut pos = 0;
+    let mut index = 0;
+    for stream in &listing.streams {
+        for block in &stream.blocks {
+            let record = Record {
+                unpadded_size: block.unpadded_size,
+                unpacked_size: block.uncompressed_size,
+            };
+            let end = pos + block.uncompressed_size as usize;
+            decode_block_at(
+                &mut inner,
+                &mut &mut output[pos..end],
+                &mut decoder,
+                StreamFlags::new(stream.check),
+                index,
+                block.compressed_offset,
+                record,
+            )?;
+            pos = end;
+            index += 1;
+        }
+    }
+    Ok(pos)
+}
+
+impl<R> Read for XzSeekReader<R>
+where
+    R: Read + Seek,
+{
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        while self.pos == self.data.len() {
+            if self.next_block == self.blocks.len() {
+                return Ok(0);
+            }
+            self.load_block(self.next_block)
+                .map_err(util::into_io_error)?;
+        }
+
+        let len = std::cmp::min(buf.len(), self.data.len() - self.pos);
+        buf[..len].copy_from_slice(&self.data[self.pos..self.pos + len]);
+        self.pos += len;
+        Ok(len)
+    }
+
+    /// Decodes the remaining blocks directly into `buf`, reserved before each
+    /// block from its size in the index, up to
+    /// [`max_block_output`](Options::max_block_output).
+    fn read_to_end(&mut self, buf: &mut Vec<u8>) -> io::Result<usize> {
+        let start = buf.len();
+        buf.extend_from_slice(&self.data[self.pos..]);
+        self.pos = self.data.len();
+
+        while self.next_block < self.blocks.len() {
+            // The index is not trusted: a failed allocation is reported as an
+            // error rather than aborting.
+            let size = self.blocks[self.next_block].record.unpacked_size;
+            let size = self.decoder.max_output.map_or(size, |max| size.min(max));
+            buf.try_reserve(usize::try_from(size).unwrap_or(usize::MAX))
+                .map_err(|e| util::into_io_error(Error::AllocationFailed(e)))?;
+            self.decode_block(self.next_block, buf)
+                .map_err(util::into_io_error)?;
+            self.next_block += 1;
+        }
+        // The data of the last decoded block is in `buf` only.
+        self.data.clear();
+        self.loaded = None;
+        self.pos = 0;
+        Ok(buf.len() - start)
+    }
+}
+
+impl<R> Debug for XzSeekReader<R>
+where
+    R: Read + Seek + Debug,
+{
+    fn fmt(&self, fmt: &mut std::fmt::Formatter) -> std::fmt::Result {
+        fmt.debug_struct("XzSeekReader")
+            .field("inner", &self.inner)
+            .field("blocks", &self.blocks.len())
+            .field("uncompressed_size", &self.uncompressed_size)
+            .field("loaded", &self.loaded)
+            .field("pos", &self.pos)
+            .finish()
+    }
+}
@@ -0,0 +1,343 @@
+//! Push-model decoder for the `.xz` file format.
+
+use crate::decode::util;
+use crate::decode::xz::{
+    check_footer, check_index, check_stream_padding_size, BlockChunks, BlockDecoder, Leniency,
+    Record,
+};
+use crate::error::{self, Error};
+use crate::xz::header::StreamHeader;
+use crate::xz::vli::VLI_BYTES_MAX;
+use crate::xz::STREAM_HEADER_FOOTER_LEN;
+use byteorder::ReadBytesExt;
+use std::fmt::Debug;
+use std::io::{self, Write};
+
+/// Internal state of the decoder, i.e. what the buffered input starts with.
+#[derive(Debug)]
+enum State {
+    /// Stream header.
+    Header,
+    /// A block header or the index.
+    Blocks(StreamHeader),
+    /// An LZMA2 chunk of a block, or its end-of-stream marker.
+    Chunks(StreamHeader, BlockChunks),
+    /// Padding and check of a block, after its end-of-stream marker.
+    BlockEnd(StreamHeader, BlockChunks),
+    /// Stream footer, given the size of the index.
+    Footer(StreamHeader, usize),
+    /// Nothing but stream padding, the stream is complete. This holds the
+    /// size of the padding so far.
+    Done(u64),
+}
+
+/// XZ decompressor that can process multiple chunks of data using the
+/// [`io::Write`] interface.
+///
+/// Input is buffered until a complete unit is available, e.g. a block header
+/// or an LZMA2 chunk of at most about 2 MiB, which is then decoded. The output
+/// of each chunk is written right away, so that the check of a block is only
+/// verified after its data is written. Blocks using other filters than LZMA2,
+/// e.g. x86, are written once complete and validated instead.
+#[cfg_attr(docsrs, doc(cfg(stream)))]
+pub struct XzWriteDecoder<W>
+where
+    W: Write,
+{
+    /// Output sink
+    output: W,
+    /// Input not processed yet, i.e. an incomplete header, chunk or index
+    buf: Vec<u8>,
+    /// Current state, [`None`] after an error
+    state: Option<State>,
+    /// Records of the blocks decoded so far, to check the index
+    records: Vec<Record>,
+    /// State and buffers reused across blocks
+    block: BlockDecoder,
+}
+
+impl<W> XzWriteDecoder<W>
+where
+    W: Write,
+{
+    /// Initialize the decoder. This will consume the `output` which is the
+    /// sink implementing [`io::Write`] that will receive decompressed bytes.
+    pub fn new(output: W) -> Self {
+        Self {
+            output,
+            buf: Vec::new(),
+            state: Some(State::Header),
+            records: Vec::new(),
+            block: BlockDecoder::new(),
+        }
+    }
+
+    /// Get a reference to the output sink.
+    pub fn get_output(&self) -> &W {
+        &self.output
+    }
+
+    /// Get a mutable reference to the output sink.
+    pub fn get_output_mut(&mut self) -> &mut W {
+        &mut self.output
+    }
+
+    /// Consumes the decoder and returns the output sink. This also makes sure
+    /// we have properly reached the end of the stream, including the footer.
+    pub fn finish(mut self) -> error::Result<W> {
+        match self.state {
+            Some(State::Done(padding_size)) => {
+                check_stream_padding_size(padding_size)?;
+                self.output.flush()?;
+                Ok(self.output)
+            }
+            Some(_) => Err(Error::Truncated(
+                "XZ stream ended before its footer".to_string(),
+            )),
+            None => Err(Error::XzError("previous write error".to_string())),
+        }
+    }
+
+    /// Process as many complete units of `input` as possible, and return the
+    /// length of the processed ones.
+    fn process(&mut self, input: &[u8]) -> error::Result<usize> {
+        let mut pos = 0;
+        while let Some(len) = self.step(&input[pos..])? {
+            pos += len;
+        }
+        Ok(pos)
+    }
+
+    /// Process the unit at the start of `pending` if it's complete, and
+    /// return its length.
+    fn step(&mut self, pending: &[u8]) -> error::Result<Option<usize>> {
+        match self.state.take() {
+            Some(State::Header) => {
+                if pending.len() < STREAM_HEADER_FOOTER_LEN {
+                    self.state = Some(State::Header);
+                    return Ok(None);
+                }
+                let header = StreamHeader::parse(&mut &pending[..STREAM_HEADER_FOOTER_LEN])?;
+                self.state = Some(State::Blocks(header));
+                Ok(Some(STREAM_HEADER_FOOTER_LEN))
+            }
+            Some(State::Blocks(header)) => {
+                self.state = Some(State::Blocks(header));
+                let header_size = match pending.first() {
+                    Some(&header_size) => header_size,
+                    None => return Ok(None),
+                };
+
+                if header_size != 0 {
+                    let len = (header_size as usize + 1) << 2;
+                    if pending.len() < len {
+                        return Ok(None);
+                    }
+                    self.block.read_header(&mut &pending[1..len], header_size)?;
+                    let chunks = self.block.start_chunks(header.stream_flags, len)?;
+                    self.state = Some(State::Chunks(header, chunks));
+                    return Ok(Some(len));
+                }
+
+                let len = match self.index_len(pending) {
+                    Some(len) => len,
+                    None => return Ok(None),
+                };
+                let mut input = &pending[..len];
+                let mut count_input = util::CountBufRead::new(&mut input);
+                count_input.read_u8()?;
+                lzma_info!("XZ records: {:?}", self.records);
+                // Offsets are only reported in lenient mode.
+                check_index(
+                    &mut count_input,
+                    &self.records,
+                    &mut Leniency::new(false),
+                    0,
+                    u64::MAX,
+                )?;
+                self.state = Some(State::Footer(header, len));
+                Ok(Some(len))
+            }
+            Some(State::Chunks(header, mut chunks)) => {
+                let len = match chunk_len(pending)? {
+                    Some(len) => len,
+                    None => {
+                        self.state = Some(State::Chunks(header, chunks));
+                        return Ok(None);
+                    }
+                };
+                let end =
+                    self.block
+                        .decode_chunk(&mut chunks, &pending[..len], &mut self.output)?;
+                self.state = Some(if end {
+                    State::BlockEnd(header, chunks)
+                } else {
+                    State::Chunks(header, chunks)
+                });
+                Ok(Some(len))
+            }
+            Some(State::BlockEnd(header, chunks)) => {
+                let len = chunks.trailer_len();
+                if pending.len() < len {
+                    self.state = Some(State::BlockEnd(header, chunks));
+                    return Ok(None);
+                }
+                self.block.finish_chunks(
+                    chunks,
+                    &pending[..len],
+                    &mut self.output,
+                    &mut self.records,
+                )?;
+                self.state = Some(State::Blocks(header));
+                Ok(Some(len))
+            }
+            Some(State::Footer(header, index_size)) => {
+                if pending.len() < STREAM_HEADER_FOOTER_LEN {
+                    self.state = Some(State::Footer(header, index_size));
+                    return Ok(None);
+                }
+                check_footer(
+                    &mut &pending[..STREAM_HEADER_FOOTER_LEN],
+                    &header,
+                    index_size,
+                )?;
+                self.state = Some(State::Done(0));
+                Ok(Some(STREAM_HEADER_FOOTER_LEN))
+            }
+            Some(State::Done(padding_size)) => {
+                if pending.iter().any(|&byte| byte != 0) {
+                    return Err(Error::XzError(
+                        "Unexpected data after last XZ block".to_string(),
+                    ));
+                }
+                self.state = Some(State::Done(padding_size + pending.len() as u64));
+                if pending.is_empty() {
+                    Ok(None)
+                } else {
+                    Ok(Some(pending.len()))
+                }
+            }
+            None => Err(Error::XzError("previous write error".to_string())),
+        }
+    }
+
+    /// Length of the index at the start of `pending`, or [`None`] if it's
+    /// incomplete.
+    fn index_len(&self, pending: &[u8]) -> Option<usize> {
+        let (num_records, mut pos) = scan_vli(pending, 1)?;
+        if num_records != self.records.len() as u64 {
+            // Let the index check report the mismatch.
+            return Some(pos);
+        }
+        for _ in 0..2 * num_records {
+            let (_, end) = scan_vli(pending, pos)?;
+            pos = end;
+        }
+        let padding_size = ((pos ^ 0x03) + 1) & 0x03;
+        let len = pos + padding_size + 4;
+        if pending.len() >= len {
+            Some(len)
+        } else {
+            None
+        }
+    }
+}
+
+/// Length of the LZMA2 chunk, or end-of-stream marker, at the start of
+/// `pending`, or [`None`] if it's incomplete.
+fn chunk_len(pending: &[u8]) -> error::Result<Option<usize>> {
+    let control = match pending.first() {
+        Some(&control) => control,
+        None => return Ok(None),
+    };
+    let len = match control {
+        0 => 1,
+        1 | 2 => match pending.get(1..3) {
+            Some(size) => 3 + (u16::from_be_bytes([size[0], size[1]]) as usize + 1),
+            None => return Ok(None),
+        },
+        0x80..=0xFF => match pending.get(3..5) {
+            Some(size) => {
+                let header_len = if control >= 0xC0 { 6 } else { 5 };
+                header_len + (u16::from_be_bytes([size[0], size[1]]) as usize + 1)
+            }
+            None => return Ok(None),
+        },
+        _ => {
+            return Err(Error::LzmaError(format!(
+                "LZMA2 invalid status {}, must be 0, 1, 2 or >= 128",
+                control
+            )))
+        }
+    };
+    Ok(if pending.len() >= len {
+        Some(len)
+    } else {
+        None
+    })
+}
+
+/// Scan the variable-length integer at `pos`, returning its value and where it
+/// ends, or [`None`] if it's incomplete. Malformed integers are left for
+/// [`check_index`] to report.
+fn scan_vli(pending: &[u8], pos: usize) -> Option<(u64, usize)> {
+    let mut value = 0;
+    for i in 0..VLI_BYTES_MAX {
+        let byte = *pending.get(pos + i)?;
+        value |= ((byte & 0x7F) as u64) << (i * 7);
+        if (byte & 0x80) == 0 {
+            return Some((value, pos + i + 1));
+        }
+    }
+    Some((value, pos + VLI_BYTES_MAX))
+}
+
+impl<W> Debug for XzWriteDecoder<W>
+where
+    W: Write + Debug,
+{
+    fn fmt(&self, fmt: &mut std::fmt::Formatter) -> std::fmt::Result {
+        fmt.debug_struct("XzWriteDecoder")
+            .field("output", &self.output)
+            .field("buf", &self.buf.len())
+            .field("state", &self.state)
+            .finish()
+    }
+}
+
+impl<W> Write for XzWriteDecoder<W>
+where
+    W: Write,
+{
+    fn write(&mut self, data: &[u8]) -> io::Result<usize> {
+        if self.state.is_none() {
+            return Err(io::Error::new(io::ErrorKind::Other, "previous write error"));
+        }
+
+        // Only the incomplete unit at the end of the input is buffered, so
+        // that large writes are processed in place.
+        let result = if self.buf.is_empty() {
+            self.process(data)
+                .map(|pos| self.buf.extend_from_slice(&data[pos..]))
+        } else {
+            let mut buf = std::mem::take(&mut self.buf);
+            buf.extend_from_slice(data);
+            let result = self.process(&buf).map(|pos| {
+                buf.drain(..pos);
+            });
+            self.buf = buf;
+            result
+        };
+        if let Err(e) = result {
+            self.state = None;
+            return Err(e.into());
+        }
+        Ok(data.len())
+    }
+
+    /// Flushes the output sink. Buffered input that doesn't form a complete
+    /// chunk yet isn't decoded.
+    fn flush(&mut self) -> io::Result<()> {
+        self.output.flush()
+    }
+}
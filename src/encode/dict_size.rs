@@ -0,0 +1,71 @@
+//! Dictionary sizes representable in the LZMA2 properties byte of `.xz`
+//! files, see sect. 5.3.1 of the XZ spec.
+//!
+//! The `.lzma` header stores the dictionary size as a raw 32-bit value, so
+//! any size up to [`u32::MAX`] is representable there.
+
+use crate::decode::lzma2::dict_size_from_props;
+
+/// Largest value of the LZMA2 dictionary size byte.
+const MAX_DICT_SIZE_BYTE: u8 = 40;
+
+/// Rounds `dict_size` up to the nearest size representable in the `.xz`
+/// format, i.e. `2^n` or `2^n + 2^(n-1)` bytes.
+///
+/// Sizes below 4 KiB are raised to 4 KiB, and sizes above 3 GiB are clamped
+/// to the maximum of `0xFFFF_FFFF` bytes.
+pub fn normalize_dict_size(dict_size: u64) -> u64 {
+    (0..=MAX_DICT_SIZE_BYTE)
+        .map(props_dict_size)
+        .find(|&size| size >= dict_size)
+        .unwrap_or(u32::MAX as u64)
+}
+
+/// Encodes `dict_size` into the 1-byte form of the `.xz` format, or returns
+/// [`None`] if it isn't representable exactly.
+///
+/// Use [`normalize_dict_size()`] to obtain a representable size.
+pub fn encode_dict_size_byte(dict_size: u64) -> Option<u8> {
+    (0..=MAX_DICT_SIZE_BYTE).find(|&props| props_dict_size(props) == dict_size)
+}
+
+fn props_dict_size(props: u8) -> u64 {
+    dict_size_from_props(props).unwrap() as u64
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_normalize_dict_size() {
+        assert_eq!(normalize_dict_size(0), 4096);
+        assert_eq!(normalize_dict_size(4096), 4096);
+        assert_eq!(normalize_dict_size(4097), 6144);
+        assert_eq!(normalize_dict_size(6144), 6144);
+        assert_eq!(normalize_dict_size(6145), 8192);
+        assert_eq!(normalize_dict_size(1_000_000), 0x0010_0000);
+        assert_eq!(normalize_dict_size(0x0080_0000), 0x0080_0000);
+        assert_eq!(normalize_dict_size(0xC000_0000), 0xC000_0000);
+        assert_eq!(normalize_dict_size(0xC000_0001), 0xFFFF_FFFF);
+        assert_eq!(normalize_dict_size(u64::MAX), 0xFFFF_FFFF);
+    }
+
+    #[test]
+    fn test_encode_dict_size_byte() {
+        assert_eq!(encode_dict_size_byte(4096), Some(0));
+        assert_eq!(encode_dict_size_byte(6144), Some(1));
+        assert_eq!(encode_dict_size_byte(0x0080_0000), Some(22));
+        assert_eq!(encode_dict_size_byte(0xC000_0000), Some(39));
+        assert_eq!(encode_dict_size_byte(0xFFFF_FFFF), Some(40));
+        assert_eq!(encode_dict_size_byte(0), None);
+        assert_eq!(encode_dict_size_byte(2048), None);
+        assert_eq!(encode_dict_size_byte(1_000_000), None);
+        assert_eq!(encode_dict_size_byte(0x1_0000_0000), None);
+        for props in 0..=MAX_DICT_SIZE_BYTE {
+            let dict_size = props_dict_size(props);
+            assert_eq!(normalize_dict_size(dict_size), dict_size);
+            assert_eq!(encode_dict_size_byte(dict_size), Some(props));
+        }
+    }
+}
@@ -1,5 +1,6 @@
-use crate::compress::{Options, UnpackedSize};
+use crate::compress::{CompressStats, Options, UnpackedSize};
 use crate::encode::rangecoder;
+use crate::util::vec2d::Vec2D;
 use byteorder::{LittleEndian, WriteBytesExt};
 use std::io;
 
@@ -8,31 +9,153 @@ where
     W: 'a + io::Write,
 {
     rangecoder: rangecoder::RangeEncoder<'a, W>,
-    literal_probs: [[u16; 0x300]; 8],
-    is_match: [u16; 4], // true = LZ, false = literal
+    model: LiteralModel,
     unpacked_size: UnpackedSize,
+    end_marker: bool,
+    header_size: u64,
+}
+
+pub(crate) const LC: u32 = 3;
+pub(crate) const LP: u32 = 0;
+pub(crate) const PB: u32 = 2;
+
+// The properties are fixed rather than given by the caller, so the literal
+// model takes `0x300 << (LC + LP)` probabilities, i.e. 12 KiB, except for
+// small inputs. They must still be valid in LZMA2, see
+// `decode::lzma2::check_props()`.
+const _: () = assert!(LC + LP <= 4 && PB <= 4);
+
+/// LZMA properties byte matching `LC`, `LP` and `PB`.
+pub(crate) const PROPS: u8 = (LC + 9 * (LP + 5 * PB)) as u8;
+
+/// Dictionary size written to the headers.
+pub(crate) const DICT_SIZE: u32 = 0x0080_0000;
+
+/// Maximum size of the inputs taking the fast path for small messages: a
+/// model without literal context, i.e. `lc = 0` and 0x300 probabilities, and
+/// the smallest dictionary in the header, which decoders then allocate.
+const SMALL_INPUT_LEN: u64 = 0x200;
+
+/// Dictionary size written to the headers of small inputs, the minimum of
+/// the format.
+const SMALL_DICT_SIZE: u32 = 0x1000;
+
+/// Probabilities of the literal-only model, kept apart from the range coder
+/// so that LZMA2 can carry them over from one chunk to the next.
+pub(crate) struct LiteralModel {
+    literal_probs: Vec2D<u16>,
+    is_match: [u16; 4], // true = LZ, false = literal
+    lc: u32,
 }
 
-const LC: u32 = 3;
-const LP: u32 = 0;
-const PB: u32 = 2;
+impl LiteralModel {
+    pub fn new() -> Self {
+        Self::with_lc(LC)
+    }
+
+    /// Model with `lc` bits of literal context instead of `LC`.
+    fn with_lc(lc: u32) -> Self {
+        LiteralModel {
+            literal_probs: Vec2D::init(0x400, (1 << (lc + LP), 0x300)),
+            is_match: [0x400; 4],
+            lc,
+        }
+    }
+
+    /// Encodes `byte` as a literal at uncompressed position `pos`.
+    pub fn encode_literal<W>(
+        &mut self,
+        rangecoder: &mut rangecoder::RangeEncoder<W>,
+        pos: usize,
+        byte: u8,
+        prev_byte: u8,
+    ) -> io::Result<()>
+    where
+        W: io::Write,
+    {
+        let pos_state = pos & 3;
+
+        // Literal
+        rangecoder.encode_bit(&mut self.is_match[pos_state], false)?;
+
+        let prev_byte = prev_byte as usize;
+
+        let mut result: usize = 1;
+        let lit_state = prev_byte >> (8 - self.lc);
+        let probs = &mut self.literal_probs[lit_state];
+
+        for i in 0..8 {
+            let bit = ((byte >> (7 - i)) & 1) != 0;
+            rangecoder.encode_bit(&mut probs[result], bit)?;
+            result = (result << 1) ^ (bit as usize);
+        }
+
+        Ok(())
+    }
+
+    /// Encodes the end-of-stream marker at uncompressed position `pos`.
+    pub fn encode_end_marker<W>(
+        &mut self,
+        rangecoder: &mut rangecoder::RangeEncoder<W>,
+        pos: usize,
+    ) -> io::Result<()>
+    where
+        W: io::Write,
+    {
+        let pos_state = pos & 3;
+
+        // Match
+        rangecoder.encode_bit(&mut self.is_match[pos_state], true)?;
+        // New distance
+        rangecoder.encode_bit(&mut 0x400, false)?;
+
+        // Dummy len, as small as possible (len = 0)
+        for _ in 0..4 {
+            rangecoder.encode_bit(&mut 0x400, false)?;
+        }
+
+        // Distance marker = 0xFFFFFFFF
+        // pos_slot = 63
+        for _ in 0..6 {
+            rangecoder.encode_bit(&mut 0x400, true)?;
+        }
+        // num_direct_bits = 30
+        // result = 3 << 30 = C000_0000
+        //        + 3FFF_FFF0  (26 bits)
+        //        + F          ( 4 bits)
+        for _ in 0..30 {
+            rangecoder.encode_bit(&mut 0x400, true)?;
+        }
+        //        = FFFF_FFFF
+        Ok(())
+    }
+}
 
 impl<'a, W> Encoder<'a, W>
 where
     W: io::Write,
 {
     pub fn from_stream(stream: &'a mut W, options: &Options) -> io::Result<Self> {
-        let dict_size = 0x0080_0000;
+        let small = matches!(
+            options.unpacked_size,
+            UnpackedSize::WriteToHeader(Some(len)) if len <= SMALL_INPUT_LEN
+        );
+        let (lc, dict_size) = if small {
+            (0, SMALL_DICT_SIZE)
+        } else {
+            (LC, DICT_SIZE)
+        };
 
         // Properties
-        let props = (LC + 9 * (LP + 5 * PB)) as u8;
-        lzma_info!("Properties {{ lc: {}, lp: {}, pb: {} }}", LC, LP, PB);
-        stream.write_u8(props)?;
+        lzma_info!("Properties {{ lc: {}, lp: {}, pb: {} }}", lc, LP, PB);
+        stream.write_u8((lc + 9 * (LP + 5 * PB)) as u8)?;
 
         // Dictionary
         lzma_info!("Dict size: {}", dict_size);
         stream.write_u32::<LittleEndian>(dict_size)?;
 
+        let mut header_size = 5;
+
         // Unpacked size
         match &options.unpacked_size {
             UnpackedSize::WriteToHeader(unpacked_size) => {
@@ -47,94 +170,67 @@ where
                     }
                 };
                 stream.write_u64::<LittleEndian>(value)?;
+                header_size += 8;
             }
             UnpackedSize::SkipWritingToHeader => {}
         };
 
         let encoder = Encoder {
             rangecoder: rangecoder::RangeEncoder::new(stream),
-            literal_probs: [[0x400; 0x300]; 8],
-            is_match: [0x400; 4],
+            model: LiteralModel::with_lc(lc),
             unpacked_size: options.unpacked_size,
+            end_marker: options.always_write_end_marker
+                || matches!(options.unpacked_size, UnpackedSize::WriteToHeader(None)),
+            header_size,
         };
 
         Ok(encoder)
     }
 
-    pub fn process<R>(mut self, input: R) -> io::Result<()>
+    pub fn process<R>(mut self, input: R) -> io::Result<CompressStats>
     where
-        R: io::Read,
+        R: io::BufRead,
     {
         let mut prev_byte = 0u8;
         let mut input_len = 0;
 
         for (out_len, byte_result) in input.bytes().enumerate() {
             let byte = byte_result?;
-            let pos_state = out_len & 3;
-            input_len = out_len;
-
-            // Literal
-            self.rangecoder
-                .encode_bit(&mut self.is_match[pos_state], false)?;
+            input_len = out_len + 1;
 
-            self.encode_literal(byte, prev_byte)?;
+            self.model
+                .encode_literal(&mut self.rangecoder, out_len, byte, prev_byte)?;
             prev_byte = byte;
         }
 
-        self.finish(input_len + 1)
+        self.finish(input_len)?;
+        Ok(CompressStats {
+            input_size: input_len as u64,
+            output_size: self.header_size + self.rangecoder.count(),
+            ..Default::default()
+        })
     }
 
     fn finish(&mut self, input_len: usize) -> io::Result<()> {
-        match self.unpacked_size {
-            UnpackedSize::SkipWritingToHeader | UnpackedSize::WriteToHeader(Some(_)) => {}
-            UnpackedSize::WriteToHeader(None) => {
-                // Write end-of-stream marker
-                let pos_state = input_len & 3;
-
-                // Match
-                self.rangecoder
-                    .encode_bit(&mut self.is_match[pos_state], true)?;
-                // New distance
-                self.rangecoder.encode_bit(&mut 0x400, false)?;
-
-                // Dummy len, as small as possible (len = 0)
-                for _ in 0..4 {
-                    self.rangecoder.encode_bit(&mut 0x400, false)?;
-                }
-
-                // Distance marker = 0xFFFFFFFF
-                // pos_slot = 63
-                for _ in 0..6 {
-                    self.rangecoder.encode_bit(&mut 0x400, true)?;
-                }
-                // num_direct_bits = 30
-                // result = 3 << 30 = C000_0000
-                //        + 3FFF_FFF0  (26 bits)
-                //        + F          ( 4 bits)
-                for _ in 0..30 {
-                    self.rangecoder.encode_bit(&mut 0x400, true)?;
-                }
-                //        = FFFF_FFFF
+        if let UnpackedSize::WriteToHeader(Some(unpacked_size)) = self.unpacked_size {
+            if unpacked_size != input_len as u64 {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    format!(
+                        "Unpacked size {} written to the header, but the input has {} bytes",
+                        unpacked_size, input_len
+                    ),
+                ));
             }
         }
 
-        // Flush range coder
-        self.rangecoder.finish()
-    }
-
-    fn encode_literal(&mut self, byte: u8, prev_byte: u8) -> io::Result<()> {
-        let prev_byte = prev_byte as usize;
-
-        let mut result: usize = 1;
-        let lit_state = prev_byte >> 5;
-        let probs = &mut self.literal_probs[lit_state];
-
-        for i in 0..8 {
-            let bit = ((byte >> (7 - i)) & 1) != 0;
-            self.rangecoder.encode_bit(&mut probs[result], bit)?;
-            result = (result << 1) ^ (bit as usize);
+        if self.end_marker {
+            // Write end-of-stream marker
+            self.model
+                .encode_end_marker(&mut self.rangecoder, input_len)?;
         }
 
-        Ok(())
+        // Flush range coder
+        self.rangecoder.finish()
     }
 }
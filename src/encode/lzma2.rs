@@ -1,26 +1,239 @@
+use crate::compress::{CompressStats, Options};
+use crate::decode::lzma2::ChunkEntry;
+use crate::encode::dumbencoder::{LiteralModel, LC, LP, PROPS};
+use crate::encode::rangecoder;
 use byteorder::{BigEndian, WriteBytesExt};
 use std::io;
+use std::io::Read;
 
-pub fn encode_stream<R, W>(input: &mut R, output: &mut W) -> io::Result<()>
+/// Maximum number of uncompressed bytes per chunk.
+const CHUNK_SIZE: usize = 0x10000;
+/// Maximum number of compressed bytes an LZMA chunk can hold.
+const MAX_PACKED_SIZE: usize = 0x10000;
+/// Maximum number of bytes an uncompressed chunk can hold, as its size is
+/// stored on 16 bits.
+const MAX_STORED_SIZE: usize = 0x10000;
+
+// The unpacked size of LZMA chunks is stored on 21 bits.
+const _: () = assert!(CHUNK_SIZE <= 1 << 21);
+
+/// Encode a raw LZMA2 stream, followed by the padding required by the
+/// `pad_to` option.
+pub fn encode_raw_stream<R, W>(
+    input: &mut R,
+    output: &mut W,
+    options: &Options,
+) -> io::Result<CompressStats>
 where
     R: io::BufRead,
     W: io::Write,
 {
-    let mut buf = vec![0u8; 0x10000];
+    if options.pad_to == Some(0) {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "LZMA2 padding alignment must be non-zero",
+        ));
+    }
+
+    let mut stats = encode_stream(input, output, options)?;
+    if let Some(pad_to) = options.pad_to {
+        let padding = vec![0; ((pad_to - stats.output_size % pad_to) % pad_to) as usize];
+        output.write_all(&padding)?;
+        stats.output_size += padding.len() as u64;
+    }
+    Ok(stats)
+}
+
+pub fn encode_stream<R, W>(
+    input: &mut R,
+    output: &mut W,
+    options: &Options,
+) -> io::Result<CompressStats>
+where
+    R: io::BufRead,
+    W: io::Write,
+{
+    if options.chunk_boundary_bytes == Some(0) {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "LZMA2 chunk boundary must be non-zero",
+        ));
+    }
+
+    let mut stats = CompressStats::default();
+    let mut buf = Vec::with_capacity(CHUNK_SIZE);
+    let mut packed = Vec::new();
+    let mut model = LiteralModel::new();
+    // The dictionary is reset by the first chunk only, so positions and the
+    // previous byte carry over from one chunk to the next.
+    let mut pos = 0;
+    let mut prev_byte = 0u8;
+    let mut first = true;
+    // Set after an uncompressed chunk: the next LZMA chunk resets the state,
+    // and also the properties if they were never written.
+    let mut need_state_reset = false;
+    let mut need_props = true;
+
     loop {
-        let n = input.read(&mut buf)?;
+        let chunk_size = match options.chunk_boundary_bytes {
+            Some(boundary) => std::cmp::min(CHUNK_SIZE as u64, boundary - pos as u64 % boundary),
+            None => CHUNK_SIZE as u64,
+        };
+        buf.clear();
+        input.by_ref().take(chunk_size).read_to_end(&mut buf)?;
+        let n = buf.len();
         if n == 0 {
             // status = EOF
             output.write_u8(0)?;
+            stats.output_size += 1;
             break;
         }
 
-        // status = uncompressed reset dict
-        output.write_u8(1)?;
-        // unpacked size
-        output.write_u16::<BigEndian>((n - 1) as u16)?;
-        // contents
-        output.write_all(&buf[..n])?;
+        // In store mode, the LZMA attempt is skipped.
+        let store = options.store || (options.auto_store && likely_incompressible(&buf, prev_byte));
+        let compressed = !store && {
+            if need_state_reset {
+                model = LiteralModel::new();
+            }
+
+            packed.clear();
+            let mut rangecoder = rangecoder::RangeEncoder::new(&mut packed);
+            let mut chunk_prev_byte = prev_byte;
+            for (i, &byte) in buf.iter().enumerate() {
+                model.encode_literal(&mut rangecoder, pos + i, byte, chunk_prev_byte)?;
+                chunk_prev_byte = byte;
+            }
+            rangecoder.finish()?;
+            packed.len() <= MAX_PACKED_SIZE && packed.len() < n
+        };
+
+        if compressed {
+            let control = if first {
+                // LZMA, reset dict, state and properties
+                0xE0
+            } else if need_props {
+                // LZMA, reset state and properties
+                0xC0
+            } else if need_state_reset {
+                // LZMA, reset state
+                0xA0
+            } else {
+                // LZMA, no reset
+                0x80
+            };
+            if options.record_chunk_map {
+                stats.chunk_map.push(ChunkEntry::new(
+                    control,
+                    stats.output_size,
+                    stats.input_size,
+                ));
+            }
+            output.write_u8(control | ((n - 1) >> 16) as u8)?;
+            // unpacked size
+            output.write_u16::<BigEndian>((n - 1) as u16)?;
+            // packed size
+            output.write_u16::<BigEndian>((packed.len() - 1) as u16)?;
+            let mut header_size = 5;
+            if need_props {
+                output.write_u8(PROPS)?;
+                header_size += 1;
+            }
+            // contents
+            output.write_all(&packed)?;
+
+            stats.output_size += header_size + packed.len() as u64;
+            stats.chunks += 1;
+            need_state_reset = false;
+            need_props = false;
+        } else {
+            // Larger spans are split into several uncompressed chunks.
+            for (i, stored) in buf.chunks(MAX_STORED_SIZE).enumerate() {
+                // status = uncompressed, reset dict for the first chunk
+                let control = if first && i == 0 { 1 } else { 2 };
+                if options.record_chunk_map {
+                    stats.chunk_map.push(ChunkEntry::new(
+                        control,
+                        stats.output_size,
+                        stats.input_size + (i * MAX_STORED_SIZE) as u64,
+                    ));
+                }
+                output.write_u8(control)?;
+                // unpacked size
+                output.write_u16::<BigEndian>((stored.len() - 1) as u16)?;
+                // contents
+                output.write_all(stored)?;
+
+                stats.output_size += 3 + stored.len() as u64;
+                stats.chunks += 1;
+            }
+            // The probabilities were updated by the discarded LZMA attempt.
+            need_state_reset = true;
+        }
+
+        stats.input_size += n as u64;
+        pos += n;
+        prev_byte = buf[n - 1];
+        first = false;
+    }
+    Ok(stats)
+}
+
+/// Whether the literals of `buf`, preceded by `prev_byte`, likely can't
+/// shrink by more than 1%, judging by their entropy in the contexts of the
+/// literal model.
+///
+/// The entropy of a sample underestimates that of its source, so short or
+/// barely compressible data is still compressed.
+fn likely_incompressible(buf: &[u8], prev_byte: u8) -> bool {
+    // The literal model uses the high bits of the previous byte as context.
+    const _: () = assert!(LP == 0);
+    let mut counts = [[0u32; 256]; 1usize << LC];
+    let mut prev_byte = prev_byte;
+    for &byte in buf {
+        counts[(prev_byte >> (8 - LC)) as usize][byte as usize] += 1;
+        prev_byte = byte;
+    }
+
+    let n_log_n = |n: u32| {
+        let n = n as f64;
+        if n > 0.0 {
+            n * n.log2()
+        } else {
+            0.0
+        }
+    };
+    let bits: f64 = counts
+        .iter()
+        .map(|context| {
+            let total = context.iter().sum();
+            n_log_n(total) - context.iter().map(|&n| n_log_n(n)).sum::<f64>()
+        })
+        .sum();
+    bits / 8.0 >= 0.99 * buf.len() as f64
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_likely_incompressible() {
+        let mut state = 0x2545_f491u32;
+        let noise: Vec<u8> = (0..CHUNK_SIZE)
+            .map(|_| {
+                state ^= state << 13;
+                state ^= state >> 17;
+                state ^= state << 5;
+                state as u8
+            })
+            .collect();
+        assert!(likely_incompressible(&noise, 0));
+        // A sample too short to tell.
+        assert!(!likely_incompressible(&noise[..1000], 0));
+        // Every byte value equally often, but predictable from the previous
+        // byte.
+        let counter: Vec<u8> = (0..CHUNK_SIZE).map(|i| i as u8).collect();
+        assert!(!likely_incompressible(&counter, 0));
+        assert!(!likely_incompressible(&b"Hello world\n".repeat(5000), 0));
     }
-    Ok(())
 }
@@ -1,8 +1,11 @@
 //! Encoding logic.
 
+pub mod dict_size;
 pub mod dumbencoder;
 pub mod lzma2;
 pub mod options;
 mod rangecoder;
-mod util;
+pub mod stats;
+pub mod util;
+#[cfg(feature = "xz")]
 pub mod xz;
@@ -1,17 +1,198 @@
-/// Options for the `lzma_compress` function.
-#[derive(Clone, Copy, Debug, Default)]
+pub use crate::xz::CheckMethod;
+
+/// Options for the `lzma_compress` and `xz_compress` functions.
+///
+/// The defaults write the smallest framing, without integrity check. See
+/// [`Options::xz_compatible()`] for the framing of the `xz` tool.
+#[derive(Clone, Copy, Debug)]
 pub struct Options {
     /// Defines whether the unpacked size should be written to the header.
     /// The default is [`UnpackedSize::WriteToHeader(None)`].
     pub unpacked_size: UnpackedSize,
+    /// Integrity check stored after each XZ block. The default is
+    /// [`CheckMethod::None`]. SHA-256 is not supported yet.
+    pub check: CheckMethod,
+    /// Maximum number of uncompressed bytes per XZ block. The input is split
+    /// into several blocks, each one listed in the index, so that they can be
+    /// located without decompressing the whole stream.
+    /// The default is [`None`], which writes a single block.
+    pub block_size: Option<u64>,
+    /// Interval of uncompressed bytes at which LZMA2 chunks must end, so
+    /// that chunk boundaries fall at every multiple of it. Unlike XZ blocks,
+    /// chunks carry the dictionary and the state over, so this only adds a
+    /// few bytes of chunk headers. Chunks are still at most 64 KiB long.
+    ///
+    /// Offsets are counted from the start of the LZMA2 stream, which is the
+    /// start of each block in XZ. The default is [`None`], which makes
+    /// chunks as long as possible.
+    pub chunk_boundary_bytes: Option<u64>,
+    /// Whether to declare the compressed and uncompressed sizes of each XZ
+    /// block in its header, which decoders check against the data. Readers
+    /// can then learn the size of a block without the index.
+    ///
+    /// Each block is then buffered once compressed, as its sizes are only
+    /// known at its end. The default is false.
+    pub store_block_sizes: bool,
+    /// Whether to store the data as is in uncompressed LZMA2 chunks, e.g.
+    /// data that is already compressed, keeping the XZ framing and checks.
+    /// The output is then larger than the input by 3 bytes per 64 KiB chunk,
+    /// besides the XZ headers, index and footer.
+    ///
+    /// This option doesn't apply to `.lzma` data, which has no uncompressed
+    /// chunks. The default is false.
+    pub store: bool,
+    /// Whether to store right away the LZMA2 chunks that look incompressible,
+    /// skipping the LZMA attempt to save time, e.g. on inputs mixing text
+    /// and already compressed data.
+    ///
+    /// Chunks are otherwise compressed and only stored if that doesn't shrink
+    /// them. The estimate is conservative: it only skips the chunks whose
+    /// literals couldn't shrink by more than 1% according to their entropy.
+    /// This option doesn't apply to `.lzma` data. The default is false.
+    pub auto_store: bool,
+    /// Alignment of the size of the compressed data, e.g. for fixed-size
+    /// records. Padding is appended after the end of the data so that its
+    /// size is a multiple of this value.
+    ///
+    /// In XZ, this is stream padding, which decoders skip, and the alignment
+    /// must be a multiple of four. Raw LZMA2 has no such thing, so null bytes
+    /// are appended after the end-of-stream marker. Decoders of raw LZMA2,
+    /// such as [`lzma2_decompress`](crate::lzma2_decompress), stop at this
+    /// marker and leave the padding unread. If several streams are stored
+    /// back to back, the reader must skip the padding itself.
+    ///
+    /// This option doesn't apply to `.lzma` data. The default is [`None`],
+    /// which adds no padding.
+    pub pad_to: Option<u64>,
+    /// Whether to fail rather than produce `.lzma` data larger than the
+    /// input, so that callers can store the input as is instead. As this
+    /// format has no uncompressed chunks, incompressible data always expands
+    /// somewhat.
+    ///
+    /// The compressed data is then buffered, and only written to the output
+    /// if it's smaller. Otherwise, nothing is written and the returned
+    /// [`io::Error`](std::io::Error) converts into
+    /// [`Error::Incompressible`](crate::error::Error::Incompressible).
+    ///
+    /// This option only applies to the `lzma_compress_with_options`
+    /// function. The default is false.
+    pub reject_incompressible: bool,
+    /// Number of threads compressing XZ blocks in parallel, like `xz -T`.
+    /// The input is split into blocks of [`block_size`](Options::block_size)
+    /// or [`block_flush_interval`](Options::block_flush_interval) bytes, or
+    /// of 3 times the dictionary size, i.e. 24 MiB, if neither is set. At
+    /// most this number of blocks are in flight at once, each one buffered
+    /// in memory along with its compressed data, and they are written in
+    /// order.
+    ///
+    /// The output only depends on the block size, not on the number of
+    /// threads. This option doesn't apply to `.lzma` and raw LZMA2 data. The
+    /// default is 1, which compresses in the calling thread.
+    pub threads: usize,
+    /// Branch/call/jump filter applied before LZMA2 in each XZ block, which
+    /// improves the compression of executable code for this architecture.
+    /// The filter is declared in the block headers, so that decoders undo it.
+    ///
+    /// The data of each block is then buffered, to filter it in place before
+    /// compressing it. This option only applies to XZ data. The default is
+    /// [`None`], which only uses LZMA2.
+    pub bcj: Option<BcjArch>,
+    /// Whether to write the end-of-stream marker of `.lzma` data even when
+    /// the unpacked size is known, i.e. written to the header or skipped.
+    /// Some decoders expect both, while decoders relying on the size stop
+    /// before the marker. It costs about 5 bytes.
+    ///
+    /// The marker is always written when the size is unknown. This option
+    /// doesn't apply to XZ and LZMA2 data, which end their chunks
+    /// differently. The default is false.
+    pub always_write_end_marker: bool,
+    /// Whether to decompress the XZ stream right after compressing it and
+    /// compare the result with the input, e.g. for archives where a corrupt
+    /// file is unacceptable. This catches encoder bugs and hardware faults
+    /// at the cost of decoding the stream once more.
+    ///
+    /// The input and the compressed stream are then buffered, and the stream
+    /// is only written to the output once checked. Otherwise, nothing is
+    /// written and the returned [`io::Error`](std::io::Error) converts into
+    /// [`Error::RoundtripFailed`](crate::error::Error::RoundtripFailed).
+    ///
+    /// This option only applies to XZ data. The default is false.
+    pub verify_roundtrip: bool,
+    /// Whether to list the LZMA2 chunks written in the
+    /// [`chunk_map`](crate::compress::CompressStats::chunk_map) of the
+    /// returned statistics, e.g. to build a seek index of the stream. The
+    /// entries are the same as the decoder lists with its own
+    /// [`record_chunk_map`](crate::decompress::Options::record_chunk_map)
+    /// option: decoding can start at any chunk resetting the dictionary.
+    ///
+    /// This option only applies to raw LZMA2 data. The default is false.
+    pub record_chunk_map: bool,
+    /// Interval of uncompressed bytes at which to end the XZ block and flush
+    /// the output, e.g. for long-running log compression: each block resets
+    /// the dictionary and gets its own index record, so if the process
+    /// crashes, the complete blocks of the partial file can be recovered
+    /// with the [`salvage`](crate::decompress::Options::salvage) decoding
+    /// option. The output is flushed at the end of the stream as well.
+    ///
+    /// Blocks are then at most this long, or
+    /// [`block_size`](Options::block_size) if it's smaller, which costs
+    /// some compression ratio. Flushing is pointless with
+    /// [`verify_roundtrip`](Options::verify_roundtrip), which only writes
+    /// the stream once complete.
+    ///
+    /// This option only applies to XZ data. The default is [`None`], which
+    /// never flushes the output.
+    pub block_flush_interval: Option<u64>,
+}
+
+impl Default for Options {
+    fn default() -> Options {
+        Options {
+            unpacked_size: UnpackedSize::default(),
+            check: CheckMethod::None,
+            block_size: None,
+            chunk_boundary_bytes: None,
+            store_block_sizes: false,
+            store: false,
+            auto_store: false,
+            pad_to: None,
+            reject_incompressible: false,
+            threads: 1,
+            bcj: None,
+            always_write_end_marker: false,
+            verify_roundtrip: false,
+            record_chunk_map: false,
+            block_flush_interval: None,
+        }
+    }
+}
+
+impl Options {
+    /// Options matching the framing of `xz -6`, the default of the `xz`
+    /// tool in single-threaded mode: a single XZ block per stream, checked
+    /// by a CRC64, with the 8 MiB dictionary of this preset declared in the
+    /// LZMA2 filter properties.
+    ///
+    /// This is a known-good starting point to exchange files with other
+    /// tools, and a baseline for compatibility testing. Note that the
+    /// compression itself doesn't match the ratio of `xz -6`, as this crate
+    /// doesn't search for matches yet.
+    pub fn xz_compatible() -> Options {
+        Options {
+            check: CheckMethod::Crc64,
+            ..Options::default()
+        }
+    }
 }
 
 /// Alternatives for handling unpacked size.
 #[derive(Clone, Copy, Debug)]
 pub enum UnpackedSize {
     /// If the value is `Some(u64)`, write the provided u64 value to the header.
-    /// There is currently no check in place that verifies that this is the
-    /// actual number of bytes provided by the input stream.
+    /// Compression fails with
+    /// [`io::ErrorKind::InvalidInput`](std::io::ErrorKind::InvalidInput) if
+    /// the input doesn't have this number of bytes, once the data is
+    /// already written.
     /// If the value is [`None`], write the special `0xFFFF_FFFF_FFFF_FFFF` code
     /// to the header, indicating that the unpacked size is unknown.
     WriteToHeader(Option<u64>),
@@ -27,3 +208,12 @@ impl Default for UnpackedSize {
         UnpackedSize::WriteToHeader(None)
     }
 }
+
+/// Architectures of the branch/call/jump filters, see
+/// [`Options::bcj`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum BcjArch {
+    /// x86 and x86-64, filter ID 0x04.
+    X86,
+}
@@ -1,9 +1,13 @@
-use byteorder::WriteBytesExt;
 use std::io;
 
 #[cfg(test)]
 use crate::util::const_assert;
 
+/// Number of output bytes gathered before writing them to the stream.
+const PENDING_LEN: usize = 1024;
+
+/// Range encoder, whose output is only complete in the stream once
+/// [`RangeEncoder::finish()`] returns.
 pub struct RangeEncoder<'a, W>
 where
     W: 'a + io::Write,
@@ -13,6 +17,13 @@ where
     low: u64,
     cache: u8,
     cachesz: u32,
+    /// Number of bytes produced, written to the stream or pending
+    count: u64,
+    /// Bytes produced but not yet written to the stream, so that the stream
+    /// receives them in a few large writes rather than one at a time
+    pending: [u8; PENDING_LEN],
+    /// Number of bytes in `pending`
+    pending_len: usize,
 }
 
 impl<'a, W> RangeEncoder<'a, W>
@@ -27,6 +38,9 @@ where
             low: 0,
             cache: 0,
             cachesz: 1,
+            count: 0,
+            pending: [0; PENDING_LEN],
+            pending_len: 0,
         };
         lzma_debug!("0 {{ range: {:08x}, low: {:010x} }}", enc.range, enc.low);
         enc
@@ -37,7 +51,12 @@ where
             let mut tmp = self.cache;
             loop {
                 let byte = tmp.wrapping_add((self.low >> 32) as u8);
-                self.stream.write_u8(byte)?;
+                self.pending[self.pending_len] = byte;
+                self.pending_len += 1;
+                if self.pending_len == PENDING_LEN {
+                    self.write_pending()?;
+                }
+                self.count += 1;
                 lzma_debug!("> byte: {:02x}", byte);
                 tmp = 0xFF;
                 self.cachesz -= 1;
@@ -53,13 +72,25 @@ where
         Ok(())
     }
 
+    fn write_pending(&mut self) -> io::Result<()> {
+        self.stream.write_all(&self.pending[..self.pending_len])?;
+        self.pending_len = 0;
+        Ok(())
+    }
+
+    /// Number of bytes produced so far.
+    pub fn count(&self) -> u64 {
+        self.count
+    }
+
+    /// Flush the range coder and write the remaining bytes to the stream.
     pub fn finish(&mut self) -> io::Result<()> {
         for _ in 0..5 {
             self.write_low()?;
 
             lzma_debug!("$ {{ range: {:08x}, low: {:010x} }}", self.range, self.low);
         }
-        Ok(())
+        self.write_pending()
     }
 
     fn normalize(&mut self) -> io::Result<()> {
@@ -310,6 +341,30 @@ mod test {
         encode_decode(0x400, &[true; 10000]);
     }
 
+    #[test]
+    fn test_encode_decode_pending() {
+        // Enough unpredictable bits to fill the pending bytes several times.
+        let mut state = 0x2545_f491u32;
+        let bits: Vec<bool> = (0..100_000)
+            .map(|_| {
+                state ^= state << 13;
+                state ^= state >> 17;
+                state ^= state << 5;
+                state & 1 != 0
+            })
+            .collect();
+        encode_decode(0x400, &bits);
+
+        let mut buf: Vec<u8> = Vec::new();
+        let mut encoder = RangeEncoder::new(&mut buf);
+        for &b in &bits {
+            encoder.encode_bit(&mut 0x400, b).unwrap();
+        }
+        encoder.finish().unwrap();
+        assert_eq!(encoder.count(), buf.len() as u64);
+        assert!(buf.len() > 4 * PENDING_LEN, "{}", buf.len());
+    }
+
     fn encode_decode_bittree<const PROBS_LEN: usize>(values: &[u32]) {
         let mut buf: Vec<u8> = Vec::new();
 
@@ -408,7 +463,7 @@ mod test {
 
         let mut bufread = BufReader::new(buf.as_slice());
         let mut decoder = RangeDecoder::new(&mut bufread).unwrap();
-        let mut len_decoder = LenDecoder::new();
+        let mut len_decoder: LenDecoder = LenDecoder::new();
         for &v in values {
             assert_eq!(
                 len_decoder.decode(&mut decoder, pos_state, true).unwrap(),
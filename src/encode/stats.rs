@@ -0,0 +1,86 @@
+use crate::decode::lzma2::ChunkEntry;
+use std::fmt;
+
+/// Statistics collected while compressing data.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct CompressStats {
+    /// Number of uncompressed bytes read from the input.
+    pub input_size: u64,
+    /// Number of compressed bytes written to the output, including headers.
+    pub output_size: u64,
+    /// Number of LZMA2 chunks written (zero for LZMA).
+    pub chunks: u64,
+    /// Number of XZ blocks written (zero for LZMA and LZMA2).
+    pub blocks: u64,
+    /// Number of LZ matches emitted.
+    pub matches: u64,
+    /// Total number of bytes covered by LZ matches.
+    pub match_bytes: u64,
+    /// LZMA2 chunks written, in order, if the
+    /// [`record_chunk_map`](crate::compress::Options::record_chunk_map)
+    /// option is set. Otherwise, this is empty.
+    pub chunk_map: Vec<ChunkEntry>,
+}
+
+impl CompressStats {
+    /// Ratio of the compressed size over the uncompressed size, or [`None`]
+    /// for an empty input.
+    pub fn ratio(&self) -> Option<f64> {
+        if self.input_size == 0 {
+            None
+        } else {
+            Some(self.output_size as f64 / self.input_size as f64)
+        }
+    }
+
+    /// Whether the compressed output is larger than the input, e.g. for
+    /// incompressible data, in which case callers may prefer storing the
+    /// input as is.
+    pub fn expanded(&self) -> bool {
+        self.output_size > self.input_size
+    }
+
+    /// Average length of the LZ matches, or [`None`] if no match was emitted.
+    pub fn average_match_len(&self) -> Option<f64> {
+        if self.matches == 0 {
+            None
+        } else {
+            Some(self.match_bytes as f64 / self.matches as f64)
+        }
+    }
+}
+
+/// Payload of the [`io::Error`](std::io::Error) returned when compressed data
+/// is rejected for being larger than its input, see
+/// [`Options::reject_incompressible`](crate::compress::Options::reject_incompressible).
+#[derive(Debug)]
+pub(crate) struct Incompressible {
+    pub(crate) input_size: u64,
+    pub(crate) output_size: u64,
+}
+
+impl fmt::Display for Incompressible {
+    fn fmt(&self, fmt: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            fmt,
+            "compressing {} bytes would produce {} bytes",
+            self.input_size, self.output_size
+        )
+    }
+}
+
+impl std::error::Error for Incompressible {}
+
+/// Payload of the [`io::Error`](std::io::Error) returned when compressed data
+/// doesn't decompress to its input, see
+/// [`Options::verify_roundtrip`](crate::compress::Options::verify_roundtrip).
+#[derive(Debug)]
+pub(crate) struct RoundtripFailed;
+
+impl fmt::Display for RoundtripFailed {
+    fn fmt(&self, fmt: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(fmt, "compressed data doesn't decompress to the input")
+    }
+}
+
+impl std::error::Error for RoundtripFailed {}
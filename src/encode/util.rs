@@ -1,28 +1,31 @@
+#[cfg(feature = "xz")]
+use crate::xz::checksum::Crc32;
 use std::io;
 
+#[cfg(feature = "xz")]
 /// An [`io::Write`] computing a digest on the bytes written.
-pub struct CrcDigestWrite<'a, 'b, W, S>
+pub struct CrcDigestWrite<'a, W>
 where
     W: 'a + io::Write,
-    S: crc::Width,
 {
     /// Underlying writer
     write: &'a mut W,
     /// Hasher
-    digest: &'a mut crc::Digest<'b, S>,
+    digest: &'a mut Crc32,
 }
 
-impl<'a, 'b, W, S> CrcDigestWrite<'a, 'b, W, S>
+#[cfg(feature = "xz")]
+impl<'a, W> CrcDigestWrite<'a, W>
 where
     W: io::Write,
-    S: crc::Width,
 {
-    pub fn new(write: &'a mut W, digest: &'a mut crc::Digest<'b, S>) -> Self {
+    pub fn new(write: &'a mut W, digest: &'a mut Crc32) -> Self {
         Self { write, digest }
     }
 }
 
-impl<'a, 'b, W> io::Write for CrcDigestWrite<'a, 'b, W, u32>
+#[cfg(feature = "xz")]
+impl<'a, W> io::Write for CrcDigestWrite<'a, W>
 where
     W: io::Write,
 {
@@ -36,6 +39,7 @@ where
     }
 }
 
+#[cfg(feature = "xz")]
 /// An [`io::Write`] counting the bytes written.
 pub struct CountWrite<'a, W>
 where
@@ -47,6 +51,7 @@ where
     count: usize,
 }
 
+#[cfg(feature = "xz")]
 impl<'a, W> CountWrite<'a, W>
 where
     W: io::Write,
@@ -60,6 +65,7 @@ where
     }
 }
 
+#[cfg(feature = "xz")]
 impl<'a, W> io::Write for CountWrite<'a, W>
 where
     W: io::Write,
@@ -74,3 +80,151 @@ where
         self.write.flush()
     }
 }
+
+#[cfg(feature = "xz")]
+/// An [`io::BufRead`] passing the bytes read to a callback.
+pub struct InspectBufRead<'a, R, F>
+where
+    R: 'a + io::BufRead,
+    F: FnMut(&[u8]),
+{
+    /// Underlying reader
+    read: &'a mut R,
+    /// Callback
+    inspect: F,
+}
+
+#[cfg(feature = "xz")]
+impl<'a, R, F> InspectBufRead<'a, R, F>
+where
+    R: io::BufRead,
+    F: FnMut(&[u8]),
+{
+    pub fn new(read: &'a mut R, inspect: F) -> Self {
+        Self { read, inspect }
+    }
+}
+
+#[cfg(feature = "xz")]
+impl<'a, R, F> io::Read for InspectBufRead<'a, R, F>
+where
+    R: io::BufRead,
+    F: FnMut(&[u8]),
+{
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let result = self.read.read(buf)?;
+        (self.inspect)(&buf[..result]);
+        Ok(result)
+    }
+}
+
+#[cfg(feature = "xz")]
+impl<'a, R, F> io::BufRead for InspectBufRead<'a, R, F>
+where
+    R: io::BufRead,
+    F: FnMut(&[u8]),
+{
+    fn fill_buf(&mut self) -> io::Result<&[u8]> {
+        self.read.fill_buf()
+    }
+
+    fn consume(&mut self, amt: usize) {
+        if let Ok(buf) = self.read.fill_buf() {
+            (self.inspect)(&buf[..amt.min(buf.len())]);
+        }
+        self.read.consume(amt);
+    }
+}
+
+/// An [`io::BufRead`] over successive chunks of bytes, without copying them
+/// into a single buffer.
+pub struct ChunksBufRead<I, B>
+where
+    I: Iterator<Item = B>,
+    B: AsRef<[u8]>,
+{
+    /// Remaining chunks
+    chunks: I,
+    /// Current chunk
+    chunk: Option<B>,
+    /// Position of the next byte to read in the current chunk
+    pos: usize,
+}
+
+impl<I, B> ChunksBufRead<I, B>
+where
+    I: Iterator<Item = B>,
+    B: AsRef<[u8]>,
+{
+    pub fn new(chunks: I) -> Self {
+        Self {
+            chunks,
+            chunk: None,
+            pos: 0,
+        }
+    }
+}
+
+impl<I, B> io::Read for ChunksBufRead<I, B>
+where
+    I: Iterator<Item = B>,
+    B: AsRef<[u8]>,
+{
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let available = io::BufRead::fill_buf(self)?;
+        let len = std::cmp::min(buf.len(), available.len());
+        buf[..len].copy_from_slice(&available[..len]);
+        io::BufRead::consume(self, len);
+        Ok(len)
+    }
+}
+
+impl<I, B> io::BufRead for ChunksBufRead<I, B>
+where
+    I: Iterator<Item = B>,
+    B: AsRef<[u8]>,
+{
+    fn fill_buf(&mut self) -> io::Result<&[u8]> {
+        // Skip exhausted and empty chunks.
+        while self
+            .chunk
+            .as_ref()
+            .map_or(true, |chunk| self.pos == chunk.as_ref().len())
+        {
+            match self.chunks.next() {
+                Some(chunk) => {
+                    self.chunk = Some(chunk);
+                    self.pos = 0;
+                }
+                None => {
+                    self.chunk = None;
+                    return Ok(&[]);
+                }
+            }
+        }
+        Ok(&self.chunk.as_ref().unwrap().as_ref()[self.pos..])
+    }
+
+    fn consume(&mut self, amt: usize) {
+        self.pos += amt;
+    }
+}
+
+/// Bytes added around the compressed data by the `.lzma` and `.xz` headers,
+/// footers and indexes, for small inputs.
+const CONTAINER_OVERHEAD: usize = 64;
+
+/// Largest initial reservation, so that huge but compressible inputs don't
+/// reserve memory they won't use.
+const MAX_RESERVATION: usize = 64 << 20;
+
+/// Advisory capacity to reserve for the compressed form of `input_len` bytes.
+///
+/// The encoder only emits literals, which already shrinks typical data such
+/// as text to between a half and two thirds, so three quarters of the input
+/// are reserved.
+/// Incompressible data then takes a single reallocation, and the output grows
+/// as needed beyond the estimate.
+pub(crate) fn estimated_compressed_len(input_len: usize) -> usize {
+    std::cmp::min(input_len - input_len / 4, MAX_RESERVATION) + CONTAINER_OVERHEAD
+}
@@ -1,39 +1,197 @@
+use crate::compress::{BcjArch, CompressStats, Options};
 use crate::decode;
-use crate::encode::{lzma2, util};
-use crate::xz::crc::CRC32;
-use crate::xz::{footer, header, CheckMethod, StreamFlags};
+use crate::encode::{dict_size, dumbencoder, lzma2, util};
+use crate::xz::checksum::{Crc32, Crc64};
+use crate::xz::{bcj, footer, vli, CheckMethod, StreamFlags, XZ_MAGIC};
 use byteorder::{LittleEndian, WriteBytesExt};
-use std::io;
-use std::io::Write;
+use std::collections::VecDeque;
+use std::io::{Read, Write};
+use std::{io, thread};
 
-pub fn encode_stream<R, W>(input: &mut R, output: &mut W) -> io::Result<()>
+pub fn encode_stream<R, W>(
+    input: &mut R,
+    output: &mut W,
+    options: &Options,
+) -> io::Result<CompressStats>
 where
     R: io::BufRead,
     W: io::Write,
 {
-    let stream_flags = StreamFlags {
-        check_method: CheckMethod::None,
-    };
+    if options.check == CheckMethod::Sha256 {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "Unsupported SHA-256 checksum (not yet implemented)",
+        ));
+    }
+
+    if options.threads == 0 {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "Number of XZ compression threads must be non-zero",
+        ));
+    }
+
+    if options.block_size == Some(0) {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "XZ block size must be non-zero",
+        ));
+    }
+
+    if options.block_flush_interval == Some(0) {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "XZ block flush interval must be non-zero",
+        ));
+    }
+
+    if options
+        .pad_to
+        .map_or(false, |pad_to| pad_to == 0 || pad_to % 4 != 0)
+    {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "XZ padding alignment must be a non-zero multiple of 4",
+        ));
+    }
+
+    if options.verify_roundtrip {
+        let mut data = Vec::new();
+        input.read_to_end(&mut data)?;
+        let mut compressed = Vec::with_capacity(util::estimated_compressed_len(data.len()));
+        let options = Options {
+            verify_roundtrip: false,
+            ..*options
+        };
+        let stats = encode_stream(&mut data.as_slice(), &mut compressed, &options)?;
+        check_roundtrip(&data, &compressed)?;
+        output.write_all(&compressed)?;
+        return Ok(stats);
+    }
+
+    let stream_flags = StreamFlags::new(options.check);
+    let mut output = util::CountWrite::new(output);
 
     // Header
-    write_header(output, stream_flags)?;
+    write_header(&mut output, stream_flags)?;
 
-    // Block
-    let (unpadded_size, unpacked_size) = write_block(input, output)?;
+    // Blocks
+    let mut index = Index::default();
+    let mut stats = CompressStats::default();
+    let mut add_block = |(unpadded_size, unpacked_size, block_stats): BlockSizes| {
+        index.add_record(unpadded_size as u64, unpacked_size as u64);
+        stats.input_size += unpacked_size as u64;
+        stats.chunks += block_stats.chunks;
+        stats.blocks += 1;
+    };
+    if options.threads > 1 {
+        write_blocks_threaded(input, &mut output, options, add_block)?;
+    } else {
+        loop {
+            add_block(match block_size(options) {
+                Some(block_size) => {
+                    write_block(&mut input.by_ref().take(block_size), &mut output, options)?
+                }
+                None => write_block(input, &mut output, options)?,
+            });
+            if options.block_flush_interval.is_some() {
+                output.flush()?;
+            }
+            if decode::util::is_eof(input)? {
+                break;
+            }
+        }
+    }
 
     // Index
-    let index_size = write_index(output, unpadded_size, unpacked_size)?;
+    let index_size = index.write(&mut output)?;
 
     // Footer
-    write_footer(output, stream_flags, index_size)
+    write_footer(&mut output, stream_flags, index_size)?;
+
+    // Stream padding, see sect. 2.2
+    if let Some(pad_to) = options.pad_to {
+        let size = output.count() as u64;
+        let padding = vec![0; ((pad_to - size % pad_to) % pad_to) as usize];
+        output.write_all(&padding)?;
+    }
+    if options.block_flush_interval.is_some() {
+        output.flush()?;
+    }
+
+    stats.output_size = output.count() as u64;
+    Ok(stats)
+}
+
+/// Copy the blocks of the `.xz` files `inputs` into a single stream with a
+/// merged index, once they are all checked, returning the number of bytes
+/// written.
+pub(crate) fn concat<B, W>(inputs: &[B], output: &mut W) -> crate::error::Result<u64>
+where
+    B: AsRef<[u8]>,
+    W: io::Write,
+{
+    let mut listings = Vec::with_capacity(inputs.len());
+    for input in inputs {
+        listings.push(decode::xz_list::list(&mut io::Cursor::new(input.as_ref()))?);
+    }
+    // Streams without blocks don't matter.
+    let mut streams = listings
+        .iter()
+        .flat_map(|listing| &listing.streams)
+        .filter(|stream| !stream.blocks.is_empty());
+    let check = streams
+        .next()
+        .map_or(CheckMethod::None, |stream| stream.check);
+    if let Some(stream) = streams.find(|stream| stream.check != check) {
+        return Err(crate::error::Error::XzError(format!(
+            "Cannot merge streams with {:?} and {:?} checks",
+            check, stream.check
+        )));
+    }
+
+    let stream_flags = StreamFlags::new(check);
+    let mut output = util::CountWrite::new(output);
+    write_header(&mut output, stream_flags)?;
+    let mut index = Index::default();
+    for (input, listing) in inputs.iter().zip(&listings) {
+        for block in listing.streams.iter().flat_map(|stream| &stream.blocks) {
+            // Blocks are padded to a multiple of four bytes, see sect. 3.
+            let start = block.compressed_offset as usize;
+            let padded_size = (block.unpadded_size as usize + 3) & !3;
+            output.write_all(&input.as_ref()[start..start + padded_size])?;
+            index.add_record(block.unpadded_size, block.uncompressed_size);
+        }
+    }
+    let index_size = index.write(&mut output)?;
+    write_footer(&mut output, stream_flags, index_size)?;
+    Ok(output.count() as u64)
+}
+
+/// Decode the XZ stream `compressed` and check that it yields `data`.
+fn check_roundtrip(data: &[u8], compressed: &[u8]) -> io::Result<()> {
+    // Corrupt data mustn't decompress to much more than the input.
+    let options = crate::decompress::Options {
+        limit: Some(data.len() as u64 + 1),
+        ..Default::default()
+    };
+    let mut decompressed = Vec::with_capacity(data.len());
+    let result = decode::xz::decode_stream(&mut &compressed[..], &mut decompressed, &options);
+    if result.is_err() || decompressed != data {
+        return Err(io::Error::new(
+            io::ErrorKind::Other,
+            crate::encode::stats::RoundtripFailed,
+        ));
+    }
+    Ok(())
 }
 
 fn write_header<W>(output: &mut W, stream_flags: StreamFlags) -> io::Result<()>
 where
     W: io::Write,
 {
-    output.write_all(header::XZ_MAGIC)?;
-    let mut digest = CRC32.digest();
+    output.write_all(XZ_MAGIC)?;
+    let mut digest = Crc32::new();
     {
         let mut digested = util::CrcDigestWrite::new(output, &mut digest);
         stream_flags.serialize(&mut digested)?;
@@ -47,7 +205,7 @@ fn write_footer<W>(output: &mut W, stream_flags: StreamFlags, index_size: usize)
 where
     W: io::Write,
 {
-    let mut digest = CRC32.digest();
+    let mut digest = Crc32::new();
     let mut footer_buf: Vec<u8> = Vec::new();
     {
         let mut digested = util::CrcDigestWrite::new(&mut footer_buf, &mut digest);
@@ -64,38 +222,119 @@ where
     Ok(())
 }
 
-fn write_block<R, W>(input: &mut R, output: &mut W) -> io::Result<(usize, usize)>
+/// Unpadded and uncompressed sizes of a block, and statistics about it.
+type BlockSizes = (usize, usize, CompressStats);
+
+/// Thread compressing a block, returning it padded and followed by its check.
+type BlockWorker = thread::JoinHandle<io::Result<(Vec<u8>, BlockSizes)>>;
+
+/// Default size of the blocks compressed in parallel, as in `xz -T`.
+const THREADED_BLOCK_SIZE: u64 = 3 * dumbencoder::DICT_SIZE as u64;
+
+/// Number of uncompressed bytes per XZ block, if the input is split into
+/// several blocks.
+fn block_size(options: &Options) -> Option<u64> {
+    match (options.block_size, options.block_flush_interval) {
+        (Some(block_size), Some(interval)) => Some(std::cmp::min(block_size, interval)),
+        (block_size, interval) => block_size.or(interval),
+    }
+}
+
+/// Compress the blocks on `options.threads` threads, each one compressing a
+/// whole block, and write them in input order as they complete. Each block
+/// is passed to `add_block` once written.
+fn write_blocks_threaded<R, W, F>(
+    input: &mut R,
+    output: &mut W,
+    options: &Options,
+    mut add_block: F,
+) -> io::Result<()>
 where
     R: io::BufRead,
     W: io::Write,
+    F: FnMut(BlockSizes),
 {
-    let (unpadded_size, unpacked_size) = {
-        let mut count_output = util::CountWrite::new(output);
-
-        // Block header
-        let mut digest = CRC32.digest();
-        {
-            let mut digested = util::CrcDigestWrite::new(&mut count_output, &mut digest);
-            let header_size = 8;
-            digested.write_u8((header_size >> 2) as u8)?;
-            let flags = 0x00; // 1 filter, no (un)packed size provided
-            digested.write_u8(flags)?;
-            let filter_id = 0x21; // LZMA2
-            digested.write_u8(filter_id)?;
-            let size_of_properties = 1;
-            digested.write_u8(size_of_properties)?;
-            let properties = 22; // TODO
-            digested.write_u8(properties)?;
-            let padding = [0, 0, 0];
-            digested.write_all(&padding)?;
+    let block_size = block_size(options).unwrap_or(THREADED_BLOCK_SIZE);
+    let options = *options;
+    let mut in_flight = VecDeque::with_capacity(options.threads);
+    let mut write_next = |in_flight: &mut VecDeque<BlockWorker>| -> io::Result<()> {
+        let worker = in_flight.pop_front().expect("block in flight");
+        let (block, sizes) = worker
+            .join()
+            .unwrap_or_else(|panic| std::panic::resume_unwind(panic))?;
+        output.write_all(&block)?;
+        if options.block_flush_interval.is_some() {
+            output.flush()?;
         }
-        let crc32 = digest.finalize();
-        count_output.write_u32::<LittleEndian>(crc32)?;
+        add_block(sizes);
+        Ok(())
+    };
+    loop {
+        if in_flight.len() == options.threads {
+            write_next(&mut in_flight)?;
+        }
+        let mut data = Vec::new();
+        input.by_ref().take(block_size).read_to_end(&mut data)?;
+        in_flight.push_back(thread::spawn(move || {
+            let mut block = Vec::new();
+            let sizes = write_block(&mut data.as_slice(), &mut block, &options)?;
+            Ok((block, sizes))
+        }));
+        if decode::util::is_eof(input)? {
+            break;
+        }
+    }
+    while !in_flight.is_empty() {
+        write_next(&mut in_flight)?;
+    }
+    Ok(())
+}
+
+fn write_block<R, W>(input: &mut R, output: &mut W, options: &Options) -> io::Result<BlockSizes>
+where
+    R: io::BufRead,
+    W: io::Write,
+{
+    let check_method = options.check;
+    let mut digest32 = Crc32::new();
+    let mut digest64 = Crc64::new();
+
+    let (unpadded_size, unpacked_size, stats) = {
+        let mut count_output = util::CountWrite::new(output);
 
         // Block
-        let mut count_input = decode::util::CountBufRead::new(input);
-        lzma2::encode_stream(&mut count_input, &mut count_output)?;
-        (count_output.count(), count_input.count())
+        let mut check_input = util::InspectBufRead::new(input, |buf| match check_method {
+            CheckMethod::None | CheckMethod::Sha256 => {}
+            CheckMethod::Crc32 => digest32.update(buf),
+            CheckMethod::Crc64 => digest64.update(buf),
+        });
+        let mut count_input = decode::util::CountBufRead::new(&mut check_input);
+        let mut filtered = Vec::new();
+        if let Some(arch) = options.bcj {
+            count_input.read_to_end(&mut filtered)?;
+            match arch {
+                BcjArch::X86 => bcj::x86_code(&mut filtered, 0, true),
+            }
+        }
+        let mut filtered_input = filtered.as_slice();
+        let mut lzma2_input: &mut dyn io::BufRead = if options.bcj.is_some() {
+            &mut filtered_input
+        } else {
+            &mut count_input
+        };
+        let stats = if options.store_block_sizes {
+            // The sizes are only known once the block is compressed.
+            let mut data = Vec::new();
+            let stats = lzma2::encode_stream(&mut lzma2_input, &mut data, options)?;
+            let sizes = (data.len() as u64, count_input.count() as u64);
+            write_block_header(&mut count_output, Some(sizes), options.bcj)?;
+            count_output.write_all(&data)?;
+            stats
+        } else {
+            write_block_header(&mut count_output, None, options.bcj)?;
+            lzma2::encode_stream(&mut lzma2_input, &mut count_output, options)?
+        };
+        (count_output.count(), count_input.count(), stats)
     };
     lzma_info!(
         "Unpadded size = {}, unpacked_size = {}",
@@ -106,57 +345,160 @@ where
     let padding_size = ((unpadded_size ^ 0x03) + 1) & 0x03;
     let padding = vec![0; padding_size];
     output.write_all(padding.as_slice())?;
-    // Checksum = None (cf. above)
 
-    Ok((unpadded_size, unpacked_size))
+    // Checksum, which counts towards the unpadded size
+    let check_size = match check_method {
+        CheckMethod::None | CheckMethod::Sha256 => 0,
+        CheckMethod::Crc32 => {
+            output.write_u32::<LittleEndian>(digest32.finalize())?;
+            4
+        }
+        CheckMethod::Crc64 => {
+            output.write_u64::<LittleEndian>(digest64.finalize())?;
+            8
+        }
+    };
+
+    Ok((unpadded_size + check_size, unpacked_size, stats))
 }
 
-fn write_index<W>(output: &mut W, unpadded_size: usize, unpacked_size: usize) -> io::Result<usize>
+/// Write the header of a block with an LZMA2 filter, preceded by a BCJ
+/// filter if given, see sect. 3.1. The packed and unpacked sizes of the
+/// block are declared if provided.
+fn write_block_header<W>(
+    output: &mut W,
+    sizes: Option<(u64, u64)>,
+    bcj: Option<BcjArch>,
+) -> io::Result<()>
 where
     W: io::Write,
 {
-    let mut count_output = util::CountWrite::new(output);
+    let mut fields = Vec::new();
+    let mut flags = match sizes {
+        Some(_) => 0xC0, // packed and unpacked sizes provided
+        None => 0x00,    // no (un)packed size provided
+    };
+    // Number of filters minus one
+    if bcj.is_some() {
+        flags |= 0x01;
+    }
+    fields.write_u8(flags)?;
+    if let Some((packed_size, unpacked_size)) = sizes {
+        vli::write_vli(&mut fields, packed_size)?;
+        vli::write_vli(&mut fields, unpacked_size)?;
+    }
+    if let Some(arch) = bcj {
+        let filter_id = match arch {
+            BcjArch::X86 => 0x04,
+        };
+        fields.write_u8(filter_id)?;
+        // No start offset
+        fields.write_u8(0)?;
+    }
+    let filter_id = 0x21; // LZMA2
+    fields.write_u8(filter_id)?;
+    let size_of_properties = 1;
+    fields.write_u8(size_of_properties)?;
+    let properties = dict_size::encode_dict_size_byte(dumbencoder::DICT_SIZE as u64)
+        .expect("representable dictionary size");
+    fields.write_u8(properties)?;
 
-    let mut digest = CRC32.digest();
+    // The header size byte and the fields are padded to a multiple of four
+    // bytes, followed by the CRC32.
+    let header_size = (1 + fields.len() + 3) & !3;
+    let mut digest = Crc32::new();
     {
-        let mut digested = util::CrcDigestWrite::new(&mut count_output, &mut digest);
-        digested.write_u8(0)?; // No more block
-        let num_records = 1;
-        write_multibyte(&mut digested, num_records)?;
+        let mut digested = util::CrcDigestWrite::new(output, &mut digest);
+        digested.write_u8((header_size >> 2) as u8)?;
+        digested.write_all(&fields)?;
+        let padding = vec![0; header_size - 1 - fields.len()];
+        digested.write_all(&padding)?;
+    }
+    let crc32 = digest.finalize();
+    output.write_u32::<LittleEndian>(crc32)?;
+    Ok(())
+}
 
-        write_multibyte(&mut digested, unpadded_size as u64)?;
-        write_multibyte(&mut digested, unpacked_size as u64)?;
+/// Records of the blocks written so far, see sect. 4.
+#[derive(Debug, Default)]
+struct Index {
+    /// Unpadded and uncompressed sizes of each block
+    records: Vec<(u64, u64)>,
+}
+
+impl Index {
+    fn add_record(&mut self, unpadded_size: u64, unpacked_size: u64) {
+        lzma_info!(
+            "XZ index record {}: unpadded_size = {}, unpacked_size = {}",
+            self.records.len(),
+            unpadded_size,
+            unpacked_size
+        );
+        self.records.push((unpadded_size, unpacked_size));
     }
 
-    // Padding
-    let count = count_output.count();
-    let padding_size = ((count ^ 0x03) + 1) & 0x03;
+    /// Writes the index and returns its size in bytes.
+    fn write<W>(&self, output: &mut W) -> io::Result<usize>
+    where
+        W: io::Write,
     {
-        let mut digested = util::CrcDigestWrite::new(&mut count_output, &mut digest);
-        let padding = vec![0; padding_size];
-        digested.write_all(padding.as_slice())?;
-    }
+        let mut count_output = util::CountWrite::new(output);
 
-    let crc32 = digest.finalize();
-    count_output.write_u32::<LittleEndian>(crc32)?;
+        let mut digest = Crc32::new();
+        {
+            let mut digested = util::CrcDigestWrite::new(&mut count_output, &mut digest);
+            digested.write_u8(0)?; // No more block
+            vli::write_vli(&mut digested, self.records.len() as u64)?;
 
-    Ok(count_output.count())
-}
+            for &(unpadded_size, unpacked_size) in &self.records {
+                vli::write_vli(&mut digested, unpadded_size)?;
+                vli::write_vli(&mut digested, unpacked_size)?;
+            }
+        }
 
-fn write_multibyte<W>(output: &mut W, mut value: u64) -> io::Result<()>
-where
-    W: io::Write,
-{
-    loop {
-        let byte = (value & 0x7F) as u8;
-        value >>= 7;
-        if value == 0 {
-            output.write_u8(byte)?;
-            break;
-        } else {
-            output.write_u8(0x80 | byte)?;
+        // Padding
+        let count = count_output.count();
+        let padding_size = ((count ^ 0x03) + 1) & 0x03;
+        {
+            let mut digested = util::CrcDigestWrite::new(&mut count_output, &mut digest);
+            let padding = vec![0; padding_size];
+            digested.write_all(padding.as_slice())?;
         }
+
+        let crc32 = digest.finalize();
+        count_output.write_u32::<LittleEndian>(crc32)?;
+
+        Ok(count_output.count())
     }
+}
 
-    Ok(())
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_check_roundtrip() {
+        let data = b"Hello world!\n".repeat(100);
+        let options = Options {
+            check: CheckMethod::Crc32,
+            ..Options::default()
+        };
+        let mut compressed = Vec::new();
+        encode_stream(&mut data.as_slice(), &mut compressed, &options).unwrap();
+        check_roundtrip(&data, &compressed).unwrap();
+
+        // Flipped bits in the stream and a differing input both fail.
+        let mut corrupt = compressed.clone();
+        corrupt[compressed.len() / 2] ^= 0x10;
+        let err = check_roundtrip(&data, &corrupt).unwrap_err();
+        assert!(matches!(
+            crate::error::Error::from(err),
+            crate::error::Error::RoundtripFailed
+        ));
+        let err = check_roundtrip(&data[1..], &compressed).unwrap_err();
+        assert!(matches!(
+            crate::error::Error::from(err),
+            crate::error::Error::RoundtripFailed
+        ));
+    }
 }
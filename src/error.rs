@@ -1,10 +1,19 @@
 //! Error handling.
 
+use crate::decode::limit::{LimitExceeded, SizeExceeded};
+use crate::decode::options::Digest;
+use crate::encode::stats::{Incompressible, RoundtripFailed};
+use crate::xz::CheckMethod;
+use std::collections::TryReserveError;
 use std::fmt::Display;
 use std::{io, result};
 
 /// Library errors.
+///
+/// More variants may be added in the future, so matches on this type need a
+/// wildcard arm.
 #[derive(Debug)]
+#[non_exhaustive]
 pub enum Error {
     /// I/O error.
     IoError(io::Error),
@@ -14,6 +23,102 @@ pub enum Error {
     LzmaError(String),
     /// XZ error.
     XzError(String),
+    /// The input ended cleanly between two units (e.g. LZMA2 chunks) but
+    /// before the end of the stream, within an LZMA2 chunk, in which case the
+    /// message names the chunk, or in the middle of a symbol of `.lzma` data,
+    /// in which case the message gives the number of bytes decoded.
+    Truncated(String),
+    /// The output exceeded the limit of a
+    /// [`LimitedWriter`](crate::decompress::LimitedWriter), or the size of
+    /// the slice given to [`xz_decompress_into()`](crate::xz_decompress_into),
+    /// in bytes.
+    OutputTooLarge(u64),
+    /// The size of the data disagrees with the size declared for it, e.g. in
+    /// an XZ block header.
+    SizeMismatch {
+        /// Declared size, in bytes.
+        expected: u64,
+        /// Size of the data, in bytes. If larger than `expected`, this is
+        /// only a lower bound as decoding stops early.
+        actual: u64,
+    },
+    /// The digest of the decompressed data differs from the one expected by
+    /// the [`expected_digest`](crate::decompress::Options::expected_digest)
+    /// option.
+    ChecksumMismatch {
+        /// Expected digest.
+        expected: Digest,
+        /// Digest of the decompressed data.
+        actual: Digest,
+    },
+    /// A buffer couldn't be allocated, e.g. under memory pressure or for sizes
+    /// read from untrusted input.
+    AllocationFailed(TryReserveError),
+    /// The XZ index declares more records than allowed, by the
+    /// [`max_index_records`](crate::decompress::Options::max_index_records)
+    /// option or by the size of the index.
+    IndexTooLarge(u64),
+    /// An LZMA2 stream has more chunks than allowed by the
+    /// [`max_chunks`](crate::decompress::Options::max_chunks) option.
+    TooManyChunks(u64),
+    /// A variable-length integer of the XZ format isn't encoded with the
+    /// minimal number of bytes. This is tolerated in
+    /// [`lenient`](crate::decompress::Options::lenient) mode.
+    NonCanonicalVli,
+    /// The stream flags repeated in an XZ stream footer disagree with those
+    /// of the stream header, e.g. in a corrupt or spliced file.
+    StreamFlagsMismatch {
+        /// Check method declared in the header.
+        header: CheckMethod,
+        /// Check method declared in the footer.
+        footer: CheckMethod,
+    },
+    /// The CRC32 of the stream flags in an XZ stream header doesn't match
+    /// them, i.e. the first bytes of the stream are corrupt.
+    StreamHeaderCorrupt {
+        /// CRC32 stored in the header.
+        expected: u32,
+        /// CRC32 of the stream flags.
+        actual: u32,
+    },
+    /// Compressed data was rejected for being larger than its input, see
+    /// [`Options::reject_incompressible`](crate::compress::Options::reject_incompressible).
+    Incompressible {
+        /// Size of the input, in bytes.
+        input_size: u64,
+        /// Size the compressed data would have, in bytes.
+        output_size: u64,
+    },
+    /// Compressed data didn't decompress to its input, see
+    /// [`Options::verify_roundtrip`](crate::compress::Options::verify_roundtrip).
+    RoundtripFailed,
+    /// An XZ block uses a filter ID unknown to this crate, e.g. from corrupt
+    /// data or a vendor extension. Filters defined by the format that aren't
+    /// implemented yet, and the IDs the format reserves for future filters,
+    /// i.e. up to 0x3F, are reported as [`Error::Unsupported`] instead.
+    UnsupportedFilter(u64),
+    /// The filter chain of an XZ block header is invalid, e.g. LZMA2 isn't
+    /// the last filter or another filter is repeated.
+    InvalidFilterChain(String),
+    /// Decoding didn't complete before the
+    /// [`deadline`](crate::decompress::Options::deadline) option.
+    TimedOut,
+    /// A match refers to data further back than the dictionary size set by
+    /// the [`override_dict_size`](crate::decompress::Options::override_dict_size)
+    /// option, although within the size declared by the stream.
+    DistanceBeyondOverride {
+        /// Distance of the match, in bytes.
+        distance: u64,
+        /// Overridden dictionary size, in bytes.
+        dict_size: u64,
+    },
+    /// The data uses a feature that this crate doesn't support yet, such as
+    /// a filter, a check type or a newer version of the format, rather than
+    /// being corrupt.
+    Unsupported {
+        /// Description of the feature.
+        what: String,
+    },
 }
 
 /// Library result alias.
@@ -21,10 +126,36 @@ pub type Result<T> = result::Result<T, Error>;
 
 impl From<io::Error> for Error {
     fn from(e: io::Error) -> Error {
+        if let Some(inner) = e.get_ref() {
+            if let Some(exceeded) = inner.downcast_ref::<LimitExceeded>() {
+                return Error::OutputTooLarge(exceeded.limit);
+            }
+            if let Some(exceeded) = inner.downcast_ref::<SizeExceeded>() {
+                return Error::SizeMismatch {
+                    expected: exceeded.expected,
+                    actual: exceeded.actual,
+                };
+            }
+            if let Some(incompressible) = inner.downcast_ref::<Incompressible>() {
+                return Error::Incompressible {
+                    input_size: incompressible.input_size,
+                    output_size: incompressible.output_size,
+                };
+            }
+            if inner.downcast_ref::<RoundtripFailed>().is_some() {
+                return Error::RoundtripFailed;
+            }
+        }
         Error::IoError(e)
     }
 }
 
+impl From<TryReserveError> for Error {
+    fn from(e: TryReserveError) -> Error {
+        Error::AllocationFailed(e)
+    }
+}
+
 impl Display for Error {
     fn fmt(&self, fmt: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
@@ -32,6 +163,64 @@ impl Display for Error {
             Error::HeaderTooShort(e) => write!(fmt, "header too short: {}", e),
             Error::LzmaError(e) => write!(fmt, "lzma error: {}", e),
             Error::XzError(e) => write!(fmt, "xz error: {}", e),
+            Error::Truncated(e) => write!(fmt, "truncated input: {}", e),
+            Error::OutputTooLarge(limit) => {
+                write!(fmt, "output too large: more than {} bytes", limit)
+            }
+            Error::SizeMismatch { expected, actual } => write!(
+                fmt,
+                "size mismatch: expected {} bytes but got {}",
+                expected, actual
+            ),
+            Error::ChecksumMismatch { expected, actual } => write!(
+                fmt,
+                "checksum mismatch: expected {} but got {}",
+                expected, actual
+            ),
+            Error::AllocationFailed(e) => write!(fmt, "allocation failed: {}", e),
+            Error::IndexTooLarge(records) => {
+                write!(fmt, "index too large: {} records", records)
+            }
+            Error::TooManyChunks(chunks) => {
+                write!(fmt, "too many chunks: {} LZMA2 chunks", chunks)
+            }
+            Error::NonCanonicalVli => {
+                write!(fmt, "non-canonical integer: not minimally encoded")
+            }
+            Error::StreamFlagsMismatch { header, footer } => write!(
+                fmt,
+                "stream flags mismatch: header declares {:?} check but footer declares {:?}",
+                header, footer
+            ),
+            Error::StreamHeaderCorrupt { expected, actual } => write!(
+                fmt,
+                "stream header corrupt: expected CRC32 0x{:08x} but got 0x{:08x}",
+                expected, actual
+            ),
+            Error::Incompressible {
+                input_size,
+                output_size,
+            } => write!(
+                fmt,
+                "incompressible data: {} bytes would compress to {} bytes",
+                input_size, output_size
+            ),
+            Error::RoundtripFailed => write!(
+                fmt,
+                "round trip failed: compressed data doesn't decompress to the input"
+            ),
+            Error::UnsupportedFilter(id) => write!(fmt, "unsupported filter: ID {:#x}", id),
+            Error::InvalidFilterChain(e) => write!(fmt, "invalid filter chain: {}", e),
+            Error::TimedOut => write!(fmt, "timed out: decoding deadline passed"),
+            Error::DistanceBeyondOverride {
+                distance,
+                dict_size,
+            } => write!(
+                fmt,
+                "distance beyond override: match distance {} exceeds the dictionary of {} bytes",
+                distance, dict_size
+            ),
+            Error::Unsupported { what } => write!(fmt, "unsupported: {}", what),
         }
     }
 }
@@ -40,14 +229,32 @@ impl std::error::Error for Error {
     fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
         match self {
             Error::IoError(e) | Error::HeaderTooShort(e) => Some(e),
-            Error::LzmaError(_) | Error::XzError(_) => None,
+            Error::AllocationFailed(e) => Some(e),
+            Error::LzmaError(_)
+            | Error::XzError(_)
+            | Error::Truncated(_)
+            | Error::OutputTooLarge(_)
+            | Error::SizeMismatch { .. }
+            | Error::ChecksumMismatch { .. }
+            | Error::IndexTooLarge(_)
+            | Error::TooManyChunks(_)
+            | Error::NonCanonicalVli
+            | Error::StreamFlagsMismatch { .. }
+            | Error::StreamHeaderCorrupt { .. }
+            | Error::Incompressible { .. }
+            | Error::RoundtripFailed
+            | Error::UnsupportedFilter(_)
+            | Error::InvalidFilterChain(_)
+            | Error::TimedOut
+            | Error::DistanceBeyondOverride { .. }
+            | Error::Unsupported { .. } => None,
         }
     }
 }
 
 #[cfg(test)]
 mod test {
-    use super::Error;
+    use super::{CheckMethod, Digest, Error};
 
     #[test]
     fn test_display() {
@@ -67,5 +274,100 @@ mod test {
             Error::XzError("this is an error".to_string()).to_string(),
             "xz error: this is an error"
         );
+        assert_eq!(
+            Error::Truncated("this is an error".to_string()).to_string(),
+            "truncated input: this is an error"
+        );
+        assert_eq!(
+            Error::OutputTooLarge(42).to_string(),
+            "output too large: more than 42 bytes"
+        );
+        assert_eq!(
+            Error::SizeMismatch {
+                expected: 42,
+                actual: 43
+            }
+            .to_string(),
+            "size mismatch: expected 42 bytes but got 43"
+        );
+        assert_eq!(
+            Error::ChecksumMismatch {
+                expected: Digest::Crc32(0xCBF4_3926),
+                actual: Digest::Crc32(0x42),
+            }
+            .to_string(),
+            "checksum mismatch: expected CRC32 0xcbf43926 but got CRC32 0x00000042"
+        );
+        assert_eq!(
+            Error::Incompressible {
+                input_size: 42,
+                output_size: 43
+            }
+            .to_string(),
+            "incompressible data: 42 bytes would compress to 43 bytes"
+        );
+        assert_eq!(
+            Error::RoundtripFailed.to_string(),
+            "round trip failed: compressed data doesn't decompress to the input"
+        );
+        assert_eq!(
+            Error::UnsupportedFilter(0x03).to_string(),
+            "unsupported filter: ID 0x3"
+        );
+        assert_eq!(
+            Error::InvalidFilterChain("this is an error".to_string()).to_string(),
+            "invalid filter chain: this is an error"
+        );
+        assert_eq!(
+            Error::Unsupported {
+                what: "this feature".to_string()
+            }
+            .to_string(),
+            "unsupported: this feature"
+        );
+        assert_eq!(
+            Error::TimedOut.to_string(),
+            "timed out: decoding deadline passed"
+        );
+        assert_eq!(
+            Error::DistanceBeyondOverride {
+                distance: 5000,
+                dict_size: 4096
+            }
+            .to_string(),
+            "distance beyond override: match distance 5000 exceeds the dictionary of 4096 bytes"
+        );
+        assert_eq!(
+            Error::IndexTooLarge(42).to_string(),
+            "index too large: 42 records"
+        );
+        assert_eq!(
+            Error::TooManyChunks(42).to_string(),
+            "too many chunks: 42 LZMA2 chunks"
+        );
+        assert_eq!(
+            Error::NonCanonicalVli.to_string(),
+            "non-canonical integer: not minimally encoded"
+        );
+        assert_eq!(
+            Error::StreamFlagsMismatch {
+                header: CheckMethod::Crc64,
+                footer: CheckMethod::Crc32
+            }
+            .to_string(),
+            "stream flags mismatch: header declares Crc64 check but footer declares Crc32"
+        );
+        assert_eq!(
+            Error::StreamHeaderCorrupt {
+                expected: 0x42,
+                actual: 0xCBF4_3926
+            }
+            .to_string(),
+            "stream header corrupt: expected CRC32 0x00000042 but got 0xcbf43926"
+        );
+        let e = Vec::<u8>::new().try_reserve(usize::MAX).unwrap_err();
+        assert!(Error::AllocationFailed(e)
+            .to_string()
+            .starts_with("allocation failed: "));
     }
 }
@@ -1,4 +1,11 @@
 //! Pure-Rust codecs for LZMA, LZMA2, and XZ.
+//!
+//! The XZ container, with its checks and filters, is compiled with the `xz`
+//! feature, which is enabled by default. Without it, only the LZMA and LZMA2
+//! codecs remain, e.g. to embed them in another format.
+//!
+//! The decoders and options hold no shared state, so they are [`Send`] and
+//! [`Sync`] whenever the readers, writers and callbacks they wrap are.
 #![cfg_attr(docsrs, feature(doc_cfg, doc_cfg_hide))]
 #![deny(missing_docs)]
 #![deny(missing_debug_implementations)]
@@ -12,31 +19,57 @@ mod encode;
 
 pub mod error;
 
+mod self_test;
 mod util;
 mod xz;
 
 use std::io;
+use std::path::Path;
 
 /// Compression helpers.
 pub mod compress {
+    pub use crate::encode::dict_size::{encode_dict_size_byte, normalize_dict_size};
     pub use crate::encode::options::*;
+    pub use crate::encode::stats::*;
 }
 
 /// Decompression helpers.
 pub mod decompress {
+    pub use crate::decode::aligned::AlignedWriter;
+    pub use crate::decode::chain::ChainedReader;
+    pub use crate::decode::format::Format;
+    pub use crate::decode::frame::FrameWriter;
+    pub use crate::decode::limit::LimitedWriter;
+    pub use crate::decode::lzma::{LzmaStatus, Symbol, Termination};
+    pub use crate::decode::lzma2::{ChunkEntry, Lzma2Gap, Lzma2Status, ResetEvent};
+    pub use crate::decode::lzma_reader::LzmaReader;
     pub use crate::decode::options::*;
+    pub use crate::decode::progress::{Progress, ProgressReader};
+    #[cfg(feature = "xz")]
+    #[cfg_attr(docsrs, doc(cfg(xz)))]
+    pub use crate::decode::xz::{VerifyReport, Warning, WarningKind, XzStatus};
+    #[cfg(feature = "xz")]
+    #[cfg_attr(docsrs, doc(cfg(xz)))]
+    pub use crate::decode::xz_list::{XzBlockInfo, XzListing, XzStreamInfo};
+    #[cfg(feature = "xz")]
+    #[cfg_attr(docsrs, doc(cfg(xz)))]
+    pub use crate::decode::xz_seek::XzSeekReader;
 
     #[cfg(feature = "raw_decoder")]
     #[cfg_attr(docsrs, doc(cfg(raw_decoder)))]
     pub mod raw {
         //! Raw decoding primitives for LZMA/LZMA2 streams.
-        pub use crate::decode::lzma::{LzmaDecoder, LzmaParams, LzmaProperties};
+        pub use crate::decode::lzbuffer::{LzAccumBuffer, LzBuffer, LzCircularBuffer};
+        pub use crate::decode::lzma::{LzmaDecoder, LzmaParams, LzmaProperties, RawLzmaDecoder};
         pub use crate::decode::lzma2::Lzma2Decoder;
     }
 
     #[cfg(feature = "stream")]
     #[cfg_attr(docsrs, doc(cfg(stream)))]
     pub use crate::decode::stream::Stream;
+    #[cfg(all(feature = "stream", feature = "xz"))]
+    #[cfg_attr(docsrs, doc(cfg(all(stream, xz))))]
+    pub use crate::decode::xz_stream::XzWriteDecoder;
 }
 
 /// Decompress LZMA data with default
@@ -54,28 +87,149 @@ pub fn lzma_decompress_with_options<R: io::BufRead, W: io::Write>(
     output: &mut W,
     options: &decompress::Options,
 ) -> error::Result<()> {
-    let params = decode::lzma::LzmaParams::read_header(input, options)?;
-    let mut decoder = decode::lzma::LzmaDecoder::new(params, options.memlimit)?;
-    decoder.decompress(input, output)
+    lzma_decompress_with_symbols(input, output, options, |_| {})
+}
+
+/// Decompress LZMA data with the provided options, passing each decoded
+/// [`Symbol`](decompress/enum.Symbol.html) to `on_symbol`, e.g. to analyze
+/// how the data was modeled.
+///
+/// The symbols of the concatenated members are reported in turn, if the
+/// [`concatenated`](decompress::Options::concatenated)
+/// option is set.
+pub fn lzma_decompress_with_symbols<R, W, F>(
+    input: &mut R,
+    output: &mut W,
+    options: &decompress::Options,
+    on_symbol: F,
+) -> error::Result<()>
+where
+    R: io::BufRead,
+    W: io::Write,
+    F: FnMut(decompress::Symbol),
+{
+    decode::lzma::decode_stream(input, output, options, on_symbol)?;
+    Ok(())
+}
+
+/// Decompress LZMA data with the provided options.
+///
+/// Returns an [`LzmaStatus`](decompress/struct.LzmaStatus.html) about the
+/// decoded stream, e.g. whether it ends with an end-of-stream marker.
+pub fn lzma_decompress_with_status<R: io::BufRead, W: io::Write>(
+    input: &mut R,
+    output: &mut W,
+    options: &decompress::Options,
+) -> error::Result<decompress::LzmaStatus> {
+    decode::lzma::decode_stream(input, output, options, |_| {})
+}
+
+/// Decompress LZMA data with default
+/// [`Options`](decompress/struct.Options.html), appending the output to
+/// `out`.
+///
+/// The existing content of `out` is kept and isn't part of the dictionary:
+/// the LZ sequences only refer to the bytes of this stream. If decompression
+/// fails, the bytes decoded so far remain appended.
+pub fn lzma_decompress_append<R: io::BufRead>(
+    input: &mut R,
+    out: &mut Vec<u8>,
+) -> error::Result<()> {
+    lzma_decompress(input, out)
+}
+
+/// Decompress LZMA data with default
+/// [`Options`](decompress/struct.Options.html), passing the output to
+/// `callback` in frames of `frame_size` bytes.
+///
+/// Only the last frame may be shorter. Errors returned by `callback` abort
+/// the decompression.
+///
+/// # Panics
+///
+/// Panics if `frame_size` is zero.
+pub fn lzma_decompress_frames<R, F>(
+    input: &mut R,
+    frame_size: usize,
+    callback: F,
+) -> error::Result<()>
+where
+    R: io::BufRead,
+    F: FnMut(&[u8]) -> io::Result<()>,
+{
+    let mut writer = decompress::FrameWriter::new(frame_size, callback);
+    lzma_decompress(input, &mut writer)?;
+    writer.finish()?;
+    Ok(())
 }
 
 /// Compresses data with LZMA and default
 /// [`Options`](compress/struct.Options.html).
+///
+/// The encoder doesn't search for matches yet and only emits literals, so it
+/// already favors speed over compression ratio.
+///
+/// Returns [`CompressStats`](compress/struct.CompressStats.html) about the
+/// compressed stream.
 pub fn lzma_compress<R: io::BufRead, W: io::Write>(
     input: &mut R,
     output: &mut W,
-) -> io::Result<()> {
+) -> io::Result<compress::CompressStats> {
     lzma_compress_with_options(input, output, &compress::Options::default())
 }
 
+/// Compresses `input` with LZMA and default
+/// [`Options`](compress/struct.Options.html), returning the compressed
+/// stream.
+///
+/// As the whole input is known, its size is written to the header and no
+/// end-of-stream marker is needed, unlike with [`lzma_compress`] which reads
+/// the input as a stream. To write the unknown size instead, use
+/// [`lzma_compress_with_options`] with
+/// [`UnpackedSize::WriteToHeader(None)`](compress::UnpackedSize::WriteToHeader).
+///
+/// The output vector is pre-allocated from an estimate of the compressed
+/// size, to avoid repeated reallocations. The estimate is only advisory: the
+/// vector grows as needed if the data compresses worse.
+///
+/// Inputs of at most 512 bytes, e.g. small messages, are encoded with a
+/// smaller model and declare the smallest dictionary, which lowers the cost
+/// of setting up the encoder and the decoder.
+pub fn lzma_compress_to_vec(input: &[u8]) -> io::Result<Vec<u8>> {
+    let mut output = Vec::with_capacity(encode::util::estimated_compressed_len(input.len()));
+    let options = compress::Options {
+        unpacked_size: compress::UnpackedSize::WriteToHeader(Some(input.len() as u64)),
+        ..Default::default()
+    };
+    lzma_compress_with_options(&mut &input[..], &mut output, &options)?;
+    Ok(output)
+}
+
 /// Compress LZMA data with the provided options.
 pub fn lzma_compress_with_options<R: io::BufRead, W: io::Write>(
     input: &mut R,
     output: &mut W,
     options: &compress::Options,
-) -> io::Result<()> {
-    let encoder = encode::dumbencoder::Encoder::from_stream(output, options)?;
-    encoder.process(input)
+) -> io::Result<compress::CompressStats> {
+    if !options.reject_incompressible {
+        let encoder = encode::dumbencoder::Encoder::from_stream(output, options)?;
+        return encoder.process(input);
+    }
+
+    let mut buf = Vec::new();
+    let encoder = encode::dumbencoder::Encoder::from_stream(&mut buf, options)?;
+    let stats = encoder.process(input)?;
+    if stats.expanded() {
+        return Err(io::Error::new(
+            io::ErrorKind::Other,
+            encode::stats::Incompressible {
+                input_size: stats.input_size,
+                output_size: stats.output_size,
+            },
+        ));
+    }
+    output.write_all(&buf)?;
+    Ok(stats)
 }
 
 /// Decompress LZMA2 data with default
@@ -87,24 +241,450 @@ pub fn lzma2_decompress<R: io::BufRead, W: io::Write>(
     decode::lzma2::Lzma2Decoder::new().decompress(input, output)
 }
 
+/// Decompress LZMA2 data with the provided options.
+pub fn lzma2_decompress_with_options<R: io::BufRead, W: io::Write>(
+    input: &mut R,
+    output: &mut W,
+    options: &decompress::Options,
+) -> error::Result<()> {
+    lzma2_decompress_with_status(input, output, options)?;
+    Ok(())
+}
+
+/// Decompress LZMA2 data with the provided options.
+///
+/// Returns an [`Lzma2Status`](decompress/struct.Lzma2Status.html) about the
+/// decoded stream, e.g. the map of its chunks if the
+/// [`record_chunk_map`](decompress::Options::record_chunk_map) option is set.
+pub fn lzma2_decompress_with_status<R: io::BufRead, W: io::Write>(
+    input: &mut R,
+    output: &mut W,
+    options: &decompress::Options,
+) -> error::Result<decompress::Lzma2Status> {
+    lzma2_decompress_with_resets(input, output, options, |_| {})
+}
+
+/// Decompress LZMA2 data with the provided options, passing each
+/// [`ResetEvent`](decompress/struct.ResetEvent.html) to `on_reset` before
+/// decoding the chunk starting with it, e.g. to note the points where
+/// decoding can later resume while streaming.
+///
+/// Returns an [`Lzma2Status`](decompress/struct.Lzma2Status.html) like
+/// [`lzma2_decompress_with_status`].
+pub fn lzma2_decompress_with_resets<R, W, F>(
+    input: &mut R,
+    output: &mut W,
+    options: &decompress::Options,
+    mut on_reset: F,
+) -> error::Result<decompress::Lzma2Status>
+where
+    R: io::BufRead,
+    W: io::Write,
+    F: FnMut(decompress::ResetEvent),
+{
+    let mut decoder = decode::lzma2::Lzma2Decoder::new();
+    decoder.force_props(options.force_props)?;
+    decoder.memlimit(options.memlimit);
+    decoder.deadline(options.deadline);
+    decoder.max_chunks(options.max_chunks);
+    let mut output = decode::limit::CheckedWriter::new(output, options);
+    let mut chunks = Vec::new();
+    let on_entry = |entry: &decompress::ChunkEntry| {
+        if entry.dict_reset || entry.state_reset {
+            on_reset(decompress::ResetEvent {
+                compressed_offset: entry.compressed_offset,
+                uncompressed_offset: entry.uncompressed_offset,
+                dict_reset: entry.dict_reset,
+            });
+        }
+        if options.record_chunk_map {
+            chunks.push(*entry);
+        }
+    };
+    let mut gaps = Vec::new();
+    if options.resync {
+        let mut data = Vec::new();
+        input.read_to_end(&mut data)?;
+        decoder.decompress_resync(&data, &mut output, &mut gaps, on_entry)?;
+    } else {
+        decoder.decompress_with_chunk_entries(input, &mut output, on_entry)?;
+    }
+    output.finish()?;
+    let status = decompress::Lzma2Status { chunks, gaps };
+    Ok(status)
+}
+
 /// Compress data with LZMA2 and default
 /// [`Options`](compress/struct.Options.html).
 pub fn lzma2_compress<R: io::BufRead, W: io::Write>(
     input: &mut R,
     output: &mut W,
-) -> io::Result<()> {
-    encode::lzma2::encode_stream(input, output)
+) -> io::Result<compress::CompressStats> {
+    lzma2_compress_with_options(input, output, &compress::Options::default())
+}
+
+/// Compress data with LZMA2 and the provided options. Only
+/// [`chunk_boundary_bytes`](compress::Options::chunk_boundary_bytes),
+/// [`pad_to`](compress::Options::pad_to),
+/// [`store`](compress::Options::store),
+/// [`auto_store`](compress::Options::auto_store) and
+/// [`record_chunk_map`](compress::Options::record_chunk_map)
+/// apply.
+pub fn lzma2_compress_with_options<R: io::BufRead, W: io::Write>(
+    input: &mut R,
+    output: &mut W,
+    options: &compress::Options,
+) -> io::Result<compress::CompressStats> {
+    encode::lzma2::encode_raw_stream(input, output, options)
+}
+
+/// Compress successive chunks of data with LZMA2 and the provided options,
+/// e.g. messages arriving one by one.
+///
+/// The chunks aren't concatenated first, and the compressed data is the same
+/// as for their concatenation: their boundaries don't end LZMA2 chunks.
+pub fn lzma2_compress_chunks<I, B, W>(
+    chunks: I,
+    output: &mut W,
+    options: &compress::Options,
+) -> io::Result<compress::CompressStats>
+where
+    I: IntoIterator<Item = B>,
+    B: AsRef<[u8]>,
+    W: io::Write,
+{
+    let mut input = encode::util::ChunksBufRead::new(chunks.into_iter());
+    lzma2_compress_with_options(&mut input, output, options)
 }
 
 /// Decompress XZ data with default [`Options`](decompress/struct.Options.html).
+#[cfg(feature = "xz")]
+#[cfg_attr(docsrs, doc(cfg(xz)))]
 pub fn xz_decompress<R: io::BufRead, W: io::Write>(
     input: &mut R,
     output: &mut W,
 ) -> error::Result<()> {
-    decode::xz::decode_stream(input, output)
+    xz_decompress_with_options(input, output, &decompress::Options::default())?;
+    Ok(())
+}
+
+/// Decompress XZ data with the provided options.
+///
+/// Returns an [`XzStatus`](decompress/struct.XzStatus.html) about the decoded
+/// stream.
+#[cfg(feature = "xz")]
+#[cfg_attr(docsrs, doc(cfg(xz)))]
+pub fn xz_decompress_with_options<R: io::BufRead, W: io::Write>(
+    input: &mut R,
+    output: &mut W,
+    options: &decompress::Options,
+) -> error::Result<decompress::XzStatus> {
+    decode::xz::decode_stream(input, output, options)
+}
+
+/// Check that XZ data decompresses cleanly, without keeping the
+/// decompressed data.
+///
+/// The whole stream is decoded and checked as by [`xz_decompress()`], but
+/// the output is discarded, which saves writing it when only its validity
+/// matters. Returns a [`VerifyReport`](decompress/struct.VerifyReport.html)
+/// about the stream.
+#[cfg(feature = "xz")]
+#[cfg_attr(docsrs, doc(cfg(xz)))]
+pub fn xz_verify<R: io::BufRead>(input: &mut R) -> error::Result<decompress::VerifyReport> {
+    decode::xz::verify(input)
+}
+
+/// Read the structure of an XZ file, like `xz --list`, without decompressing
+/// it.
+///
+/// Only the stream headers, footers and indexes are read. Files made of
+/// several streams, possibly separated by stream padding, are supported.
+#[cfg(feature = "xz")]
+#[cfg_attr(docsrs, doc(cfg(xz)))]
+pub fn xz_list<R: io::Read + io::Seek>(input: &mut R) -> error::Result<decompress::XzListing> {
+    decode::xz_list::list(input)
+}
+
+/// Decompress the block number `block` of the XZ file `input`, counting the
+/// blocks of all its streams in order, as listed by [`xz_list()`].
+///
+/// Only this block is read and decoded, and checked against its check and
+/// its index record. Blocks are independent, as they start by resetting the
+/// dictionary, so they can be decoded in parallel or in any order. The
+/// decompressed data starts at the block's
+/// [`uncompressed_offset`](decompress::XzBlockInfo::uncompressed_offset).
+#[cfg(feature = "xz")]
+#[cfg_attr(docsrs, doc(cfg(xz)))]
+pub fn xz_decompress_block<R, W>(
+    input: &mut R,
+    listing: &decompress::XzListing,
+    block: usize,
+    output: &mut W,
+) -> error::Result<()>
+where
+    R: io::Read + io::Seek,
+    W: io::Write,
+{
+    decode::xz_seek::decompress_block(input, listing, block, output)
+}
+
+/// Decompress the XZ file `input` into `output`, e.g. a pre-allocated or
+/// memory-mapped region, returning the size of the decompressed data.
+///
+/// The index is parsed first, to check that `output` can hold the total
+/// decompressed size. Otherwise, this fails with
+/// [`Error::OutputTooLarge`](error::Error::OutputTooLarge) before decoding
+/// anything. The blocks are then decoded in order, each one starting at the
+/// end of the previous one in `output`, so no buffer is allocated for the
+/// output itself. Files made of several streams are supported.
+#[cfg(feature = "xz")]
+#[cfg_attr(docsrs, doc(cfg(xz)))]
+pub fn xz_decompress_into<R: io::Read + io::Seek>(
+    input: &mut R,
+    output: &mut [u8],
+) -> error::Result<usize> {
+    decode::xz_seek::decompress_into(input, output)
 }
 
 /// Compress data with XZ and default [`Options`](compress/struct.Options.html).
-pub fn xz_compress<R: io::BufRead, W: io::Write>(input: &mut R, output: &mut W) -> io::Result<()> {
-    encode::xz::encode_stream(input, output)
+#[cfg(feature = "xz")]
+#[cfg_attr(docsrs, doc(cfg(xz)))]
+pub fn xz_compress<R: io::BufRead, W: io::Write>(
+    input: &mut R,
+    output: &mut W,
+) -> io::Result<compress::CompressStats> {
+    xz_compress_with_options(input, output, &compress::Options::default())
+}
+
+/// Compresses `input` with XZ and default
+/// [`Options`](compress/struct.Options.html), returning the compressed
+/// stream. The output is pre-allocated as for [`lzma_compress_to_vec()`].
+#[cfg(feature = "xz")]
+#[cfg_attr(docsrs, doc(cfg(xz)))]
+pub fn xz_compress_to_vec(input: &[u8]) -> io::Result<Vec<u8>> {
+    let mut output = Vec::with_capacity(encode::util::estimated_compressed_len(input.len()));
+    xz_compress(&mut &input[..], &mut output)?;
+    Ok(output)
+}
+
+/// Compress data with XZ and the provided options.
+#[cfg(feature = "xz")]
+#[cfg_attr(docsrs, doc(cfg(xz)))]
+pub fn xz_compress_with_options<R: io::BufRead, W: io::Write>(
+    input: &mut R,
+    output: &mut W,
+    options: &compress::Options,
+) -> io::Result<compress::CompressStats> {
+    encode::xz::encode_stream(input, output, options)
+}
+
+/// Compress successive chunks of data with XZ and the provided options,
+/// e.g. messages arriving one by one.
+///
+/// The chunks aren't concatenated first, and the compressed data is the same
+/// as for their concatenation: their boundaries don't end LZMA2 chunks or XZ
+/// blocks.
+#[cfg(feature = "xz")]
+#[cfg_attr(docsrs, doc(cfg(xz)))]
+pub fn xz_compress_chunks<I, B, W>(
+    chunks: I,
+    output: &mut W,
+    options: &compress::Options,
+) -> io::Result<compress::CompressStats>
+where
+    I: IntoIterator<Item = B>,
+    B: AsRef<[u8]>,
+    W: io::Write,
+{
+    let mut input = encode::util::ChunksBufRead::new(chunks.into_iter());
+    xz_compress_with_options(&mut input, output, options)
+}
+
+/// Concatenate the `.xz` files `inputs` into a single one, without
+/// recompressing them, e.g. to merge archives. Returns the number of bytes
+/// written to `output`.
+///
+/// The blocks of all the streams of the inputs are copied as is into a
+/// single stream, whose index lists them all, so that it decompresses to the
+/// concatenation of the data of the inputs. Stream padding is dropped. The
+/// structure of each input is checked as by [`xz_list()`] before anything is
+/// written, but not the compressed data of its blocks.
+///
+/// All the streams holding blocks must use the same check method, as it's
+/// declared for the whole stream. Without any block, an empty stream is
+/// written.
+#[cfg(feature = "xz")]
+#[cfg_attr(docsrs, doc(cfg(xz)))]
+pub fn xz_concat<I, B, W>(inputs: I, output: &mut W) -> error::Result<u64>
+where
+    I: IntoIterator<Item = B>,
+    B: AsRef<[u8]>,
+    W: io::Write,
+{
+    let inputs: Vec<B> = inputs.into_iter().collect();
+    encode::xz::concat(&inputs, output)
+}
+
+/// Decompress the `.xz` or `.lzma` file at `path` with default
+/// [`Options`](decompress/struct.Options.html), returning its content.
+///
+/// The format is detected from the XZ magic bytes, and other files are
+/// decoded as `.lzma`. Errors opening or reading the file are returned as
+/// [`Error::IoError`](error/enum.Error.html#variant.IoError).
+pub fn decompress_file<P: AsRef<Path>>(path: P) -> error::Result<Vec<u8>> {
+    let mut output = Vec::new();
+    decompress_file_to(path, &mut output)?;
+    Ok(output)
+}
+
+/// Decompress the `.xz` or `.lzma` file at `path` with default
+/// [`Options`](decompress/struct.Options.html), writing its content to
+/// `output`. See [`decompress_file()`].
+pub fn decompress_file_to<P: AsRef<Path>, W: io::Write>(
+    path: P,
+    output: &mut W,
+) -> error::Result<()> {
+    let mut input = io::BufReader::new(std::fs::File::open(path)?);
+    match sniff_format(&mut input)? {
+        #[cfg(feature = "xz")]
+        decompress::Format::Xz => xz_decompress(&mut input, output),
+        #[cfg(not(feature = "xz"))]
+        decompress::Format::Xz => Err(xz_disabled()),
+        _ => lzma_decompress(&mut input, output),
+    }
+}
+
+/// Error for XZ input, which can't be decoded without the `xz` feature.
+#[cfg(not(feature = "xz"))]
+fn xz_disabled() -> error::Error {
+    error::Error::Unsupported {
+        what: "XZ data without the `xz` feature".to_string(),
+    }
+}
+
+/// Detect whether `input` is [`Xz`](decompress::Format::Xz) or
+/// [`LzmaAlone`](decompress::Format::LzmaAlone) data from its leading bytes,
+/// without consuming them, e.g. to route it to the right decoder.
+///
+/// XZ data starts with its magic bytes. The `.lzma` header has none, so it's
+/// recognized from a valid properties byte and an unpacked size that is
+/// either unknown or below 256 GiB, like liblzma does. Anything else,
+/// including input too short to hold either header in the buffer of
+/// `input`, is [`Unknown`](decompress::Format::Unknown), for the caller to
+/// decide.
+pub fn sniff_format<R: io::BufRead>(input: &mut R) -> error::Result<decompress::Format> {
+    decode::format::sniff(input)
+}
+
+/// Decompress the `.xz` or `.lzma` input while keeping the memory used by
+/// the decoder under `max_memory` bytes, e.g. for untrusted input on
+/// memory-constrained systems. The format is detected as in
+/// [`decompress_file()`].
+///
+/// The budget covers the probability model, the dictionary and the buffers
+/// of the decoder, but neither `input` nor `output`. Inputs that need more
+/// fail instead of allocating it:
+/// - `.lzma` inputs whose declared dictionary doesn't fit are rejected before
+///   decoding.
+/// - XZ blocks are decoded in memory before they're validated against their
+///   check, possibly twice with a BCJ filter, in buffers that grow
+///   geometrically. Each block can therefore take up to a quarter of the budget
+///   left by the model, through the [`memlimit`](decompress::Options::memlimit)
+///   and [`max_block_output`](decompress::Options::max_block_output) options.
+///   Blocks declaring a larger size are rejected before being decoded.
+pub fn decompress_bounded<R: io::BufRead, W: io::Write>(
+    input: &mut R,
+    output: &mut W,
+    max_memory: usize,
+) -> error::Result<()> {
+    let exceeded = |needed: u64| {
+        error::Error::LzmaError(format!(
+            "decoding needs at least {} bytes, beyond the memory limit of {}",
+            needed, max_memory
+        ))
+    };
+    match sniff_format(input)? {
+        #[cfg(feature = "xz")]
+        decompress::Format::Xz => {
+            // LZMA2 properties satisfy `lc + lp <= 4`.
+            let model = <decode::lzma::DecoderState>::memory_usage(decode::lzma::LzmaProperties {
+                lc: 4,
+                lp: 0,
+                pb: 0,
+            });
+            let buffers = max_memory
+                .checked_sub(model)
+                .ok_or_else(|| exceeded(model as u64))?;
+            let options = decompress::Options {
+                memlimit: Some(buffers / 4),
+                max_block_output: Some((buffers / 4) as u64),
+                ..Default::default()
+            };
+            xz_decompress_with_options(input, output, &options)?;
+            Ok(())
+        }
+        #[cfg(not(feature = "xz"))]
+        decompress::Format::Xz => Err(xz_disabled()),
+        _ => {
+            // The header is read ahead to size the decoder, and decoded again.
+            let mut header = [0; 13];
+            input
+                .read_exact(&mut header)
+                .map_err(error::Error::HeaderTooShort)?;
+            let options = decompress::Options::default();
+            let params = decode::lzma::LzmaParams::read_header(&mut &header[..], &options)?;
+            let model = <decode::lzma::DecoderState>::memory_usage(params.properties);
+            let needed = model as u64 + params.dict_size as u64;
+            if needed > max_memory as u64 {
+                return Err(exceeded(needed));
+            }
+            let options = decompress::Options {
+                memlimit: Some(max_memory - model),
+                ..options
+            };
+            lzma_decompress_with_options(&mut io::Read::chain(&header[..], input), output, &options)
+        }
+    }
+}
+
+/// Estimate the size of the decompressed data of the `.xz` or `.lzma` input
+/// from the sizes it declares, without decompressing it, e.g. to pre-size
+/// the output buffer.
+///
+/// For `.xz`, this is the total of the indexes of all the streams, as listed
+/// by [`xz_list()`]. For `.lzma`, this is the unpacked size of the header,
+/// or [`None`] if the header declares it unknown. The format is detected as
+/// in [`decompress_file()`], and the input is left at its initial position.
+///
+/// The declared sizes aren't checked against the data, and untrusted input
+/// may declare any size: bound the estimate, e.g. by the
+/// [`memlimit`](decompress::Options::memlimit) option, before allocating
+/// from it.
+pub fn estimate_decompressed_size<R: io::BufRead + io::Seek>(
+    input: &mut R,
+) -> error::Result<Option<u64>> {
+    let start = input.stream_position()?;
+    let size = match sniff_format(input)? {
+        #[cfg(feature = "xz")]
+        decompress::Format::Xz => Some(xz_list(input)?.uncompressed_size()),
+        #[cfg(not(feature = "xz"))]
+        decompress::Format::Xz => return Err(xz_disabled()),
+        _ => {
+            decode::lzma::LzmaParams::read_header(input, &decompress::Options::default())?
+                .unpacked_size
+        }
+    };
+    input.seek(io::SeekFrom::Start(start))?;
+    Ok(size)
+}
+
+/// Check that the codecs work in this build, e.g. when cross-compiling to an
+/// unusual target or at startup.
+///
+/// A fixed payload is compressed and decompressed again with LZMA, LZMA2 and
+/// XZ, and a reference `.lzma` stream is decoded. An error is returned if
+/// anything fails or doesn't round-trip.
+pub fn self_test() -> error::Result<()> {
+    self_test::run()
 }
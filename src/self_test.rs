@@ -0,0 +1,64 @@
+//! Runtime check of the codecs, see [`self_test()`](crate::self_test()).
+
+#[cfg(feature = "xz")]
+use crate::compress::{CheckMethod, Options};
+use crate::error::{self, Error};
+
+/// `.lzma` stream of "Hello world\n", as compressed by liblzma.
+const HELLO_LZMA: &[u8] = b"\x5d\x00\x00\x80\x00\xff\xff\xff\xff\xff\xff\xff\
+                            \xff\x00\x24\x19\x49\x98\x6f\x10\x19\xc6\xd7\x31\
+                            \xeb\x36\x50\xb2\x98\x48\xff\xfe\xa5\xb0\x00";
+
+/// Payload mixing text and binary data, long enough to span two LZMA2
+/// chunks.
+fn payload() -> Vec<u8> {
+    let mut payload = b"The quick brown fox jumps over the lazy dog.\n".repeat(1000);
+    payload.extend((0..0x6000u32).map(|i| (i * 31 % 251) as u8));
+    payload
+}
+
+fn check(codec: &str, expected: &[u8], actual: &[u8]) -> error::Result<()> {
+    if expected != actual {
+        return Err(Error::LzmaError(format!(
+            "self-test failed: {} round trip gave {} bytes instead of {}",
+            codec,
+            actual.len(),
+            expected.len()
+        )));
+    }
+    Ok(())
+}
+
+pub fn run() -> error::Result<()> {
+    let mut decomp = Vec::new();
+    crate::lzma_decompress(&mut &HELLO_LZMA[..], &mut decomp)?;
+    check("reference LZMA", b"Hello world\n", &decomp)?;
+
+    let payload = payload();
+
+    let mut compressed = Vec::new();
+    crate::lzma_compress(&mut payload.as_slice(), &mut compressed)?;
+    let mut decomp = Vec::new();
+    crate::lzma_decompress(&mut compressed.as_slice(), &mut decomp)?;
+    check("LZMA", &payload, &decomp)?;
+
+    let mut compressed = Vec::new();
+    crate::lzma2_compress(&mut payload.as_slice(), &mut compressed)?;
+    let mut decomp = Vec::new();
+    crate::lzma2_decompress(&mut compressed.as_slice(), &mut decomp)?;
+    check("LZMA2", &payload, &decomp)?;
+
+    #[cfg(feature = "xz")]
+    for &check_method in &[CheckMethod::None, CheckMethod::Crc32, CheckMethod::Crc64] {
+        let options = Options {
+            check: check_method,
+            ..Default::default()
+        };
+        let mut compressed = Vec::new();
+        crate::xz_compress_with_options(&mut payload.as_slice(), &mut compressed, &options)?;
+        let mut decomp = Vec::new();
+        crate::xz_decompress(&mut compressed.as_slice(), &mut decomp)?;
+        check("XZ", &payload, &decomp)?;
+    }
+    Ok(())
+}
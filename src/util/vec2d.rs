@@ -22,14 +22,6 @@ impl<T> Vec2D<T> {
             cols,
         }
     }
-
-    /// Fills the grid with elements by cloning `value`.
-    pub fn fill(&mut self, value: T)
-    where
-        T: Clone,
-    {
-        self.data.fill(value)
-    }
 }
 
 impl<T> Index<usize> for Vec2D<T> {
@@ -71,14 +63,6 @@ mod test {
         Vec2D::init(1, (usize::MAX, usize::MAX));
     }
 
-    #[test]
-    fn fill() {
-        let mut vec2d = Vec2D::init(0, (2, 3));
-        vec2d.fill(7);
-        assert_eq!(vec2d[0], [7, 7, 7]);
-        assert_eq!(vec2d[1], [7, 7, 7]);
-    }
-
     #[test]
     fn index() {
         let vec2d = Vec2D {
@@ -0,0 +1,141 @@
+//! Branch/call/jump (BCJ) filter for x86 code, see spec section 5.3.2.
+//!
+//! The relative addresses of `CALL` and `JMP` instructions are converted to
+//! absolute ones when encoding, so that repeated calls to the same function
+//! become repeated byte strings that LZMA2 compresses better. This follows
+//! the reference implementation of liblzma, so that the output is identical.
+
+/// Whether `b` is a plausible most significant byte of a 32-bit offset of a
+/// near `CALL` or `JMP`, i.e. of a small positive or negative number.
+fn test_ms_byte(b: u8) -> bool {
+    b == 0x00 || b == 0xFF
+}
+
+/// Apply the x86 filter to `buf` in place, forward if `encode` is true or
+/// backward otherwise. The buffer holds the whole data of a block, starting
+/// at the offset `start_offset`.
+///
+/// The last 4 bytes are never converted, as they can't hold a complete
+/// instruction, which matches how liblzma ends its streams.
+pub(crate) fn x86_code(buf: &mut [u8], start_offset: u32, encode: bool) {
+    const MASK_TO_ALLOWED_STATUS: [bool; 8] = [true, true, true, false, true, false, false, false];
+    const MASK_TO_BIT_NUMBER: [u32; 8] = [0, 1, 2, 2, 3, 3, 3, 3];
+
+    if buf.len() < 5 {
+        return;
+    }
+
+    let mut prev_mask = 0u32;
+    let mut prev_pos = start_offset.wrapping_sub(5);
+    let limit = buf.len() - 5;
+    let mut buf_pos = 0;
+    while buf_pos <= limit {
+        let b = buf[buf_pos];
+        if b != 0xE8 && b != 0xE9 {
+            buf_pos += 1;
+            continue;
+        }
+
+        let now_pos = start_offset.wrapping_add(buf_pos as u32);
+        let offset = now_pos.wrapping_sub(prev_pos);
+        prev_pos = now_pos;
+        if offset > 5 {
+            prev_mask = 0;
+        } else {
+            for _ in 0..offset {
+                prev_mask &= 0x77;
+                prev_mask <<= 1;
+            }
+        }
+
+        let b = buf[buf_pos + 4];
+        if test_ms_byte(b)
+            && MASK_TO_ALLOWED_STATUS[((prev_mask >> 1) & 0x7) as usize]
+            && (prev_mask >> 1) < 0x10
+        {
+            let mut src = u32::from_le_bytes([
+                buf[buf_pos + 1],
+                buf[buf_pos + 2],
+                buf[buf_pos + 3],
+                buf[buf_pos + 4],
+            ]);
+            let next_pos = now_pos.wrapping_add(5);
+            let mut dest;
+            loop {
+                dest = if encode {
+                    src.wrapping_add(next_pos)
+                } else {
+                    src.wrapping_sub(next_pos)
+                };
+                if prev_mask == 0 {
+                    break;
+                }
+                let i = MASK_TO_BIT_NUMBER[(prev_mask >> 1) as usize];
+                if !test_ms_byte((dest >> (24 - i * 8)) as u8) {
+                    break;
+                }
+                src = dest ^ ((1 << (32 - i * 8)) - 1);
+            }
+
+            // Bit 24 is extended to the most significant byte, so that it's
+            // again 0x00 or 0xFF.
+            buf[buf_pos + 1..buf_pos + 4].copy_from_slice(&dest.to_le_bytes()[..3]);
+            buf[buf_pos + 4] = if dest & 0x0100_0000 != 0 { 0xFF } else { 0x00 };
+            buf_pos += 5;
+            prev_mask = 0;
+        } else {
+            buf_pos += 1;
+            prev_mask |= 1;
+            if test_ms_byte(b) {
+                prev_mask |= 0x10;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_x86_code() {
+        // A call at offset 2 to offset 0x10 + 7 becomes a call to 0x17.
+        let mut buf = *b"\x90\x90\xE8\x10\x00\x00\x00\x90\x90";
+        x86_code(&mut buf, 0, true);
+        assert_eq!(&buf, b"\x90\x90\xE8\x17\x00\x00\x00\x90\x90");
+        x86_code(&mut buf, 0, false);
+        assert_eq!(&buf, b"\x90\x90\xE8\x10\x00\x00\x00\x90\x90");
+
+        // Backward calls and start offsets.
+        let mut buf = *b"\xE9\xF0\xFF\xFF\xFF";
+        x86_code(&mut buf, 0x1000, true);
+        assert_eq!(&buf, b"\xE9\xF5\x0F\x00\x00");
+        x86_code(&mut buf, 0x1000, false);
+        assert_eq!(&buf, b"\xE9\xF0\xFF\xFF\xFF");
+
+        // Operands that aren't near offsets and truncated instructions are
+        // kept.
+        let mut buf = *b"\xE8\x00\x00\x00\x42\xE8\x00\x00\x00";
+        x86_code(&mut buf, 0, true);
+        assert_eq!(&buf, b"\xE8\x00\x00\x00\x42\xE8\x00\x00\x00");
+    }
+
+    #[test]
+    fn test_x86_round_trip() {
+        let mut state = 0x2545_f491u32;
+        let data: Vec<u8> = (0..0x10000)
+            .map(|_| {
+                state ^= state << 13;
+                state ^= state >> 17;
+                state ^= state << 5;
+                // Plenty of opcodes and near offsets.
+                [0xE8, 0xE9, 0x00, 0xFF, state as u8][(state >> 8) as usize % 5]
+            })
+            .collect();
+        let mut buf = data.clone();
+        x86_code(&mut buf, 42, true);
+        assert_ne!(buf, data);
+        x86_code(&mut buf, 42, false);
+        assert_eq!(buf, data);
+    }
+}
@@ -0,0 +1,149 @@
+//! Table-based CRC32 and CRC64, as used by the `.xz` format, see sect. 6.
+//!
+//! Only [`core`] is used, and the tables are computed at compile time.
+
+/// Reflected polynomial of CRC32 (IEEE 802.3).
+const CRC32_POLY: u32 = 0xEDB8_8320;
+/// Reflected polynomial of CRC64 (ECMA-182).
+const CRC64_POLY: u64 = 0xC96C_5795_D787_0F42;
+
+const CRC32_TABLE: [u32; 256] = {
+    let mut table = [0; 256];
+    let mut i = 0;
+    while i < 256 {
+        let mut crc = i as u32;
+        let mut bit = 0;
+        while bit < 8 {
+            crc = if crc & 1 != 0 {
+                (crc >> 1) ^ CRC32_POLY
+            } else {
+                crc >> 1
+            };
+            bit += 1;
+        }
+        table[i] = crc;
+        i += 1;
+    }
+    table
+};
+
+const CRC64_TABLE: [u64; 256] = {
+    let mut table = [0; 256];
+    let mut i = 0;
+    while i < 256 {
+        let mut crc = i as u64;
+        let mut bit = 0;
+        while bit < 8 {
+            crc = if crc & 1 != 0 {
+                (crc >> 1) ^ CRC64_POLY
+            } else {
+                crc >> 1
+            };
+            bit += 1;
+        }
+        table[i] = crc;
+        i += 1;
+    }
+    table
+};
+
+/// Incremental CRC32 computation.
+#[derive(Clone, Copy, Debug)]
+pub(crate) struct Crc32 {
+    /// Inverted CRC of the bytes so far
+    crc: u32,
+}
+
+impl Crc32 {
+    pub(crate) fn new() -> Self {
+        Self { crc: !0 }
+    }
+
+    pub(crate) fn update(&mut self, buf: &[u8]) {
+        for &byte in buf {
+            self.crc = CRC32_TABLE[((self.crc as u8) ^ byte) as usize] ^ (self.crc >> 8);
+        }
+    }
+
+    pub(crate) fn finalize(self) -> u32 {
+        !self.crc
+    }
+
+    /// CRC32 of `buf`.
+    #[cfg(test)]
+    pub(crate) fn checksum(buf: &[u8]) -> u32 {
+        let mut digest = Self::new();
+        digest.update(buf);
+        digest.finalize()
+    }
+}
+
+/// Incremental CRC64 computation.
+#[derive(Clone, Copy, Debug)]
+pub(crate) struct Crc64 {
+    /// Inverted CRC of the bytes so far
+    crc: u64,
+}
+
+impl Crc64 {
+    pub(crate) fn new() -> Self {
+        Self { crc: !0 }
+    }
+
+    pub(crate) fn update(&mut self, buf: &[u8]) {
+        for &byte in buf {
+            self.crc = CRC64_TABLE[((self.crc as u8) ^ byte) as usize] ^ (self.crc >> 8);
+        }
+    }
+
+    pub(crate) fn finalize(self) -> u64 {
+        !self.crc
+    }
+
+    /// CRC64 of `buf`.
+    #[cfg(test)]
+    pub(crate) fn checksum(buf: &[u8]) -> u64 {
+        let mut digest = Self::new();
+        digest.update(buf);
+        digest.finalize()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_vectors() {
+        assert_eq!(Crc32::checksum(b""), 0);
+        assert_eq!(Crc32::checksum(b"123456789"), 0xCBF4_3926);
+        assert_eq!(Crc64::checksum(b""), 0);
+        assert_eq!(Crc64::checksum(b"123456789"), 0x995D_C9BB_DF19_39FA);
+    }
+
+    #[test]
+    fn incremental() {
+        let data = b"The quick brown fox jumps over the lazy dog";
+        for split in 0..=data.len() {
+            let mut crc32 = Crc32::new();
+            let mut crc64 = Crc64::new();
+            crc32.update(&data[..split]);
+            crc64.update(&data[..split]);
+            crc32.update(&data[split..]);
+            crc64.update(&data[split..]);
+            assert_eq!(crc32.finalize(), 0x414F_A339);
+            assert_eq!(crc64.finalize(), Crc64::checksum(data));
+        }
+    }
+
+    #[test]
+    fn matches_crc_crate() {
+        let crc32 = crc::Crc::<u32>::new(&crc::CRC_32_ISO_HDLC);
+        let crc64 = crc::Crc::<u64>::new(&crc::CRC_64_XZ);
+        let data: Vec<u8> = (0..1000u32).map(|i| ((i * i) ^ (i >> 3)) as u8).collect();
+        for len in [0, 1, 7, 8, 255, 1000] {
+            assert_eq!(Crc32::checksum(&data[..len]), crc32.checksum(&data[..len]));
+            assert_eq!(Crc64::checksum(&data[..len]), crc64.checksum(&data[..len]));
+        }
+    }
+}
@@ -2,12 +2,26 @@
 
 use crate::decode::util;
 use crate::error;
-use crate::xz::crc::CRC32;
-use crate::xz::StreamFlags;
+use crate::xz::checksum::Crc32;
+use crate::xz::{StreamFlags, XZ_MAGIC};
 use byteorder::{BigEndian, LittleEndian, ReadBytesExt};
 
-/// File format magic header signature, see sect. 2.1.1.1.
-pub(crate) const XZ_MAGIC: &[u8] = &[0xFD, 0x37, 0x7A, 0x58, 0x5A, 0x00];
+/// Explain an invalid magic that looks like a damaged XZ magic, e.g. when
+/// the file was byte-swapped or re-encoded as text in transfer.
+fn magic_hint(magic: &[u8; 6]) -> Option<&'static str> {
+    let swapped_16 = [magic[1], magic[0], magic[3], magic[2], magic[5], magic[4]];
+    let swapped_32 = [magic[3], magic[2], magic[1], magic[0]];
+    if swapped_16 == XZ_MAGIC || swapped_32 == XZ_MAGIC[..4] {
+        return Some("; the input looks byte-swapped");
+    }
+    // 0xFD re-encoded in UTF-8 from Latin-1, or replaced by U+FFFD
+    if magic.starts_with(&[0xC3, 0xBD, 0x37, 0x7A, 0x58, 0x5A])
+        || magic.starts_with(&[0xEF, 0xBF, 0xBD, 0x37, 0x7A, 0x58])
+    {
+        return Some("; the input looks re-encoded as text, was it transferred in text mode?");
+    }
+    None
+}
 
 /// Stream Header, see sect. 2.1.1.
 #[derive(Clone, Copy, Debug)]
@@ -21,15 +35,18 @@ impl StreamHeader {
     where
         BR: std::io::BufRead,
     {
-        if !util::read_tag(input, XZ_MAGIC)? {
+        let mut magic = [0; 6];
+        input.read_exact(&mut magic)?;
+        if magic != XZ_MAGIC {
             return Err(error::Error::XzError(format!(
-                "Invalid XZ magic, expected {:?}",
-                XZ_MAGIC
+                "Invalid XZ magic, expected {:?}{}",
+                XZ_MAGIC,
+                magic_hint(&magic).unwrap_or_default()
             )));
         }
 
         let (flags, digested) = {
-            let mut digest = CRC32.digest();
+            let mut digest = Crc32::new();
             let mut digest_rd = util::CrcDigestRead::new(input, &mut digest);
             let flags = digest_rd.read_u16::<BigEndian>()?;
             (flags, digest.finalize())
@@ -37,16 +54,16 @@ impl StreamHeader {
 
         let crc32 = input.read_u32::<LittleEndian>()?;
         if crc32 != digested {
-            return Err(error::Error::XzError(format!(
-                "Invalid header CRC32: expected 0x{:08x} but got 0x{:08x}",
-                crc32, digested
-            )));
+            return Err(error::Error::StreamHeaderCorrupt {
+                expected: crc32,
+                actual: digested,
+            });
         }
 
         let stream_flags = StreamFlags::parse(flags)?;
         let header = Self { stream_flags };
 
-        lzma_info!("XZ check method: {:?}", header.stream_flags.check_method);
+        lzma_info!("XZ check ID: {}", header.stream_flags.check_id());
         Ok(header)
     }
 }
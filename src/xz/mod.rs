@@ -4,48 +4,101 @@
 //!
 //! [spec]: https://tukaani.org/xz/xz-file-format.txt
 
+//!
+//! Without the `xz` feature, only the checksums and the types shared with
+//! the options and errors are compiled.
+
 use crate::error;
+#[cfg(feature = "xz")]
 use std::io;
 
-pub(crate) mod crc;
+#[cfg(feature = "xz")]
+pub(crate) mod bcj;
+pub(crate) mod checksum;
+#[cfg(feature = "xz")]
 pub(crate) mod footer;
+#[cfg(feature = "xz")]
 pub(crate) mod header;
+#[cfg(feature = "xz")]
+pub(crate) mod vli;
+
+/// File format magic header signature, see sect. 2.1.1.1.
+pub(crate) const XZ_MAGIC: &[u8] = &[0xFD, 0x37, 0x7A, 0x58, 0x5A, 0x00];
+
+/// Size of the stream header and of the stream footer, see sect. 2.1.
+#[cfg(feature = "xz")]
+pub(crate) const STREAM_HEADER_FOOTER_LEN: usize = 12;
 
 /// Stream flags, see sect. 2.1.1.2.
 ///
 /// This does not store the leading null byte, which is currently unused.
+#[cfg(feature = "xz")]
 #[derive(Clone, Copy, Debug, Eq, PartialEq)]
 pub(crate) struct StreamFlags {
-    pub(crate) check_method: CheckMethod,
+    /// Check ID, which may be one this crate doesn't support
+    check_id: u8,
 }
 
+#[cfg(feature = "xz")]
 impl StreamFlags {
+    pub(crate) fn new(check_method: CheckMethod) -> Self {
+        Self {
+            check_id: check_method.into(),
+        }
+    }
+
     /// Parse Stream Flags from a 16bits value.
     pub(crate) fn parse(input: u16) -> error::Result<Self> {
         let flags_bytes = input.to_be_bytes();
 
+        // Reserved bits are only set by newer versions of the format, as
+        // the flags are covered by a CRC32.
         if flags_bytes[0] != 0x00 {
-            return Err(error::Error::XzError(format!(
-                "Invalid null byte in Stream Flags: {:x}",
-                flags_bytes[0]
-            )));
+            return Err(error::Error::Unsupported {
+                what: format!("reserved stream flags {:#x}", flags_bytes[0]),
+            });
         }
 
-        let flags = Self {
-            check_method: CheckMethod::try_from(flags_bytes[1])?,
-        };
-        Ok(flags)
+        // The upper bits of the check ID are reserved, but all the IDs below
+        // are valid, even those without a defined check yet.
+        if flags_bytes[1] > 0x0F {
+            return Err(error::Error::Unsupported {
+                what: format!("check ID {:#x}, must be at most 0x0F", flags_bytes[1]),
+            });
+        }
+
+        Ok(Self {
+            check_id: flags_bytes[1],
+        })
     }
 
     /// Serialize Stream Flags into a writer.
-    pub(crate) fn serialize<W>(self, writer: &mut W) -> io::Result<usize>
+    pub(crate) fn serialize<W>(self, writer: &mut W) -> io::Result<()>
     where
         W: io::Write,
     {
         // First byte is currently unused and hard-coded to null.
-        writer
-            .write(&[0x00, self.check_method as u8])
-            .map_err(Into::into)
+        writer.write_all(&[0x00, self.check_id])
+    }
+
+    /// Check ID of the stream.
+    pub(crate) fn check_id(self) -> u8 {
+        self.check_id
+    }
+
+    /// Check method of the stream, or [`None`] if the check ID has no
+    /// defined check yet.
+    pub(crate) fn check_method(self) -> Option<CheckMethod> {
+        CheckMethod::try_from(self.check_id).ok()
+    }
+
+    /// Size of the "Block Check" field of the blocks, which is defined by
+    /// ranges of three check IDs, even those without a defined check yet.
+    pub(crate) fn check_size(self) -> usize {
+        match self.check_id {
+            0 => 0,
+            id => 4 << ((id - 1) / 3),
+        }
     }
 }
 
@@ -53,9 +106,13 @@ impl StreamFlags {
 #[derive(Clone, Copy, Debug, Eq, PartialEq)]
 #[repr(u8)]
 pub enum CheckMethod {
+    /// No check.
     None = 0x00,
+    /// CRC32 of the uncompressed data.
     Crc32 = 0x01,
+    /// CRC64 of the uncompressed data.
     Crc64 = 0x04,
+    /// SHA-256 of the uncompressed data.
     Sha256 = 0x0A,
 }
 
@@ -84,13 +141,15 @@ impl From<CheckMethod> for u8 {
 #[cfg(test)]
 mod test {
     use super::*;
+    #[cfg(feature = "xz")]
     use byteorder::{BigEndian, ReadBytesExt};
+    #[cfg(feature = "xz")]
     use std::io::{Seek, SeekFrom};
 
     #[test]
     fn test_checkmethod_roundtrip() {
         let mut count_valid = 0;
-        for input in 0..std::u8::MAX {
+        for input in 0..u8::MAX {
             if let Ok(check) = CheckMethod::try_from(input) {
                 let output: u8 = check.into();
                 assert_eq!(input, output);
@@ -100,15 +159,30 @@ mod test {
         assert_eq!(count_valid, 4);
     }
 
+    #[cfg(feature = "xz")]
+    #[test]
+    fn test_check_size() {
+        let sizes: Vec<usize> = (0..=0x0F)
+            .map(|id| StreamFlags { check_id: id }.check_size())
+            .collect();
+        assert_eq!(
+            sizes,
+            [0, 4, 4, 4, 8, 8, 8, 16, 16, 16, 32, 32, 32, 64, 64, 64]
+        );
+        assert_eq!(StreamFlags::new(CheckMethod::Crc64).check_size(), 8);
+        assert_eq!(StreamFlags::new(CheckMethod::Sha256).check_size(), 32);
+        assert!(StreamFlags::parse(0x0010).is_err());
+        assert_eq!(StreamFlags::parse(0x0002).unwrap().check_method(), None);
+    }
+
+    #[cfg(feature = "xz")]
     #[test]
     fn test_streamflags_roundtrip() {
-        let input = StreamFlags {
-            check_method: CheckMethod::Crc32,
-        };
+        let input = StreamFlags::new(CheckMethod::Crc32);
 
         let mut cursor = std::io::Cursor::new(vec![0u8; 2]);
-        let len = input.serialize(&mut cursor).unwrap();
-        assert_eq!(len, 2);
+        input.serialize(&mut cursor).unwrap();
+        assert_eq!(cursor.position(), 2);
 
         cursor.seek(SeekFrom::Start(0)).unwrap();
         let field = cursor.read_u16::<BigEndian>().unwrap();
@@ -0,0 +1,126 @@
+//! Variable-length integers, see sect. 1.2.
+
+use crate::error;
+use byteorder::{ReadBytesExt, WriteBytesExt};
+use std::io;
+
+/// Maximum number of bytes of an encoded integer.
+pub(crate) const VLI_BYTES_MAX: usize = 9;
+/// Maximum value of an integer, which fits in 63 bits.
+pub(crate) const VLI_MAX: u64 = u64::MAX / 2;
+
+/// Read a variable-length integer, returning its value and the number of
+/// bytes of its encoding.
+///
+/// Encodings longer than 9 bytes are rejected, but encodings that aren't
+/// minimal (i.e. ending with a null byte after the first one) are accepted:
+/// they are longer than [`encoded_len()`] of the value.
+pub(crate) fn read_vli<R>(input: &mut R) -> error::Result<(u64, usize)>
+where
+    R: io::Read,
+{
+    let mut result = 0;
+    for i in 0..VLI_BYTES_MAX {
+        let byte = input.read_u8()?;
+        result |= ((byte & 0x7F) as u64) << (i * 7);
+        if (byte & 0x80) == 0 {
+            return Ok((result, i + 1));
+        }
+    }
+
+    Err(error::Error::XzError(
+        "Invalid multi-byte encoding".to_string(),
+    ))
+}
+
+/// Number of bytes of the minimal encoding of `value`.
+pub(crate) fn encoded_len(mut value: u64) -> usize {
+    let mut len = 1;
+    while value >= 0x80 {
+        value >>= 7;
+        len += 1;
+    }
+    len
+}
+
+/// Write a variable-length integer, with the minimal number of bytes.
+pub(crate) fn write_vli<W>(output: &mut W, mut value: u64) -> io::Result<()>
+where
+    W: io::Write,
+{
+    if value > VLI_MAX {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("Integer {} too large for a multi-byte encoding", value),
+        ));
+    }
+
+    loop {
+        let byte = (value & 0x7F) as u8;
+        value >>= 7;
+        if value == 0 {
+            output.write_u8(byte)?;
+            break;
+        } else {
+            output.write_u8(0x80 | byte)?;
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn encode(value: u64) -> Vec<u8> {
+        let mut buf = Vec::new();
+        write_vli(&mut buf, value).unwrap();
+        buf
+    }
+
+    #[test]
+    fn test_vli_roundtrip() {
+        for (value, len) in [
+            (0, 1),
+            (127, 1),
+            (128, 2),
+            (0x3FFF, 2),
+            (0x4000, 3),
+            (VLI_MAX, VLI_BYTES_MAX),
+        ] {
+            let buf = encode(value);
+            assert_eq!(buf.len(), len, "length of {}", value);
+            assert_eq!(encoded_len(value), len, "length of {}", value);
+            assert_eq!(read_vli(&mut buf.as_slice()).unwrap(), (value, len));
+        }
+        assert_eq!(encode(0), [0x00]);
+        assert_eq!(encode(127), [0x7F]);
+        assert_eq!(encode(128), [0x80, 0x01]);
+    }
+
+    #[test]
+    fn test_vli_too_large() {
+        let mut buf = Vec::new();
+        assert!(write_vli(&mut buf, VLI_MAX + 1).is_err());
+        assert!(buf.is_empty());
+    }
+
+    #[test]
+    fn test_vli_malformed() {
+        // More than 9 bytes
+        let overlong = [0xFF; 10];
+        assert!(read_vli(&mut &overlong[..]).is_err());
+        // Truncated
+        assert!(read_vli(&mut &[0x80][..]).is_err());
+    }
+
+    #[test]
+    fn test_vli_non_minimal() {
+        // Non-minimal encodings of 0 and 1, longer than the minimal ones
+        assert_eq!(read_vli(&mut &[0x80, 0x00][..]).unwrap(), (0, 2));
+        assert_eq!(read_vli(&mut &[0x81, 0x80, 0x00][..]).unwrap(), (1, 3));
+        assert_eq!(encoded_len(0), 1);
+        assert_eq!(encoded_len(1), 1);
+    }
+}
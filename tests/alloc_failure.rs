@@ -0,0 +1,56 @@
+//! Recovery from failed allocations, in a separate test binary to make them
+//! fail with a global allocator.
+
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::ptr;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+/// Allocator failing the allocations larger than `MAX_SIZE`.
+struct ConstrainedAlloc;
+
+static MAX_SIZE: AtomicUsize = AtomicUsize::new(usize::MAX);
+
+unsafe impl GlobalAlloc for ConstrainedAlloc {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        if layout.size() > MAX_SIZE.load(Ordering::Relaxed) {
+            return ptr::null_mut();
+        }
+        System.alloc(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout)
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        if new_size > MAX_SIZE.load(Ordering::Relaxed) {
+            return ptr::null_mut();
+        }
+        System.realloc(ptr, layout, new_size)
+    }
+}
+
+#[global_allocator]
+static GLOBAL: ConstrainedAlloc = ConstrainedAlloc;
+
+#[test]
+fn lzma2_chunk_too_large_to_allocate() {
+    #[cfg(feature = "enable_logging")]
+    let _ = env_logger::try_init();
+    // LZMA chunk resetting the dictionary, state and properties, declaring
+    // the maximal unpacked size of 2 MiB.
+    let input: &[u8] = b"\xff\xff\xff\x00\x04\x5d\x00\x00\x00\x00\x00\x00";
+
+    MAX_SIZE.store(1 << 20, Ordering::Relaxed);
+    let mut decomp: Vec<u8> = Vec::new();
+    let result = lzma_rs::lzma2_decompress(&mut &input[..], &mut decomp);
+    MAX_SIZE.store(usize::MAX, Ordering::Relaxed);
+
+    let err = result.unwrap_err();
+    assert!(
+        matches!(err, lzma_rs::error::Error::AllocationFailed(_)),
+        "{}",
+        err
+    );
+    assert!(decomp.is_empty());
+}
@@ -0,0 +1,101 @@
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::io::Write;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+/// Allocator tracking the peak of the allocated bytes
+struct PeakAlloc;
+
+static ALLOCATED: AtomicUsize = AtomicUsize::new(0);
+static PEAK: AtomicUsize = AtomicUsize::new(0);
+
+unsafe impl GlobalAlloc for PeakAlloc {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        let ptr = System.alloc(layout);
+        if !ptr.is_null() {
+            let allocated = ALLOCATED.fetch_add(layout.size(), Ordering::SeqCst) + layout.size();
+            PEAK.fetch_max(allocated, Ordering::SeqCst);
+        }
+        ptr
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout);
+        ALLOCATED.fetch_sub(layout.size(), Ordering::SeqCst);
+    }
+}
+
+#[global_allocator]
+static GLOBAL: PeakAlloc = PeakAlloc;
+
+/// Sink checking the output against the expected data, without allocating
+struct CompareWriter<'a> {
+    expected: &'a [u8],
+}
+
+impl<'a> Write for CompareWriter<'a> {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        assert!(self.expected.starts_with(buf));
+        self.expected = &self.expected[buf.len()..];
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+/// Decompress `input` within `max_memory` bytes, returning the peak of the
+/// memory allocated meanwhile.
+fn decompress_bounded(
+    input: &[u8],
+    expected: &[u8],
+    max_memory: usize,
+) -> lzma_rs::error::Result<usize> {
+    let mut output = CompareWriter { expected };
+    let start = ALLOCATED.load(Ordering::SeqCst);
+    PEAK.store(start, Ordering::SeqCst);
+    lzma_rs::decompress_bounded(&mut &input[..], &mut output, max_memory)?;
+    assert!(output.expected.is_empty());
+    Ok(PEAK.load(Ordering::SeqCst) - start)
+}
+
+// The allocations are counted for the whole process, so this is the only
+// test of this file, to run alone.
+#[test]
+fn decompress_bounded_memory() {
+    #[cfg(feature = "enable_logging")]
+    let _ = env_logger::try_init();
+    let x: Vec<u8> = (0..0x10_0000u32)
+        .map(|i| b"Hello world\n"[(i % 12) as usize] ^ (i >> 14) as u8)
+        .collect();
+    let max_memory = 0x40000;
+
+    // The declared 8 MiB dictionary doesn't fit.
+    let mut lzma = lzma_rs::lzma_compress_to_vec(&x).unwrap();
+    assert!(decompress_bounded(&lzma, &x, max_memory).is_err());
+    // The encoder only writes literals, so a 64 KiB dictionary decodes the
+    // same data.
+    lzma[1..5].copy_from_slice(&0x10000u32.to_le_bytes());
+    let peak = decompress_bounded(&lzma, &x, max_memory).unwrap();
+    assert!(peak <= max_memory, "{}", peak);
+
+    #[cfg(feature = "xz")]
+    {
+        // Small XZ blocks fit, but not a single large one.
+        let options = lzma_rs::compress::Options {
+            block_size: Some(0x8000),
+            ..Default::default()
+        };
+        let mut xz: Vec<u8> = Vec::new();
+        lzma_rs::xz_compress_with_options(&mut x.as_slice(), &mut xz, &options).unwrap();
+        let peak = decompress_bounded(&xz, &x, max_memory).unwrap();
+        assert!(peak <= max_memory, "{}", peak);
+
+        let xz = lzma_rs::xz_compress_to_vec(&x).unwrap();
+        assert!(decompress_bounded(&xz, &x, max_memory).is_err());
+        let peak = decompress_bounded(&xz, &x, 0x80_0000).unwrap();
+        assert!(peak <= 0x80_0000, "{}", peak);
+        // Even the model doesn't fit in a few bytes.
+        assert!(decompress_bounded(&xz, &x, 100).is_err());
+    }
+}
@@ -13,12 +13,23 @@ fn read_all_file(filename: &str) -> std::io::Result<Vec<u8>> {
     Ok(data)
 }
 
+/// Display `err` without the recent symbols appended with the `trace`
+/// feature.
+fn without_trace(err: &lzma_rs::error::Error) -> String {
+    let err = err.to_string();
+    match err.find(" (recent symbols: ") {
+        Some(index) => err[..index].to_string(),
+        None => err,
+    }
+}
+
 fn round_trip(x: &[u8]) {
     round_trip_no_options(x);
 
     // Do another round trip, but this time also write it to the header
     let encode_options = lzma_rs::compress::Options {
         unpacked_size: lzma_rs::compress::UnpackedSize::WriteToHeader(Some(x.len() as u64)),
+        ..Default::default()
     };
     let decode_options = lzma_rs::decompress::Options {
         unpacked_size: lzma_rs::decompress::UnpackedSize::ReadFromHeader,
@@ -29,7 +40,9 @@ fn round_trip(x: &[u8]) {
 
 fn round_trip_no_options(x: &[u8]) {
     let mut compressed: Vec<u8> = Vec::new();
-    lzma_rs::lzma_compress(&mut std::io::BufReader::new(x), &mut compressed).unwrap();
+    let stats = lzma_rs::lzma_compress(&mut std::io::BufReader::new(x), &mut compressed).unwrap();
+    assert_eq!(stats.input_size, x.len() as u64);
+    assert_eq!(stats.output_size, compressed.len() as u64);
     #[cfg(feature = "enable_logging")]
     info!("Compressed {} -> {} bytes", x.len(), compressed.len());
     #[cfg(feature = "enable_logging")]
@@ -44,12 +57,14 @@ fn assert_round_trip_with_options(
     decode_options: &lzma_rs::decompress::Options,
 ) {
     let mut compressed: Vec<u8> = Vec::new();
-    lzma_rs::lzma_compress_with_options(
+    let stats = lzma_rs::lzma_compress_with_options(
         &mut std::io::BufReader::new(x),
         &mut compressed,
         encode_options,
     )
     .unwrap();
+    assert_eq!(stats.input_size, x.len() as u64);
+    assert_eq!(stats.output_size, compressed.len() as u64);
     #[cfg(feature = "enable_logging")]
     info!("Compressed {} -> {} bytes", x.len(), compressed.len());
     #[cfg(feature = "enable_logging")]
@@ -132,6 +147,85 @@ fn assert_decomp_eq(compressed: &[u8], expected: &[u8], compare_to_liblzma: bool
     }
 }
 
+#[cfg(feature = "stream")]
+#[test]
+fn stream_update_finalize() {
+    #[cfg(feature = "enable_logging")]
+    let _ = env_logger::try_init();
+    let x = read_all_file("tests/files/foo.txt").unwrap();
+    let mut compressed: Vec<u8> = Vec::new();
+    lzma_rs::lzma_compress(&mut x.as_slice(), &mut compressed).unwrap();
+
+    let mut stream = lzma_rs::decompress::Stream::new(Vec::new());
+    let mut decomp: Vec<u8> = Vec::new();
+    for byte in compressed.chunks(1) {
+        decomp.extend(stream.update(byte).unwrap());
+    }
+    // Most of the output is available before the end of the stream.
+    assert!(decomp.len() > x.len() / 2);
+    decomp.extend(stream.finalize().unwrap());
+    assert_eq!(decomp, x);
+
+    // The end of the stream is still validated.
+    let mut stream = lzma_rs::decompress::Stream::new(Vec::new());
+    stream.update(&compressed[..compressed.len() / 2]).unwrap();
+    stream.finalize().unwrap_err();
+}
+
+#[test]
+fn unpacked_size_field() {
+    #[cfg(feature = "enable_logging")]
+    let _ = env_logger::try_init();
+    let x = b"Hello world";
+    // The one-shot path knows the size of the input.
+    let one_shot = lzma_rs::lzma_compress_to_vec(x).unwrap();
+    assert_eq!(&one_shot[5..13], &(x.len() as u64).to_le_bytes());
+    // The streaming path doesn't, so it writes the unknown size and ends the
+    // data with a marker.
+    let mut streamed: Vec<u8> = Vec::new();
+    lzma_rs::lzma_compress(&mut &x[..], &mut streamed).unwrap();
+    assert_eq!(&streamed[5..13], &[0xFF; 8]);
+    assert!(streamed.len() > one_shot.len());
+    for compressed in [one_shot, streamed] {
+        let mut decomp: Vec<u8> = Vec::new();
+        lzma_rs::lzma_decompress(&mut compressed.as_slice(), &mut decomp).unwrap();
+        assert_eq!(decomp, x);
+    }
+
+    // An explicit size must match the input.
+    let options = lzma_rs::compress::Options {
+        unpacked_size: lzma_rs::compress::UnpackedSize::WriteToHeader(Some(5)),
+        ..Default::default()
+    };
+    let mut compressed: Vec<u8> = Vec::new();
+    let err =
+        lzma_rs::lzma_compress_with_options(&mut &x[..], &mut compressed, &options).unwrap_err();
+    assert_eq!(err.kind(), std::io::ErrorKind::InvalidInput);
+}
+
+#[test]
+fn compress_small_inputs() {
+    #[cfg(feature = "enable_logging")]
+    let _ = env_logger::try_init();
+    let x = read_all_file("tests/files/foo.txt").unwrap();
+    for len in [0, 1, 100, 512, 513, 5000] {
+        let compressed = lzma_rs::lzma_compress_to_vec(&x[..len]).unwrap();
+        // Small inputs take a lighter model and dictionary, declared in the
+        // header.
+        let (props, dict_size) = if len <= 512 {
+            (0x5a, 0x1000u32)
+        } else {
+            (0x5d, 0x80_0000)
+        };
+        assert_eq!(compressed[0], props, "{} bytes", len);
+        assert_eq!(&compressed[1..5], &dict_size.to_le_bytes(), "{} bytes", len);
+        let mut decomp: Vec<u8> = Vec::new();
+        lzma_rs::lzma_decompress(&mut compressed.as_slice(), &mut decomp).unwrap();
+        assert_eq!(decomp, &x[..len]);
+        assert_eq!(lzma::decompress(&compressed).unwrap(), &x[..len]);
+    }
+}
+
 #[test]
 #[should_panic(expected = "HeaderTooShort")]
 fn decompress_short_header() {
@@ -176,6 +270,15 @@ fn decompress_big_file() {
     assert_decomp_eq(&compressed, &expected, /* compare_to_liblzma */ true);
 }
 
+#[test]
+fn decompress_runs() {
+    #[cfg(feature = "enable_logging")]
+    let _ = env_logger::try_init();
+    let compressed = read_all_file("tests/files/runs.lzma").unwrap();
+    let expected = read_all_file("tests/files/runs").unwrap();
+    assert_decomp_eq(&compressed, &expected, /* compare_to_liblzma */ true);
+}
+
 #[test]
 fn decompress_big_file_with_huge_dict() {
     #[cfg(feature = "enable_logging")]
@@ -194,6 +297,17 @@ fn decompress_range_coder_edge_case() {
     assert_decomp_eq(&compressed, &expected, /* compare_to_liblzma */ true);
 }
 
+#[test]
+fn decompress_max_pb() {
+    #[cfg(feature = "enable_logging")]
+    let _ = env_logger::try_init();
+    let compressed = read_all_file("tests/files/records-pb4-lp2.lzma").unwrap();
+    let expected = read_all_file("tests/files/records-pb4-lp2").unwrap();
+    // lc=1, lp=2, pb=4
+    assert_eq!(compressed[0], (4 * 5 + 2) * 9 + 1);
+    assert_decomp_eq(&compressed, &expected, /* compare_to_liblzma */ true);
+}
+
 #[test]
 fn decompress_empty_world() {
     #[cfg(feature = "enable_logging")]
@@ -238,6 +352,7 @@ fn unpacked_size_write_to_header() {
     let data = b"Some data";
     let encode_options = lzma_rs::compress::Options {
         unpacked_size: lzma_rs::compress::UnpackedSize::WriteToHeader(Some(data.len() as u64)),
+        ..Default::default()
     };
     let decode_options = lzma_rs::decompress::Options {
         unpacked_size: lzma_rs::decompress::UnpackedSize::ReadFromHeader,
@@ -251,6 +366,7 @@ fn unpacked_size_provided_outside() {
     let data = b"Some data";
     let encode_options = lzma_rs::compress::Options {
         unpacked_size: lzma_rs::compress::UnpackedSize::SkipWritingToHeader,
+        ..Default::default()
     };
     let decode_options = lzma_rs::decompress::Options {
         unpacked_size: lzma_rs::decompress::UnpackedSize::UseProvided(Some(data.len() as u64)),
@@ -264,6 +380,7 @@ fn unpacked_size_write_some_to_header_but_use_provided_on_read() {
     let data = b"Some data";
     let encode_options = lzma_rs::compress::Options {
         unpacked_size: lzma_rs::compress::UnpackedSize::WriteToHeader(Some(data.len() as u64)),
+        ..Default::default()
     };
     let decode_options = lzma_rs::decompress::Options {
         unpacked_size: lzma_rs::decompress::UnpackedSize::ReadHeaderButUseProvided(Some(
@@ -279,6 +396,7 @@ fn unpacked_size_write_none_to_header_and_use_provided_on_read() {
     let data = b"Some data";
     let encode_options = lzma_rs::compress::Options {
         unpacked_size: lzma_rs::compress::UnpackedSize::WriteToHeader(None),
+        ..Default::default()
     };
     let decode_options = lzma_rs::decompress::Options {
         unpacked_size: lzma_rs::decompress::UnpackedSize::ReadHeaderButUseProvided(Some(
@@ -294,6 +412,7 @@ fn unpacked_size_write_none_to_header_and_use_provided_none_on_read() {
     let data = b"Some data";
     let encode_options = lzma_rs::compress::Options {
         unpacked_size: lzma_rs::compress::UnpackedSize::WriteToHeader(None),
+        ..Default::default()
     };
     let decode_options = lzma_rs::decompress::Options {
         unpacked_size: lzma_rs::decompress::UnpackedSize::ReadHeaderButUseProvided(None),
@@ -302,11 +421,58 @@ fn unpacked_size_write_none_to_header_and_use_provided_none_on_read() {
     assert_round_trip_with_options(&data[..], &encode_options, &decode_options);
 }
 
+#[test]
+fn always_write_end_marker() {
+    #[cfg(feature = "enable_logging")]
+    let _ = env_logger::try_init();
+    use lzma_rs::decompress::Termination;
+    let data = b"Some data";
+    let encode = |always_write_end_marker| {
+        let options = lzma_rs::compress::Options {
+            unpacked_size: lzma_rs::compress::UnpackedSize::WriteToHeader(Some(data.len() as u64)),
+            always_write_end_marker,
+            ..Default::default()
+        };
+        let mut compressed = Vec::new();
+        lzma_rs::lzma_compress_with_options(&mut &data[..], &mut compressed, &options).unwrap();
+        compressed
+    };
+    let without_marker = encode(false);
+    let compressed = encode(true);
+    assert!(compressed.len() > without_marker.len());
+
+    // Decoding stops at the size in the header, before the marker.
+    let mut decomp = Vec::new();
+    lzma_rs::lzma_decompress(&mut compressed.as_slice(), &mut decomp).unwrap();
+    assert_eq!(decomp, data);
+
+    // Ignoring the size, decoding stops at the marker.
+    let options = lzma_rs::decompress::Options {
+        unpacked_size: lzma_rs::decompress::UnpackedSize::ReadHeaderButUseProvided(None),
+        ..Default::default()
+    };
+    let termination = |compressed: &[u8]| {
+        let mut decomp = Vec::new();
+        let status =
+            lzma_rs::lzma_decompress_with_status(&mut &compressed[..], &mut decomp, &options)
+                .unwrap();
+        assert_eq!(decomp, data);
+        status.termination
+    };
+    assert_eq!(termination(&compressed), Some(Termination::EndMarker));
+    // Without the marker, the data only ends with the input.
+    assert_eq!(termination(&without_marker), Some(Termination::EndOfInput));
+
+    // liblzma accepts a marker after the declared size.
+    assert_eq!(lzma::decompress(&compressed).unwrap(), data);
+}
+
 #[test]
 fn memlimit() {
     let data = b"Some data";
     let encode_options = lzma_rs::compress::Options {
         unpacked_size: lzma_rs::compress::UnpackedSize::WriteToHeader(None),
+        ..Default::default()
     };
     let decode_options = lzma_rs::decompress::Options {
         unpacked_size: lzma_rs::decompress::UnpackedSize::ReadHeaderButUseProvided(None),
@@ -354,3 +520,908 @@ fn memlimit() {
         );
     }
 }
+
+#[test]
+fn override_dict_size() {
+    #[cfg(feature = "enable_logging")]
+    let _ = env_logger::try_init();
+    let compressed = read_all_file("tests/files/foo.txt.lzma").unwrap();
+    let expected = read_all_file("tests/files/foo.txt").unwrap();
+    // The header declares an 8 MiB dictionary.
+    assert_eq!(compressed[1..5], 0x80_0000u32.to_le_bytes());
+    let decompress = |dict_size| {
+        let options = lzma_rs::decompress::Options::builder()
+            .override_dict_size(dict_size)
+            .build()
+            .unwrap();
+        let mut decomp: Vec<u8> = Vec::new();
+        lzma_rs::lzma_decompress_with_options(&mut compressed.as_slice(), &mut decomp, &options)
+            .map(|_| decomp)
+    };
+
+    // A window holding the whole file is enough for its references.
+    assert_eq!(decompress(0x40000).unwrap(), expected);
+    // Larger sizes than the declared one are ignored.
+    assert_eq!(decompress(u64::MAX).unwrap(), expected);
+
+    // The file refers further back than 4 KiB.
+    match decompress(0x1000).unwrap_err() {
+        lzma_rs::error::Error::DistanceBeyondOverride {
+            distance,
+            dict_size,
+        } => {
+            assert_eq!(dict_size, 0x1000);
+            assert!(distance > 0x1000 && distance <= 0x80_0000, "{}", distance);
+        }
+        e => panic!("unexpected error: {}", e),
+    }
+}
+
+#[test]
+fn output_limit() {
+    #[cfg(feature = "enable_logging")]
+    let _ = env_logger::try_init();
+    let data = vec![0x42; 100_000];
+    let mut compressed: Vec<u8> = Vec::new();
+    lzma_rs::lzma_compress(&mut data.as_slice(), &mut compressed).unwrap();
+
+    let mut output = lzma_rs::decompress::LimitedWriter::new(Vec::new(), 1000);
+    let error = lzma_rs::lzma_decompress(&mut compressed.as_slice(), &mut output).unwrap_err();
+    assert!(
+        matches!(error, lzma_rs::error::Error::OutputTooLarge(1000)),
+        "{}",
+        error
+    );
+    assert_eq!(output.count(), 1000);
+
+    // An exact fit is fine.
+    let mut output = lzma_rs::decompress::LimitedWriter::new(Vec::new(), data.len() as u64);
+    lzma_rs::lzma_decompress(&mut compressed.as_slice(), &mut output).unwrap();
+    assert_eq!(output.into_inner(), data);
+}
+
+#[test]
+fn decompress_frames() {
+    #[cfg(feature = "enable_logging")]
+    let _ = env_logger::try_init();
+    let data = read_all_file("tests/files/foo.txt").unwrap();
+    let mut compressed: Vec<u8> = Vec::new();
+    lzma_rs::lzma_compress(&mut data.as_slice(), &mut compressed).unwrap();
+
+    let mut frames: Vec<Vec<u8>> = Vec::new();
+    lzma_rs::lzma_decompress_frames(&mut compressed.as_slice(), 1000, |frame| {
+        frames.push(frame.to_vec());
+        Ok(())
+    })
+    .unwrap();
+    let (last, full) = frames.split_last().unwrap();
+    assert!(full.iter().all(|frame| frame.len() == 1000));
+    assert_eq!(last.len(), data.len() % 1000);
+    assert_eq!(frames.concat(), data);
+
+    // Errors from the callback abort the decompression.
+    let error = lzma_rs::lzma_decompress_frames(&mut compressed.as_slice(), 1000, |_| {
+        Err(std::io::Error::new(std::io::ErrorKind::Other, "stop"))
+    })
+    .unwrap_err();
+    assert_eq!(error.to_string(), "io error: stop");
+}
+
+#[test]
+fn expected_size() {
+    #[cfg(feature = "enable_logging")]
+    let _ = env_logger::try_init();
+    // The header doesn't declare the unpacked size.
+    let mut compressed: Vec<u8> = Vec::new();
+    lzma_rs::lzma_compress(&mut &b"Hello world"[..], &mut compressed).unwrap();
+    assert_eq!(&compressed[5..13], &[0xFF; 8]);
+
+    let decompress = |expected_size| {
+        let options = lzma_rs::decompress::Options {
+            expected_size: Some(expected_size),
+            ..Default::default()
+        };
+        let mut decomp: Vec<u8> = Vec::new();
+        lzma_rs::lzma_decompress_with_options(&mut compressed.as_slice(), &mut decomp, &options)
+            .map(|_| decomp)
+    };
+    assert_eq!(decompress(11).unwrap(), b"Hello world");
+    for (expected_size, actual_size) in [(5, 11), (20, 11)] {
+        let err = decompress(expected_size).unwrap_err();
+        assert!(
+            matches!(
+                err,
+                lzma_rs::error::Error::SizeMismatch { expected, actual }
+                    if expected == expected_size && actual == actual_size
+            ),
+            "{}",
+            err
+        );
+    }
+}
+
+#[test]
+fn decompress_with_status() {
+    #[cfg(feature = "enable_logging")]
+    let _ = env_logger::try_init();
+    use lzma_rs::decompress::Termination;
+    let termination = |filename: &str, options: &lzma_rs::decompress::Options| {
+        let compressed = read_all_file(filename).unwrap();
+        let mut decomp: Vec<u8> = Vec::new();
+        lzma_rs::lzma_decompress_with_status(&mut compressed.as_slice(), &mut decomp, options)
+            .unwrap()
+            .termination
+    };
+    let options = lzma_rs::decompress::Options::default();
+
+    assert_eq!(
+        termination("tests/files/foo.txt.lzma", &options),
+        Some(Termination::EndMarker)
+    );
+    let size = read_all_file("tests/files/range-coder-edge-case")
+        .unwrap()
+        .len() as u64;
+    assert_eq!(
+        termination("tests/files/range-coder-edge-case.lzma", &options),
+        Some(Termination::KnownSize(size))
+    );
+
+    // Stopping at the limit leaves the end of a stream of unknown size
+    // unknown.
+    let limit = lzma_rs::decompress::Options {
+        limit: Some(100),
+        ..Default::default()
+    };
+    assert_eq!(termination("tests/files/foo.txt.lzma", &limit), None);
+    assert_eq!(
+        termination("tests/files/range-coder-edge-case.lzma", &limit),
+        Some(Termination::KnownSize(size))
+    );
+}
+
+#[cfg(feature = "trace")]
+#[test]
+fn corrupt_stream_recent_symbols() {
+    #[cfg(feature = "enable_logging")]
+    let _ = env_logger::try_init();
+    let data = read_all_file("tests/files/foo.txt").unwrap();
+    let mut compressed: Vec<u8> = Vec::new();
+    lzma_rs::lzma_compress(&mut data.as_slice(), &mut compressed).unwrap();
+    compressed[500] ^= 0x55;
+
+    let err = lzma_rs::lzma_decompress(&mut compressed.as_slice(), &mut Vec::new()).unwrap_err();
+    let err = err.to_string();
+    let (message, symbols) = err.split_once(" (recent symbols: ").unwrap();
+    assert!(
+        message.starts_with("lzma error: Invalid match distance"),
+        "{}",
+        err
+    );
+    // The ring holds the last 8 symbols.
+    let count = symbols.matches("Literal(").count()
+        + symbols.matches("Match {").count()
+        + symbols.matches("Rep {").count();
+    assert_eq!(count, 8, "{}", err);
+    assert!(symbols.ends_with(')'), "{}", err);
+}
+
+#[test]
+fn declared_size_is_bound() {
+    #[cfg(feature = "enable_logging")]
+    let _ = env_logger::try_init();
+    let data = read_all_file("tests/files/foo.txt").unwrap();
+    let compressed = read_all_file("tests/files/foo.txt.lzma").unwrap();
+    let with_size = |size: u64| {
+        let mut compressed = compressed.clone();
+        compressed[5..13].copy_from_slice(&size.to_le_bytes());
+        compressed
+    };
+    let decompress = |compressed: &[u8]| {
+        let mut decomp: Vec<u8> = Vec::new();
+        lzma_rs::lzma_decompress(&mut &compressed[..], &mut decomp).map(|()| decomp)
+    };
+
+    // The end-of-stream marker comes before the declared size.
+    let size = data.len() as u64;
+    let err = decompress(&with_size(size + 10)).unwrap_err();
+    assert!(
+        matches!(err, lzma_rs::error::Error::SizeMismatch { expected, actual }
+            if expected == size + 10 && actual == size),
+        "{}",
+        err
+    );
+
+    // A match crosses the declared size.
+    let mut pos = 0;
+    let mut crossing = None;
+    lzma_rs::lzma_decompress_with_symbols(
+        &mut compressed.as_slice(),
+        &mut Vec::new(),
+        &lzma_rs::decompress::Options::default(),
+        |symbol| {
+            let len = match symbol {
+                lzma_rs::decompress::Symbol::Literal(_) => 1,
+                lzma_rs::decompress::Symbol::Match { len, .. }
+                | lzma_rs::decompress::Symbol::Rep { len, .. } => len,
+                _ => unreachable!(),
+            };
+            if len > 2 && crossing.is_none() {
+                crossing = Some((pos, len));
+            }
+            pos += len;
+        },
+    )
+    .unwrap();
+    let (pos, len) = crossing.unwrap();
+    let err = decompress(&with_size(pos as u64 + 1)).unwrap_err();
+    assert!(
+        matches!(err, lzma_rs::error::Error::SizeMismatch { expected, actual }
+            if expected == pos as u64 + 1 && actual == (pos + len) as u64),
+        "{}",
+        err
+    );
+
+    // Stopping exactly at the declared size is fine.
+    assert_eq!(decompress(&with_size(pos as u64)).unwrap(), &data[..pos]);
+    assert_eq!(
+        decompress(&with_size((pos + len) as u64)).unwrap(),
+        &data[..pos + len]
+    );
+}
+
+#[test]
+fn decompress_append() {
+    #[cfg(feature = "enable_logging")]
+    let _ = env_logger::try_init();
+    let data = read_all_file("tests/files/foo.txt").unwrap();
+    let mut compressed: Vec<u8> = Vec::new();
+    lzma_rs::lzma_compress(&mut data.as_slice(), &mut compressed).unwrap();
+    let mut hello: Vec<u8> = Vec::new();
+    lzma_rs::lzma_compress(&mut &b"Hello world\n"[..], &mut hello).unwrap();
+
+    let mut out = b"prefix".to_vec();
+    lzma_rs::lzma_decompress_append(&mut compressed.as_slice(), &mut out).unwrap();
+    lzma_rs::lzma_decompress_append(&mut hello.as_slice(), &mut out).unwrap();
+    assert_eq!(out, [&b"prefix"[..], &data, b"Hello world\n"].concat());
+}
+
+#[cfg(feature = "raw_decoder")]
+#[test]
+fn raw_decoder_reuse() {
+    use lzma_rs::decompress::raw::{LzmaDecoder, LzmaParams};
+
+    #[cfg(feature = "enable_logging")]
+    let _ = env_logger::try_init();
+    let messages: [&[u8]; 4] = [
+        b"Hello world",
+        b"",
+        b"aaaaaaaaaaaaaaaaaaaaaaaaa",
+        b"\x00\xff",
+    ];
+    let compressed: Vec<Vec<u8>> = messages
+        .iter()
+        .map(|x| {
+            let mut compressed = Vec::new();
+            lzma_rs::lzma_compress(&mut std::io::BufReader::new(*x), &mut compressed).unwrap();
+            compressed
+        })
+        .collect();
+
+    let options = lzma_rs::decompress::Options::default();
+    let mut decoder: Option<LzmaDecoder> = None;
+    for (message, compressed) in messages.iter().zip(compressed.iter()) {
+        let mut input = compressed.as_slice();
+        let params = LzmaParams::read_header(&mut input, &options).unwrap();
+        let decoder = match decoder.as_mut() {
+            Some(decoder) => {
+                decoder.reset(None);
+                decoder
+            }
+            None => decoder.insert(LzmaDecoder::new(params, None).unwrap()),
+        };
+
+        let mut decomp = Vec::new();
+        decoder.decompress(&mut input, &mut decomp).unwrap();
+        assert_eq!(&decomp, message);
+
+        // Compare with a fresh decoder.
+        let mut fresh = Vec::new();
+        lzma_rs::lzma_decompress(&mut compressed.as_slice(), &mut fresh).unwrap();
+        assert_eq!(decomp, fresh);
+    }
+}
+
+#[cfg(feature = "raw_decoder")]
+#[test]
+fn raw_decoder_across_chunks() {
+    use lzma_rs::decompress::raw::{LzmaParams, RawLzmaDecoder};
+
+    #[cfg(feature = "enable_logging")]
+    let _ = env_logger::try_init();
+    let options = lzma_rs::decompress::Options::default();
+    for (compfile, rawfile) in [
+        ("tests/files/hello.txt.lzma", "tests/files/hello.txt"),
+        ("tests/files/foo.txt.lzma", "tests/files/foo.txt"),
+    ] {
+        let compressed = read_all_file(compfile).unwrap();
+        let expected = read_all_file(rawfile).unwrap();
+        let mut stream = compressed.as_slice();
+        let params = LzmaParams::read_header(&mut stream, &options).unwrap();
+
+        let decode = |pieces: &mut dyn Iterator<Item = &[u8]>| {
+            let mut decoder = RawLzmaDecoder::new(params, None);
+            let mut decomp = Vec::new();
+            for piece in pieces {
+                decomp.extend_from_slice(&decoder.feed(piece).unwrap());
+            }
+            let fed = decomp.len();
+            decomp.extend_from_slice(&decoder.finish().unwrap());
+            (decomp, fed)
+        };
+
+        // Split the stream once, around the range coder initialization and
+        // further in.
+        for split in [0, 1, 4, 5, 6, 20, 21, stream.len() / 2, stream.len() - 1] {
+            let (head, tail) = stream.split_at(split);
+            let (decomp, _) = decode(&mut [head, &[], tail].iter().copied());
+            assert_eq!(decomp, expected, "{} split at {}", compfile, split);
+        }
+
+        // Feed small chunks, the output comes as the stream is decoded.
+        for chunk in [1, 7, 1000] {
+            let (decomp, fed) = decode(&mut stream.chunks(chunk));
+            assert_eq!(decomp, expected, "{} in chunks of {}", compfile, chunk);
+            assert!(fed + 20 >= expected.len(), "{} < {}", fed, expected.len());
+        }
+    }
+
+    // The end of the stream is only checked when finishing.
+    let compressed = read_all_file("tests/files/foo.txt.lzma").unwrap();
+    let mut stream = compressed.as_slice();
+    let params = LzmaParams::read_header(&mut stream, &options).unwrap();
+    let mut decoder = RawLzmaDecoder::new(params, None);
+    decoder.feed(&stream[..stream.len() / 2]).unwrap();
+    assert!(decoder.finish().is_err());
+    assert!(RawLzmaDecoder::new(params, None).finish().is_err());
+}
+
+#[cfg(feature = "raw_decoder")]
+#[test]
+fn raw_decoder_custom_buffer() {
+    use lzma_rs::decompress::raw::{LzBuffer, LzmaDecoder, LzmaParams};
+    use lzma_rs::error::{Error, Result};
+
+    /// Keeps all the data in memory, recording the operations appended,
+    /// with a window of at most `window` bytes.
+    struct RecordingBuffer {
+        data: Vec<u8>,
+        window: usize,
+        literals: usize,
+        matches: Vec<(usize, usize)>,
+    }
+
+    impl LzBuffer<Vec<u8>> for RecordingBuffer {
+        fn len(&self) -> usize {
+            self.data.len()
+        }
+
+        fn window_len(&self) -> usize {
+            std::cmp::min(self.data.len(), self.window)
+        }
+
+        fn last_or(&self, lit: u8) -> u8 {
+            self.data.last().copied().unwrap_or(lit)
+        }
+
+        fn last_n(&self, dist: usize) -> Result<u8> {
+            match self.data.len().checked_sub(dist) {
+                Some(index) if dist > 0 && dist <= self.window => Ok(self.data[index]),
+                _ => Err(Error::LzmaError(format!("distance {} out of window", dist))),
+            }
+        }
+
+        fn append_literal(&mut self, lit: u8) -> Result<()> {
+            self.literals += 1;
+            self.data.push(lit);
+            Ok(())
+        }
+
+        fn append_lz(&mut self, len: usize, dist: usize) -> Result<()> {
+            self.matches.push((len, dist));
+            for _ in 0..len {
+                let byte = self.last_n(dist)?;
+                self.data.push(byte);
+            }
+            Ok(())
+        }
+
+        fn finish(self) -> std::io::Result<Vec<u8>> {
+            Ok(self.data)
+        }
+    }
+
+    #[cfg(feature = "enable_logging")]
+    let _ = env_logger::try_init();
+    let compressed = read_all_file("tests/files/foo.txt.lzma").unwrap();
+    let expected = read_all_file("tests/files/foo.txt").unwrap();
+    let options = lzma_rs::decompress::Options::default();
+    let decode = |window| {
+        let mut input = compressed.as_slice();
+        let params = LzmaParams::read_header(&mut input, &options).unwrap();
+        let mut decoder = LzmaDecoder::new(params, None).unwrap();
+        let mut buffer = RecordingBuffer {
+            data: Vec::new(),
+            window,
+            literals: 0,
+            matches: Vec::new(),
+        };
+        decoder
+            .decompress_with_buffer(&mut input, &mut buffer)
+            .map(|()| buffer)
+    };
+
+    let buffer = decode(usize::MAX).unwrap();
+    assert!(!buffer.matches.is_empty());
+    let copied: usize = buffer.matches.iter().map(|&(len, _)| len).sum();
+    assert_eq!(buffer.literals + copied, expected.len());
+    let max_dist = buffer.matches.iter().map(|&(_, dist)| dist).max().unwrap();
+    assert_eq!(buffer.finish().unwrap(), expected);
+
+    // Sequences reaching beyond the window of the buffer are rejected.
+    assert!(decode(max_dist).is_ok());
+    assert!(decode(max_dist - 1).is_err());
+}
+
+#[test]
+fn compress_to_vec() {
+    #[cfg(feature = "enable_logging")]
+    let _ = env_logger::try_init();
+    let text = read_all_file("tests/files/foo.txt").unwrap();
+    // Incompressible data outgrows the reserved capacity.
+    let mut state = 0x2545_f491u32;
+    let noise: Vec<u8> = (0..0x10000)
+        .map(|_| {
+            state ^= state << 13;
+            state ^= state >> 17;
+            state ^= state << 5;
+            state as u8
+        })
+        .collect();
+    for x in [&b""[..], b"Hello world", &text, &noise] {
+        let compressed = lzma_rs::lzma_compress_to_vec(x).unwrap();
+        let options = lzma_rs::compress::Options {
+            unpacked_size: lzma_rs::compress::UnpackedSize::WriteToHeader(Some(x.len() as u64)),
+            ..Default::default()
+        };
+        let mut expected: Vec<u8> = Vec::new();
+        lzma_rs::lzma_compress_with_options(&mut &x[..], &mut expected, &options).unwrap();
+        assert_eq!(compressed, expected);
+        let mut decomp: Vec<u8> = Vec::new();
+        lzma_rs::lzma_decompress(&mut compressed.as_slice(), &mut decomp).unwrap();
+        assert_eq!(decomp, x);
+    }
+}
+
+#[test]
+fn decompress_concatenated() {
+    #[cfg(feature = "enable_logging")]
+    let _ = env_logger::try_init();
+    let first = read_all_file("tests/files/foo.txt").unwrap();
+    let second = b"Hello world\n";
+    let compress = |x: &[u8], unpacked_size| {
+        let options = lzma_rs::compress::Options {
+            unpacked_size,
+            ..Default::default()
+        };
+        let mut compressed: Vec<u8> = Vec::new();
+        lzma_rs::lzma_compress_with_options(&mut &x[..], &mut compressed, &options).unwrap();
+        compressed
+    };
+    let known_size =
+        |x: &[u8]| lzma_rs::compress::UnpackedSize::WriteToHeader(Some(x.len() as u64));
+    let concatenated_options = lzma_rs::decompress::Options {
+        concatenated: true,
+        ..Default::default()
+    };
+
+    let mut compressed = compress(&first, known_size(&first));
+    compressed.extend(compress(second, known_size(second)));
+    let mut decomp: Vec<u8> = Vec::new();
+    lzma_rs::lzma_decompress_with_options(
+        &mut compressed.as_slice(),
+        &mut decomp,
+        &concatenated_options,
+    )
+    .unwrap();
+    assert_eq!(decomp, [&first[..], second].concat());
+
+    // By default, only the first member is decoded.
+    let mut decomp: Vec<u8> = Vec::new();
+    lzma_rs::lzma_decompress(&mut compressed.as_slice(), &mut decomp).unwrap();
+    assert_eq!(decomp, first);
+
+    // A member with an end-of-payload marker must be the last one.
+    let mut compressed = compress(&first, lzma_rs::compress::UnpackedSize::WriteToHeader(None));
+    compressed.extend(compress(second, known_size(second)));
+    let mut decomp: Vec<u8> = Vec::new();
+    let error = lzma_rs::lzma_decompress_with_options(
+        &mut compressed.as_slice(),
+        &mut decomp,
+        &concatenated_options,
+    )
+    .unwrap_err();
+    assert_eq!(
+        without_trace(&error),
+        "lzma error: Found end-of-stream marker but more bytes are available"
+    );
+}
+
+#[test]
+fn decompress_limit() {
+    #[cfg(feature = "enable_logging")]
+    let _ = env_logger::try_init();
+    let data = read_all_file("tests/files/foo.txt").unwrap();
+    let mut compressed: Vec<u8> = Vec::new();
+    lzma_rs::lzma_compress(&mut data.as_slice(), &mut compressed).unwrap();
+
+    // The rest of the stream isn't decoded, so cutting it doesn't matter.
+    let head = &compressed[..compressed.len() / 2];
+    for limit in [0, 1, 100, 12345] {
+        let options = lzma_rs::decompress::Options {
+            limit: Some(limit as u64),
+            ..Default::default()
+        };
+        let mut decomp: Vec<u8> = Vec::new();
+        lzma_rs::lzma_decompress_with_options(&mut &head[..], &mut decomp, &options).unwrap();
+        assert_eq!(decomp, data[..limit]);
+    }
+
+    // A limit beyond the end has no effect.
+    let options = lzma_rs::decompress::Options {
+        limit: Some(data.len() as u64 + 1),
+        ..Default::default()
+    };
+    let mut decomp: Vec<u8> = Vec::new();
+    lzma_rs::lzma_decompress_with_options(&mut compressed.as_slice(), &mut decomp, &options)
+        .unwrap();
+    assert_eq!(decomp, data);
+}
+
+#[test]
+fn reject_incompressible() {
+    #[cfg(feature = "enable_logging")]
+    let _ = env_logger::try_init();
+    let mut x = Vec::new();
+    let mut state = 0x2545_f491_4f6c_dd1du64;
+    for _ in 0..0x1000 {
+        state ^= state << 13;
+        state ^= state >> 7;
+        state ^= state << 17;
+        x.push(state as u8);
+    }
+    let options = lzma_rs::compress::Options {
+        reject_incompressible: true,
+        ..Default::default()
+    };
+
+    // Random data is rejected, without writing anything.
+    let mut compressed: Vec<u8> = Vec::new();
+    let err = lzma_rs::lzma_compress_with_options(&mut x.as_slice(), &mut compressed, &options)
+        .unwrap_err();
+    assert!(compressed.is_empty());
+    match lzma_rs::error::Error::from(err) {
+        lzma_rs::error::Error::Incompressible {
+            input_size,
+            output_size,
+        } => {
+            assert_eq!(input_size, x.len() as u64);
+            assert!(output_size > input_size);
+        }
+        err => panic!("{}", err),
+    }
+
+    // Compressible data goes through.
+    let x = b"Hello world\n".repeat(100);
+    let mut compressed: Vec<u8> = Vec::new();
+    let stats =
+        lzma_rs::lzma_compress_with_options(&mut x.as_slice(), &mut compressed, &options).unwrap();
+    assert!(!stats.expanded());
+    assert_eq!(stats.output_size, compressed.len() as u64);
+    let mut decomp: Vec<u8> = Vec::new();
+    lzma_rs::lzma_decompress(&mut compressed.as_slice(), &mut decomp).unwrap();
+    assert_eq!(decomp, x);
+}
+
+#[test]
+fn decompress_with_symbols() {
+    #[cfg(feature = "enable_logging")]
+    let _ = env_logger::try_init();
+    use lzma_rs::decompress::Symbol;
+    // "abcabcabcabc xyz abcabc", compressed by `xz --format=lzma -9`.
+    let compressed: &[u8] = b"\x5d\x00\x00\x00\x04\xff\xff\xff\xff\xff\xff\xff\
+                              \xff\x00\x30\x98\x88\xa7\xea\x4b\xbb\x3e\x29\xdd\
+                              \x1c\x57\xfe\xe1\xd7\xff\xff\xda\xa8\x00\x00";
+    let mut symbols = Vec::new();
+    let mut decomp: Vec<u8> = Vec::new();
+    lzma_rs::lzma_decompress_with_symbols(
+        &mut &compressed[..],
+        &mut decomp,
+        &Default::default(),
+        |symbol| symbols.push(symbol),
+    )
+    .unwrap();
+    assert_eq!(decomp, b"abcabcabcabc xyz abcabc");
+    let mut expected: Vec<Symbol> = b"abc".iter().map(|&b| Symbol::Literal(b)).collect();
+    expected.push(Symbol::Match { len: 9, dist: 3 });
+    expected.extend(b" xyz ".iter().map(|&b| Symbol::Literal(b)));
+    expected.push(Symbol::Match { len: 6, dist: 11 });
+    assert_eq!(symbols, expected);
+
+    // Replaying the symbols of a larger file, which has all kinds of them,
+    // gives back its contents.
+    let compressed = read_all_file("tests/files/foo.txt.lzma").unwrap();
+    let mut replayed = Vec::new();
+    let (mut matches, mut reps) = (0, 0);
+    let mut decomp: Vec<u8> = Vec::new();
+    lzma_rs::lzma_decompress_with_symbols(
+        &mut compressed.as_slice(),
+        &mut decomp,
+        &Default::default(),
+        |symbol| {
+            let (len, dist) = match symbol {
+                Symbol::Literal(byte) => {
+                    replayed.push(byte);
+                    return;
+                }
+                Symbol::Match { len, dist } => {
+                    matches += 1;
+                    (len, dist)
+                }
+                Symbol::Rep { index, len, dist } => {
+                    assert!(index < 4);
+                    reps += 1;
+                    (len, dist)
+                }
+                _ => unreachable!(),
+            };
+            for _ in 0..len {
+                replayed.push(replayed[replayed.len() - dist]);
+            }
+        },
+    )
+    .unwrap();
+    assert_eq!(replayed, decomp);
+    assert!(matches > 0 && reps > 0);
+    assert_eq!(decomp, read_all_file("tests/files/foo.txt").unwrap());
+}
+
+#[test]
+fn reader_with_dict() {
+    #[cfg(feature = "enable_logging")]
+    let _ = env_logger::try_init();
+    use std::io::Read;
+
+    // Generated by liblzma's raw LZMA1 encoder with `dict` as the preset
+    // dictionary, and prefixed with a header of unknown unpacked size.
+    let dict = b"GET /index.html HTTP/1.1\r\nHost: example.com\r\nUser-Agent: lzma-rs\r\nAccept: */*\r\n\r\n";
+    let message = b"GET /about.html HTTP/1.1\r\nHost: example.com\r\nUser-Agent: lzma-rs\r\nAccept: */*\r\n\r\n";
+    let compressed: &[u8] = b"\x5d\x00\x10\x00\x00\xff\xff\xff\xff\xff\xff\xff\xff\
+                              \x00\x8c\xc0\x94\x4c\x47\x22\x95\x48\x76\x39\xdc\x3b\
+                              \xff\xff\xf9\xa0\x40\x00";
+
+    let mut reader = lzma_rs::decompress::LzmaReader::with_dict(compressed, dict.to_vec());
+    let mut decomp = Vec::new();
+    let mut buf = [0; 10];
+    loop {
+        let len = reader.read(&mut buf).unwrap();
+        if len == 0 {
+            break;
+        }
+        decomp.extend_from_slice(&buf[..len]);
+    }
+    assert_eq!(decomp, &message[..]);
+
+    // The stream references the dictionary, starting with a match at
+    // distance 81, the length of the dictionary.
+    let mut reader = lzma_rs::decompress::LzmaReader::new(compressed);
+    assert!(reader.read_to_end(&mut Vec::new()).is_err());
+    let err = lzma_rs::lzma_decompress(&mut &compressed[..], &mut Vec::new()).unwrap_err();
+    assert_eq!(
+        err.to_string(),
+        "lzma error: Invalid match distance 81 at position 0, beyond the 0 bytes of the dictionary window"
+    );
+
+    // Without a dictionary, the reader decodes plain streams.
+    let mut compressed = Vec::new();
+    lzma_rs::lzma_compress(&mut &message[..], &mut compressed).unwrap();
+    let mut decomp = Vec::new();
+    lzma_rs::decompress::LzmaReader::new(compressed.as_slice())
+        .read_to_end(&mut decomp)
+        .unwrap();
+    assert_eq!(decomp, &message[..]);
+}
+
+#[test]
+fn reader_with_large_dict() {
+    #[cfg(feature = "enable_logging")]
+    let _ = env_logger::try_init();
+    use std::io::Read;
+
+    // Same stream as in `reader_with_dict`, with a 4 KiB dictionary.
+    let dict = b"GET /index.html HTTP/1.1\r\nHost: example.com\r\nUser-Agent: lzma-rs\r\nAccept: */*\r\n\r\n";
+    let message = b"GET /about.html HTTP/1.1\r\nHost: example.com\r\nUser-Agent: lzma-rs\r\nAccept: */*\r\n\r\n";
+    let compressed: &[u8] = b"\x5d\x00\x10\x00\x00\xff\xff\xff\xff\xff\xff\xff\xff\
+                              \x00\x8c\xc0\x94\x4c\x47\x22\x95\x48\x76\x39\xdc\x3b\
+                              \xff\xff\xf9\xa0\x40\x00";
+
+    // Only the last 4 KiB of a larger shared dictionary are used, and they
+    // fit in the memory limit even though the whole dictionary doesn't.
+    let large_dict = [&[b'x'; 0x3000][..], &dict[..]].concat();
+    let options = lzma_rs::decompress::Options {
+        memlimit: Some(0x1000),
+        ..Default::default()
+    };
+    let mut decomp = Vec::new();
+    lzma_rs::decompress::LzmaReader::with_options(compressed, large_dict, &options)
+        .read_to_end(&mut decomp)
+        .unwrap();
+    assert_eq!(decomp, &message[..]);
+
+    // A dictionary filling the window exactly works too.
+    let window_dict = [&[b'x'; 0x1000 - 81][..], &dict[..]].concat();
+    let mut decomp = Vec::new();
+    lzma_rs::decompress::LzmaReader::with_options(compressed, window_dict, &options)
+        .read_to_end(&mut decomp)
+        .unwrap();
+    assert_eq!(decomp, &message[..]);
+
+    // The stream doesn't decode if the referenced bytes are out of the
+    // window.
+    let head = [&dict[..], &[b'x'; 0x1000][..]].concat();
+    let mut reader = lzma_rs::decompress::LzmaReader::with_dict(compressed, head);
+    assert!(reader.read_to_end(&mut Vec::new()).is_err());
+}
+
+#[cfg(feature = "raw_decoder")]
+#[test]
+fn reader_raw() {
+    use lzma_rs::decompress::raw::{LzmaParams, LzmaProperties};
+    use lzma_rs::decompress::LzmaReader;
+    use std::io::Read;
+
+    #[cfg(feature = "enable_logging")]
+    let _ = env_logger::try_init();
+    let data = read_all_file("tests/files/foo.txt").unwrap();
+    let compressed = read_all_file("tests/files/foo.txt.lzma").unwrap();
+    let properties = LzmaProperties {
+        lc: 3,
+        lp: 0,
+        pb: 2,
+    };
+
+    let mut headered = Vec::new();
+    LzmaReader::new(compressed.as_slice())
+        .read_to_end(&mut headered)
+        .unwrap();
+    assert_eq!(headered, data);
+
+    // The same stream without its 13-byte header, whose parameters are
+    // given out of band instead.
+    let raw = &compressed[13..];
+    let params = LzmaParams::new(properties, 0x80_0000, Some(data.len() as u64));
+    let mut decomp = Vec::new();
+    LzmaReader::new_raw(raw, params)
+        .read_to_end(&mut decomp)
+        .unwrap();
+    assert_eq!(decomp, headered);
+
+    // The stream has an end-of-payload marker, so its size can be omitted.
+    let params = LzmaParams::new(properties, 0x80_0000, None);
+    let mut reader = LzmaReader::new_raw(raw, params);
+    let mut decomp = Vec::new();
+    reader.read_to_end(&mut decomp).unwrap();
+    assert_eq!(decomp, data);
+    assert_eq!(reader.compressed_bytes_consumed(), raw.len() as u64);
+
+    // Headers aren't parsed in raw mode.
+    let params = LzmaParams::new(properties, 0x80_0000, None);
+    let mut reader = LzmaReader::new_raw(compressed.as_slice(), params);
+    assert!(reader.read_to_end(&mut Vec::new()).is_err());
+}
+
+#[test]
+fn reader_compressed_bytes_consumed() {
+    #[cfg(feature = "enable_logging")]
+    let _ = env_logger::try_init();
+    // Streams of known size, without end-of-stream marker, can be followed
+    // by more data.
+    let compress = |data: &[u8], output: &mut Vec<u8>| {
+        let options = lzma_rs::compress::Options {
+            unpacked_size: lzma_rs::compress::UnpackedSize::WriteToHeader(Some(data.len() as u64)),
+            ..Default::default()
+        };
+        lzma_rs::lzma_compress_with_options(&mut &data[..], output, &options).unwrap();
+    };
+    let mut first = Vec::new();
+    compress(b"Hello world", &mut first);
+    let mut input = first.clone();
+    compress(b"Goodbye", &mut input);
+
+    let mut reader = lzma_rs::decompress::LzmaReader::new(input.as_slice());
+    assert_eq!(reader.compressed_bytes_consumed(), 0);
+    let mut buf = [0; 5];
+    reader.read_exact(&mut buf).unwrap();
+    assert_eq!(&buf, b"Hello");
+    // The whole stream is consumed by the first read, and nothing more.
+    assert_eq!(reader.compressed_bytes_consumed(), first.len() as u64);
+    let rest = reader.into_inner();
+    assert_eq!(rest, &input[first.len()..]);
+
+    let mut decomp = Vec::new();
+    lzma_rs::decompress::LzmaReader::new(rest)
+        .read_to_end(&mut decomp)
+        .unwrap();
+    assert_eq!(decomp, b"Goodbye");
+}
+
+#[test]
+fn truncated_unknown_size() {
+    #[cfg(feature = "enable_logging")]
+    let _ = env_logger::try_init();
+    let x = read_all_file("tests/files/foo.txt").unwrap()[..10_000].to_vec();
+    // Without a known size, the stream ends with an end-of-stream marker.
+    let compressed = lzma_rs::lzma_compress_to_vec(&x).unwrap();
+    let mut compressed_unknown = Vec::new();
+    lzma_rs::lzma_compress(&mut x.as_slice(), &mut compressed_unknown).unwrap();
+    let status = lzma_rs::lzma_decompress_with_status(
+        &mut compressed_unknown.as_slice(),
+        &mut Vec::new(),
+        &lzma_rs::decompress::Options::default(),
+    )
+    .unwrap();
+    assert_eq!(
+        status.termination,
+        Some(lzma_rs::decompress::Termination::EndMarker)
+    );
+
+    // Past the header and the start of the range coder, the input ends in
+    // the middle of a symbol, and the output decoded so far is reported.
+    for compressed in [&compressed, &compressed_unknown] {
+        let mut last_pos = 0;
+        for len in (18..compressed.len())
+            .step_by(61)
+            .chain([compressed.len() - 1])
+        {
+            let mut decomp = Vec::new();
+            let err = lzma_rs::lzma_decompress(&mut &compressed[..len], &mut decomp).unwrap_err();
+            let message = match err {
+                lzma_rs::error::Error::Truncated(message) => message,
+                err => panic!("{} bytes: {}", len, err),
+            };
+            let pos: usize = message
+                .trim_end_matches(" bytes of output")
+                .rsplit(' ')
+                .next()
+                .unwrap()
+                .parse()
+                .unwrap();
+            assert!(pos >= last_pos && pos <= x.len(), "{}", message);
+            last_pos = pos;
+        }
+    }
+
+    // Decoding as a stream reports the same.
+    #[cfg(feature = "stream")]
+    {
+        let mut stream = lzma_rs::decompress::Stream::new(Vec::new());
+        stream
+            .write_all(&compressed_unknown[..compressed_unknown.len() - 1])
+            .unwrap();
+        let err = stream.finish().unwrap_err();
+        assert!(
+            matches!(err, lzma_rs::error::Error::Truncated(_)),
+            "{}",
+            err
+        );
+    }
+}
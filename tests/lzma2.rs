@@ -11,7 +11,9 @@ fn read_all_file(filename: &str) -> std::io::Result<Vec<u8>> {
 
 fn round_trip(x: &[u8]) {
     let mut compressed: Vec<u8> = Vec::new();
-    lzma_rs::lzma2_compress(&mut std::io::BufReader::new(x), &mut compressed).unwrap();
+    let stats = lzma_rs::lzma2_compress(&mut std::io::BufReader::new(x), &mut compressed).unwrap();
+    assert_eq!(stats.input_size, x.len() as u64);
+    assert_eq!(stats.output_size, compressed.len() as u64);
     #[cfg(feature = "enable_logging")]
     info!("Compressed {} -> {} bytes", x.len(), compressed.len());
     #[cfg(feature = "enable_logging")]
@@ -50,3 +52,832 @@ fn round_trip_files() {
     let _ = env_logger::try_init();
     round_trip_file("tests/files/foo.txt");
 }
+
+#[test]
+fn missing_initial_properties() {
+    #[cfg(feature = "enable_logging")]
+    let _ = env_logger::try_init();
+    // First chunk resets the state but not the properties (control byte 0xA0).
+    let input: &[u8] = b"\xa0\x00\x00\x00\x04\x00\x00\x00\x00\x00\x00";
+    let mut decomp: Vec<u8> = Vec::new();
+    let err = lzma_rs::lzma2_decompress(&mut &input[..], &mut decomp).unwrap_err();
+    assert_eq!(
+        err.to_string(),
+        "lzma error: LZMA2 missing initial properties"
+    );
+}
+
+#[test]
+fn round_trip_incompressible() {
+    #[cfg(feature = "enable_logging")]
+    let _ = env_logger::try_init();
+    // Pseudo-random bytes don't shrink, so they are stored in uncompressed
+    // chunks, interleaved with compressed ones.
+    let mut x = Vec::new();
+    let mut state = 0x2545_f491_4f6c_dd1du64;
+    for _ in 0..0x18000 {
+        state ^= state << 13;
+        state ^= state >> 7;
+        state ^= state << 17;
+        x.push(state as u8);
+    }
+    x.extend(vec![b'a'; 0x20000]);
+    x.extend_from_slice(&x.clone()[..0x18000]);
+    round_trip(x.as_slice());
+}
+
+#[test]
+fn expanded_output() {
+    #[cfg(feature = "enable_logging")]
+    let _ = env_logger::try_init();
+    let mut x = Vec::new();
+    let mut state = 0x2545_f491_4f6c_dd1du64;
+    for _ in 0..0x30000 {
+        state ^= state << 13;
+        state ^= state >> 7;
+        state ^= state << 17;
+        x.push(state as u8);
+    }
+
+    // Without an uncompressed fallback, random data expands noticeably.
+    let mut compressed: Vec<u8> = Vec::new();
+    let stats = lzma_rs::lzma_compress(&mut x.as_slice(), &mut compressed).unwrap();
+    assert!(stats.expanded());
+    assert!(stats.output_size > stats.input_size + 0x100);
+
+    // LZMA2 stores it in uncompressed chunks instead, which only adds their
+    // headers and the end marker.
+    let mut compressed: Vec<u8> = Vec::new();
+    let stats = lzma_rs::lzma2_compress(&mut x.as_slice(), &mut compressed).unwrap();
+    assert!(stats.expanded());
+    assert_eq!(stats.output_size, stats.input_size + 3 * stats.chunks + 1);
+
+    let mut compressed: Vec<u8> = Vec::new();
+    let x = vec![b'a'; 0x30000];
+    let stats = lzma_rs::lzma2_compress(&mut x.as_slice(), &mut compressed).unwrap();
+    assert!(!stats.expanded());
+}
+
+#[test]
+fn first_chunk_sets_properties() {
+    #[cfg(feature = "enable_logging")]
+    let _ = env_logger::try_init();
+    let mut compressed: Vec<u8> = Vec::new();
+    let x = vec![b'a'; 1000];
+    lzma_rs::lzma2_compress(&mut x.as_slice(), &mut compressed).unwrap();
+    // LZMA chunk resetting the dictionary, state and properties.
+    assert_eq!(compressed[0], 0xE0);
+    // lc = 3, lp = 0, pb = 2
+    assert_eq!(compressed[5], 0x5D);
+    assert_eq!(compressed.last(), Some(&0));
+}
+
+/// Walk the chunks of an LZMA2 stream, returning the control byte and the
+/// uncompressed size of each chunk.
+fn chunk_headers(compressed: &[u8]) -> Vec<(u8, usize)> {
+    let mut chunks = Vec::new();
+    let mut pos = 0;
+    while compressed[pos] != 0 {
+        let control = compressed[pos];
+        let size = |i: usize| u16::from_be_bytes([compressed[i], compressed[i + 1]]) as usize + 1;
+        let (unpacked_size, len) = match control {
+            1 | 2 => (size(pos + 1), 3 + size(pos + 1)),
+            _ => {
+                let unpacked_size = ((control as usize & 0x1F) << 16) + size(pos + 1);
+                let header_len = if control >= 0xC0 { 6 } else { 5 };
+                (unpacked_size, header_len + size(pos + 3))
+            }
+        };
+        chunks.push((control, unpacked_size));
+        pos += len;
+    }
+    assert_eq!(pos + 1, compressed.len());
+    chunks
+}
+
+#[test]
+fn only_first_chunk_resets_dict() {
+    #[cfg(feature = "enable_logging")]
+    let _ = env_logger::try_init();
+    let x = b"Hello world\n".repeat(0x4000);
+    let mut compressed: Vec<u8> = Vec::new();
+    let stats = lzma_rs::lzma2_compress(&mut x.as_slice(), &mut compressed).unwrap();
+    assert_eq!(stats.chunks, 3);
+
+    let controls: Vec<u8> = chunk_headers(&compressed)
+        .iter()
+        .map(|&(control, _)| control)
+        .collect();
+    // Only the first chunk resets the dictionary, the next ones carry the
+    // history and the state on.
+    assert_eq!(controls, [0xE0, 0x80, 0x80]);
+    round_trip(x.as_slice());
+
+    // Compressing each chunk on its own, as if the dictionary were reset
+    // every time, gives a larger output.
+    let mut reset_every_chunk = 0;
+    for chunk in x.chunks(0x10000) {
+        let mut compressed: Vec<u8> = Vec::new();
+        lzma_rs::lzma2_compress(&mut &chunk[..], &mut compressed).unwrap();
+        reset_every_chunk += compressed.len();
+    }
+    assert!(compressed.len() < reset_every_chunk);
+}
+
+#[test]
+fn uncompressed_dict_reset() {
+    #[cfg(feature = "enable_logging")]
+    let _ = env_logger::try_init();
+    let mut first: Vec<u8> = Vec::new();
+    lzma_rs::lzma2_compress(&mut &[b'a'; 1000][..], &mut first).unwrap();
+    assert_eq!(first.pop(), Some(0));
+    assert_eq!(first[0], 0xE0);
+    // Uncompressed chunks, with and without dictionary reset.
+    let hello_reset: &[u8] = b"\x01\x00\x0bHello world\n";
+    let hello: &[u8] = b"\x02\x00\x0bHello world\n";
+    // LZMA chunk resetting the state but keeping the properties, decoding
+    // to "Hello world\n" twice from a match into the previous 12 bytes.
+    let lzma_chunk: &[u8] = b"\xa0\x00\x17\x00\x06\x00\xb0\x61\xac\x00\x00\x00";
+
+    let decompress = |chunks: &[&[u8]]| {
+        let input = [chunks, &[b"\x00"]].concat().concat();
+        let mut decomp: Vec<u8> = Vec::new();
+        lzma_rs::lzma2_decompress(&mut input.as_slice(), &mut decomp).map(|()| decomp)
+    };
+
+    assert_eq!(
+        decompress(&[&first, hello, lzma_chunk]).unwrap(),
+        [&[b'a'; 1000][..], &b"Hello world\n".repeat(3)].concat()
+    );
+    // After a dictionary reset by an uncompressed chunk, the next LZMA chunk
+    // must set the properties again, even though it only refers to the
+    // uncompressed data.
+    let err = decompress(&[&first, hello_reset, lzma_chunk]).unwrap_err();
+    assert_eq!(
+        err.to_string(),
+        "lzma error: LZMA2 missing initial properties"
+    );
+}
+
+#[test]
+fn record_chunk_map() {
+    #[cfg(feature = "enable_logging")]
+    let _ = env_logger::try_init();
+    let mut first: Vec<u8> = Vec::new();
+    lzma_rs::lzma2_compress(&mut &[b'a'; 1000][..], &mut first).unwrap();
+    assert_eq!(first.pop(), Some(0));
+    let hello_reset: &[u8] = b"\x01\x00\x0bHello world\n";
+    let hello: &[u8] = b"\x02\x00\x0bHello world\n";
+    // LZMA chunk setting the properties but keeping the dictionary, see
+    // `dict_reset_keeps_output()`.
+    let lzma_chunk: &[u8] = b"\xc0\x00\x17\x00\x06\x5d\x00\xb0\x61\xac\x00\x00\x00";
+    let input = [&first[..], hello_reset, hello, lzma_chunk, b"\x00"].concat();
+
+    let options = lzma_rs::decompress::Options {
+        record_chunk_map: true,
+        ..Default::default()
+    };
+    let mut decomp: Vec<u8> = Vec::new();
+    let status =
+        lzma_rs::lzma2_decompress_with_status(&mut input.as_slice(), &mut decomp, &options)
+            .unwrap();
+    assert_eq!(
+        decomp,
+        [&[b'a'; 1000][..], &b"Hello world\n".repeat(4)].concat()
+    );
+    // Flags: uncompressed, dictionary reset, state reset, properties reset.
+    let chunk = |compressed_offset: usize, uncompressed_offset, flags: [bool; 4]| {
+        lzma_rs::decompress::ChunkEntry {
+            compressed_offset: compressed_offset as u64,
+            uncompressed_offset,
+            uncompressed: flags[0],
+            dict_reset: flags[1],
+            state_reset: flags[2],
+            props_reset: flags[3],
+        }
+    };
+    assert_eq!(
+        status.chunks,
+        [
+            chunk(0, 0, [false, true, true, true]),
+            chunk(first.len(), 1000, [true, true, false, false]),
+            chunk(first.len() + 15, 1012, [true, false, false, false]),
+            chunk(first.len() + 30, 1024, [false, false, true, true]),
+        ]
+    );
+
+    // Nothing is recorded by default.
+    let mut decomp: Vec<u8> = Vec::new();
+    let status = lzma_rs::lzma2_decompress_with_status(
+        &mut input.as_slice(),
+        &mut decomp,
+        &Default::default(),
+    )
+    .unwrap();
+    assert!(status.chunks.is_empty());
+}
+
+#[test]
+fn encoder_chunk_map() {
+    #[cfg(feature = "enable_logging")]
+    let _ = env_logger::try_init();
+    let text = read_all_file("tests/files/foo.txt").unwrap();
+    let mut state = 0x2545_f491_4f6c_dd1du64;
+    let random: Vec<u8> = (0..70_000)
+        .map(|_| {
+            state ^= state << 13;
+            state ^= state >> 7;
+            state ^= state << 17;
+            state as u8
+        })
+        .collect();
+    // Compressed and stored chunks, which reset the state afterwards.
+    let x = [&text[..30_000], &random, &text[30_000..50_000]].concat();
+    let options = lzma_rs::compress::Options {
+        chunk_boundary_bytes: Some(25_000),
+        auto_store: true,
+        pad_to: Some(16),
+        record_chunk_map: true,
+        ..Default::default()
+    };
+    let mut compressed: Vec<u8> = Vec::new();
+    let stats =
+        lzma_rs::lzma2_compress_with_options(&mut x.as_slice(), &mut compressed, &options).unwrap();
+    assert_eq!(stats.chunk_map.len() as u64, stats.chunks);
+    assert!(stats.chunk_map[0].dict_reset);
+    assert!(stats.chunk_map.iter().any(|chunk| chunk.uncompressed));
+    assert!(stats
+        .chunk_map
+        .iter()
+        .any(|chunk| !chunk.uncompressed && !chunk.props_reset && chunk.state_reset));
+
+    // The decoder sees the same chunks.
+    let options = lzma_rs::decompress::Options {
+        record_chunk_map: true,
+        ..Default::default()
+    };
+    let mut decomp: Vec<u8> = Vec::new();
+    let status =
+        lzma_rs::lzma2_decompress_with_status(&mut compressed.as_slice(), &mut decomp, &options)
+            .unwrap();
+    assert_eq!(decomp, x);
+    assert_eq!(status.chunks, stats.chunk_map);
+
+    // Nothing is recorded by default.
+    let mut compressed: Vec<u8> = Vec::new();
+    let stats = lzma_rs::lzma2_compress(&mut x.as_slice(), &mut compressed).unwrap();
+    assert!(stats.chunk_map.is_empty());
+}
+
+#[test]
+fn reset_events() {
+    #[cfg(feature = "enable_logging")]
+    let _ = env_logger::try_init();
+    let mut first: Vec<u8> = Vec::new();
+    lzma_rs::lzma2_compress(&mut &[b'a'; 1000][..], &mut first).unwrap();
+    assert_eq!(first.pop(), Some(0));
+    let hello_reset: &[u8] = b"\x01\x00\x0bHello world\n";
+    let hello: &[u8] = b"\x02\x00\x0bHello world\n";
+    // LZMA chunk setting the properties but keeping the dictionary, see
+    // `dict_reset_keeps_output()`.
+    let lzma_chunk: &[u8] = b"\xc0\x00\x17\x00\x06\x5d\x00\xb0\x61\xac\x00\x00\x00";
+    let input = [&first[..], hello_reset, hello, lzma_chunk, b"\x00"].concat();
+
+    let mut events = Vec::new();
+    let mut decomp: Vec<u8> = Vec::new();
+    let status = lzma_rs::lzma2_decompress_with_resets(
+        &mut input.as_slice(),
+        &mut decomp,
+        &Default::default(),
+        |event| events.push(event),
+    )
+    .unwrap();
+    assert_eq!(
+        decomp,
+        [&[b'a'; 1000][..], &b"Hello world\n".repeat(4)].concat()
+    );
+    assert!(status.chunks.is_empty());
+    let event = |compressed_offset: usize, uncompressed_offset, dict_reset| {
+        lzma_rs::decompress::ResetEvent {
+            compressed_offset: compressed_offset as u64,
+            uncompressed_offset,
+            dict_reset,
+        }
+    };
+    // The uncompressed chunk without dictionary reset has no event.
+    assert_eq!(
+        events,
+        [
+            event(0, 0, true),
+            event(first.len(), 1000, true),
+            event(first.len() + 30, 1024, false),
+        ]
+    );
+
+    // Events fire before the chunk is decoded, even if it's corrupt.
+    let mut events = Vec::new();
+    let corrupt = [&first[..], &[0xE0, 0xFF, 0xFF, 0x00, 0x00, 0x5D]].concat();
+    assert!(lzma_rs::lzma2_decompress_with_resets(
+        &mut corrupt.as_slice(),
+        &mut Vec::new(),
+        &Default::default(),
+        |event| events.push(event),
+    )
+    .is_err());
+    assert_eq!(events, [event(0, 0, true), event(first.len(), 1000, true)]);
+}
+
+#[test]
+fn resync() {
+    #[cfg(feature = "enable_logging")]
+    let _ = env_logger::try_init();
+    // Three parts compressed independently, so that each one starts by
+    // resetting the dictionary.
+    let text = read_all_file("tests/files/foo.txt").unwrap();
+    let parts = [
+        &text[..0x4000],
+        &text[0x4000..0x8000],
+        &text[0x8000..0xC000],
+    ];
+    let compressed_parts: Vec<Vec<u8>> = parts
+        .iter()
+        .map(|part| {
+            let mut compressed: Vec<u8> = Vec::new();
+            lzma_rs::lzma2_compress(&mut &part[..], &mut compressed).unwrap();
+            assert_eq!(compressed.pop(), Some(0));
+            compressed
+        })
+        .collect();
+    let mut input = [&compressed_parts.concat()[..], b"\x00"].concat();
+    let second = compressed_parts[0].len();
+    let third = second + compressed_parts[1].len();
+    // Damage the middle of the second part.
+    input[(second + third) / 2] ^= 0x55;
+
+    let decompress = |input: &[u8], resync| {
+        let options = lzma_rs::decompress::Options {
+            resync,
+            ..Default::default()
+        };
+        let mut decomp: Vec<u8> = Vec::new();
+        lzma_rs::lzma2_decompress_with_status(&mut &input[..], &mut decomp, &options)
+            .map(|status| (decomp, status.gaps))
+    };
+    assert!(decompress(&input, false).is_err());
+    let (decomp, gaps) = decompress(&input, true).unwrap();
+    assert_eq!(decomp, [parts[0], parts[2]].concat());
+    assert_eq!(
+        gaps,
+        [lzma_rs::decompress::Lzma2Gap {
+            compressed_offset: second as u64,
+            uncompressed_offset: parts[0].len() as u64,
+            skipped: (third - second) as u64,
+        }]
+    );
+
+    // Without a later resume point, the rest of the input is skipped.
+    let truncated = &input[..third + 10];
+    let (decomp, gaps) = decompress(truncated, true).unwrap();
+    assert_eq!(decomp, parts[0]);
+    assert_eq!(
+        gaps,
+        [lzma_rs::decompress::Lzma2Gap {
+            compressed_offset: second as u64,
+            uncompressed_offset: parts[0].len() as u64,
+            skipped: (truncated.len() - second) as u64,
+        }]
+    );
+
+    // Valid streams decode as usual.
+    let valid = [&compressed_parts.concat()[..], b"\x00"].concat();
+    let (decomp, gaps) = decompress(&valid, true).unwrap();
+    assert_eq!(decomp, parts.concat());
+    assert!(gaps.is_empty());
+}
+
+#[test]
+fn dict_reset_keeps_output() {
+    #[cfg(feature = "enable_logging")]
+    let _ = env_logger::try_init();
+    // Uncompressed chunk resetting the dictionary.
+    let hello: &[u8] = b"\x01\x00\x0bHello world\n";
+    let reset: &[u8] = b"\x01\x00\x00x";
+    // LZMA chunk resetting the state but not the dictionary, generated by
+    // liblzma with "Hello world\n" as preset dictionary. It decodes to
+    // "Hello world\n" twice, which starts with a match into the dictionary.
+    let lzma_chunk: &[u8] = b"\xc0\x00\x17\x00\x06\x5d\x00\xb0\x61\xac\x00\x00\x00";
+
+    let decompress = |chunks: &[&[u8]]| {
+        let input = [chunks, &[b"\x00"]].concat().concat();
+        let mut decomp: Vec<u8> = Vec::new();
+        let result = lzma_rs::lzma2_decompress(&mut input.as_slice(), &mut decomp);
+        #[cfg(feature = "raw_decoder")]
+        {
+            // Same when the output is also the dictionary.
+            let mut in_place: Vec<u8> = Vec::new();
+            let in_place_result = lzma_rs::decompress::raw::Lzma2Decoder::new()
+                .decompress_to_vec(&mut input.as_slice(), &mut in_place);
+            assert_eq!(in_place_result.is_ok(), result.is_ok());
+            if result.is_ok() {
+                assert_eq!(in_place, decomp);
+            }
+        }
+        result.map(|()| decomp)
+    };
+
+    assert_eq!(
+        decompress(&[hello, lzma_chunk]).unwrap(),
+        b"Hello world\n".repeat(3)
+    );
+    // The output written before a dictionary reset is kept.
+    assert_eq!(
+        decompress(&[hello, reset, hello, reset]).unwrap(),
+        b"Hello world\nxHello world\nx"
+    );
+    // But it can't be referenced anymore.
+    let err = decompress(&[hello, reset, lzma_chunk]).unwrap_err();
+    assert!(err.to_string().contains("beyond"), "{}", err);
+}
+
+#[test]
+fn decompress_from_slice() {
+    #[cfg(feature = "enable_logging")]
+    let _ = env_logger::try_init();
+    let x = b"Hello world\n".repeat(0x2000);
+    let mut compressed: Vec<u8> = Vec::new();
+    lzma_rs::lzma2_compress(&mut x.as_slice(), &mut compressed).unwrap();
+    compressed.extend_from_slice(b"trailer");
+
+    // The slice is read in place, so it's left right after the stream.
+    let mut input = compressed.as_slice();
+    let mut decomp: Vec<u8> = Vec::new();
+    lzma_rs::lzma2_decompress(&mut input, &mut decomp).unwrap();
+    assert_eq!(decomp, x);
+    assert_eq!(input, b"trailer");
+}
+
+#[test]
+fn force_props() {
+    #[cfg(feature = "enable_logging")]
+    let _ = env_logger::try_init();
+    let x = b"Hello world\n".repeat(100);
+    let mut compressed: Vec<u8> = Vec::new();
+    lzma_rs::lzma2_compress(&mut x.as_slice(), &mut compressed).unwrap();
+    // lc = 3, lp = 0, pb = 2
+    assert_eq!(compressed[5], 0x5D);
+
+    let decompress = |lc, lp, pb| {
+        let options = lzma_rs::decompress::Options::builder()
+            .force_props(lc, lp, pb)
+            .build()
+            .unwrap();
+        let mut decomp: Vec<u8> = Vec::new();
+        lzma_rs::lzma2_decompress_with_options(&mut compressed.as_slice(), &mut decomp, &options)
+            .map(|()| decomp)
+    };
+    // Forcing the properties of the stream changes nothing, forcing other
+    // ones changes how literals are decoded.
+    assert_eq!(decompress(3, 0, 2).unwrap(), x);
+    assert!(decompress(0, 0, 2).map_or(true, |decomp| decomp != x));
+
+    let options = lzma_rs::decompress::Options {
+        force_props: Some((5, 0, 0)),
+        ..Default::default()
+    };
+    let mut decomp: Vec<u8> = Vec::new();
+    let err =
+        lzma_rs::lzma2_decompress_with_options(&mut compressed.as_slice(), &mut decomp, &options)
+            .unwrap_err();
+    assert_eq!(
+        err.to_string(),
+        "lzma error: LZMA2 invalid properties: lc + lp (5 + 0) must be <= 4"
+    );
+}
+
+#[test]
+fn packed_size_mismatch() {
+    #[cfg(feature = "enable_logging")]
+    let _ = env_logger::try_init();
+    let mut compressed: Vec<u8> = Vec::new();
+    let x = vec![b'a'; 1000];
+    lzma_rs::lzma2_compress(&mut x.as_slice(), &mut compressed).unwrap();
+    assert_eq!(compressed[0], 0xE0);
+    let packed_size = u16::from_be_bytes([compressed[3], compressed[4]]);
+
+    // Declare one more byte than the chunk really holds.
+    let mut longer = compressed.clone();
+    longer[3..5].copy_from_slice(&(packed_size + 1).to_be_bytes());
+    longer.insert(compressed.len() - 1, 0);
+    let mut decomp: Vec<u8> = Vec::new();
+    let err = lzma_rs::lzma2_decompress(&mut longer.as_slice(), &mut decomp).unwrap_err();
+    assert_eq!(err.to_string(), "lzma error: LZMA2 packed size mismatch");
+
+    // Declare one byte less than the chunk really holds.
+    let mut shorter = compressed;
+    shorter[3..5].copy_from_slice(&(packed_size - 1).to_be_bytes());
+    let mut decomp: Vec<u8> = Vec::new();
+    let err = lzma_rs::lzma2_decompress(&mut shorter.as_slice(), &mut decomp).unwrap_err();
+    assert_eq!(err.to_string(), "lzma error: LZMA2 packed size mismatch");
+}
+
+#[test]
+fn truncated_before_end_marker() {
+    #[cfg(feature = "enable_logging")]
+    let _ = env_logger::try_init();
+    let mut compressed: Vec<u8> = Vec::new();
+    let x = vec![b'a'; 1000];
+    lzma_rs::lzma2_compress(&mut x.as_slice(), &mut compressed).unwrap();
+    assert_eq!(compressed.pop(), Some(0));
+    let mut decomp: Vec<u8> = Vec::new();
+    let err = lzma_rs::lzma2_decompress(&mut compressed.as_slice(), &mut decomp).unwrap_err();
+    assert!(
+        matches!(err, lzma_rs::error::Error::Truncated(_)),
+        "unexpected error: {}",
+        err
+    );
+}
+
+#[test]
+fn truncated_chunk() {
+    #[cfg(feature = "enable_logging")]
+    let _ = env_logger::try_init();
+    let mut compressed: Vec<u8> = Vec::new();
+    let x = b"Hello world\n".repeat(0x1800);
+    lzma_rs::lzma2_compress(&mut x.as_slice(), &mut compressed).unwrap();
+    let chunks = chunk_headers(&compressed);
+    assert_eq!(chunks.len(), 2);
+    assert_eq!(chunks[1].0, 0x80);
+    let last_chunk = 6 + u16::from_be_bytes([compressed[3], compressed[4]]) as usize + 1;
+
+    // Cut the last chunk within the range coder start, and later within its
+    // data.
+    for &cut in &[last_chunk + 5 + 2, compressed.len() - 10] {
+        let mut decomp: Vec<u8> = Vec::new();
+        let err = lzma_rs::lzma2_decompress(&mut &compressed[..cut], &mut decomp).unwrap_err();
+        match err {
+            lzma_rs::error::Error::Truncated(e) => assert!(e.contains("chunk 1"), "{}", e),
+            err => panic!("unexpected error: {}", err),
+        }
+    }
+}
+
+#[test]
+fn truncated_stored_chunk() {
+    #[cfg(feature = "enable_logging")]
+    let _ = env_logger::try_init();
+    let options = lzma_rs::compress::Options {
+        store: true,
+        ..Default::default()
+    };
+    let mut compressed: Vec<u8> = Vec::new();
+    let x = b"Hello world\n".repeat(0x1800);
+    lzma_rs::lzma2_compress_with_options(&mut x.as_slice(), &mut compressed, &options).unwrap();
+    let chunks = chunk_headers(&compressed);
+    assert_eq!(chunks.len(), 2);
+    assert_eq!(chunks[1].0, 0x02);
+    let last_chunk = 3 + 0x1_0000;
+
+    // Cut the last chunk within its size, and later within its data.
+    for &cut in &[last_chunk + 2, compressed.len() - 10] {
+        let mut decomp: Vec<u8> = Vec::new();
+        let err = lzma_rs::lzma2_decompress(&mut &compressed[..cut], &mut decomp).unwrap_err();
+        match err {
+            lzma_rs::error::Error::Truncated(e) => assert!(e.contains("chunk 1"), "{}", e),
+            err => panic!("unexpected error: {}", err),
+        }
+    }
+}
+
+#[test]
+fn decompress_buffer_capacities() {
+    #[cfg(feature = "enable_logging")]
+    let _ = env_logger::try_init();
+    // Chunks are bounded with `io::Take` over the caller's reader, so the
+    // buffering of that reader must not change how chunk boundaries are found.
+    let mut x = read_all_file("tests/files/foo.txt").unwrap();
+    x.extend(vec![0x42; 0x30000]);
+    let mut compressed: Vec<u8> = Vec::new();
+    lzma_rs::lzma2_compress(&mut x.as_slice(), &mut compressed).unwrap();
+    for capacity in [1, 2, 7, 64, 4096, 0x20000] {
+        let mut bf = std::io::BufReader::with_capacity(capacity, compressed.as_slice());
+        let mut decomp: Vec<u8> = Vec::new();
+        lzma_rs::lzma2_decompress(&mut bf, &mut decomp).unwrap();
+        assert_eq!(decomp, x, "capacity {}", capacity);
+    }
+}
+
+/// Reader exposing all of its remaining data at once from `fill_buf`, which
+/// panics if more is consumed than was exposed.
+struct ExposeAllReader<'a> {
+    data: &'a [u8],
+    pos: usize,
+    /// Number of bytes exposed by the last `fill_buf` and not consumed yet
+    exposed: usize,
+}
+
+impl std::io::Read for ExposeAllReader<'_> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let len = std::io::Read::read(&mut &self.data[self.pos..], buf)?;
+        self.pos += len;
+        self.exposed = 0;
+        Ok(len)
+    }
+}
+
+impl std::io::BufRead for ExposeAllReader<'_> {
+    fn fill_buf(&mut self) -> std::io::Result<&[u8]> {
+        self.exposed = self.data.len() - self.pos;
+        Ok(&self.data[self.pos..])
+    }
+
+    fn consume(&mut self, amt: usize) {
+        assert!(amt <= self.exposed, "consumed {} of {}", amt, self.exposed);
+        self.exposed -= amt;
+        self.pos += amt;
+    }
+}
+
+#[test]
+fn chunks_consume_exactly() {
+    #[cfg(feature = "enable_logging")]
+    let _ = env_logger::try_init();
+    // LZMA chunks followed by an uncompressed chunk and the end marker, with
+    // more data after the stream, all exposed at once to the decoder.
+    let x = read_all_file("tests/files/foo.txt").unwrap();
+    let mut compressed: Vec<u8> = Vec::new();
+    lzma_rs::lzma2_compress(&mut x.as_slice(), &mut compressed).unwrap();
+    assert_eq!(compressed.pop(), Some(0));
+    compressed.extend_from_slice(b"\x02\x00\x0bHello world\n\x00");
+    let stream_len = compressed.len();
+    compressed.extend(vec![0x42; 0x20000]);
+
+    let mut input = ExposeAllReader {
+        data: &compressed,
+        pos: 0,
+        exposed: 0,
+    };
+    let mut decomp: Vec<u8> = Vec::new();
+    lzma_rs::lzma2_decompress(&mut input, &mut decomp).unwrap();
+    assert_eq!(decomp, [&x[..], b"Hello world\n"].concat());
+    assert_eq!(input.pos, stream_len);
+}
+
+#[cfg(feature = "raw_decoder")]
+#[test]
+fn raw_decoder_to_vec() {
+    #[cfg(feature = "enable_logging")]
+    let _ = env_logger::try_init();
+    let x = read_all_file("tests/files/foo.txt").unwrap();
+    let mut compressed: Vec<u8> = Vec::new();
+    lzma_rs::lzma2_compress(&mut x.as_slice(), &mut compressed).unwrap();
+
+    // The decoded data is appended after the existing contents.
+    let mut decomp = b"prefix".to_vec();
+    lzma_rs::decompress::raw::Lzma2Decoder::new()
+        .decompress_to_vec(&mut compressed.as_slice(), &mut decomp)
+        .unwrap();
+    assert_eq!(&decomp[..6], b"prefix");
+    assert_eq!(&decomp[6..], x.as_slice());
+}
+
+#[test]
+fn store() {
+    #[cfg(feature = "enable_logging")]
+    let _ = env_logger::try_init();
+    let x = b"Hello world\n".repeat(0x4000);
+    let options = lzma_rs::compress::Options {
+        store: true,
+        ..Default::default()
+    };
+    let mut compressed: Vec<u8> = Vec::new();
+    let stats =
+        lzma_rs::lzma2_compress_with_options(&mut x.as_slice(), &mut compressed, &options).unwrap();
+    assert_eq!(stats.chunks, 3);
+    assert_eq!(compressed.len(), x.len() + 3 * 3 + 1);
+
+    // Only the first chunk resets the dictionary.
+    let controls: Vec<u8> = chunk_headers(&compressed)
+        .into_iter()
+        .map(|(control, _)| control)
+        .collect();
+    assert_eq!(controls, [1, 2, 2]);
+    let mut decomp: Vec<u8> = Vec::new();
+    lzma_rs::lzma2_decompress(&mut compressed.as_slice(), &mut decomp).unwrap();
+    assert_eq!(decomp, x);
+}
+
+#[test]
+fn stored_chunk_size() {
+    #[cfg(feature = "enable_logging")]
+    let _ = env_logger::try_init();
+    // An incompressible span of more than 64 KiB is split into several
+    // uncompressed chunks, whose size is stored on 16 bits.
+    let mut x = Vec::new();
+    let mut state = 0x2545_f491_4f6c_dd1du64;
+    for _ in 0..0x28000 {
+        state ^= state << 13;
+        state ^= state >> 7;
+        state ^= state << 17;
+        x.push(state as u8);
+    }
+    let mut compressed: Vec<u8> = Vec::new();
+    let stats = lzma_rs::lzma2_compress(&mut x.as_slice(), &mut compressed).unwrap();
+    assert_eq!(stats.chunks, 3);
+    assert_eq!(
+        chunk_headers(&compressed),
+        [(1, 0x10000), (2, 0x10000), (2, 0x8000)]
+    );
+    let mut decomp: Vec<u8> = Vec::new();
+    lzma_rs::lzma2_decompress(&mut compressed.as_slice(), &mut decomp).unwrap();
+    assert_eq!(decomp, x);
+}
+
+#[test]
+fn chunk_boundaries() {
+    #[cfg(feature = "enable_logging")]
+    let _ = env_logger::try_init();
+    let x: Vec<u8> = (0..0x280000u32).map(|i| (i % 251) as u8).collect();
+    for &boundary in &[0x100000u64, 100_000] {
+        let options = lzma_rs::compress::Options {
+            chunk_boundary_bytes: Some(boundary),
+            ..Default::default()
+        };
+        let mut compressed: Vec<u8> = Vec::new();
+        lzma_rs::lzma2_compress_with_options(&mut x.as_slice(), &mut compressed, &options).unwrap();
+
+        let chunks = chunk_headers(&compressed);
+        let mut offsets = Vec::new();
+        let mut offset = 0;
+        for (i, &(control, unpacked_size)) in chunks.iter().enumerate() {
+            // The boundaries don't reset the dictionary.
+            if i > 0 {
+                assert!(control != 1 && control < 0xE0, "chunk {}", i);
+            }
+            assert!(unpacked_size <= 0x10000);
+            offsets.push(offset as u64);
+            offset += unpacked_size;
+        }
+        assert_eq!(offset, x.len());
+        let mut boundary_offset = 0;
+        while boundary_offset < x.len() as u64 {
+            assert!(offsets.contains(&boundary_offset), "{}", boundary_offset);
+            boundary_offset += boundary;
+        }
+
+        let mut decomp: Vec<u8> = Vec::new();
+        lzma_rs::lzma2_decompress(&mut compressed.as_slice(), &mut decomp).unwrap();
+        assert_eq!(decomp, x);
+    }
+
+    let options = lzma_rs::compress::Options {
+        chunk_boundary_bytes: Some(0),
+        ..Default::default()
+    };
+    let mut compressed: Vec<u8> = Vec::new();
+    let err = lzma_rs::lzma2_compress_with_options(&mut x.as_slice(), &mut compressed, &options)
+        .unwrap_err();
+    assert_eq!(err.kind(), std::io::ErrorKind::InvalidInput);
+}
+
+#[test]
+fn pad_to() {
+    #[cfg(feature = "enable_logging")]
+    let _ = env_logger::try_init();
+    let x = read_all_file("tests/files/foo.txt").unwrap();
+    let options = lzma_rs::compress::Options {
+        pad_to: Some(1000),
+        ..Default::default()
+    };
+    let mut compressed: Vec<u8> = Vec::new();
+    let stats =
+        lzma_rs::lzma2_compress_with_options(&mut x.as_slice(), &mut compressed, &options).unwrap();
+    assert_eq!(compressed.len() % 1000, 0);
+    assert_eq!(stats.output_size, compressed.len() as u64);
+
+    // Decoding stops at the end-of-stream marker, leaving the padding.
+    let mut input = compressed.as_slice();
+    let mut decomp: Vec<u8> = Vec::new();
+    lzma_rs::lzma2_decompress(&mut input, &mut decomp).unwrap();
+    assert_eq!(decomp, x);
+    assert!(!input.is_empty());
+    assert!(input.iter().all(|&byte| byte == 0));
+}
+
+#[test]
+fn compress_chunks() {
+    #[cfg(feature = "enable_logging")]
+    let _ = env_logger::try_init();
+    let x = b"Hello world\n".repeat(0x2000);
+    let options = lzma_rs::compress::Options::default();
+    let mut expected: Vec<u8> = Vec::new();
+    lzma_rs::lzma2_compress(&mut x.as_slice(), &mut expected).unwrap();
+
+    for &chunk_size in &[1, 1000, 0x10000, 0x10001] {
+        let mut compressed: Vec<u8> = Vec::new();
+        lzma_rs::lzma2_compress_chunks(x.chunks(chunk_size), &mut compressed, &options).unwrap();
+        assert_eq!(compressed, expected, "{}", chunk_size);
+    }
+    let mut compressed: Vec<u8> = Vec::new();
+    lzma_rs::lzma2_compress_chunks(std::iter::empty::<&[u8]>(), &mut compressed, &options).unwrap();
+    assert_eq!(compressed, [0]);
+}
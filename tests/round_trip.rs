@@ -0,0 +1,201 @@
+//! Round trips of generated inputs through every encoder and decoder, across
+//! the encoding options.
+
+#[cfg(feature = "xz")]
+use std::io::Read;
+
+/// Deterministic xorshift generator, so that failures can be reproduced.
+struct Rng(u64);
+
+impl Rng {
+    fn next(&mut self) -> u64 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        self.0
+    }
+
+    fn below(&mut self, n: u64) -> u64 {
+        self.next() % n
+    }
+}
+
+/// Lengths around the edges of LZMA2 chunks and of the encoder buffers.
+const LENGTHS: &[usize] = &[0, 1, 2, 3, 255, 4096, 0xFFFF, 0x10000, 0x10001, 0x20007];
+
+/// Generate an input of kind `kind` and length `len`.
+fn generate(rng: &mut Rng, kind: usize, len: usize) -> Vec<u8> {
+    match kind {
+        // All zeros
+        0 => vec![0; len],
+        // Random bytes
+        1 => (0..len).map(|_| rng.next() as u8).collect(),
+        // Long runs of random bytes
+        2 => {
+            let mut x = Vec::with_capacity(len);
+            while x.len() < len {
+                let byte = rng.next() as u8;
+                let run = 1 + rng.below(1000) as usize;
+                x.extend(std::iter::repeat(byte).take(std::cmp::min(run, len - x.len())));
+            }
+            x
+        }
+        // Text-like bytes from a small alphabet, with repeated words
+        _ => {
+            let words: Vec<Vec<u8>> = (0..16)
+                .map(|_| {
+                    (0..1 + rng.below(8))
+                        .map(|_| b'a' + rng.below(26) as u8)
+                        .collect()
+                })
+                .collect();
+            let mut x = Vec::with_capacity(len);
+            while x.len() < len {
+                x.extend_from_slice(&words[rng.below(16) as usize]);
+                x.push(b' ');
+            }
+            x.truncate(len);
+            x
+        }
+    }
+}
+
+/// Run `check` on every generated input.
+fn for_each_input<F>(max_len: usize, mut check: F)
+where
+    F: FnMut(&[u8]),
+{
+    let mut rng = Rng(0x2545_f491_4f6c_dd1d);
+    for &len in LENGTHS.iter().filter(|&&len| len <= max_len) {
+        for kind in 0..4 {
+            let x = generate(&mut rng, kind, len);
+            check(&x);
+        }
+    }
+}
+
+#[test]
+fn round_trip_lzma() {
+    #[cfg(feature = "enable_logging")]
+    let _ = env_logger::try_init();
+    use lzma_rs::compress::UnpackedSize as C;
+    use lzma_rs::decompress::UnpackedSize as D;
+    for_each_input(usize::MAX, |x| {
+        let len = Some(x.len() as u64);
+        for (encode, decode) in [
+            (C::WriteToHeader(None), D::ReadFromHeader),
+            (C::WriteToHeader(len), D::ReadFromHeader),
+            (C::WriteToHeader(None), D::ReadHeaderButUseProvided(len)),
+            (C::SkipWritingToHeader, D::UseProvided(len)),
+        ] {
+            let options = lzma_rs::compress::Options {
+                unpacked_size: encode,
+                ..Default::default()
+            };
+            let mut compressed: Vec<u8> = Vec::new();
+            let stats = lzma_rs::lzma_compress_with_options(&mut &x[..], &mut compressed, &options)
+                .unwrap();
+            assert_eq!(stats.output_size, compressed.len() as u64);
+
+            let options = lzma_rs::decompress::Options {
+                unpacked_size: decode,
+                ..Default::default()
+            };
+            let mut decomp: Vec<u8> = Vec::new();
+            lzma_rs::lzma_decompress_with_options(
+                &mut compressed.as_slice(),
+                &mut decomp,
+                &options,
+            )
+            .unwrap();
+            assert_eq!(decomp, x, "{:?} {:?}", encode, decode);
+
+            #[cfg(feature = "stream")]
+            if let D::ReadFromHeader = decode {
+                use std::io::Write;
+                let mut stream = lzma_rs::decompress::Stream::new(Vec::new());
+                stream.write_all(&compressed).unwrap();
+                assert_eq!(stream.finish().unwrap(), x);
+            }
+        }
+    });
+}
+
+#[test]
+fn round_trip_lzma2() {
+    #[cfg(feature = "enable_logging")]
+    let _ = env_logger::try_init();
+    for_each_input(usize::MAX, |x| {
+        let mut compressed: Vec<u8> = Vec::new();
+        let stats = lzma_rs::lzma2_compress(&mut &x[..], &mut compressed).unwrap();
+        assert_eq!(stats.output_size, compressed.len() as u64);
+
+        let mut decomp: Vec<u8> = Vec::new();
+        lzma_rs::lzma2_decompress(&mut compressed.as_slice(), &mut decomp).unwrap();
+        assert_eq!(decomp, x);
+
+        #[cfg(feature = "raw_decoder")]
+        {
+            let mut decomp: Vec<u8> = Vec::new();
+            lzma_rs::decompress::raw::Lzma2Decoder::new()
+                .decompress_to_vec(&mut compressed.as_slice(), &mut decomp)
+                .unwrap();
+            assert_eq!(decomp, x);
+        }
+    });
+}
+
+#[cfg(feature = "xz")]
+#[test]
+fn round_trip_xz() {
+    #[cfg(feature = "enable_logging")]
+    let _ = env_logger::try_init();
+    use lzma_rs::compress::CheckMethod;
+    for_each_input(0x10001, |x| {
+        for check in [CheckMethod::None, CheckMethod::Crc32, CheckMethod::Crc64] {
+            for block_size in [None, Some(1), Some(1000)] {
+                if block_size == Some(1) && x.len() > 4096 {
+                    continue;
+                }
+                let options = lzma_rs::compress::Options {
+                    check,
+                    block_size,
+                    ..Default::default()
+                };
+                let mut compressed: Vec<u8> = Vec::new();
+                let stats =
+                    lzma_rs::xz_compress_with_options(&mut &x[..], &mut compressed, &options)
+                        .unwrap();
+                assert_eq!(stats.output_size, compressed.len() as u64);
+
+                let mut decomp: Vec<u8> = Vec::new();
+                lzma_rs::xz_decompress(&mut compressed.as_slice(), &mut decomp).unwrap();
+                assert_eq!(decomp, x, "{:?} {:?}", check, block_size);
+
+                let mut reader =
+                    lzma_rs::decompress::XzSeekReader::new(std::io::Cursor::new(&compressed))
+                        .unwrap();
+                let mut decomp: Vec<u8> = Vec::new();
+                reader.read_to_end(&mut decomp).unwrap();
+                assert_eq!(decomp, x, "{:?} {:?}", check, block_size);
+
+                #[cfg(feature = "stream")]
+                {
+                    use std::io::Write;
+                    let mut decoder = lzma_rs::decompress::XzWriteDecoder::new(Vec::new());
+                    for chunk in compressed.chunks(1000) {
+                        decoder.write_all(chunk).unwrap();
+                    }
+                    assert_eq!(decoder.finish().unwrap(), x);
+                }
+            }
+        }
+    });
+}
+
+#[test]
+fn self_test() {
+    #[cfg(feature = "enable_logging")]
+    let _ = env_logger::try_init();
+    lzma_rs::self_test().unwrap();
+}
@@ -0,0 +1,49 @@
+//! Compile-time checks that the public types can be sent and shared across
+//! threads, as long as their type parameters can.
+
+#[cfg(feature = "xz")]
+use std::io::Cursor;
+
+fn assert_send_sync<T: Send + Sync>() {}
+
+#[test]
+fn decoders() {
+    assert_send_sync::<lzma_rs::decompress::LzmaReader<&[u8]>>();
+    assert_send_sync::<lzma_rs::decompress::LimitedWriter<Vec<u8>>>();
+    assert_send_sync::<lzma_rs::decompress::AlignedWriter<Vec<u8>>>();
+    assert_send_sync::<lzma_rs::decompress::FrameWriter<fn(&[u8]) -> std::io::Result<()>>>();
+    assert_send_sync::<lzma_rs::decompress::ChainedReader>();
+    assert_send_sync::<lzma_rs::decompress::ProgressReader<&[u8], fn(lzma_rs::decompress::Progress)>>(
+    );
+    #[cfg(feature = "stream")]
+    assert_send_sync::<lzma_rs::decompress::Stream<Vec<u8>>>();
+    #[cfg(feature = "xz")]
+    assert_send_sync::<lzma_rs::decompress::XzSeekReader<Cursor<Vec<u8>>>>();
+    #[cfg(all(feature = "stream", feature = "xz"))]
+    assert_send_sync::<lzma_rs::decompress::XzWriteDecoder<Vec<u8>>>();
+    #[cfg(feature = "raw_decoder")]
+    {
+        assert_send_sync::<lzma_rs::decompress::raw::LzmaDecoder>();
+        assert_send_sync::<lzma_rs::decompress::raw::Lzma2Decoder>();
+        assert_send_sync::<lzma_rs::decompress::raw::RawLzmaDecoder>();
+    }
+}
+
+#[test]
+fn values() {
+    assert_send_sync::<lzma_rs::error::Error>();
+    assert_send_sync::<lzma_rs::decompress::Options>();
+    assert_send_sync::<lzma_rs::decompress::LzmaStatus>();
+    assert_send_sync::<lzma_rs::decompress::Lzma2Status>();
+    assert_send_sync::<lzma_rs::decompress::ResetEvent>();
+    assert_send_sync::<lzma_rs::decompress::Lzma2Gap>();
+    assert_send_sync::<lzma_rs::decompress::Format>();
+    assert_send_sync::<lzma_rs::compress::Options>();
+    assert_send_sync::<lzma_rs::compress::CompressStats>();
+    #[cfg(feature = "xz")]
+    {
+        assert_send_sync::<lzma_rs::decompress::XzStatus>();
+        assert_send_sync::<lzma_rs::decompress::VerifyReport>();
+        assert_send_sync::<lzma_rs::decompress::XzListing>();
+    }
+}
@@ -1,5 +1,9 @@
+#![cfg(feature = "xz")]
+
 #[cfg(feature = "enable_logging")]
 use log::{debug, info};
+#[cfg(feature = "stream")]
+use std::io::Write;
 use std::io::{BufReader, Cursor, Read};
 
 /// Utility function to read a file into memory
@@ -11,7 +15,10 @@ fn read_all_file(filename: &str) -> std::io::Result<Vec<u8>> {
 
 fn round_trip(x: &[u8]) {
     let mut compressed: Vec<u8> = Vec::new();
-    lzma_rs::xz_compress(&mut std::io::BufReader::new(x), &mut compressed).unwrap();
+    let stats = lzma_rs::xz_compress(&mut std::io::BufReader::new(x), &mut compressed).unwrap();
+    assert_eq!(stats.input_size, x.len() as u64);
+    assert_eq!(stats.output_size, compressed.len() as u64);
+    assert_eq!(stats.blocks, 1);
     #[cfg(feature = "enable_logging")]
     info!("Compressed {} -> {} bytes", x.len(), compressed.len());
     #[cfg(feature = "enable_logging")]
@@ -80,6 +87,10 @@ fn big_file() {
         "tests/files/good-1-lzma2-4.xz",
         "tests/files/good-1-lzma2-4",
     );
+    decomp_big_file(
+        "tests/files/records-pb4-lp2.xz",
+        "tests/files/records-pb4-lp2",
+    );
 }
 
 #[test]
@@ -144,3 +155,2393 @@ fn test_xz_block_check_crc32_invalid() {
         "xz error: Invalid footer CRC32: expected 0x01234567 but got 0x8b0d303e"
     )
 }
+
+fn round_trip_with_check(x: &[u8], check: lzma_rs::compress::CheckMethod) -> Vec<u8> {
+    let options = lzma_rs::compress::Options {
+        check,
+        ..Default::default()
+    };
+    let mut compressed: Vec<u8> = Vec::new();
+    let stats = lzma_rs::xz_compress_with_options(&mut &x[..], &mut compressed, &options).unwrap();
+    assert_eq!(stats.output_size, compressed.len() as u64);
+    // Check ID in the stream flags of the header and footer
+    assert_eq!(compressed[7], check as u8);
+    assert_eq!(compressed[compressed.len() - 3], check as u8);
+    let mut decomp: Vec<u8> = Vec::new();
+    lzma_rs::xz_decompress(&mut compressed.as_slice(), &mut decomp).unwrap();
+    assert_eq!(decomp, x);
+    compressed
+}
+
+#[test]
+fn round_trip_checks() {
+    #[cfg(feature = "enable_logging")]
+    let _ = env_logger::try_init();
+    use lzma_rs::compress::CheckMethod;
+    let x = read_all_file("tests/files/foo.txt").unwrap();
+    let none = round_trip_with_check(&x, CheckMethod::None);
+    let crc32 = round_trip_with_check(&x, CheckMethod::Crc32);
+    let crc64 = round_trip_with_check(&x, CheckMethod::Crc64);
+    // Only the block check differs, the index and footer stay 4-byte aligned.
+    assert_eq!(crc32.len(), none.len() + 4);
+    assert_eq!(crc64.len(), none.len() + 8);
+    round_trip_with_check(b"", CheckMethod::Crc32);
+}
+
+#[test]
+fn unsupported_sha256_check() {
+    let options = lzma_rs::compress::Options {
+        check: lzma_rs::compress::CheckMethod::Sha256,
+        ..Default::default()
+    };
+    let mut compressed: Vec<u8> = Vec::new();
+    let err =
+        lzma_rs::xz_compress_with_options(&mut &b"foo"[..], &mut compressed, &options).unwrap_err();
+    assert_eq!(err.kind(), std::io::ErrorKind::InvalidInput);
+    assert!(compressed.is_empty());
+}
+
+#[test]
+fn output_limit() {
+    #[cfg(feature = "enable_logging")]
+    let _ = env_logger::try_init();
+    let data = read_all_file("tests/files/foo.txt").unwrap();
+    let mut compressed: Vec<u8> = Vec::new();
+    lzma_rs::xz_compress(&mut data.as_slice(), &mut compressed).unwrap();
+
+    let mut output = lzma_rs::decompress::LimitedWriter::new(Vec::new(), 10);
+    let error = lzma_rs::xz_decompress(&mut compressed.as_slice(), &mut output).unwrap_err();
+    assert!(
+        matches!(error, lzma_rs::error::Error::OutputTooLarge(10)),
+        "{}",
+        error
+    );
+}
+
+#[test]
+fn round_trip_multi_block() {
+    #[cfg(feature = "enable_logging")]
+    let _ = env_logger::try_init();
+    let x = read_all_file("tests/files/foo.txt").unwrap();
+    for (block_size, check) in [
+        (1000, lzma_rs::compress::CheckMethod::None),
+        (1000, lzma_rs::compress::CheckMethod::Crc64),
+        (x.len() as u64 / 2, lzma_rs::compress::CheckMethod::Crc32),
+    ] {
+        let options = lzma_rs::compress::Options {
+            check,
+            block_size: Some(block_size),
+            ..Default::default()
+        };
+        let mut compressed: Vec<u8> = Vec::new();
+        let stats = lzma_rs::xz_compress_with_options(&mut x.as_slice(), &mut compressed, &options)
+            .unwrap();
+        let expected_blocks = (x.len() as u64 + block_size - 1) / block_size;
+        assert_eq!(stats.blocks, expected_blocks);
+        assert_eq!(stats.output_size, compressed.len() as u64);
+        // The decoder checks each block against its index record.
+        let mut decomp: Vec<u8> = Vec::new();
+        lzma_rs::xz_decompress(&mut compressed.as_slice(), &mut decomp).unwrap();
+        assert_eq!(decomp, x);
+    }
+}
+
+#[test]
+fn salvage_truncated() {
+    #[cfg(feature = "enable_logging")]
+    let _ = env_logger::try_init();
+    let x = read_all_file("tests/files/foo.txt").unwrap();
+    let encode_options = lzma_rs::compress::Options {
+        block_size: Some(50_000),
+        check: lzma_rs::compress::CheckMethod::Crc32,
+        ..Default::default()
+    };
+    let mut compressed: Vec<u8> = Vec::new();
+    let stats =
+        lzma_rs::xz_compress_with_options(&mut x.as_slice(), &mut compressed, &encode_options)
+            .unwrap();
+    assert_eq!(stats.blocks, 4);
+    let salvage = lzma_rs::decompress::Options {
+        salvage: true,
+        ..Default::default()
+    };
+
+    // A complete stream isn't reported as truncated.
+    let mut decomp: Vec<u8> = Vec::new();
+    let status =
+        lzma_rs::xz_decompress_with_options(&mut compressed.as_slice(), &mut decomp, &salvage)
+            .unwrap();
+    assert_eq!(
+        status,
+        lzma_rs::decompress::XzStatus {
+            truncated: false,
+            blocks: 4,
+            warnings: Vec::new(),
+        }
+    );
+    assert_eq!(decomp, x);
+
+    // Cut the stream in the middle of the third block.
+    let truncated = &compressed[..compressed.len() * 5 / 8];
+    let mut decomp: Vec<u8> = Vec::new();
+    lzma_rs::xz_decompress(&mut &truncated[..], &mut decomp).unwrap_err();
+
+    let mut decomp: Vec<u8> = Vec::new();
+    let status =
+        lzma_rs::xz_decompress_with_options(&mut &truncated[..], &mut decomp, &salvage).unwrap();
+    assert_eq!(
+        status,
+        lzma_rs::decompress::XzStatus {
+            truncated: true,
+            blocks: 2,
+            warnings: Vec::new(),
+        }
+    );
+    assert_eq!(decomp, &x[..100_000]);
+}
+
+#[test]
+fn salvage_only_input_errors() {
+    #[cfg(feature = "enable_logging")]
+    let _ = env_logger::try_init();
+
+    /// Sink failing once it holds `capacity` bytes, like a full disk
+    struct FullSink {
+        data: Vec<u8>,
+        capacity: usize,
+    }
+
+    impl std::io::Write for FullSink {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            if self.data.len() + buf.len() > self.capacity {
+                // The same kind as input running out, which is salvaged.
+                return Err(std::io::Error::new(
+                    std::io::ErrorKind::UnexpectedEof,
+                    "disk full",
+                ));
+            }
+            self.data.extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    let x = read_all_file("tests/files/foo.txt").unwrap();
+    let encode_options = lzma_rs::compress::Options {
+        block_size: Some(50_000),
+        ..Default::default()
+    };
+    let mut compressed: Vec<u8> = Vec::new();
+    lzma_rs::xz_compress_with_options(&mut x.as_slice(), &mut compressed, &encode_options).unwrap();
+    let salvage = lzma_rs::decompress::Options {
+        salvage: true,
+        ..Default::default()
+    };
+
+    // Failing to write the output isn't reported as a truncated stream.
+    let mut sink = FullSink {
+        data: Vec::new(),
+        capacity: 120_000,
+    };
+    let err = lzma_rs::xz_decompress_with_options(&mut compressed.as_slice(), &mut sink, &salvage)
+        .unwrap_err();
+    assert!(
+        matches!(&err, lzma_rs::error::Error::IoError(e) if e.to_string() == "disk full"),
+        "{}",
+        err
+    );
+    assert_eq!(sink.data, &x[..100_000]);
+
+    // Neither are the limits set by the caller.
+    let deadline = lzma_rs::decompress::Options {
+        deadline: Some(std::time::Instant::now()),
+        ..salvage
+    };
+    let mut decomp: Vec<u8> = Vec::new();
+    let err =
+        lzma_rs::xz_decompress_with_options(&mut compressed.as_slice(), &mut decomp, &deadline)
+            .unwrap_err();
+    assert!(matches!(err, lzma_rs::error::Error::TimedOut), "{}", err);
+}
+
+#[test]
+fn block_flush_interval() {
+    #[cfg(feature = "enable_logging")]
+    let _ = env_logger::try_init();
+
+    /// Sink recording the length of the output at each flush
+    #[derive(Default)]
+    struct FlushRecorder {
+        data: Vec<u8>,
+        flushes: Vec<usize>,
+    }
+
+    impl std::io::Write for FlushRecorder {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.data.extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            self.flushes.push(self.data.len());
+            Ok(())
+        }
+    }
+
+    let x = read_all_file("tests/files/foo.txt").unwrap();
+    let salvage = lzma_rs::decompress::Options {
+        salvage: true,
+        ..Default::default()
+    };
+    for threads in [1, 2] {
+        let encode_options = lzma_rs::compress::Options {
+            block_flush_interval: Some(50_000),
+            check: lzma_rs::compress::CheckMethod::Crc64,
+            threads,
+            ..Default::default()
+        };
+        let mut recorder = FlushRecorder::default();
+        let stats =
+            lzma_rs::xz_compress_with_options(&mut x.as_slice(), &mut recorder, &encode_options)
+                .unwrap();
+        assert_eq!(stats.blocks, 4);
+        // The output is flushed after each block and at the end.
+        assert_eq!(recorder.flushes.len(), 5, "{:?}", recorder.flushes);
+        assert_eq!(recorder.flushes[4], recorder.data.len());
+        let mut decomp: Vec<u8> = Vec::new();
+        lzma_rs::xz_decompress(&mut recorder.data.as_slice(), &mut decomp).unwrap();
+        assert_eq!(decomp, x);
+
+        // A crash after a flush leaves the complete blocks recoverable.
+        for (blocks, &len) in recorder.flushes.iter().enumerate().take(3) {
+            let truncated = &recorder.data[..len];
+            let mut decomp: Vec<u8> = Vec::new();
+            let status =
+                lzma_rs::xz_decompress_with_options(&mut &truncated[..], &mut decomp, &salvage)
+                    .unwrap();
+            assert!(status.truncated);
+            assert_eq!(status.blocks, blocks as u64 + 1);
+            assert_eq!(decomp, &x[..50_000 * (blocks + 1)]);
+        }
+    }
+
+    // Blocks end at the smaller of the block size and the flush interval.
+    let encode_options = lzma_rs::compress::Options {
+        block_flush_interval: Some(50_000),
+        block_size: Some(20_000),
+        ..Default::default()
+    };
+    let mut recorder = FlushRecorder::default();
+    let stats =
+        lzma_rs::xz_compress_with_options(&mut x.as_slice(), &mut recorder, &encode_options)
+            .unwrap();
+    assert_eq!(stats.blocks, 9);
+    assert_eq!(recorder.flushes.len(), 10);
+
+    // Without an interval, the output is never flushed.
+    let encode_options = lzma_rs::compress::Options {
+        block_size: Some(20_000),
+        ..Default::default()
+    };
+    let mut recorder = FlushRecorder::default();
+    lzma_rs::xz_compress_with_options(&mut x.as_slice(), &mut recorder, &encode_options).unwrap();
+    assert!(recorder.flushes.is_empty());
+
+    let encode_options = lzma_rs::compress::Options {
+        block_flush_interval: Some(0),
+        ..Default::default()
+    };
+    assert!(
+        lzma_rs::xz_compress_with_options(&mut x.as_slice(), &mut Vec::new(), &encode_options)
+            .is_err()
+    );
+}
+
+#[test]
+fn invalid_dict_size_property() {
+    #[cfg(feature = "enable_logging")]
+    let _ = env_logger::try_init();
+    let mut compressed: Vec<u8> = Vec::new();
+    lzma_rs::xz_compress(&mut &b"Hello world"[..], &mut compressed).unwrap();
+    // The block header follows the 12-byte stream header: size, flags, filter
+    // id, size of properties and the dictionary size property.
+    assert_eq!(&compressed[12..17], &[0x02, 0x00, 0x21, 0x01, 22]);
+    compressed[16] = 41;
+    let crc32 = crc::Crc::<u32>::new(&crc::CRC_32_ISO_HDLC).checksum(&compressed[12..20]);
+    compressed[20..24].copy_from_slice(&crc32.to_le_bytes());
+
+    let mut decomp: Vec<u8> = Vec::new();
+    let err = lzma_rs::xz_decompress(&mut compressed.as_slice(), &mut decomp).unwrap_err();
+    assert_eq!(
+        err.to_string(),
+        "lzma error: LZMA2 invalid dictionary size property 41, must be <= 40"
+    );
+}
+
+#[test]
+fn lenient_warnings() {
+    #[cfg(feature = "enable_logging")]
+    let _ = env_logger::try_init();
+    let mut compressed: Vec<u8> = Vec::new();
+    lzma_rs::xz_compress(&mut &b"Hello world"[..], &mut compressed).unwrap();
+    // Set a reserved bit of the block flags and a byte of the block header
+    // padding, keeping the header CRC32 valid.
+    assert_eq!(&compressed[12..20], &[0x02, 0x00, 0x21, 0x01, 22, 0, 0, 0]);
+    compressed[13] |= 0x04;
+    compressed[18] = 1;
+    let crc32 = crc::Crc::<u32>::new(&crc::CRC_32_ISO_HDLC).checksum(&compressed[12..20]);
+    compressed[20..24].copy_from_slice(&crc32.to_le_bytes());
+    // There is no block check by default, so the block padding directly
+    // follows the unpadded size of the block, as found in the 8-byte index.
+    let len = compressed.len();
+    let unpadded_size = compressed[len - 12 - 8 + 2] as usize;
+    let padding_offset = 12 + unpadded_size;
+    assert_eq!(compressed[padding_offset], 0);
+    compressed[padding_offset] = 1;
+
+    let mut decomp: Vec<u8> = Vec::new();
+    let err = lzma_rs::xz_decompress(&mut compressed.as_slice(), &mut decomp).unwrap_err();
+    assert_eq!(
+        err.to_string(),
+        "xz error: Invalid block flags 4, reserved bits (mask 0x3C) must be zero"
+    );
+
+    let options = lzma_rs::decompress::Options {
+        lenient: true,
+        ..Default::default()
+    };
+    let mut decomp: Vec<u8> = Vec::new();
+    let status =
+        lzma_rs::xz_decompress_with_options(&mut compressed.as_slice(), &mut decomp, &options)
+            .unwrap();
+    assert_eq!(decomp, b"Hello world");
+    use lzma_rs::decompress::{Warning, WarningKind};
+    assert_eq!(
+        status.warnings,
+        vec![
+            Warning {
+                kind: WarningKind::ReservedBlockFlags,
+                offset: 13
+            },
+            Warning {
+                kind: WarningKind::BlockHeaderPadding,
+                offset: 18
+            },
+            Warning {
+                kind: WarningKind::BlockPadding,
+                offset: padding_offset as u64
+            },
+        ]
+    );
+}
+
+#[test]
+fn unsupported_filter() {
+    #[cfg(feature = "enable_logging")]
+    let _ = env_logger::try_init();
+    let mut compressed: Vec<u8> = Vec::new();
+    lzma_rs::xz_compress(&mut &b"Hello world"[..], &mut compressed).unwrap();
+    // Declare a delta filter before LZMA2, in place of the block header
+    // padding. The data is still valid, but the crate can't decode it.
+    assert_eq!(&compressed[12..20], &[0x02, 0x00, 0x21, 0x01, 22, 0, 0, 0]);
+    compressed[12..20].copy_from_slice(&[0x02, 0x01, 0x03, 0x01, 0x00, 0x21, 0x01, 22]);
+    let crc32 = crc::Crc::<u32>::new(&crc::CRC_32_ISO_HDLC).checksum(&compressed[12..20]);
+    compressed[20..24].copy_from_slice(&crc32.to_le_bytes());
+
+    let mut decomp: Vec<u8> = Vec::new();
+    let err = lzma_rs::xz_decompress(&mut compressed.as_slice(), &mut decomp).unwrap_err();
+    assert!(
+        matches!(&err, lzma_rs::error::Error::Unsupported { what } if what == "Delta filter (ID 0x3)"),
+        "{}",
+        err
+    );
+
+    // A filter ID reserved for future versions of the format is refused too,
+    // even in lenient mode, as the data would decode to garbage without it.
+    compressed[12..20].copy_from_slice(&[0x02, 0x01, 0x0C, 0x00, 0x21, 0x01, 22, 0x00]);
+    let crc32 = crc::Crc::<u32>::new(&crc::CRC_32_ISO_HDLC).checksum(&compressed[12..20]);
+    compressed[20..24].copy_from_slice(&crc32.to_le_bytes());
+    let options = lzma_rs::decompress::Options {
+        lenient: true,
+        ..Default::default()
+    };
+    let err =
+        lzma_rs::xz_decompress_with_options(&mut compressed.as_slice(), &mut decomp, &options)
+            .unwrap_err();
+    assert!(
+        matches!(&err, lzma_rs::error::Error::Unsupported { what } if what == "reserved filter (ID 0xc)"),
+        "{}",
+        err
+    );
+}
+
+#[test]
+fn declared_unpacked_size_mismatch() {
+    #[cfg(feature = "enable_logging")]
+    let _ = env_logger::try_init();
+    let mut compressed: Vec<u8> = Vec::new();
+    lzma_rs::xz_compress(&mut &b"Hello world"[..], &mut compressed).unwrap();
+    // Declare the uncompressed size in the block header, in place of its
+    // padding so that the header keeps its size.
+    assert_eq!(&compressed[12..20], &[0x02, 0x00, 0x21, 0x01, 22, 0, 0, 0]);
+    let with_declared_size = |size: u8| {
+        let mut compressed = compressed.clone();
+        compressed[12..20].copy_from_slice(&[0x02, 0x80, size, 0x21, 0x01, 22, 0, 0]);
+        let crc32 = crc::Crc::<u32>::new(&crc::CRC_32_ISO_HDLC).checksum(&compressed[12..20]);
+        compressed[20..24].copy_from_slice(&crc32.to_le_bytes());
+        compressed
+    };
+
+    let mut decomp: Vec<u8> = Vec::new();
+    lzma_rs::xz_decompress(&mut with_declared_size(11).as_slice(), &mut decomp).unwrap();
+    assert_eq!(decomp, b"Hello world");
+
+    for (size, actual) in [(5, 11), (20, 11)] {
+        let mut decomp: Vec<u8> = Vec::new();
+        let err = lzma_rs::xz_decompress(&mut with_declared_size(size).as_slice(), &mut decomp)
+            .unwrap_err();
+        assert!(
+            matches!(
+                err,
+                lzma_rs::error::Error::SizeMismatch { expected, actual: a }
+                    if expected == size as u64 && a == actual
+            ),
+            "{}",
+            err
+        );
+        assert!(decomp.is_empty());
+    }
+}
+
+#[test]
+fn non_canonical_vli() {
+    #[cfg(feature = "enable_logging")]
+    let _ = env_logger::try_init();
+    let mut compressed: Vec<u8> = Vec::new();
+    lzma_rs::xz_compress(&mut &b"Hello world"[..], &mut compressed).unwrap();
+    // Declare the uncompressed size in the block header with two bytes
+    // instead of one, in place of its padding.
+    assert_eq!(&compressed[12..20], &[0x02, 0x00, 0x21, 0x01, 22, 0, 0, 0]);
+    compressed[12..20].copy_from_slice(&[0x02, 0x80, 0x8B, 0x00, 0x21, 0x01, 22, 0]);
+    let crc32 = crc::Crc::<u32>::new(&crc::CRC_32_ISO_HDLC).checksum(&compressed[12..20]);
+    compressed[20..24].copy_from_slice(&crc32.to_le_bytes());
+
+    let mut decomp: Vec<u8> = Vec::new();
+    let err = lzma_rs::xz_decompress(&mut compressed.as_slice(), &mut decomp).unwrap_err();
+    assert!(
+        matches!(err, lzma_rs::error::Error::NonCanonicalVli),
+        "{}",
+        err
+    );
+
+    let options = lzma_rs::decompress::Options {
+        lenient: true,
+        ..Default::default()
+    };
+    let mut decomp: Vec<u8> = Vec::new();
+    let status =
+        lzma_rs::xz_decompress_with_options(&mut compressed.as_slice(), &mut decomp, &options)
+            .unwrap();
+    assert_eq!(decomp, b"Hello world");
+    assert_eq!(
+        status.warnings,
+        vec![lzma_rs::decompress::Warning {
+            kind: lzma_rs::decompress::WarningKind::NonCanonicalVli,
+            offset: 14
+        }]
+    );
+}
+
+#[test]
+fn compress_threads() {
+    #[cfg(feature = "enable_logging")]
+    let _ = env_logger::try_init();
+    let x = read_all_file("tests/files/foo.txt").unwrap().repeat(4);
+    let compress = |threads| {
+        let options = lzma_rs::compress::Options {
+            block_size: Some(50_000),
+            check: lzma_rs::compress::CheckMethod::Crc64,
+            threads,
+            ..Default::default()
+        };
+        let mut compressed: Vec<u8> = Vec::new();
+        let stats = lzma_rs::xz_compress_with_options(&mut x.as_slice(), &mut compressed, &options)
+            .unwrap();
+        assert_eq!(stats.input_size, x.len() as u64);
+        assert_eq!(stats.output_size, compressed.len() as u64);
+        (compressed, stats.blocks)
+    };
+
+    let (expected, blocks) = compress(1);
+    let expected_blocks = (x.len() as u64 + 49_999) / 50_000;
+    assert_eq!(blocks, expected_blocks);
+    // The blocks are written in order, whatever the number of threads.
+    for threads in [2, 3, 8] {
+        let (compressed, blocks) = compress(threads);
+        assert_eq!(blocks, expected_blocks);
+        assert_eq!(compressed, expected, "{} threads", threads);
+    }
+
+    let (compressed, _) = compress(4);
+    let mut decomp: Vec<u8> = Vec::new();
+    lzma_rs::xz_decompress(&mut compressed.as_slice(), &mut decomp).unwrap();
+    assert_eq!(decomp, x);
+    assert_eq!(lzma::decompress(&compressed).unwrap(), x);
+    let listing = lzma_rs::xz_list(&mut Cursor::new(&compressed)).unwrap();
+    assert_eq!(listing.block_count() as u64, expected_blocks);
+
+    // An empty input still makes one empty block.
+    let options = lzma_rs::compress::Options {
+        threads: 4,
+        ..Default::default()
+    };
+    let mut compressed: Vec<u8> = Vec::new();
+    let stats =
+        lzma_rs::xz_compress_with_options(&mut &b""[..], &mut compressed, &options).unwrap();
+    assert_eq!(stats.blocks, 1);
+    let mut single: Vec<u8> = Vec::new();
+    lzma_rs::xz_compress(&mut &b""[..], &mut single).unwrap();
+    assert_eq!(compressed, single);
+
+    let options = lzma_rs::compress::Options {
+        threads: 0,
+        ..Default::default()
+    };
+    let err = lzma_rs::xz_compress_with_options(&mut x.as_slice(), &mut Vec::new(), &options)
+        .unwrap_err();
+    assert_eq!(err.kind(), std::io::ErrorKind::InvalidInput);
+}
+
+#[test]
+fn store_block_sizes() {
+    #[cfg(feature = "enable_logging")]
+    let _ = env_logger::try_init();
+    let x = read_all_file("tests/files/foo.txt").unwrap();
+    let options = lzma_rs::compress::Options {
+        block_size: Some(50_000),
+        check: lzma_rs::compress::CheckMethod::Crc64,
+        store_block_sizes: true,
+        ..Default::default()
+    };
+    let mut compressed: Vec<u8> = Vec::new();
+    let stats =
+        lzma_rs::xz_compress_with_options(&mut x.as_slice(), &mut compressed, &options).unwrap();
+    assert_eq!(stats.blocks, 4);
+    assert_eq!(stats.output_size, compressed.len() as u64);
+    let mut decomp: Vec<u8> = Vec::new();
+    lzma_rs::xz_decompress(&mut compressed.as_slice(), &mut decomp).unwrap();
+    assert_eq!(decomp, x);
+    // So does liblzma.
+    assert_eq!(lzma::decompress(&compressed).unwrap(), x);
+
+    // The header of a single block declares both sizes, each one as a
+    // single byte in place of the padding.
+    let options = lzma_rs::compress::Options {
+        store_block_sizes: true,
+        ..Default::default()
+    };
+    let mut compressed: Vec<u8> = Vec::new();
+    lzma_rs::xz_compress_with_options(&mut &b"Hello world"[..], &mut compressed, &options).unwrap();
+    let packed_size = compressed[14];
+    assert_eq!(
+        &compressed[12..20],
+        &[0x02, 0xC0, packed_size, 11, 0x21, 0x01, 22, 0]
+    );
+    let crc32 = crc::Crc::<u32>::new(&crc::CRC_32_ISO_HDLC);
+    assert_eq!(
+        &compressed[20..24],
+        &crc32.checksum(&compressed[12..20]).to_le_bytes()
+    );
+
+    // The decoder checks the declared sizes.
+    compressed[15] = 12;
+    let header_crc32 = crc32.checksum(&compressed[12..20]);
+    compressed[20..24].copy_from_slice(&header_crc32.to_le_bytes());
+    let mut decomp: Vec<u8> = Vec::new();
+    let err = lzma_rs::xz_decompress(&mut compressed.as_slice(), &mut decomp).unwrap_err();
+    assert!(
+        matches!(
+            err,
+            lzma_rs::error::Error::SizeMismatch {
+                expected: 12,
+                actual: 11
+            }
+        ),
+        "{}",
+        err
+    );
+}
+
+#[test]
+fn decompress_file() {
+    #[cfg(feature = "enable_logging")]
+    let _ = env_logger::try_init();
+    let expected = read_all_file("tests/files/foo.txt").unwrap();
+    for path in ["tests/files/foo.txt.xz", "tests/files/foo.txt.lzma"] {
+        assert_eq!(
+            lzma_rs::decompress_file(path).unwrap(),
+            expected,
+            "{}",
+            path
+        );
+        let mut decomp: Vec<u8> = Vec::new();
+        lzma_rs::decompress_file_to(path, &mut decomp).unwrap();
+        assert_eq!(decomp, expected, "{}", path);
+    }
+
+    let err = lzma_rs::decompress_file("tests/files/missing.xz").unwrap_err();
+    assert!(
+        matches!(&err, lzma_rs::error::Error::IoError(e) if e.kind() == std::io::ErrorKind::NotFound),
+        "{}",
+        err
+    );
+}
+
+#[test]
+fn progress_reader() {
+    #[cfg(feature = "enable_logging")]
+    let _ = env_logger::try_init();
+    let expected = read_all_file("tests/files/foo.txt").unwrap();
+    let compressed = read_all_file("tests/files/foo.txt.xz").unwrap();
+    let mut fractions = Vec::new();
+    let mut input = lzma_rs::decompress::ProgressReader::new(
+        compressed.as_slice(),
+        Some(compressed.len() as u64),
+        |progress| fractions.push(progress.fraction.unwrap()),
+    )
+    .with_interval(4096);
+    let mut decomp: Vec<u8> = Vec::new();
+    lzma_rs::xz_decompress(&mut input, &mut decomp).unwrap();
+    assert_eq!(input.consumed(), compressed.len() as u64);
+    assert_eq!(decomp, expected);
+
+    assert!(fractions.len() > compressed.len() / 4096);
+    assert!(fractions.windows(2).all(|w| w[0] < w[1]));
+    assert_eq!(fractions.last(), Some(&1.0));
+}
+
+#[test]
+fn estimate_decompressed_size() {
+    #[cfg(feature = "enable_logging")]
+    let _ = env_logger::try_init();
+    let estimate = |compressed: &[u8]| {
+        let mut input = Cursor::new(compressed);
+        let size = lzma_rs::estimate_decompressed_size(&mut input).unwrap();
+        assert_eq!(input.position(), 0);
+        size
+    };
+    for (compressed, expected) in [
+        ("tests/files/foo.txt.xz", "tests/files/foo.txt"),
+        ("tests/files/empty.txt.xz", "tests/files/empty.txt"),
+        (
+            "tests/files/range-coder-edge-case.lzma",
+            "tests/files/range-coder-edge-case",
+        ),
+    ] {
+        let compressed = read_all_file(compressed).unwrap();
+        let expected = read_all_file(expected).unwrap();
+        assert_eq!(estimate(&compressed), Some(expected.len() as u64));
+    }
+
+    // The sizes of concatenated streams add up.
+    let mut compressed = read_all_file("tests/files/foo.txt.xz").unwrap();
+    compressed.extend(read_all_file("tests/files/hello.txt.xz").unwrap());
+    let expected = read_all_file("tests/files/foo.txt").unwrap().len()
+        + read_all_file("tests/files/hello.txt").unwrap().len();
+    assert_eq!(estimate(&compressed), Some(expected as u64));
+
+    // The size of streamed `.lzma` data is unknown.
+    let compressed = read_all_file("tests/files/foo.txt.lzma").unwrap();
+    assert_eq!(estimate(&compressed), None);
+}
+
+/// Code-like data, made of a few instruction patterns and of calls to a few
+/// functions, whose relative offsets differ at each call site.
+fn x86_like_code(len: usize) -> Vec<u8> {
+    let patterns: [&[u8]; 6] = [
+        b"\x55",
+        b"\x48\x89\xE5",
+        b"\x48\x83\xEC\x20",
+        b"\x5D",
+        b"\xC3",
+        b"\x8B\x45\xFC",
+    ];
+    let mut state = 0x2545_f491u32;
+    let mut code = Vec::new();
+    while code.len() < len {
+        state ^= state << 13;
+        state ^= state >> 17;
+        state ^= state << 5;
+        if state % 3 == 0 {
+            let target = ((state >> 8) % 64) * 0x400;
+            let rel = target.wrapping_sub(code.len() as u32 + 5);
+            code.push(0xE8);
+            code.extend_from_slice(&rel.to_le_bytes());
+        } else {
+            code.extend_from_slice(patterns[(state >> 8) as usize % patterns.len()]);
+        }
+    }
+    code.truncate(len);
+    code
+}
+
+#[test]
+fn bcj_x86() {
+    #[cfg(feature = "enable_logging")]
+    let _ = env_logger::try_init();
+    let x = x86_like_code(0x10000);
+    let compress = |bcj| {
+        let options = lzma_rs::compress::Options {
+            check: lzma_rs::compress::CheckMethod::Crc64,
+            store_block_sizes: true,
+            bcj,
+            ..Default::default()
+        };
+        let mut compressed: Vec<u8> = Vec::new();
+        lzma_rs::xz_compress_with_options(&mut x.as_slice(), &mut compressed, &options).unwrap();
+        compressed
+    };
+    let compressed = compress(Some(lzma_rs::compress::BcjArch::X86));
+    let plain = compress(None);
+    #[cfg(feature = "enable_logging")]
+    info!(
+        "Compressed {} -> {} bytes with BCJ, {} bytes without",
+        x.len(),
+        compressed.len(),
+        plain.len()
+    );
+    assert!(compressed.len() < plain.len());
+
+    let mut decomp: Vec<u8> = Vec::new();
+    lzma_rs::xz_decompress(&mut compressed.as_slice(), &mut decomp).unwrap();
+    assert_eq!(decomp, x);
+    // Consistency with liblzma.
+    assert_eq!(lzma::decompress(&compressed).unwrap(), x);
+    #[cfg(feature = "stream")]
+    assert_eq!(write_decompress(&compressed).unwrap(), x);
+}
+
+#[test]
+fn store() {
+    #[cfg(feature = "enable_logging")]
+    let _ = env_logger::try_init();
+    let x = read_all_file("tests/files/foo.txt").unwrap();
+    let options = lzma_rs::compress::Options {
+        check: lzma_rs::compress::CheckMethod::Crc64,
+        store: true,
+        ..Default::default()
+    };
+    let mut compressed: Vec<u8> = Vec::new();
+    let stats =
+        lzma_rs::xz_compress_with_options(&mut x.as_slice(), &mut compressed, &options).unwrap();
+    assert_eq!(stats.output_size, compressed.len() as u64);
+    // Each chunk adds 3 bytes, and the stream header, block header, block
+    // check, index and footer 64 bytes at most.
+    let expansion = compressed.len() as u64 - x.len() as u64;
+    assert!(expansion <= 3 * stats.chunks + 64, "{}", expansion);
+    assert!(expansion >= 3 * stats.chunks, "{}", expansion);
+
+    let mut decomp: Vec<u8> = Vec::new();
+    lzma_rs::xz_decompress(&mut compressed.as_slice(), &mut decomp).unwrap();
+    assert_eq!(decomp, x);
+    // So does liblzma.
+    assert_eq!(lzma::decompress(&compressed).unwrap(), x);
+
+    // The block check still covers the stored data.
+    let pos = compressed.len() / 2;
+    compressed[pos] ^= 1;
+    let mut decomp: Vec<u8> = Vec::new();
+    lzma_rs::xz_decompress(&mut compressed.as_slice(), &mut decomp).unwrap_err();
+}
+
+#[test]
+fn auto_store() {
+    #[cfg(feature = "enable_logging")]
+    let _ = env_logger::try_init();
+    let text = read_all_file("tests/files/foo.txt").unwrap();
+    let mut state = 0x2545_f491u32;
+    let noise: Vec<u8> = (0..0x30000)
+        .map(|_| {
+            state ^= state << 13;
+            state ^= state >> 17;
+            state ^= state << 5;
+            state as u8
+        })
+        .collect();
+    let mixed = [&text[..], &noise, &text].concat();
+    let compress = |x: &[u8], auto_store| {
+        let options = lzma_rs::compress::Options {
+            check: lzma_rs::compress::CheckMethod::Crc64,
+            auto_store,
+            ..Default::default()
+        };
+        let mut compressed: Vec<u8> = Vec::new();
+        lzma_rs::xz_compress_with_options(&mut &x[..], &mut compressed, &options).unwrap();
+        compressed
+    };
+
+    // Skipping the LZMA attempt on incompressible chunks gives the same
+    // output, and compressible ones are still compressed.
+    for x in [&text, &noise, &mixed] {
+        let compressed = compress(x, true);
+        assert_eq!(compressed, compress(x, false));
+        let mut decomp: Vec<u8> = Vec::new();
+        lzma_rs::xz_decompress(&mut compressed.as_slice(), &mut decomp).unwrap();
+        assert_eq!(&decomp, x);
+    }
+    assert!(compress(&text, true).len() < text.len());
+}
+
+#[test]
+fn decompress_block() {
+    #[cfg(feature = "enable_logging")]
+    let _ = env_logger::try_init();
+    let x = read_all_file("tests/files/foo.txt").unwrap();
+    let options = lzma_rs::compress::Options {
+        block_size: Some(50_000),
+        check: lzma_rs::compress::CheckMethod::Crc32,
+        ..Default::default()
+    };
+    // Two streams, so that the blocks are numbered across them.
+    let mut compressed: Vec<u8> = Vec::new();
+    lzma_rs::xz_compress_with_options(&mut x.as_slice(), &mut compressed, &options).unwrap();
+    lzma_rs::xz_compress_with_options(&mut &b"Hello world"[..], &mut compressed, &options).unwrap();
+    let mut expected = x.clone();
+    expected.extend_from_slice(b"Hello world");
+
+    let listing = lzma_rs::xz_list(&mut Cursor::new(&compressed)).unwrap();
+    assert_eq!(listing.block_count(), 5);
+    let blocks: Vec<_> = listing
+        .streams
+        .iter()
+        .flat_map(|stream| stream.blocks.iter().copied())
+        .collect();
+    // In reverse order, to show that the blocks are independent.
+    for (i, block) in blocks.iter().enumerate().rev() {
+        let mut decomp: Vec<u8> = Vec::new();
+        lzma_rs::xz_decompress_block(&mut Cursor::new(&compressed), &listing, i, &mut decomp)
+            .unwrap();
+        let start = block.uncompressed_offset as usize;
+        assert_eq!(
+            decomp,
+            &expected[start..start + decomp.len()],
+            "block {}",
+            i
+        );
+        assert_eq!(decomp.len() as u64, block.uncompressed_size, "block {}", i);
+    }
+
+    let mut decomp: Vec<u8> = Vec::new();
+    let err = lzma_rs::xz_decompress_block(&mut Cursor::new(&compressed), &listing, 5, &mut decomp)
+        .unwrap_err();
+    assert_eq!(
+        err.to_string(),
+        "xz error: Block 5 is out of range, the file has 5 blocks"
+    );
+
+    // A block whose first chunk doesn't reset the dictionary would depend on
+    // the previous one.
+    let offset = blocks[1].compressed_offset as usize;
+    let control = offset + (compressed[offset] as usize + 1) * 4;
+    assert_eq!(compressed[control] & 0xE0, 0xE0);
+    compressed[control] &= !0x20;
+    let mut decomp: Vec<u8> = Vec::new();
+    let err = lzma_rs::xz_decompress_block(&mut Cursor::new(&compressed), &listing, 1, &mut decomp)
+        .unwrap_err();
+    assert_eq!(
+        err.to_string(),
+        "lzma error: LZMA2 first chunk with status 192 doesn't reset the dictionary"
+    );
+    assert!(decomp.is_empty());
+}
+
+#[test]
+fn expected_size() {
+    #[cfg(feature = "enable_logging")]
+    let _ = env_logger::try_init();
+    let x = read_all_file("tests/files/foo.txt").unwrap();
+    let compressed = read_all_file("tests/files/foo.txt.xz").unwrap();
+    let decompress = |options: &lzma_rs::decompress::Options| {
+        let mut decomp: Vec<u8> = Vec::new();
+        lzma_rs::xz_decompress_with_options(&mut compressed.as_slice(), &mut decomp, options)
+            .map(|_| decomp)
+    };
+    let len = x.len() as u64;
+    let options = |expected_size| lzma_rs::decompress::Options {
+        expected_size: Some(expected_size),
+        ..Default::default()
+    };
+    assert_eq!(decompress(&options(len)).unwrap(), x);
+    for expected_size in [len - 1, len + 1] {
+        let err = decompress(&options(expected_size)).unwrap_err();
+        assert!(
+            matches!(err, lzma_rs::error::Error::SizeMismatch { expected, .. } if expected == expected_size),
+            "{}",
+            err
+        );
+    }
+
+    // The size isn't checked when decoding stops at the limit.
+    let limited = lzma_rs::decompress::Options {
+        limit: Some(100),
+        ..options(len)
+    };
+    assert_eq!(decompress(&limited).unwrap(), &x[..100]);
+}
+
+#[test]
+fn expected_digest() {
+    #[cfg(feature = "enable_logging")]
+    let _ = env_logger::try_init();
+    use lzma_rs::decompress::Digest;
+    let x = read_all_file("tests/files/foo.txt").unwrap();
+    let compressed = read_all_file("tests/files/foo.txt.xz").unwrap();
+    let decompress = |expected_digest| {
+        let options = lzma_rs::decompress::Options {
+            expected_digest: Some(expected_digest),
+            ..Default::default()
+        };
+        let mut decomp: Vec<u8> = Vec::new();
+        lzma_rs::xz_decompress_with_options(&mut compressed.as_slice(), &mut decomp, &options)
+            .map(|_| decomp)
+    };
+    let crc32 = crc::Crc::<u32>::new(&crc::CRC_32_ISO_HDLC).checksum(&x);
+    let crc64 = crc::Crc::<u64>::new(&crc::CRC_64_XZ).checksum(&x);
+    assert_eq!(decompress(Digest::Crc32(crc32)).unwrap(), x);
+    assert_eq!(decompress(Digest::Crc64(crc64)).unwrap(), x);
+
+    for expected in [Digest::Crc32(crc32 ^ 1), Digest::Crc64(crc64 ^ 1)] {
+        let err = decompress(expected).unwrap_err();
+        assert!(
+            matches!(err, lzma_rs::error::Error::ChecksumMismatch { expected: e, .. } if e == expected),
+            "{}",
+            err
+        );
+    }
+}
+
+/// Replace the index and footer of `compressed`, a stream of a single block
+/// without check, by an index declaring `num_records` records, each one with
+/// the given sizes.
+fn with_index(compressed: &[u8], num_records: u64, record: (u64, u64)) -> Vec<u8> {
+    fn push_vli(buf: &mut Vec<u8>, mut value: u64) {
+        while value >= 0x80 {
+            buf.push(value as u8 | 0x80);
+            value >>= 7;
+        }
+        buf.push(value as u8);
+    }
+    let crc32 = crc::Crc::<u32>::new(&crc::CRC_32_ISO_HDLC);
+
+    // The single index record is 4 bytes long, without padding.
+    let mut stream = compressed[..compressed.len() - 12 - 8].to_vec();
+    let mut index = vec![0];
+    push_vli(&mut index, num_records);
+    for _ in 0..std::cmp::min(num_records, 16) {
+        push_vli(&mut index, record.0);
+        push_vli(&mut index, record.1);
+    }
+    while index.len() % 4 != 0 {
+        index.push(0);
+    }
+    index.extend_from_slice(&crc32.checksum(&index).to_le_bytes());
+    stream.extend_from_slice(&index);
+
+    let mut footer = (index.len() as u32 / 4 - 1).to_le_bytes().to_vec();
+    footer.extend_from_slice(&compressed[6..8]);
+    stream.extend_from_slice(&crc32.checksum(&footer).to_le_bytes());
+    stream.extend_from_slice(&footer);
+    stream.extend_from_slice(b"YZ");
+    stream
+}
+
+#[test]
+fn index_too_large() {
+    #[cfg(feature = "enable_logging")]
+    let _ = env_logger::try_init();
+    let mut compressed: Vec<u8> = Vec::new();
+    lzma_rs::xz_compress(&mut &b"Hello world"[..], &mut compressed).unwrap();
+    let unpadded_size = compressed[compressed.len() - 12 - 8 + 2] as u64;
+    // The index is rebuilt identically from its single record.
+    assert_eq!(with_index(&compressed, 1, (unpadded_size, 11)), compressed);
+    let max_records = lzma_rs::decompress::Options {
+        max_index_records: Some(1000),
+        ..Default::default()
+    };
+
+    let absurd = with_index(&compressed, 1 << 40, (unpadded_size, 11));
+    let err = lzma_rs::decompress::XzSeekReader::new(std::io::Cursor::new(&absurd)).unwrap_err();
+    assert!(
+        matches!(err, lzma_rs::error::Error::IndexTooLarge(n) if n == 1 << 40),
+        "{}",
+        err
+    );
+    let mut decomp: Vec<u8> = Vec::new();
+    let err =
+        lzma_rs::xz_decompress_with_options(&mut absurd.as_slice(), &mut decomp, &max_records)
+            .unwrap_err();
+    assert!(
+        matches!(err, lzma_rs::error::Error::IndexTooLarge(n) if n == 1 << 40),
+        "{}",
+        err
+    );
+
+    // Record sizes must fit before the index.
+    let oversized = with_index(&compressed, 1, (1000, 11));
+    let err = lzma_rs::decompress::XzSeekReader::new(std::io::Cursor::new(&oversized)).unwrap_err();
+    assert_eq!(
+        err.to_string(),
+        "xz error: Invalid index for record 0: unpadded size (1000) exceeds the stream"
+    );
+
+    // The limit also applies to the number of blocks.
+    let no_records = lzma_rs::decompress::Options {
+        max_index_records: Some(0),
+        ..Default::default()
+    };
+    let mut decomp: Vec<u8> = Vec::new();
+    let err =
+        lzma_rs::xz_decompress_with_options(&mut compressed.as_slice(), &mut decomp, &no_records)
+            .unwrap_err();
+    assert!(
+        matches!(err, lzma_rs::error::Error::IndexTooLarge(1)),
+        "{}",
+        err
+    );
+    let err = lzma_rs::decompress::XzSeekReader::with_options(
+        std::io::Cursor::new(&compressed),
+        &no_records,
+    )
+    .unwrap_err();
+    assert!(
+        matches!(err, lzma_rs::error::Error::IndexTooLarge(1)),
+        "{}",
+        err
+    );
+}
+
+#[test]
+fn seek_reader_forged_index() {
+    #[cfg(feature = "enable_logging")]
+    let _ = env_logger::try_init();
+    let mut compressed: Vec<u8> = Vec::new();
+    lzma_rs::xz_compress(&mut &b"Hello world"[..], &mut compressed).unwrap();
+    let unpadded_size = compressed[compressed.len() - 12 - 8 + 2] as u64;
+    // The index declares an absurd uncompressed size for the single block.
+    let forged = with_index(&compressed, 1, (unpadded_size, 1 << 62));
+
+    let mut reader = lzma_rs::decompress::XzSeekReader::new(Cursor::new(&forged)).unwrap();
+    assert_eq!(reader.uncompressed_size(), 1 << 62);
+    let mut decomp: Vec<u8> = Vec::new();
+    let err = reader.read_to_end(&mut decomp).unwrap_err();
+    assert!(err.to_string().contains("allocation failed"), "{}", err);
+
+    // With a limit, only the allowed output is reserved, and the block is
+    // then checked against the index.
+    let limited = lzma_rs::decompress::Options {
+        max_block_output: Some(1 << 20),
+        ..Default::default()
+    };
+    let mut reader =
+        lzma_rs::decompress::XzSeekReader::with_options(Cursor::new(&forged), &limited).unwrap();
+    let err = reader.read_to_end(&mut decomp).unwrap_err();
+    assert!(
+        err.to_string().contains("Invalid index for record 0"),
+        "{}",
+        err
+    );
+}
+
+#[test]
+fn block_without_sizes() {
+    #[cfg(feature = "enable_logging")]
+    let _ = env_logger::try_init();
+    let mut compressed: Vec<u8> = Vec::new();
+    lzma_rs::xz_compress(&mut &b"Hello world"[..], &mut compressed).unwrap();
+    // The block flags declare neither the compressed nor the uncompressed
+    // size, so the block ends with the LZMA2 end marker.
+    assert_eq!(compressed[12 + 1], 0x00);
+    let unpadded_size = compressed[compressed.len() - 12 - 8 + 2] as u64;
+    let mut decomp: Vec<u8> = Vec::new();
+    lzma_rs::xz_decompress(&mut compressed.as_slice(), &mut decomp).unwrap();
+    assert_eq!(decomp, b"Hello world");
+
+    // The sizes found by decoding the block are checked against the index.
+    let decompress = |stream: &[u8]| {
+        let mut decomp: Vec<u8> = Vec::new();
+        lzma_rs::xz_decompress(&mut &stream[..], &mut decomp)
+            .unwrap_err()
+            .to_string()
+    };
+    assert_eq!(
+        decompress(&with_index(&compressed, 1, (unpadded_size, 12))),
+        "xz error: Invalid index for record 0: unpacked size (11) does not match index (12)"
+    );
+    assert_eq!(
+        decompress(&with_index(&compressed, 1, (unpadded_size - 1, 11))),
+        format!(
+            "xz error: Invalid index for record 0: unpadded size ({}) does not match index ({})",
+            unpadded_size,
+            unpadded_size - 1
+        )
+    );
+    // Counting the block padding in the unpadded size is an error too.
+    let padded = with_index(&compressed, 1, ((unpadded_size + 3) & !3, 11));
+    assert_eq!(
+        decompress(&padded),
+        format!(
+            "xz error: Invalid index for record 0: unpadded size ({}) does not match index ({})",
+            unpadded_size,
+            (unpadded_size + 3) & !3
+        )
+    );
+
+    #[cfg(feature = "stream")]
+    {
+        let mut decoder = lzma_rs::decompress::XzWriteDecoder::new(Vec::new());
+        let err = decoder.write_all(&padded).unwrap_err();
+        assert!(err.to_string().contains("unpadded size"), "{}", err);
+    }
+}
+
+#[test]
+fn compress_to_vec() {
+    #[cfg(feature = "enable_logging")]
+    let _ = env_logger::try_init();
+    let text = read_all_file("tests/files/foo.txt").unwrap();
+    // Incompressible data outgrows the reserved capacity.
+    let mut state = 0x2545_f491u32;
+    let noise: Vec<u8> = (0..0x10000)
+        .map(|_| {
+            state ^= state << 13;
+            state ^= state >> 17;
+            state ^= state << 5;
+            state as u8
+        })
+        .collect();
+    for x in [&b""[..], b"Hello world", &text, &noise] {
+        let compressed = lzma_rs::xz_compress_to_vec(x).unwrap();
+        let mut expected: Vec<u8> = Vec::new();
+        lzma_rs::xz_compress(&mut &x[..], &mut expected).unwrap();
+        assert_eq!(compressed, expected);
+        let mut decomp: Vec<u8> = Vec::new();
+        lzma_rs::xz_decompress(&mut compressed.as_slice(), &mut decomp).unwrap();
+        assert_eq!(decomp, x);
+    }
+}
+
+#[test]
+fn blocks_reset_the_dictionary() {
+    #[cfg(feature = "enable_logging")]
+    let _ = env_logger::try_init();
+    let x = b"Hello world, hello world".repeat(10);
+    for (store, status) in [(false, 0xC0), (true, 0x02)] {
+        let options = lzma_rs::compress::Options {
+            block_size: Some(120),
+            store,
+            ..Default::default()
+        };
+        let mut compressed: Vec<u8> = Vec::new();
+        lzma_rs::xz_compress_with_options(&mut x.as_slice(), &mut compressed, &options).unwrap();
+        let listing = lzma_rs::xz_list(&mut Cursor::new(&compressed)).unwrap();
+        assert_eq!(listing.block_count(), 2);
+
+        // The second block starts with a chunk continuing the dictionary of
+        // the first one, which it can't reference.
+        let offset = listing.streams[0].blocks[1].compressed_offset as usize;
+        let control = offset + (compressed[offset] as usize + 1) * 4;
+        assert_eq!(compressed[control], if store { 0x01 } else { 0xE0 });
+        compressed[control] = status;
+        let mut decomp: Vec<u8> = Vec::new();
+        let err = lzma_rs::xz_decompress(&mut compressed.as_slice(), &mut decomp).unwrap_err();
+        let message = format!(
+            "lzma error: LZMA2 first chunk with status {} doesn't reset the dictionary",
+            status
+        );
+        assert_eq!(err.to_string(), message);
+        assert_eq!(decomp, &x[..120]);
+
+        #[cfg(feature = "stream")]
+        {
+            let mut decoder = lzma_rs::decompress::XzWriteDecoder::new(Vec::new());
+            let err = decoder.write_all(&compressed).unwrap_err();
+            assert!(
+                err.to_string().contains("doesn't reset the dictionary"),
+                "{}",
+                err
+            );
+        }
+    }
+}
+
+#[test]
+fn index_indicator_after_block() {
+    #[cfg(feature = "enable_logging")]
+    let _ = env_logger::try_init();
+    let mut compressed: Vec<u8> = Vec::new();
+    lzma_rs::xz_compress(&mut &b"Hello world"[..], &mut compressed).unwrap();
+    // The block header size byte encodes (size / 4) - 1: 0x02 for the 12
+    // bytes of the header, including its CRC32.
+    assert_eq!(compressed[12], 0x02);
+    // The block (header, compressed data and padding) is followed by a null
+    // byte, which indicates the index rather than a block header.
+    let len = compressed.len();
+    let index_offset = len - 12 - 8;
+    let unpadded_size = compressed[index_offset + 2] as usize;
+    assert_eq!(index_offset, 12 + ((unpadded_size + 3) & !3));
+    assert_eq!(compressed[index_offset], 0x00);
+
+    let mut decomp: Vec<u8> = Vec::new();
+    let status = lzma_rs::xz_decompress_with_options(
+        &mut compressed.as_slice(),
+        &mut decomp,
+        &Default::default(),
+    )
+    .unwrap();
+    assert_eq!(decomp, b"Hello world");
+    assert_eq!(status.blocks, 1);
+
+    // Any other value starts a second block header, here of 4 bytes where
+    // the index records land in the flags and filter id fields.
+    compressed[index_offset] = 0x01;
+    let mut decomp: Vec<u8> = Vec::new();
+    let err = lzma_rs::xz_decompress(&mut compressed.as_slice(), &mut decomp).unwrap_err();
+    assert_eq!(
+        err.to_string(),
+        format!("unsupported: reserved filter (ID {:#x})", unpadded_size)
+    );
+}
+
+#[test]
+fn invalid_block_check() {
+    #[cfg(feature = "enable_logging")]
+    let _ = env_logger::try_init();
+    let x = read_all_file("tests/files/foo.txt").unwrap();
+    let options = lzma_rs::compress::Options {
+        check: lzma_rs::compress::CheckMethod::Crc64,
+        ..Default::default()
+    };
+    let mut compressed: Vec<u8> = Vec::new();
+    lzma_rs::xz_compress_with_options(&mut x.as_slice(), &mut compressed, &options).unwrap();
+
+    // The block check precedes the index, whose size is in the footer.
+    let len = compressed.len();
+    let backward_size = u32::from_le_bytes([
+        compressed[len - 8],
+        compressed[len - 7],
+        compressed[len - 6],
+        compressed[len - 5],
+    ]);
+    let index_size = (backward_size as usize + 1) * 4;
+    compressed[len - 12 - index_size - 8] ^= 1;
+
+    let mut decomp: Vec<u8> = Vec::new();
+    let err = lzma_rs::xz_decompress(&mut compressed.as_slice(), &mut decomp).unwrap_err();
+    assert!(
+        err.to_string().starts_with("xz error: Invalid block CRC64"),
+        "{}",
+        err
+    );
+}
+
+/// Decompress `compressed` with the push-model decoder, in writes of varying
+/// sizes.
+#[cfg(feature = "stream")]
+fn write_decompress(compressed: &[u8]) -> lzma_rs::error::Result<Vec<u8>> {
+    let mut decoder = lzma_rs::decompress::XzWriteDecoder::new(Vec::new());
+    let mut input = compressed;
+    let mut size = 1;
+    while !input.is_empty() {
+        let len = std::cmp::min(size, input.len());
+        decoder.write_all(&input[..len])?;
+        input = &input[len..];
+        size = size * 7 % 1000 + 1;
+    }
+    decoder.finish()
+}
+
+#[test]
+#[cfg(feature = "stream")]
+fn write_decoder() {
+    #[cfg(feature = "enable_logging")]
+    let _ = env_logger::try_init();
+    for (compfile, plainfile) in [
+        ("tests/files/empty.txt.xz", "tests/files/empty.txt"),
+        ("tests/files/foo.txt.xz", "tests/files/foo.txt"),
+        (
+            "tests/files/good-1-lzma2-1.xz",
+            "tests/files/good-1-lzma2-1",
+        ),
+        (
+            "tests/files/good-1-lzma2-2.xz",
+            "tests/files/good-1-lzma2-2",
+        ),
+        (
+            "tests/files/good-1-lzma2-3.xz",
+            "tests/files/good-1-lzma2-3",
+        ),
+        (
+            "tests/files/good-1-lzma2-4.xz",
+            "tests/files/good-1-lzma2-4",
+        ),
+        (
+            "tests/files/block-check-crc32.txt.xz",
+            "tests/files/block-check-crc32.txt",
+        ),
+    ] {
+        let compressed = read_all_file(compfile).unwrap();
+        let expected = read_all_file(plainfile).unwrap();
+        assert_eq!(
+            write_decompress(&compressed).unwrap(),
+            expected,
+            "{}",
+            compfile
+        );
+    }
+}
+
+#[test]
+#[cfg(feature = "stream")]
+fn write_decoder_multi_block() {
+    #[cfg(feature = "enable_logging")]
+    let _ = env_logger::try_init();
+    let x = read_all_file("tests/files/foo.txt").unwrap();
+    let options = lzma_rs::compress::Options {
+        check: lzma_rs::compress::CheckMethod::Crc64,
+        block_size: Some(1000),
+        ..Default::default()
+    };
+    let mut compressed: Vec<u8> = Vec::new();
+    lzma_rs::xz_compress_with_options(&mut x.as_slice(), &mut compressed, &options).unwrap();
+    assert_eq!(write_decompress(&compressed).unwrap(), x);
+
+    // Each complete block is written out as soon as it's decoded.
+    let mut decoder = lzma_rs::decompress::XzWriteDecoder::new(Vec::new());
+    decoder
+        .write_all(&compressed[..compressed.len() / 2])
+        .unwrap();
+    let partial = decoder.get_output().len();
+    assert!(partial > 0 && partial < x.len());
+    assert_eq!(decoder.get_output()[..], x[..partial]);
+    let err = decoder.finish().unwrap_err();
+    assert!(
+        matches!(err, lzma_rs::error::Error::Truncated(_)),
+        "{}",
+        err
+    );
+}
+
+#[test]
+#[cfg(feature = "stream")]
+fn write_decoder_streams_chunks() {
+    #[cfg(feature = "enable_logging")]
+    let _ = env_logger::try_init();
+    // Random 4 KiB pieces, often repeating one of the last 60 pieces, i.e.
+    // from within the dictionary of 256 KiB of preset 0.
+    let mut state = 0x2545_f491u32;
+    let mut next = || {
+        state ^= state << 13;
+        state ^= state >> 17;
+        state ^= state << 5;
+        state
+    };
+    let mut x: Vec<u8> = Vec::new();
+    while x.len() < 6 << 20 {
+        let pieces = x.len() / 4096;
+        if pieces > 0 && next() % 2 == 0 {
+            let piece = pieces - 1 - (next() as usize % std::cmp::min(pieces, 60));
+            x.extend_from_within(piece * 4096..(piece + 1) * 4096);
+        } else {
+            x.extend((0..4096).map(|_| next() as u8));
+        }
+    }
+    let compressed = lzma::compress(&x, 0).unwrap();
+    let listing = lzma_rs::xz_list(&mut Cursor::new(&compressed)).unwrap();
+    assert_eq!(listing.block_count(), 1);
+
+    // The output of each chunk reaches the sink as soon as it's decoded,
+    // long before the end of the block.
+    let mut decoder = lzma_rs::decompress::XzWriteDecoder::new(Vec::new());
+    let half = compressed.len() / 2;
+    for chunk in compressed[..half].chunks(10000) {
+        decoder.write_all(chunk).unwrap();
+    }
+    let partial = decoder.get_output().len();
+    assert!(partial >= 2 << 20, "{}", partial);
+    assert_eq!(decoder.get_output()[..], x[..partial]);
+
+    // Everything but the block check, the index and the footer.
+    let len = compressed.len();
+    let backward_size = u32::from_le_bytes([
+        compressed[len - 8],
+        compressed[len - 7],
+        compressed[len - 6],
+        compressed[len - 5],
+    ]);
+    let check = len - 12 - (backward_size as usize + 1) * 4 - 8;
+    decoder.write_all(&compressed[half..check]).unwrap();
+    assert_eq!(decoder.get_output().len(), x.len());
+    decoder.write_all(&compressed[check..]).unwrap();
+    assert_eq!(decoder.finish().unwrap(), x);
+
+    // A corrupt check is reported at the end of the block.
+    let mut corrupt = compressed.clone();
+    corrupt[check] ^= 1;
+    let err = write_decompress(&corrupt).unwrap_err();
+    assert!(err.to_string().contains("Invalid block CRC64"), "{}", err);
+}
+
+#[test]
+#[cfg(feature = "stream")]
+fn write_decoder_errors() {
+    #[cfg(feature = "enable_logging")]
+    let _ = env_logger::try_init();
+    let mut compressed = read_all_file("tests/files/foo.txt.xz").unwrap();
+
+    // Trailing data
+    let mut trailing = compressed.clone();
+    trailing.push(0);
+    assert!(write_decompress(&trailing).is_err());
+
+    // Corrupted block, after which further writes fail
+    let len = compressed.len();
+    compressed[len / 2] ^= 0xFF;
+    let mut decoder = lzma_rs::decompress::XzWriteDecoder::new(Vec::new());
+    assert!(decoder.write_all(&compressed).is_err());
+    assert!(decoder.write_all(b"").is_ok());
+    assert!(decoder.write(b"x").is_err());
+    assert!(decoder.finish().is_err());
+}
+
+#[test]
+fn seek_reader() {
+    #[cfg(feature = "enable_logging")]
+    let _ = env_logger::try_init();
+    let x = read_all_file("tests/files/foo.txt").unwrap();
+    let options = lzma_rs::compress::Options {
+        check: lzma_rs::compress::CheckMethod::Crc64,
+        block_size: Some(1000),
+        ..Default::default()
+    };
+    let mut compressed: Vec<u8> = Vec::new();
+    let stats =
+        lzma_rs::xz_compress_with_options(&mut x.as_slice(), &mut compressed, &options).unwrap();
+
+    let mut reader = lzma_rs::decompress::XzSeekReader::new(Cursor::new(compressed)).unwrap();
+    assert_eq!(reader.uncompressed_size(), x.len() as u64);
+    assert_eq!(reader.block_count() as u64, stats.blocks);
+
+    // Read a slice spanning several blocks, from the middle of a block.
+    let offset = x.len() / 3 + 7;
+    reader.seek_to_uncompressed(offset as u64).unwrap();
+    let mut slice = vec![0; 3000];
+    reader.read_exact(&mut slice).unwrap();
+    assert_eq!(slice, x[offset..offset + 3000]);
+
+    // Seek backwards, within the loaded block and to the start.
+    reader.seek_to_uncompressed(offset as u64 + 1).unwrap();
+    let mut slice = vec![0; 10];
+    reader.read_exact(&mut slice).unwrap();
+    assert_eq!(slice, x[offset + 1..offset + 11]);
+    reader.seek_to_uncompressed(0).unwrap();
+    let mut decomp = Vec::new();
+    reader.read_to_end(&mut decomp).unwrap();
+    assert_eq!(decomp, x);
+
+    // Reading to the end gives the same bytes after reads of any size.
+    for size in [1, 999, 1000, 4096] {
+        reader.seek_to_uncompressed(0).unwrap();
+        let mut decomp = vec![0; size];
+        reader.read_exact(&mut decomp).unwrap();
+        reader.read_to_end(&mut decomp).unwrap();
+        assert_eq!(decomp, x);
+        assert_eq!(reader.position(), x.len() as u64);
+    }
+    reader.seek_to_uncompressed(offset as u64).unwrap();
+    assert_eq!(reader.position(), offset as u64);
+    let mut decomp = Vec::new();
+    reader.read_to_end(&mut decomp).unwrap();
+    assert_eq!(decomp, x[offset..]);
+
+    // The end of the data is a valid offset, but not beyond.
+    reader.seek_to_uncompressed(x.len() as u64).unwrap();
+    assert_eq!(reader.read(&mut slice).unwrap(), 0);
+    assert!(reader.seek_to_uncompressed(x.len() as u64 + 1).is_err());
+}
+
+#[test]
+fn seek_reader_files() {
+    #[cfg(feature = "enable_logging")]
+    let _ = env_logger::try_init();
+    for (compfile, plainfile) in [
+        ("tests/files/empty.txt.xz", "tests/files/empty.txt"),
+        (
+            "tests/files/good-1-lzma2-1.xz",
+            "tests/files/good-1-lzma2-1",
+        ),
+        (
+            "tests/files/block-check-crc32.txt.xz",
+            "tests/files/block-check-crc32.txt",
+        ),
+    ] {
+        let compressed = std::fs::File::open(compfile).unwrap();
+        let expected = read_all_file(plainfile).unwrap();
+        let mut reader = lzma_rs::decompress::XzSeekReader::new(compressed).unwrap();
+        let mut decomp = Vec::new();
+        reader.read_to_end(&mut decomp).unwrap();
+        assert_eq!(decomp, expected, "{}", compfile);
+    }
+
+    // The index is checked against the blocks.
+    let mut compressed = read_all_file("tests/files/foo.txt.xz").unwrap();
+    let len = compressed.len();
+    compressed.truncate(len - 1);
+    assert!(lzma_rs::decompress::XzSeekReader::new(Cursor::new(compressed)).is_err());
+}
+
+#[test]
+fn damaged_magic_hint() {
+    #[cfg(feature = "enable_logging")]
+    let _ = env_logger::try_init();
+    let compressed = read_all_file("tests/files/hello.txt.xz").unwrap();
+    let decompress = |data: &[u8]| {
+        let mut decomp: Vec<u8> = Vec::new();
+        lzma_rs::xz_decompress(&mut &data[..], &mut decomp)
+            .unwrap_err()
+            .to_string()
+    };
+    let expected = "xz error: Invalid XZ magic, expected [253, 55, 122, 88, 90, 0]";
+
+    // Swapped in 16-bit and in 32-bit words
+    let swapped_16: Vec<u8> = compressed
+        .chunks(2)
+        .flat_map(|word| word.iter().rev().copied())
+        .collect();
+    let swapped_32: Vec<u8> = compressed
+        .chunks(4)
+        .flat_map(|word| word.iter().rev().copied())
+        .collect();
+    for swapped in [swapped_16, swapped_32] {
+        assert_eq!(
+            decompress(&swapped),
+            format!("{}; the input looks byte-swapped", expected)
+        );
+    }
+
+    // Transferred as Latin-1 text and re-encoded in UTF-8
+    let mut text = vec![0xC3, 0xBD];
+    text.extend_from_slice(&compressed[1..]);
+    assert_eq!(
+        decompress(&text),
+        format!(
+            "{}; the input looks re-encoded as text, was it transferred in text mode?",
+            expected
+        )
+    );
+
+    // No hint for unrelated data
+    assert_eq!(decompress(b"Hello world"), expected);
+}
+
+#[test]
+fn corrupt_stream_header() {
+    #[cfg(feature = "enable_logging")]
+    let _ = env_logger::try_init();
+    let compressed = read_all_file("tests/files/hello.txt.xz").unwrap();
+
+    // Magic
+    let mut corrupted = compressed.clone();
+    corrupted[1] ^= 1;
+    let mut decomp: Vec<u8> = Vec::new();
+    let err = lzma_rs::xz_decompress(&mut corrupted.as_slice(), &mut decomp).unwrap_err();
+    assert!(
+        err.to_string().starts_with("xz error: Invalid XZ magic"),
+        "{}",
+        err
+    );
+
+    // Both stream flags bytes are covered by the CRC32, which is checked
+    // before the flags are interpreted.
+    for i in [6, 7] {
+        let mut corrupted = compressed.clone();
+        corrupted[i] ^= 1;
+        let mut decomp: Vec<u8> = Vec::new();
+        let err = lzma_rs::xz_decompress(&mut corrupted.as_slice(), &mut decomp).unwrap_err();
+        assert!(
+            matches!(
+                err,
+                lzma_rs::error::Error::StreamHeaderCorrupt { expected, actual }
+                    if expected != actual
+            ),
+            "{}",
+            err
+        );
+        assert!(decomp.is_empty());
+    }
+}
+
+#[test]
+fn max_block_output() {
+    #[cfg(feature = "enable_logging")]
+    let _ = env_logger::try_init();
+    let x = read_all_file("tests/files/foo.txt").unwrap();
+    for store_block_sizes in [false, true] {
+        let options = lzma_rs::compress::Options {
+            block_size: Some(1000),
+            store_block_sizes,
+            ..Default::default()
+        };
+        let mut compressed: Vec<u8> = Vec::new();
+        lzma_rs::xz_compress_with_options(&mut x.as_slice(), &mut compressed, &options).unwrap();
+
+        let decompress = |max_block_output| {
+            let options = lzma_rs::decompress::Options {
+                max_block_output: Some(max_block_output),
+                ..Default::default()
+            };
+            let mut decomp: Vec<u8> = Vec::new();
+            lzma_rs::xz_decompress_with_options(&mut compressed.as_slice(), &mut decomp, &options)
+                .map(|_| decomp)
+        };
+        assert_eq!(decompress(1000).unwrap(), x);
+        // The first block is already too large, whether its header declares
+        // its size or not.
+        let err = decompress(999).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "xz error: Block at offset 12 exceeds the maximum output of 999 bytes"
+        );
+
+        // Stopping at a limit on the total output doesn't lift it.
+        let options = lzma_rs::decompress::Options {
+            max_block_output: Some(999),
+            limit: Some(10),
+            ..Default::default()
+        };
+        let mut decomp: Vec<u8> = Vec::new();
+        let err =
+            lzma_rs::xz_decompress_with_options(&mut compressed.as_slice(), &mut decomp, &options)
+                .unwrap_err();
+        assert!(err.to_string().contains("maximum output"), "{}", err);
+        assert!(decomp.is_empty());
+
+        let mut reader = lzma_rs::decompress::XzSeekReader::with_options(
+            Cursor::new(&compressed),
+            &lzma_rs::decompress::Options {
+                max_block_output: Some(999),
+                ..Default::default()
+            },
+        )
+        .unwrap();
+        let mut decomp: Vec<u8> = Vec::new();
+        let err = reader.read_to_end(&mut decomp).unwrap_err();
+        assert!(
+            err.to_string()
+                .contains("exceeds the maximum output of 999 bytes"),
+            "{}",
+            err
+        );
+    }
+}
+
+#[test]
+fn footer_flags_mismatch() {
+    #[cfg(feature = "enable_logging")]
+    let _ = env_logger::try_init();
+    let options = lzma_rs::compress::Options {
+        check: lzma_rs::compress::CheckMethod::Crc32,
+        ..Default::default()
+    };
+    let mut compressed: Vec<u8> = Vec::new();
+    lzma_rs::xz_compress_with_options(&mut &b"Hello world"[..], &mut compressed, &options).unwrap();
+
+    // The flags of the footer are compared to those of the header before its
+    // CRC32 is checked, as a spliced footer may have a valid CRC32.
+    let pos = compressed.len() - 3;
+    assert_eq!(compressed[pos], 0x01);
+    compressed[pos] = 0x04;
+    let is_mismatch = |err: &lzma_rs::error::Error| {
+        matches!(
+            err,
+            lzma_rs::error::Error::StreamFlagsMismatch {
+                header: lzma_rs::compress::CheckMethod::Crc32,
+                footer: lzma_rs::compress::CheckMethod::Crc64,
+            }
+        )
+    };
+    let mut decomp: Vec<u8> = Vec::new();
+    let err = lzma_rs::xz_decompress(&mut compressed.as_slice(), &mut decomp).unwrap_err();
+    assert!(is_mismatch(&err), "{}", err);
+    let err = lzma_rs::xz_list(&mut Cursor::new(&compressed)).unwrap_err();
+    assert!(is_mismatch(&err), "{}", err);
+}
+
+#[test]
+fn unsupported_check() {
+    #[cfg(feature = "enable_logging")]
+    let _ = env_logger::try_init();
+    let options = lzma_rs::compress::Options {
+        check: lzma_rs::compress::CheckMethod::Crc64,
+        ..Default::default()
+    };
+    let mut compressed: Vec<u8> = Vec::new();
+    lzma_rs::xz_compress_with_options(&mut &b"Hello world"[..], &mut compressed, &options).unwrap();
+
+    // Check ID 0x05 has no defined check yet, but is 8 bytes long like
+    // CRC64, so the stream is still framed correctly.
+    let crc32 = crc::Crc::<u32>::new(&crc::CRC_32_ISO_HDLC);
+    let len = compressed.len();
+    assert_eq!(compressed[7], 0x04);
+    assert_eq!(compressed[len - 3], 0x04);
+    compressed[7] = 0x05;
+    compressed[len - 3] = 0x05;
+    let header_crc32 = crc32.checksum(&compressed[6..8]);
+    compressed[8..12].copy_from_slice(&header_crc32.to_le_bytes());
+    let footer_crc32 = crc32.checksum(&compressed[len - 8..len - 2]);
+    compressed[len - 12..len - 8].copy_from_slice(&footer_crc32.to_le_bytes());
+    // The check of the single block directly precedes the index.
+    let mut backward_size = [0; 4];
+    backward_size.copy_from_slice(&compressed[len - 8..len - 4]);
+    let index_offset = len - 12 - (u32::from_le_bytes(backward_size) as usize + 1) * 4;
+    let check_offset = index_offset - 8;
+
+    let mut decomp: Vec<u8> = Vec::new();
+    let err = lzma_rs::xz_decompress(&mut compressed.as_slice(), &mut decomp).unwrap_err();
+    assert!(
+        matches!(&err, lzma_rs::error::Error::Unsupported { what } if what == "check ID 0x5"),
+        "{}",
+        err
+    );
+
+    let options = lzma_rs::decompress::Options {
+        lenient: true,
+        ..Default::default()
+    };
+    let mut decomp: Vec<u8> = Vec::new();
+    let status =
+        lzma_rs::xz_decompress_with_options(&mut compressed.as_slice(), &mut decomp, &options)
+            .unwrap();
+    assert_eq!(decomp, b"Hello world");
+    assert_eq!(
+        status.warnings,
+        vec![lzma_rs::decompress::Warning {
+            kind: lzma_rs::decompress::WarningKind::UnsupportedCheck,
+            offset: check_offset as u64
+        }]
+    );
+}
+
+#[test]
+fn chained_slices() {
+    #[cfg(feature = "enable_logging")]
+    let _ = env_logger::try_init();
+    let x = read_all_file("tests/files/foo.txt").unwrap();
+    // Several blocks declaring their sizes, so that headers, VLIs, checks
+    // and the index all have a chance to span two slices.
+    let options = lzma_rs::compress::Options {
+        check: lzma_rs::compress::CheckMethod::Crc64,
+        block_size: Some(1000),
+        store_block_sizes: true,
+        ..Default::default()
+    };
+    let mut compressed: Vec<u8> = Vec::new();
+    lzma_rs::xz_compress_with_options(&mut x.as_slice(), &mut compressed, &options).unwrap();
+
+    for chunk_sizes in [&[1][..], &[2, 0, 3], &[5, 7, 11, 0], &[13, 1, 64]] {
+        let mut slices: Vec<&[u8]> = Vec::new();
+        let mut rest = compressed.as_slice();
+        for &len in chunk_sizes.iter().cycle() {
+            if rest.is_empty() {
+                break;
+            }
+            let (slice, tail) = rest.split_at(std::cmp::min(len, rest.len()));
+            slices.push(slice);
+            rest = tail;
+        }
+        let mut input = lzma_rs::decompress::ChainedReader::new(&slices);
+        let mut decomp: Vec<u8> = Vec::new();
+        lzma_rs::xz_decompress(&mut input, &mut decomp).unwrap();
+        assert_eq!(decomp, x, "chunk sizes {:?}", chunk_sizes);
+        assert_eq!(input.remaining(), 0);
+    }
+}
+
+#[test]
+fn decompress_limit() {
+    #[cfg(feature = "enable_logging")]
+    let _ = env_logger::try_init();
+    let x = read_all_file("tests/files/foo.txt").unwrap();
+    for block_size in [None, Some(1000)] {
+        let options = lzma_rs::compress::Options {
+            check: lzma_rs::compress::CheckMethod::Crc32,
+            block_size,
+            ..Default::default()
+        };
+        let mut compressed: Vec<u8> = Vec::new();
+        lzma_rs::xz_compress_with_options(&mut x.as_slice(), &mut compressed, &options).unwrap();
+
+        // The rest of the stream isn't decoded, so cutting it doesn't matter.
+        let head = &compressed[..compressed.len() / 2];
+        for limit in [0, 100, 1000, 2500] {
+            let options = lzma_rs::decompress::Options {
+                limit: Some(limit as u64),
+                ..Default::default()
+            };
+            let mut decomp: Vec<u8> = Vec::new();
+            let status =
+                lzma_rs::xz_decompress_with_options(&mut &head[..], &mut decomp, &options).unwrap();
+            assert!(!status.truncated);
+            assert_eq!(decomp, x[..limit], "{:?} {}", block_size, limit);
+        }
+
+        let options = lzma_rs::decompress::Options {
+            limit: Some(x.len() as u64 + 1),
+            ..Default::default()
+        };
+        let mut decomp: Vec<u8> = Vec::new();
+        lzma_rs::xz_decompress_with_options(&mut compressed.as_slice(), &mut decomp, &options)
+            .unwrap();
+        assert_eq!(decomp, x);
+    }
+}
+
+#[test]
+fn list() {
+    #[cfg(feature = "enable_logging")]
+    let _ = env_logger::try_init();
+    use lzma_rs::compress::CheckMethod;
+    let foo = read_all_file("tests/files/foo.txt").unwrap();
+    let mut file: Vec<u8> = Vec::new();
+    let options = lzma_rs::compress::Options {
+        check: CheckMethod::Crc32,
+        block_size: Some(100_000),
+        ..Default::default()
+    };
+    lzma_rs::xz_compress_with_options(&mut foo.as_slice(), &mut file, &options).unwrap();
+    let first_len = file.len() as u64;
+    // The second stream follows some stream padding.
+    file.extend_from_slice(&[0; 8]);
+    let options = lzma_rs::compress::Options {
+        check: CheckMethod::Crc64,
+        ..Default::default()
+    };
+    lzma_rs::xz_compress_with_options(&mut &b"Hello world\n"[..], &mut file, &options).unwrap();
+
+    let listing = lzma_rs::xz_list(&mut std::io::Cursor::new(&file)).unwrap();
+    assert_eq!(listing.file_size, file.len() as u64);
+    assert_eq!(listing.streams.len(), 2);
+    assert_eq!(listing.block_count(), 3);
+    assert_eq!(listing.uncompressed_size(), foo.len() as u64 + 12);
+    assert_eq!(
+        listing.ratio(),
+        Some(file.len() as f64 / (foo.len() as f64 + 12.0))
+    );
+
+    let first = &listing.streams[0];
+    assert_eq!(first.offset, 0);
+    assert_eq!(first.compressed_size, first_len);
+    assert_eq!(first.padding, 8);
+    assert_eq!(first.check, CheckMethod::Crc32);
+    assert_eq!(first.uncompressed_size(), foo.len() as u64);
+    let sizes: Vec<u64> = first.blocks.iter().map(|b| b.uncompressed_size).collect();
+    assert_eq!(sizes, [100_000, foo.len() as u64 - 100_000]);
+    assert_eq!(first.blocks[0].compressed_offset, 12);
+    assert_eq!(
+        first.blocks[1].compressed_offset,
+        12 + ((first.blocks[0].unpadded_size + 3) & !3)
+    );
+    assert_eq!(first.blocks[1].uncompressed_offset, 100_000);
+
+    let second = &listing.streams[1];
+    assert_eq!(second.offset, first_len + 8);
+    assert_eq!(second.compressed_size, file.len() as u64 - first_len - 8);
+    assert_eq!(second.padding, 0);
+    assert_eq!(second.check, CheckMethod::Crc64);
+    assert_eq!(second.blocks[0].compressed_offset, first_len + 8 + 12);
+    assert_eq!(second.blocks[0].uncompressed_offset, foo.len() as u64);
+    assert_eq!(second.blocks[0].uncompressed_size, 12);
+
+    // Stream padding comes in multiples of four bytes.
+    let mut misaligned = file.clone();
+    misaligned.push(0);
+    assert!(lzma_rs::xz_list(&mut std::io::Cursor::new(&misaligned)).is_err());
+    assert!(lzma_rs::xz_list(&mut std::io::Cursor::new(&foo)).is_err());
+    assert!(lzma_rs::xz_list(&mut std::io::Cursor::new(&[])).is_err());
+}
+
+#[test]
+fn pad_to() {
+    #[cfg(feature = "enable_logging")]
+    let _ = env_logger::try_init();
+    let x = read_all_file("tests/files/foo.txt").unwrap();
+    let options = lzma_rs::compress::Options {
+        check: lzma_rs::compress::CheckMethod::Crc64,
+        pad_to: Some(512),
+        ..Default::default()
+    };
+    let mut compressed: Vec<u8> = Vec::new();
+    let stats =
+        lzma_rs::xz_compress_with_options(&mut x.as_slice(), &mut compressed, &options).unwrap();
+    assert_eq!(compressed.len() % 512, 0);
+    assert_eq!(stats.output_size, compressed.len() as u64);
+    assert_eq!(compressed.last(), Some(&0));
+
+    // The stream padding is skipped by every decoder.
+    let mut decomp: Vec<u8> = Vec::new();
+    lzma_rs::xz_decompress(&mut compressed.as_slice(), &mut decomp).unwrap();
+    assert_eq!(decomp, x);
+    let mut reader =
+        lzma_rs::decompress::XzSeekReader::new(std::io::Cursor::new(&compressed)).unwrap();
+    let mut decomp: Vec<u8> = Vec::new();
+    reader.read_to_end(&mut decomp).unwrap();
+    assert_eq!(decomp, x);
+    let listing = lzma_rs::xz_list(&mut std::io::Cursor::new(&compressed)).unwrap();
+    let stream = &listing.streams[0];
+    assert_eq!(
+        stream.compressed_size + stream.padding,
+        compressed.len() as u64
+    );
+    #[cfg(feature = "stream")]
+    {
+        use std::io::Write;
+        let mut decoder = lzma_rs::decompress::XzWriteDecoder::new(Vec::new());
+        for chunk in compressed.chunks(100) {
+            decoder.write_all(chunk).unwrap();
+        }
+        assert_eq!(decoder.finish().unwrap(), x);
+    }
+
+    // The padding must be made of null bytes in multiples of four.
+    let padding = stream.padding as usize;
+    let mut unaligned = compressed[..compressed.len() - padding].to_vec();
+    unaligned.extend_from_slice(&[0; 3]);
+    let mut decomp: Vec<u8> = Vec::new();
+    let err = lzma_rs::xz_decompress(&mut unaligned.as_slice(), &mut decomp).unwrap_err();
+    assert_eq!(
+        err.to_string(),
+        "xz error: Invalid stream padding of 3 bytes, must be a multiple of 4"
+    );
+    #[cfg(feature = "stream")]
+    {
+        use std::io::Write;
+        let mut decoder = lzma_rs::decompress::XzWriteDecoder::new(Vec::new());
+        decoder.write_all(&unaligned).unwrap();
+        assert!(decoder.finish().is_err());
+    }
+    let mut garbage = compressed.clone();
+    *garbage.last_mut().unwrap() = 1;
+    let mut decomp: Vec<u8> = Vec::new();
+    let err = lzma_rs::xz_decompress(&mut garbage.as_slice(), &mut decomp).unwrap_err();
+    assert_eq!(
+        err.to_string(),
+        "xz error: Unexpected data after last XZ block"
+    );
+
+    let options = lzma_rs::compress::Options {
+        pad_to: Some(6),
+        ..Default::default()
+    };
+    let err = lzma_rs::xz_compress_with_options(&mut x.as_slice(), &mut Vec::new(), &options)
+        .unwrap_err();
+    assert_eq!(err.kind(), std::io::ErrorKind::InvalidInput);
+}
+
+#[test]
+fn compress_chunks() {
+    #[cfg(feature = "enable_logging")]
+    let _ = env_logger::try_init();
+    let x = read_all_file("tests/files/foo.txt").unwrap();
+    let options = lzma_rs::compress::Options {
+        check: lzma_rs::compress::CheckMethod::Crc32,
+        block_size: Some(10_000),
+        ..Default::default()
+    };
+    let mut expected: Vec<u8> = Vec::new();
+    lzma_rs::xz_compress_with_options(&mut x.as_slice(), &mut expected, &options).unwrap();
+
+    for &chunk_size in &[1, 7, 4096, 0x10001] {
+        let mut compressed: Vec<u8> = Vec::new();
+        let stats =
+            lzma_rs::xz_compress_chunks(x.chunks(chunk_size), &mut compressed, &options).unwrap();
+        assert_eq!(stats.input_size, x.len() as u64);
+        assert_eq!(compressed, expected, "{}", chunk_size);
+    }
+
+    // Empty chunks are skipped, and owned chunks work too.
+    let chunks: Vec<Vec<u8>> = x
+        .chunks(1000)
+        .flat_map(|chunk| vec![Vec::new(), chunk.to_vec()])
+        .collect();
+    let mut compressed: Vec<u8> = Vec::new();
+    lzma_rs::xz_compress_chunks(chunks, &mut compressed, &options).unwrap();
+    assert_eq!(compressed, expected);
+}
+
+#[test]
+fn xz_compatible() {
+    #[cfg(feature = "enable_logging")]
+    let _ = env_logger::try_init();
+    let x = read_all_file("tests/files/foo.txt").unwrap();
+    let options = lzma_rs::compress::Options::xz_compatible();
+    let mut compressed: Vec<u8> = Vec::new();
+    let stats =
+        lzma_rs::xz_compress_with_options(&mut x.as_slice(), &mut compressed, &options).unwrap();
+    assert_eq!(stats.blocks, 1);
+    // Same stream flags as `xz`, i.e. a CRC64 check.
+    let reference = read_all_file("tests/files/foo.txt.xz").unwrap();
+    assert_eq!(compressed[..12], reference[..12]);
+    assert_eq!(
+        compressed[compressed.len() - 4..],
+        reference[reference.len() - 4..]
+    );
+    // The LZMA2 filter declares the same dictionary size.
+    assert_eq!(compressed[12..20], reference[12..20]);
+    // Only literals are coded, which still shrinks text.
+    assert!(compressed.len() < x.len());
+
+    let mut decomp: Vec<u8> = Vec::new();
+    lzma_rs::xz_decompress(&mut compressed.as_slice(), &mut decomp).unwrap();
+    assert_eq!(decomp, x);
+    // So does liblzma, like `xz -d`.
+    assert_eq!(lzma::decompress(&compressed).unwrap(), x);
+}
+
+#[test]
+fn empty_index() {
+    #[cfg(feature = "enable_logging")]
+    let _ = env_logger::try_init();
+    // Made by `echo -n | xz`: a stream without blocks, whose index has zero
+    // records.
+    let file = read_all_file("tests/files/empty.txt.xz").unwrap();
+    let mut decomp: Vec<u8> = Vec::new();
+    lzma_rs::xz_decompress(&mut file.as_slice(), &mut decomp).unwrap();
+    assert!(decomp.is_empty());
+
+    let listing = lzma_rs::xz_list(&mut std::io::Cursor::new(&file)).unwrap();
+    assert_eq!(listing.streams.len(), 1);
+    assert_eq!(listing.block_count(), 0);
+    assert_eq!(listing.uncompressed_size(), 0);
+    assert_eq!(listing.streams[0].compressed_size, file.len() as u64);
+
+    // An index listing one record is checked against the zero decoded
+    // blocks. It has the same size, so the footer still matches.
+    let mut damaged = file.clone();
+    damaged[12..16].copy_from_slice(b"\x00\x01\x00\x00");
+    let crc32 = crc::Crc::<u32>::new(&crc::CRC_32_ISO_HDLC);
+    let digest = crc32.checksum(&damaged[12..16]);
+    damaged[16..20].copy_from_slice(&digest.to_le_bytes());
+    let err = lzma_rs::xz_decompress(&mut damaged.as_slice(), &mut Vec::new()).unwrap_err();
+    assert_eq!(
+        err.to_string(),
+        "xz error: Expected 1 records but got 0 records"
+    );
+}
+
+#[test]
+fn multi_block_check() {
+    #[cfg(feature = "enable_logging")]
+    let _ = env_logger::try_init();
+    let x = read_all_file("tests/files/foo.txt").unwrap();
+    let options = lzma_rs::compress::Options {
+        block_size: Some(50_000),
+        check: lzma_rs::compress::CheckMethod::Crc64,
+        ..Default::default()
+    };
+    let mut compressed: Vec<u8> = Vec::new();
+    lzma_rs::xz_compress_with_options(&mut x.as_slice(), &mut compressed, &options).unwrap();
+
+    // The stream flags set the check of every block: each one ends with the
+    // 8 bytes of the CRC64 of its data, after the padding of the compressed
+    // data, which the unpadded size leaves out.
+    let crc64 = crc::Crc::<u64>::new(&crc::CRC_64_XZ);
+    let listing = lzma_rs::xz_list(&mut Cursor::new(&compressed)).unwrap();
+    let blocks = &listing.streams[0].blocks;
+    assert_eq!(blocks.len(), 4);
+    let check_offset = |block: &lzma_rs::decompress::XzBlockInfo| {
+        (block.compressed_offset + ((block.unpadded_size - 8 + 3) & !3)) as usize
+    };
+    for block in blocks {
+        let check_offset = check_offset(block);
+        let start = block.uncompressed_offset as usize;
+        let data = &x[start..start + block.uncompressed_size as usize];
+        assert_eq!(
+            compressed[check_offset..check_offset + 8],
+            crc64.checksum(data).to_le_bytes()
+        );
+    }
+    let mut decomp: Vec<u8> = Vec::new();
+    lzma_rs::xz_decompress(&mut compressed.as_slice(), &mut decomp).unwrap();
+    assert_eq!(decomp, x);
+
+    // The check of a later block is validated the same way.
+    let check_offset = check_offset(&blocks[2]);
+    compressed[check_offset] ^= 1;
+    let err = lzma_rs::xz_decompress(&mut compressed.as_slice(), &mut Vec::new()).unwrap_err();
+    assert!(err.to_string().contains("Invalid block CRC64"), "{}", err);
+}
+
+#[test]
+fn sniff_format() {
+    #[cfg(feature = "enable_logging")]
+    let _ = env_logger::try_init();
+    use lzma_rs::decompress::Format;
+    for (path, expected) in [
+        ("tests/files/foo.txt.xz", Format::Xz),
+        ("tests/files/foo.txt.lzma", Format::LzmaAlone),
+        ("tests/files/range-coder-edge-case.lzma", Format::LzmaAlone),
+        ("tests/files/foo.txt", Format::Unknown),
+    ] {
+        let data = read_all_file(path).unwrap();
+        let mut input = BufReader::new(data.as_slice());
+        assert_eq!(
+            lzma_rs::sniff_format(&mut input).unwrap(),
+            expected,
+            "{}",
+            path
+        );
+        // Nothing was consumed.
+        let mut rest = Vec::new();
+        input.read_to_end(&mut rest).unwrap();
+        assert_eq!(rest, data, "{}", path);
+    }
+
+    // Raw LZMA2 has no header.
+    let mut lzma2: Vec<u8> = Vec::new();
+    lzma_rs::lzma2_compress(&mut &b"Hello world\n"[..], &mut lzma2).unwrap();
+    assert_eq!(
+        lzma_rs::sniff_format(&mut lzma2.as_slice()).unwrap(),
+        Format::Unknown
+    );
+}
+
+#[test]
+fn deadline() {
+    #[cfg(feature = "enable_logging")]
+    let _ = env_logger::try_init();
+    let x = read_all_file("tests/files/foo.txt").unwrap();
+    let compressed = lzma_rs::xz_compress_to_vec(&x).unwrap();
+    let decompress = |deadline| {
+        let options = lzma_rs::decompress::Options::builder()
+            .deadline(deadline)
+            .build()
+            .unwrap();
+        let mut decomp: Vec<u8> = Vec::new();
+        lzma_rs::xz_decompress_with_options(&mut compressed.as_slice(), &mut decomp, &options)
+            .map(|_| decomp)
+    };
+
+    let now = std::time::Instant::now();
+    let err = decompress(now).unwrap_err();
+    assert!(matches!(err, lzma_rs::error::Error::TimedOut), "{}", err);
+    let decomp = decompress(now + std::time::Duration::from_secs(3600)).unwrap();
+    assert_eq!(decomp, x);
+}
+
+#[test]
+fn verify() {
+    #[cfg(feature = "enable_logging")]
+    let _ = env_logger::try_init();
+    let x = read_all_file("tests/files/foo.txt").unwrap();
+    let mut compressed = read_all_file("tests/files/foo.txt.xz").unwrap();
+    assert_eq!(
+        lzma_rs::xz_verify(&mut compressed.as_slice()).unwrap(),
+        lzma_rs::decompress::VerifyReport {
+            uncompressed_size: x.len() as u64,
+            blocks: 1,
+            checks_verified: 1,
+        }
+    );
+
+    // Without integrity check, only the structure and sizes are verified.
+    let options = lzma_rs::compress::Options {
+        block_size: Some(50_000),
+        ..Default::default()
+    };
+    let mut unchecked: Vec<u8> = Vec::new();
+    lzma_rs::xz_compress_with_options(&mut x.as_slice(), &mut unchecked, &options).unwrap();
+    let report = lzma_rs::xz_verify(&mut unchecked.as_slice()).unwrap();
+    assert_eq!(report.blocks, 4);
+    assert_eq!(report.checks_verified, 0);
+
+    // The CRC64 precedes the index and the 12-byte footer.
+    let index_size = 4 * (compressed[compressed.len() - 8] as usize + 1);
+    let check_end = compressed.len() - 12 - index_size;
+    compressed[check_end - 1] ^= 1;
+    let err = lzma_rs::xz_verify(&mut compressed.as_slice()).unwrap_err();
+    assert!(err.to_string().contains("Invalid block CRC64"), "{}", err);
+}
+
+#[test]
+fn max_chunks() {
+    #[cfg(feature = "enable_logging")]
+    let _ = env_logger::try_init();
+    let x = read_all_file("tests/files/foo.txt").unwrap();
+    // 100 + 74 chunks of at most 1000 bytes, in two blocks.
+    let options = lzma_rs::compress::Options {
+        block_size: Some(100_000),
+        chunk_boundary_bytes: Some(1000),
+        ..Default::default()
+    };
+    let mut compressed: Vec<u8> = Vec::new();
+    lzma_rs::xz_compress_with_options(&mut x.as_slice(), &mut compressed, &options).unwrap();
+    let decompress = |max_chunks| {
+        let options = lzma_rs::decompress::Options::builder()
+            .max_chunks(max_chunks)
+            .build()
+            .unwrap();
+        let mut decomp: Vec<u8> = Vec::new();
+        lzma_rs::xz_decompress_with_options(&mut compressed.as_slice(), &mut decomp, &options)
+            .map(|_| decomp)
+    };
+
+    assert_eq!(decompress(174).unwrap(), x);
+    // The chunks of all the blocks are counted.
+    let err = decompress(173).unwrap_err();
+    assert!(
+        matches!(err, lzma_rs::error::Error::TooManyChunks(174)),
+        "{}",
+        err
+    );
+    let err = decompress(0).unwrap_err();
+    assert!(
+        matches!(err, lzma_rs::error::Error::TooManyChunks(1)),
+        "{}",
+        err
+    );
+}
+
+#[test]
+fn decompress_into() {
+    #[cfg(feature = "enable_logging")]
+    let _ = env_logger::try_init();
+    let x = read_all_file("tests/files/foo.txt").unwrap();
+    let options = lzma_rs::compress::Options {
+        block_size: Some(50_000),
+        check: lzma_rs::compress::CheckMethod::Crc32,
+        ..Default::default()
+    };
+    // Two streams of several blocks.
+    let mut compressed: Vec<u8> = Vec::new();
+    lzma_rs::xz_compress_with_options(&mut x.as_slice(), &mut compressed, &options).unwrap();
+    lzma_rs::xz_compress_with_options(&mut x.as_slice(), &mut compressed, &options).unwrap();
+    let expected = [x.as_slice(), x.as_slice()].concat();
+
+    let mut out = vec![0; expected.len()];
+    let size = lzma_rs::xz_decompress_into(&mut Cursor::new(&compressed), &mut out).unwrap();
+    assert_eq!(size, expected.len());
+    assert_eq!(out, expected);
+
+    // A larger slice is only filled up to the decompressed size.
+    let mut out = vec![0xAA; expected.len() + 10];
+    let size = lzma_rs::xz_decompress_into(&mut Cursor::new(&compressed), &mut out).unwrap();
+    assert_eq!(size, expected.len());
+    assert_eq!(out[..size], expected[..]);
+    assert!(out[size..].iter().all(|&byte| byte == 0xAA));
+
+    // A smaller slice is rejected before decoding.
+    let mut out = vec![0; expected.len() - 1];
+    let err = lzma_rs::xz_decompress_into(&mut Cursor::new(&compressed), &mut out).unwrap_err();
+    assert!(
+        matches!(err, lzma_rs::error::Error::OutputTooLarge(size) if size == out.len() as u64),
+        "{}",
+        err
+    );
+    assert!(out.iter().all(|&byte| byte == 0));
+}
+
+#[test]
+fn verify_roundtrip() {
+    #[cfg(feature = "enable_logging")]
+    let _ = env_logger::try_init();
+    let x = read_all_file("tests/files/foo.txt").unwrap();
+    let compress = |options: &lzma_rs::compress::Options| {
+        let mut compressed: Vec<u8> = Vec::new();
+        let stats =
+            lzma_rs::xz_compress_with_options(&mut x.as_slice(), &mut compressed, options).unwrap();
+        (compressed, stats)
+    };
+    let options = lzma_rs::compress::Options {
+        check: lzma_rs::compress::CheckMethod::Crc64,
+        block_size: Some(100_000),
+        threads: 2,
+        ..Default::default()
+    };
+    let expected = compress(&options);
+    // The checked stream is the same.
+    let verified = compress(&lzma_rs::compress::Options {
+        verify_roundtrip: true,
+        ..options
+    });
+    assert_eq!(verified, expected);
+    let mut decomp: Vec<u8> = Vec::new();
+    lzma_rs::xz_decompress(&mut verified.0.as_slice(), &mut decomp).unwrap();
+    assert_eq!(decomp, x);
+}
+
+#[test]
+fn concat() {
+    #[cfg(feature = "enable_logging")]
+    let _ = env_logger::try_init();
+    let x = read_all_file("tests/files/foo.txt").unwrap();
+    let first = round_trip_with_check(&x[..1000], lzma_rs::compress::CheckMethod::Crc64);
+    let options = lzma_rs::compress::Options {
+        check: lzma_rs::compress::CheckMethod::Crc64,
+        block_size: Some(10_000),
+        pad_to: Some(512),
+        ..Default::default()
+    };
+    let mut second: Vec<u8> = Vec::new();
+    lzma_rs::xz_compress_with_options(&mut &x[1000..], &mut second, &options).unwrap();
+
+    let mut merged: Vec<u8> = Vec::new();
+    let size = lzma_rs::xz_concat([&first, &second], &mut merged).unwrap();
+    assert_eq!(size, merged.len() as u64);
+    let mut decomp: Vec<u8> = Vec::new();
+    lzma_rs::xz_decompress(&mut merged.as_slice(), &mut decomp).unwrap();
+    assert_eq!(decomp, x);
+    let decomp = lzma::decompress(&merged).unwrap();
+    assert_eq!(decomp, x);
+    // A single stream lists the blocks of both, without the padding.
+    let listing = lzma_rs::xz_list(&mut Cursor::new(&merged)).unwrap();
+    assert_eq!(listing.streams.len(), 1);
+    assert_eq!(listing.streams[0].padding, 0);
+    let block_count = |xz: &[u8]| {
+        lzma_rs::xz_list(&mut Cursor::new(xz))
+            .unwrap()
+            .block_count()
+    };
+    assert_eq!(
+        listing.block_count(),
+        block_count(&first) + block_count(&second)
+    );
+    assert_eq!(listing.uncompressed_size(), x.len() as u64);
+
+    // Merged files can be merged again, and nothing merges to an empty
+    // stream.
+    let mut empty: Vec<u8> = Vec::new();
+    lzma_rs::xz_concat(Vec::<Vec<u8>>::new(), &mut empty).unwrap();
+    let mut decomp: Vec<u8> = Vec::new();
+    lzma_rs::xz_decompress(&mut empty.as_slice(), &mut decomp).unwrap();
+    assert!(decomp.is_empty());
+    let mut twice: Vec<u8> = Vec::new();
+    lzma_rs::xz_concat([&merged, &empty, &merged], &mut twice).unwrap();
+    let mut decomp: Vec<u8> = Vec::new();
+    lzma_rs::xz_decompress(&mut twice.as_slice(), &mut decomp).unwrap();
+    assert_eq!(decomp, [&x[..], &x[..]].concat());
+
+    // Nothing is written if an input isn't a whole `.xz` file, or if the
+    // checks differ.
+    let mut output: Vec<u8> = Vec::new();
+    let truncated = &first[..first.len() - 4];
+    assert!(lzma_rs::xz_concat([&second[..], truncated], &mut output).is_err());
+    let crc32 = round_trip_with_check(&x, lzma_rs::compress::CheckMethod::Crc32);
+    let err = lzma_rs::xz_concat([&second, &crc32], &mut output).unwrap_err();
+    assert_eq!(
+        err.to_string(),
+        "xz error: Cannot merge streams with Crc64 and Crc32 checks"
+    );
+    assert!(output.is_empty());
+}
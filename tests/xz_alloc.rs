@@ -0,0 +1,61 @@
+//! Allocation behaviour of the XZ decoder, in a separate test binary to count
+//! the allocations with a global allocator.
+#![cfg(feature = "xz")]
+
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+/// Allocator counting the allocations made through it.
+struct CountingAlloc;
+
+static ALLOCATIONS: AtomicUsize = AtomicUsize::new(0);
+
+unsafe impl GlobalAlloc for CountingAlloc {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+        System.alloc(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout)
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+        System.realloc(ptr, layout, new_size)
+    }
+}
+
+#[global_allocator]
+static GLOBAL: CountingAlloc = CountingAlloc;
+
+#[test]
+fn many_small_blocks() {
+    #[cfg(feature = "enable_logging")]
+    let _ = env_logger::try_init();
+    let x = std::fs::read("tests/files/foo.txt").unwrap();
+    let block_size = 16;
+    let options = lzma_rs::compress::Options {
+        check: lzma_rs::compress::CheckMethod::Crc32,
+        block_size: Some(block_size),
+        ..Default::default()
+    };
+    let mut compressed: Vec<u8> = Vec::new();
+    let stats =
+        lzma_rs::xz_compress_with_options(&mut x.as_slice(), &mut compressed, &options).unwrap();
+    assert!(stats.blocks > 1000);
+
+    let mut decomp: Vec<u8> = Vec::with_capacity(x.len());
+    let before = ALLOCATIONS.load(Ordering::Relaxed);
+    lzma_rs::xz_decompress(&mut compressed.as_slice(), &mut decomp).unwrap();
+    let allocations = ALLOCATIONS.load(Ordering::Relaxed) - before;
+    assert_eq!(decomp, x);
+    // Only the list of index records grows with the number of blocks, each
+    // block reuses the buffers of the previous one.
+    assert!(
+        allocations < 64,
+        "{} allocations for {} blocks",
+        allocations,
+        stats.blocks
+    );
+}